@@ -0,0 +1,67 @@
+//! Having Cargo put built binaries straight into a chosen directory, with
+//! `--artifact-dir`, when the Cargo in use supports it.
+
+use once_cell::sync::Lazy;
+use std::{
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    process::Command,
+    sync::Mutex,
+};
+
+/// The flag each Cargo understands, by its path.
+static FLAGS: Lazy<Mutex<HashMap<OsString, Option<&'static str>>>> = Lazy::new(Default::default);
+
+/// The flag to give `cargo` to put binaries in a directory of our choosing,
+/// or `None` if it can't. This is unstable, so only nightly Cargo has it, and
+/// it was called `--out-dir` before 1.79.
+pub(crate) fn flag(cargo: &OsStr) -> Option<&'static str> {
+    let mut flags = FLAGS.lock().unwrap_or_else(|e| e.into_inner());
+    *flags.entry(cargo.to_owned()).or_insert_with(|| {
+        let output = Command::new(cargo).arg("-V").output().ok()?;
+        flag_for_version(&String::from_utf8_lossy(&output.stdout))
+    })
+}
+
+/// Picks the flag from the output of `cargo -V` eg.
+/// `cargo 1.97.0-nightly (4d1f98451 2026-05-15)`.
+fn flag_for_version(version: &str) -> Option<&'static str> {
+    let version = version.split_whitespace().nth(1)?;
+    let (numbers, channel) = version.split_once('-')?;
+    if !(channel.starts_with("nightly") || channel.starts_with("dev")) {
+        return None;
+    }
+    let minor: u32 = numbers.split('.').nth(1)?.parse().ok()?;
+    Some(if minor >= 79 {
+        "--artifact-dir"
+    } else {
+        "--out-dir"
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions() {
+        assert_eq!(
+            flag_for_version("cargo 1.97.0-nightly (4d1f98451 2026-05-15)\n"),
+            Some("--artifact-dir")
+        );
+        assert_eq!(
+            flag_for_version("cargo 1.70.0-nightly (0e474cfd7 2023-03-31)"),
+            Some("--out-dir")
+        );
+        assert_eq!(flag_for_version("cargo 1.80.0-dev"), Some("--artifact-dir"));
+        assert_eq!(
+            flag_for_version("cargo 1.95.0 (f2d3ce0bd 2026-03-21)"),
+            None
+        );
+        assert_eq!(
+            flag_for_version("cargo 1.96.0-beta.2 (0123 2026-04-01)"),
+            None
+        );
+        assert_eq!(flag_for_version(""), None);
+    }
+}
@@ -0,0 +1,52 @@
+//! Auditing the dependencies of test binaries.
+//!
+//! Test binaries aren't members of the parent's workspace, so tools like
+//! `cargo audit` don't see their dependencies. This lets a check be run on
+//! them whenever they're built instead.
+
+use crate::{Dependency, TestBinaryError};
+use std::{cell::RefCell, path::Path, rc::Rc};
+
+/// What an audit set with [`TestBinary::with_audit()`] gets to look at.
+///
+/// [`TestBinary::with_audit()`]: crate::TestBinary::with_audit
+#[derive(Debug)]
+pub struct AuditInput<'a> {
+    pub(crate) name: &'a str,
+    pub(crate) lock_file: &'a Path,
+    pub(crate) dependencies: &'a [Dependency],
+}
+
+impl AuditInput<'_> {
+    /// The name of the binary that was built.
+    pub fn name(&self) -> &str {
+        self.name
+    }
+
+    /// The `Cargo.lock` the binary's dependencies were resolved from. This is
+    /// what tools like `cargo audit` take, with `--file`.
+    pub fn lock_file(&self) -> &Path {
+        self.lock_file
+    }
+
+    /// The packages the binary was built with, as for
+    /// [`Artifact::dependencies()`](crate::Artifact::dependencies).
+    pub fn dependencies(&self) -> &[Dependency] {
+        self.dependencies
+    }
+}
+
+/// Shared, so that clones of a builder all report to the same audit.
+pub(crate) type AuditCallback<'a> =
+    Rc<RefCell<dyn FnMut(&AuditInput<'_>) -> Result<(), String> + 'a>>;
+
+/// Runs the audit, turning a failure into an error.
+pub(crate) fn run(
+    audit: &AuditCallback<'_>,
+    input: &AuditInput<'_>,
+) -> Result<(), TestBinaryError> {
+    (audit.borrow_mut())(input).map_err(|findings| TestBinaryError::AuditFailed {
+        name: input.name.to_owned(),
+        findings,
+    })
+}
@@ -0,0 +1,127 @@
+//! Simple timing of repeated test binary runs.
+
+use crate::{Artifact, RunError};
+use std::{
+    ffi::{OsStr, OsString},
+    process::Stdio,
+    time::{Duration, Instant},
+};
+
+/// Options for [`Artifact::bench()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BenchOptions {
+    /// Number of untimed runs before measuring, eg. to warm filesystem caches.
+    pub warmups: usize,
+    /// Number of timed runs. At least one run is always timed.
+    pub iterations: usize,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        Self {
+            warmups: 1,
+            iterations: 10,
+        }
+    }
+}
+
+/// Wall-clock timing statistics from [`Artifact::bench()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BenchStats {
+    samples: Vec<Duration>,
+}
+
+impl BenchStats {
+    fn new(mut samples: Vec<Duration>) -> Self {
+        samples.sort();
+        Self { samples }
+    }
+
+    /// The fastest run.
+    pub fn min(&self) -> Duration {
+        self.samples[0]
+    }
+
+    /// The slowest run.
+    pub fn max(&self) -> Duration {
+        self.samples[self.samples.len() - 1]
+    }
+
+    /// The mean duration of all the runs.
+    pub fn mean(&self) -> Duration {
+        let total: Duration = self.samples.iter().sum();
+        total / self.samples.len() as u32
+    }
+
+    /// The 95th percentile duration (nearest rank).
+    pub fn p95(&self) -> Duration {
+        let rank = (self.samples.len() * 95).div_ceil(100);
+        self.samples[rank.max(1) - 1]
+    }
+
+    /// All the timed runs, sorted from fastest to slowest.
+    pub fn samples(&self) -> &[Duration] {
+        &self.samples
+    }
+}
+
+impl Artifact {
+    /// Runs the binary repeatedly with the given arguments and measures how
+    /// long each run takes, from spawning to exit. This is intended for
+    /// tracking things like startup latency, not for rigorous benchmarking.
+    ///
+    /// The binary's output is discarded. Any unsuccessful run is an error.
+    pub fn bench<I, S>(&self, args: I, options: BenchOptions) -> Result<BenchStats, RunError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let args: Vec<OsString> = args.into_iter().map(|a| a.as_ref().to_owned()).collect();
+
+        let run_once = || -> Result<Duration, RunError> {
+            let start = Instant::now();
+            let status = self
+                .command()
+                .args(&args)
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()?;
+            let elapsed = start.elapsed();
+
+            if status.success() {
+                Ok(elapsed)
+            } else {
+                Err(RunError::BinaryFailure(status))
+            }
+        };
+
+        for _ in 0..options.warmups {
+            run_once()?;
+        }
+
+        let samples = (0..options.iterations.max(1))
+            .map(|_| run_once())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(BenchStats::new(samples))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statistics() {
+        let stats = BenchStats::new((1..=20).rev().map(Duration::from_millis).collect());
+        assert_eq!(stats.min(), Duration::from_millis(1));
+        assert_eq!(stats.max(), Duration::from_millis(20));
+        assert_eq!(stats.mean(), Duration::from_micros(10_500));
+        assert_eq!(stats.p95(), Duration::from_millis(19));
+
+        let single = BenchStats::new(vec![Duration::from_millis(7)]);
+        assert_eq!(single.p95(), Duration::from_millis(7));
+        assert_eq!(single.mean(), Duration::from_millis(7));
+    }
+}
@@ -0,0 +1,105 @@
+//! Putting built binaries in a directory of their own, to be found on `PATH`.
+
+use crate::{build_test_binary, TestBinaryError};
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+use tempfile::TempDir;
+
+/// A temporary directory of built binaries, for code under test that finds
+/// its helpers by name on `PATH` eg. `git`-style subcommands.
+///
+/// ```rust
+/// # use test_binary::BinDir;
+/// let bins = BinDir::build(["does-build", "multiple"], "testbins").unwrap();
+///
+/// let status = std::process::Command::new("does-build")
+///     .env("PATH", bins.path_env())
+///     .status()
+///     .unwrap();
+/// assert!(status.success());
+/// ```
+///
+/// Binaries are hard linked into the directory where possible, and copied
+/// otherwise. The directory is deleted when the `BinDir` is dropped.
+#[derive(Debug)]
+pub struct BinDir {
+    dir: TempDir,
+}
+
+impl BinDir {
+    /// Creates an empty directory.
+    pub fn new() -> std::io::Result<Self> {
+        Ok(Self {
+            dir: tempfile::Builder::new()
+                .prefix("test-binary-bin-")
+                .tempdir()?,
+        })
+    }
+
+    /// Builds each of the named binaries with [`build_test_binary()`], and
+    /// puts them in a new directory.
+    pub fn build<I, S, R>(names: I, directory: R) -> Result<Self, TestBinaryError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+        R: AsRef<Path>,
+    {
+        let mut bins = Self::new()?;
+        for name in names {
+            let path = build_test_binary(name.as_ref(), directory.as_ref())?;
+            bins.add(path)?;
+        }
+        Ok(bins)
+    }
+
+    /// Adds the binary at `path`, under its own file name.
+    pub fn add<P: AsRef<Path>>(&mut self, path: P) -> std::io::Result<&mut Self> {
+        let path = path.as_ref();
+        let file_name = path.file_name().ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "path has no file name")
+        })?;
+        self.place(path, self.dir.path().join(file_name))?;
+        Ok(self)
+    }
+
+    /// Adds the binary at `path` under another name eg. `git-fla` for a
+    /// subcommand. On platforms where executables have an extension (ie.
+    /// Windows), it's added to the name if it isn't there already.
+    pub fn add_as<P: AsRef<Path>>(&mut self, name: &str, path: P) -> std::io::Result<&mut Self> {
+        let suffix = std::env::consts::EXE_SUFFIX;
+        let file_name = if name.ends_with(suffix) {
+            name.to_owned()
+        } else {
+            format!("{}{}", name, suffix)
+        };
+        self.place(path.as_ref(), self.dir.path().join(file_name))?;
+        Ok(self)
+    }
+
+    /// The directory.
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// A value for `PATH` with the directory first, followed by the current
+    /// `PATH`, so that the binaries here take precedence.
+    pub fn path_env(&self) -> OsString {
+        let current = std::env::var_os("PATH").unwrap_or_default();
+        let dirs = std::iter::once(self.path().to_owned()).chain(std::env::split_paths(&current));
+        // The rest came from PATH already, so this only fails if the temporary
+        // directory's path has a separator in it.
+        std::env::join_paths(dirs).expect("temporary directory can't be put in PATH")
+    }
+
+    fn place(&self, source: &Path, destination: PathBuf) -> std::io::Result<()> {
+        if destination.exists() {
+            std::fs::remove_file(&destination)?;
+        }
+        if std::fs::hard_link(source, &destination).is_err() {
+            std::fs::copy(source, &destination)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,145 @@
+//! Cancelling builds that are in progress, eg. when a test harness is shutting
+//! down.
+
+use crate::TestBinary;
+use std::{
+    process::{Child, Command},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// How often a build with a [`CancelToken`] checks whether it's been
+/// cancelled.
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A handle for cancelling builds from another thread. Clones share the same
+/// state, so a harness can keep one and give clones to the builds it starts.
+/// See [`TestBinary::with_cancel_token()`].
+///
+/// ```rust
+/// # use test_binary::{CancelToken, TestBinary, TestBinaryError};
+/// let token = CancelToken::new();
+/// token.cancel();
+///
+/// let result = TestBinary::relative_to_parent(
+///     "does-build",
+///     &std::path::PathBuf::from_iter(["testbins", "does-build", "Cargo.toml"]),
+/// )
+/// .unwrap()
+/// .with_cancel_token(&token)
+/// .build();
+/// assert!(matches!(result, Err(TestBinaryError::Cancelled)));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    /// Creates a token that hasn't been cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels every build using this token, now and in future.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether [`CancelToken::cancel()`] has been called.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+impl<'a> TestBinary<'a> {
+    /// Cancels the build when `token` is cancelled. Cargo is killed, along
+    /// with the compilers it started, and [`TestBinaryError::Cancelled`] is
+    /// returned. A build that's already been cancelled doesn't start at all.
+    ///
+    /// On Unix, this runs Cargo in a process group of its own, so that
+    /// everything it started can be killed with it. That means Ctrl-C in a
+    /// terminal doesn't reach it directly, so cancel the token instead when
+    /// handling it. If the process exits without doing that, Cargo is killed
+    /// anyway.
+    ///
+    /// [`TestBinaryError::Cancelled`]: crate::TestBinaryError::Cancelled
+    pub fn with_cancel_token(&mut self, token: &CancelToken) -> &mut Self {
+        self.cancel = Some(token.clone());
+        self
+    }
+
+    /// Whether the build has been cancelled.
+    pub(crate) fn cancelled(&self) -> bool {
+        self.cancel.as_ref().is_some_and(CancelToken::is_cancelled)
+    }
+}
+
+/// Cargo's process, which is killed along with everything it started if it's
+/// in a process group of its own.
+#[derive(Debug)]
+pub(crate) struct Cargo {
+    id: u32,
+    grouped: bool,
+}
+
+impl Cargo {
+    /// Spawns `command`, in a group of its own if `isolate` is set and it's
+    /// supported. A group is also killed at exit if it's still around.
+    pub(crate) fn spawn(command: &mut Command, isolate: bool) -> std::io::Result<(Child, Self)> {
+        let grouped = cfg!(unix) && isolate;
+        #[cfg(unix)]
+        if grouped {
+            std::os::unix::process::CommandExt::process_group(command, 0);
+        }
+        let child = command.spawn()?;
+        let id = child.id();
+        #[cfg(unix)]
+        if grouped {
+            crate::reaper::register(id);
+        }
+        Ok((child, Self { id, grouped }))
+    }
+
+    /// Kills Cargo, and whatever else is in its group, then waits for it.
+    pub(crate) fn kill(&self, child: &mut Child) -> std::io::Result<()> {
+        #[cfg(unix)]
+        if self.grouped {
+            use nix::{
+                sys::signal::{killpg, Signal},
+                unistd::Pid,
+            };
+            // Cargo might already have exited in the meantime.
+            let _ = killpg(Pid::from_raw(self.id as i32), Signal::SIGKILL);
+        }
+        let _ = child.kill();
+        child.wait().map(drop)
+    }
+}
+
+impl Drop for Cargo {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if self.grouped {
+            crate::reaper::unregister(self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clones_share_state() {
+        let token = CancelToken::new();
+        let clone = token.clone();
+        assert!(!clone.is_cancelled());
+        token.cancel();
+        assert!(clone.is_cancelled());
+        assert!(!CancelToken::new().is_cancelled());
+    }
+}
@@ -0,0 +1,458 @@
+//! A running test binary, and capturing its output while it runs.
+
+use crate::{
+    log::{LineSink, LineSplitter, Stream},
+    Crash, LaunchContext, Panic, RunError, RunOutput, Signal,
+};
+use command_group::GroupChild;
+use std::{
+    fs::File,
+    io::{Read, Write},
+    path::PathBuf,
+    process::{Child, ExitStatus},
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+use tempfile::TempPath;
+
+/// How a child's output is captured, besides being kept in memory.
+#[derive(Debug, Default)]
+pub(crate) struct Capturing {
+    /// Where each line is sent as it arrives.
+    pub(crate) sink: Option<LineSink>,
+    /// The log file the sink writes to, if any.
+    pub(crate) log_file: Option<PathBuf>,
+    /// How much of each stream to keep in memory before spilling it to a
+    /// file.
+    pub(crate) spill_threshold: Option<usize>,
+    /// Whether to look for a panic in stderr once the child exits.
+    pub(crate) panic: bool,
+}
+
+/// Output captured from one of a child's pipes, which can be inspected while
+/// the child is still running.
+#[derive(Debug, Default)]
+pub(crate) struct Capture {
+    state: Mutex<CaptureState>,
+    updated: Condvar,
+    spill_threshold: Option<usize>,
+}
+
+#[derive(Debug, Default)]
+struct CaptureState {
+    /// Everything captured, or if it's been spilled, the start of it.
+    data: Vec<u8>,
+    closed: bool,
+    spilled: Option<Spilled>,
+}
+
+/// Output that has grown too big to keep in memory, and is being written to
+/// a temporary file instead.
+#[derive(Debug)]
+struct Spilled {
+    file: File,
+    path: Arc<TempPath>,
+}
+
+impl Capture {
+    /// Starts reading everything from the pipe on a separate thread. If there's
+    /// a sink, each line is also sent there as it arrives.
+    pub(crate) fn start<R: Read + Send + 'static>(
+        pipe: Option<R>,
+        stream: Stream,
+        sink: Option<Arc<LineSink>>,
+        spill_threshold: Option<usize>,
+    ) -> (Arc<Self>, JoinHandle<std::io::Result<()>>) {
+        let capture = Arc::new(Self {
+            spill_threshold,
+            ..Self::default()
+        });
+        let writer = Arc::clone(&capture);
+
+        let handle = std::thread::spawn(move || {
+            let result = match pipe {
+                Some(pipe) => writer.read_from(pipe, stream, sink.as_deref()),
+                None => Ok(()),
+            };
+            writer.lock().closed = true;
+            writer.updated.notify_all();
+            result
+        });
+
+        (capture, handle)
+    }
+
+    fn read_from<R: Read>(
+        &self,
+        mut pipe: R,
+        stream: Stream,
+        sink: Option<&LineSink>,
+    ) -> std::io::Result<()> {
+        let mut buffer = [0; 8192];
+        let mut splitter = LineSplitter::new(stream);
+
+        loop {
+            let count = match pipe.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(count) => count,
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+
+            if let Some(sink) = sink {
+                splitter.push(&buffer[..count], sink);
+            }
+
+            self.append(&buffer[..count])?;
+            self.updated.notify_all();
+        }
+
+        if let Some(sink) = sink {
+            splitter.finish(sink);
+        }
+
+        Ok(())
+    }
+
+    /// Adds a chunk of output, spilling it all to a file if it's grown past
+    /// the threshold.
+    fn append(&self, chunk: &[u8]) -> std::io::Result<()> {
+        let mut state = self.lock();
+        let state = &mut *state;
+
+        let threshold = match self.spill_threshold {
+            Some(threshold) => threshold,
+            None => {
+                state.data.extend_from_slice(chunk);
+                return Ok(());
+            }
+        };
+
+        if state.spilled.is_none() && state.data.len() + chunk.len() > threshold {
+            let (mut file, path) = tempfile::Builder::new()
+                .prefix("test-binary-output-")
+                .tempfile()?
+                .into_parts();
+            file.write_all(&state.data)?;
+            state.spilled = Some(Spilled {
+                file,
+                path: Arc::new(path),
+            });
+        }
+
+        match &mut state.spilled {
+            Some(spilled) => {
+                let room = threshold.saturating_sub(state.data.len()).min(chunk.len());
+                state.data.extend_from_slice(&chunk[..room]);
+                spilled.file.write_all(chunk)
+            }
+            None => {
+                state.data.extend_from_slice(chunk);
+                Ok(())
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, CaptureState> {
+        // Nothing we do while holding the lock can panic.
+        self.state.lock().expect("capture lock poisoned")
+    }
+
+    /// A copy of everything captured so far, or the start of it if it's been
+    /// spilled to a file.
+    pub(crate) fn contents(&self) -> Vec<u8> {
+        self.lock().data.clone()
+    }
+
+    /// Waits until `found` finds something in what's been captured so far,
+    /// the pipe is closed, or the deadline passes.
+    pub(crate) fn wait_for<T, F>(&self, deadline: Instant, mut found: F) -> Option<T>
+    where
+        F: FnMut(&[u8]) -> Option<T>,
+    {
+        let mut state = self.lock();
+        loop {
+            if let Some(result) = found(&state.data) {
+                return Some(result);
+            }
+            let now = Instant::now();
+            if state.closed || now >= deadline {
+                return None;
+            }
+            state = self
+                .updated
+                .wait_timeout(state, deadline - now)
+                .expect("capture lock poisoned")
+                .0;
+        }
+    }
+
+    /// Takes what's been captured, along with the file it was spilled to, if
+    /// any.
+    fn take(&self) -> (Vec<u8>, Option<Arc<TempPath>>) {
+        let mut state = self.lock();
+        let spilled = state.spilled.take().map(|spilled| spilled.path);
+        (std::mem::take(&mut state.data), spilled)
+    }
+}
+
+/// A test binary that is running in the background. Start with
+/// [`Runner::spawn()`](crate::Runner::spawn).
+///
+/// The binary's stdout and stderr are captured while it runs. If the guard is
+/// dropped before the binary exits, the binary is sent [`Signal::Term`] and
+/// then killed if it hasn't exited by the end of its [grace
+/// period](ChildGuard::grace_period), so it won't outlive your test even if
+/// the test panics.
+///
+/// The binary is started in a process group of its own on Unix, or a job
+/// object on Windows, so any processes it starts are killed along with it.
+///
+/// If the guard is never dropped (eg. it's leaked, or kept in a `static`),
+/// the binary and anything it started are still killed when the test process
+/// exits.
+#[derive(Debug)]
+pub struct ChildGuard {
+    name: String,
+    child: GroupChild,
+    stdout: Arc<Capture>,
+    stderr: Arc<Capture>,
+    readers: Vec<JoinHandle<std::io::Result<()>>>,
+    capture_backtrace: bool,
+    capture_panic: bool,
+    log_file: Option<PathBuf>,
+    launch: LaunchContext,
+    grace_period: Duration,
+    dump: Option<crate::dump::Registration>,
+    /// How the binary exited, if it was reaped without going through `child`.
+    reaped: Option<ExitStatus>,
+}
+
+impl ChildGuard {
+    pub(crate) fn new(
+        mut child: GroupChild,
+        name: String,
+        capture_backtrace: bool,
+        capturing: Capturing,
+        launch: LaunchContext,
+        grace_period: Duration,
+    ) -> Self {
+        let Capturing {
+            sink,
+            log_file,
+            spill_threshold,
+            panic: capture_panic,
+        } = capturing;
+        #[cfg(unix)]
+        crate::reaper::register(child.id());
+
+        let sink = sink.map(Arc::new);
+        let (stdout, stdout_reader) = Capture::start(
+            child.inner().stdout.take(),
+            Stream::Stdout,
+            sink.clone(),
+            spill_threshold,
+        );
+        let (stderr, stderr_reader) = Capture::start(
+            child.inner().stderr.take(),
+            Stream::Stderr,
+            sink,
+            spill_threshold,
+        );
+
+        let dump = crate::dump::register(&name, child.id(), &stdout, &stderr);
+
+        Self {
+            name,
+            child,
+            stdout,
+            stderr,
+            readers: vec![stdout_reader, stderr_reader],
+            capture_backtrace,
+            capture_panic,
+            log_file,
+            launch,
+            grace_period,
+            dump,
+            reaped: None,
+        }
+    }
+
+    /// The binary's own process. Waiting on this rather than the group means
+    /// we don't wait for (or reap) anything the binary started.
+    pub(crate) fn child_mut(&mut self) -> &mut Child {
+        self.child.inner()
+    }
+
+    /// Records how the binary exited, when it's been reaped some other way
+    /// than through `child_mut()`, which can't be waited on after that.
+    pub(crate) fn set_reaped(&mut self, status: ExitStatus) {
+        self.reaped = Some(status);
+    }
+
+    fn wait_child(&mut self) -> std::io::Result<ExitStatus> {
+        match self.reaped {
+            Some(status) => Ok(status),
+            None => self.child_mut().wait(),
+        }
+    }
+
+    fn try_wait_child(&mut self) -> std::io::Result<Option<ExitStatus>> {
+        match self.reaped {
+            Some(status) => Ok(Some(status)),
+            None => self.child_mut().try_wait(),
+        }
+    }
+
+    /// The binary's stdout, as it's captured.
+    pub(crate) fn stdout_capture(&self) -> &Arc<Capture> {
+        &self.stdout
+    }
+
+    /// Kills the binary and everything in its group, without waiting.
+    pub(crate) fn kill_group(&mut self) -> std::io::Result<()> {
+        self.child.kill()
+    }
+
+    /// The name of the binary, ie. its file name without any extension.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The OS process ID of the binary.
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// How the binary was started.
+    pub fn launch(&self) -> &LaunchContext {
+        &self.launch
+    }
+
+    /// How long the binary gets to exit after being asked to, when the guard
+    /// is dropped. See [`Runner::grace_period()`](crate::Runner::grace_period).
+    pub fn grace_period(&self) -> Duration {
+        self.grace_period
+    }
+
+    /// Everything the binary has written to stdout so far.
+    pub fn stdout_so_far(&self) -> Vec<u8> {
+        self.stdout.contents()
+    }
+
+    /// Everything the binary has written to stderr so far.
+    pub fn stderr_so_far(&self) -> Vec<u8> {
+        self.stderr.contents()
+    }
+
+    /// Checks whether the binary has exited, without blocking.
+    pub fn try_wait(&mut self) -> Result<Option<ExitStatus>, RunError> {
+        Ok(self.try_wait_child()?)
+    }
+
+    /// Kills the binary (and any processes it started) immediately, and waits
+    /// for it to exit.
+    pub fn kill(mut self) -> Result<RunOutput, RunError> {
+        self.kill_group()?;
+        self.finish()
+    }
+
+    /// Waits for the binary to exit on its own, and returns its output.
+    pub fn wait(mut self) -> Result<RunOutput, RunError> {
+        self.finish()
+    }
+
+    /// Waits up to `timeout` for the binary to exit on its own. If it doesn't
+    /// exit in time, you get the guard back.
+    pub fn wait_timeout(mut self, timeout: Duration) -> Result<Result<RunOutput, Self>, RunError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if self.try_wait_child()?.is_some() {
+                return self.finish().map(Ok);
+            }
+            if Instant::now() >= deadline {
+                return Ok(Err(self));
+            }
+            std::thread::sleep(crate::run::POLL_INTERVAL);
+        }
+    }
+
+    /// Reaps the child and collects its output.
+    pub(crate) fn finish(&mut self) -> Result<RunOutput, RunError> {
+        let status = self.wait_child()?;
+        self.dump = None;
+        #[cfg(unix)]
+        crate::reaper::unregister(self.id());
+
+        for reader in self.readers.drain(..) {
+            // The thread only reads into a Vec, so if it panicked, something
+            // has gone very wrong.
+            reader.join().expect("output reader thread panicked")?;
+        }
+
+        let (stdout, stdout_spill) = self.stdout.take();
+        let (stderr, stderr_spill) = self.stderr.take();
+
+        let crash = if self.capture_backtrace {
+            Crash::detect(status, &stderr)
+        } else {
+            None
+        };
+
+        let panic = if self.capture_panic && !status.success() {
+            Panic::detect(&String::from_utf8_lossy(&stderr))
+        } else {
+            None
+        };
+
+        // Only keep the log file around if there's a failure to look into.
+        let log_file = match self.log_file.take() {
+            Some(path) if status.success() => {
+                std::fs::remove_file(path)?;
+                None
+            }
+            other => other,
+        };
+
+        Ok(RunOutput {
+            status,
+            stdout,
+            stderr,
+            stdout_spill,
+            stderr_spill,
+            crash,
+            panic,
+            log_file,
+            usage: None,
+            timed_out: false,
+            previous_attempts: vec![],
+            launch: self.launch.clone(),
+        })
+    }
+}
+
+impl Drop for ChildGuard {
+    fn drop(&mut self) {
+        // Give the binary a chance to clean up after itself first, unless
+        // it's already gone.
+        if !self.grace_period.is_zero()
+            && matches!(self.try_wait_child(), Ok(None))
+            && self.signal(Signal::Term).is_ok()
+        {
+            let deadline = Instant::now() + self.grace_period;
+            while matches!(self.try_wait_child(), Ok(None)) && Instant::now() < deadline {
+                std::thread::sleep(crate::run::POLL_INTERVAL);
+            }
+        }
+
+        // This also kills anything the child left behind, even if it was
+        // already waited on. There's nothing useful to do with errors while
+        // dropping.
+        let _ = self.kill_group();
+        if self.wait_child().is_ok() {
+            #[cfg(unix)]
+            crate::reaper::unregister(self.id());
+        }
+    }
+}
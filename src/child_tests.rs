@@ -0,0 +1,98 @@
+//! Running a test binary's own tests.
+
+use std::process::{ExitStatus, Output};
+
+/// The outcome of running a test binary's own tests with
+/// [`TestBinary::run_child_tests()`](crate::TestBinary::run_child_tests).
+#[derive(Debug, Clone)]
+pub struct ChildTests {
+    status: ExitStatus,
+    stdout: String,
+    stderr: String,
+    passed: usize,
+    failed: usize,
+}
+
+impl ChildTests {
+    pub(crate) fn new(output: Output) -> Self {
+        let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+
+        // Each test harness (unit tests, each integration test, doc tests)
+        // prints its own summary.
+        let (passed, failed) = stdout
+            .lines()
+            .filter_map(summary_counts)
+            .fold((0, 0), |(p, f), (passed, failed)| (p + passed, f + failed));
+
+        Self {
+            status: output.status,
+            stdout,
+            stderr,
+            passed,
+            failed,
+        }
+    }
+
+    /// Whether the tests built and all passed.
+    pub fn success(&self) -> bool {
+        self.status.success()
+    }
+
+    /// The exit status of `cargo test`.
+    pub fn status(&self) -> ExitStatus {
+        self.status
+    }
+
+    /// The number of tests that passed, across all the test harnesses that ran.
+    pub fn passed(&self) -> usize {
+        self.passed
+    }
+
+    /// The number of tests that failed, across all the test harnesses that ran.
+    /// Note that this can be zero even if [`ChildTests::success()`] is false
+    /// eg. if the tests didn't build.
+    pub fn failed(&self) -> usize {
+        self.failed
+    }
+
+    /// The test harnesses' output, converted lossily to UTF-8.
+    pub fn stdout(&self) -> &str {
+        &self.stdout
+    }
+
+    /// Cargo's output, including any build errors, converted lossily to UTF-8.
+    pub fn stderr(&self) -> &str {
+        &self.stderr
+    }
+}
+
+/// The passed and failed counts from a test harness' summary line eg.
+/// `test result: ok. 3 passed; 0 failed; 0 ignored; ...`.
+fn summary_counts(line: &str) -> Option<(usize, usize)> {
+    let (_, counts) = line.strip_prefix("test result: ")?.split_once(". ")?;
+    let count = |label: &str| {
+        counts
+            .split("; ")
+            .find_map(|part| part.strip_suffix(label)?.trim().parse().ok())
+    };
+    Some((count(" passed")?, count(" failed")?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary() {
+        assert_eq!(
+            summary_counts(
+                "test result: FAILED. 3 passed; 1 failed; 0 ignored; 0 measured; \
+                 0 filtered out; finished in 0.00s"
+            ),
+            Some((3, 1))
+        );
+        assert_eq!(summary_counts("test fla ... ok"), None);
+        assert_eq!(summary_counts("test result: fla"), None);
+    }
+}
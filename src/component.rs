@@ -0,0 +1,150 @@
+//! Building test binaries as WASI preview 2 components, and running them with
+//! a component runtime.
+
+use crate::{Artifact, TestBinary, TestBinaryError};
+use std::{
+    ffi::{OsStr, OsString},
+    path::Path,
+    process::Command,
+};
+
+/// The target that Rust builds WASI preview 2 components for.
+const COMPONENT_TARGET: &str = "wasm32-wasip2";
+
+/// Where Cargo looks for a runner for the component target, if one is set.
+const RUNNER_ENV: &str = "CARGO_TARGET_WASM32_WASIP2_RUNNER";
+
+/// A program that runs WebAssembly components, like `wasmtime run`. The
+/// component's path is given to it after its own arguments, followed by the
+/// arguments for the component. See [`TestBinary::build_component()`].
+///
+/// The runtime decides what the component can see of the host. The default,
+/// [`ComponentRuntime::wasmtime()`], passes on the environment but gives no
+/// access to the filesystem, so add eg. `--dir .` for tests that need it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ComponentRuntime {
+    program: OsString,
+    args: Vec<OsString>,
+}
+
+impl ComponentRuntime {
+    /// Creates a runtime that runs components with `program`.
+    pub fn new<S: AsRef<OsStr>>(program: S) -> Self {
+        Self {
+            program: program.as_ref().to_owned(),
+            args: vec![],
+        }
+    }
+
+    /// Runs components with `wasmtime run`, passing on the environment.
+    /// `wasmtime` must be on the `PATH`.
+    pub fn wasmtime() -> Self {
+        let mut runtime = Self::new("wasmtime");
+        runtime.args(["run", "-S", "inherit-env"]);
+        runtime
+    }
+
+    /// The runner Cargo would use for components, from
+    /// `CARGO_TARGET_WASM32_WASIP2_RUNNER` if it's set, and
+    /// [`ComponentRuntime::wasmtime()`] otherwise.
+    pub fn from_env() -> Self {
+        let runner = std::env::var(RUNNER_ENV).unwrap_or_default();
+        let mut words = runner.split_whitespace();
+        match words.next() {
+            Some(program) => {
+                let mut runtime = Self::new(program);
+                runtime.args(words);
+                runtime
+            }
+            None => Self::wasmtime(),
+        }
+    }
+
+    /// Adds an argument for the runtime itself, before the component.
+    pub fn arg<S: AsRef<OsStr>>(&mut self, arg: S) -> &mut Self {
+        self.args.push(arg.as_ref().to_owned());
+        self
+    }
+
+    /// Adds arguments for the runtime itself, before the component.
+    pub fn args<I, S>(&mut self, args: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.args
+            .extend(args.into_iter().map(|arg| arg.as_ref().to_owned()));
+        self
+    }
+
+    /// The program that runs components.
+    pub fn program(&self) -> &OsStr {
+        &self.program
+    }
+
+    /// A command that runs `component` with this runtime.
+    pub(crate) fn command(&self, component: &Path) -> Command {
+        let mut command = Command::new(&self.program);
+        command.args(&self.args).arg(component);
+        command
+    }
+}
+
+impl Default for ComponentRuntime {
+    fn default() -> Self {
+        Self::from_env()
+    }
+}
+
+impl<'a> TestBinary<'a> {
+    /// Builds the binary as a WASI preview 2 component, for the
+    /// `wasm32-wasip2` target, and returns an artifact that's run with the
+    /// [runtime](TestBinary::with_component_runtime). A target set with
+    /// [`TestBinary::with_target()`] or `TEST_BINARY_TARGET` is ignored,
+    /// unless it's also a WASI preview 2 target ie. ends with `-wasip2`.
+    ///
+    /// Rust builds components for this target directly, so `cargo component`
+    /// isn't needed, but the target must be installed eg. with `rustup target
+    /// add wasm32-wasip2`.
+    pub fn build_component(&mut self) -> Result<Artifact, TestBinaryError> {
+        let requested = self.requested_target();
+        if !requested.is_some_and(|target| target.ends_with("-wasip2")) {
+            self.forced_target = Some(COMPONENT_TARGET);
+        }
+        let built = self.build_artifact();
+        self.forced_target = None;
+
+        let mut artifact = built?;
+        artifact.runtime = Some(self.component_runtime.clone().unwrap_or_default());
+        Ok(artifact)
+    }
+
+    /// Specifies the runtime for components built with
+    /// [`TestBinary::build_component()`]. By default, this is
+    /// [`ComponentRuntime::from_env()`].
+    pub fn with_component_runtime(&mut self, runtime: ComponentRuntime) -> &mut Self {
+        self.component_runtime = Some(runtime);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command() {
+        let command = ComponentRuntime::wasmtime().command(Path::new("fla.wasm"));
+        assert_eq!(command.get_program(), "wasmtime");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            ["run", "-S", "inherit-env", "fla.wasm"]
+        );
+
+        let mut runtime = ComponentRuntime::new("jco");
+        runtime.arg("run");
+        let command = runtime.command(Path::new("fla.wasm"));
+        assert_eq!(command.get_program(), "jco");
+        assert_eq!(command.get_args().collect::<Vec<_>>(), ["run", "fla.wasm"]);
+    }
+}
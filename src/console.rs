@@ -0,0 +1,25 @@
+//! Windows console control events.
+//!
+//! Windows has no safe API for sending console events, so this needs unsafe
+//! code.
+
+#![allow(unsafe_code)]
+
+use winapi::um::wincon::{GenerateConsoleCtrlEvent, CTRL_BREAK_EVENT};
+
+/// The process creation flag for starting a binary in a new process group, so
+/// that console events can be sent to it alone.
+pub(crate) use winapi::um::winbase::CREATE_NEW_PROCESS_GROUP;
+
+/// Sends `CTRL_BREAK_EVENT` to the process group with this ID, which is the
+/// process ID of the first process in it.
+pub(crate) fn ctrl_break(process_group: u32) -> std::io::Result<()> {
+    // SAFETY: this only takes integers, and fails cleanly if there is no such
+    // process group.
+    let sent = unsafe { GenerateConsoleCtrlEvent(CTRL_BREAK_EVENT, process_group) };
+    if sent == 0 {
+        Err(std::io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
@@ -0,0 +1,168 @@
+//! Sharing builds between callers in the same process.
+//!
+//! Tests run in parallel, and several of them often want the same binary
+//! built the same way. Cargo would sort that out by itself, but only by
+//! making all but one of them wait for its lock, and then checking that
+//! there's nothing to do. So identical builds are done once, and the other
+//! callers wait for that one and share its result.
+//!
+//! A result is only reused while the sources of the child package, and of
+//! every package it depends on by path (such as the parent), are unchanged,
+//! so that a process that keeps running (eg. in a watch loop) still picks up
+//! edits. If they can't be found, the build isn't shared. Failed builds aren't
+//! kept; the next caller tries again.
+//!
+//! Callers that watch their own build, with a timeout, watchdog, cancel token,
+//! progress callback or log file, don't share it with anyone. Waiting on
+//! someone else's build, they'd see nothing of it and couldn't stop it.
+//! Neither does [`build_test_binary_once!`] when it rebuilds because the
+//! parent changed, since that's the one thing it knows and we might not.
+//!
+//! [`build_test_binary_once!`]: crate::build_test_binary_once
+
+use crate::{diagnostics::DiagnosticOptions, Artifact, Pinning, TestBinaryError};
+use once_cell::sync::Lazy;
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    sync::{Arc, Mutex},
+};
+
+/// Everything that determines the result of a build.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct BuildKey {
+    /// The Cargo command, including its arguments and any environment set for
+    /// it. This covers the manifest, binaries, features, profile, target and
+    /// so on.
+    pub(crate) command: String,
+    /// The profile is in the command too, but since it decides where the
+    /// binary goes, it's kept separately so that it can't be missed.
+    pub(crate) profile: Option<String>,
+    pub(crate) frozen: bool,
+    pub(crate) diagnostics: DiagnosticOptions,
+    pub(crate) pinning: Option<Pinning>,
+    pub(crate) dependencies: bool,
+    /// These are only added to the command when it's run.
+    pub(crate) rustc_flags: Vec<OsString>,
+}
+
+/// A successful build, and the fingerprint of its sources from before it ran.
+#[derive(Debug)]
+struct Shared {
+    fingerprint: u64,
+    artifacts: Vec<(String, Artifact)>,
+}
+
+type Slot = Arc<Mutex<Option<Shared>>>;
+
+static BUILDS: Lazy<Mutex<HashMap<BuildKey, Slot>>> = Lazy::new(Default::default);
+
+/// Runs `build`, unless a build with the same key has already been done with
+/// the same `fingerprint`, in which case its result is returned instead. If an
+/// identical build is in progress, this waits for it.
+pub(crate) fn shared<F>(
+    key: BuildKey,
+    fingerprint: u64,
+    build: F,
+) -> Result<Vec<(String, Artifact)>, TestBinaryError>
+where
+    F: FnOnce() -> Result<Vec<(String, Artifact)>, TestBinaryError>,
+{
+    let slot = BUILDS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .entry(key)
+        .or_default()
+        .clone();
+
+    // Only builds with the same key wait on each other.
+    let mut slot = slot.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(shared) = &*slot {
+        if shared.fingerprint == fingerprint {
+            return Ok(shared.artifacts.clone());
+        }
+    }
+
+    let artifacts = build()?;
+    *slot = Some(Shared {
+        fingerprint,
+        artifacts: artifacts.clone(),
+    });
+    Ok(artifacts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+        time::Duration,
+    };
+
+    fn key(command: &str) -> BuildKey {
+        BuildKey {
+            command: command.to_owned(),
+            profile: None,
+            frozen: false,
+            diagnostics: DiagnosticOptions::default(),
+            pinning: None,
+            dependencies: false,
+            rustc_flags: vec![],
+        }
+    }
+
+    fn artifacts(path: &str) -> Vec<(String, Artifact)> {
+        vec![("fla".to_owned(), Artifact::new(path))]
+    }
+
+    #[test]
+    fn concurrent_builds_share() {
+        static BUILT: AtomicUsize = AtomicUsize::new(0);
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                thread::spawn(|| {
+                    shared(key("concurrent"), 0, || {
+                        BUILT.fetch_add(1, Ordering::SeqCst);
+                        thread::sleep(Duration::from_millis(100));
+                        Ok(artifacts("/fla"))
+                    })
+                    .unwrap()
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            let built = thread.join().unwrap();
+            assert_eq!(built[0].1.path(), std::path::Path::new("/fla"));
+        }
+        assert_eq!(BUILT.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn rebuilds_when_needed() {
+        let built = shared(key("rebuild"), 0, || Ok(artifacts("/fla"))).unwrap();
+        assert_eq!(built[0].1.path(), std::path::Path::new("/fla"));
+
+        // Same key and sources.
+        let built = shared(key("rebuild"), 0, || Ok(artifacts("/mingo"))).unwrap();
+        assert_eq!(built[0].1.path(), std::path::Path::new("/fla"));
+
+        // Changed sources.
+        let built = shared(key("rebuild"), 1, || Ok(artifacts("/mingo"))).unwrap();
+        assert_eq!(built[0].1.path(), std::path::Path::new("/mingo"));
+
+        // A different build.
+        let built = shared(key("other"), 1, || Ok(artifacts("/pink"))).unwrap();
+        assert_eq!(built[0].1.path(), std::path::Path::new("/pink"));
+
+        // Failures aren't kept.
+        let failed = shared(key("failure"), 0, || {
+            Err(TestBinaryError::BuildError("fla".to_owned()))
+        });
+        assert!(failed.is_err());
+        let built = shared(key("failure"), 0, || Ok(artifacts("/fla"))).unwrap();
+        assert_eq!(built[0].1.path(), std::path::Path::new("/fla"));
+    }
+}
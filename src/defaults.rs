@@ -0,0 +1,124 @@
+//! Process-wide default settings for builds.
+
+use once_cell::sync::OnceCell;
+use std::path::{Path, PathBuf};
+
+static DEFAULTS: OnceCell<BuildDefaults> = OnceCell::new();
+
+/// The environment variable that sets the profile for every build, overriding
+/// [`TestBinary::with_profile()`](crate::TestBinary::with_profile) and the
+/// [`BuildDefaults`].
+pub const PROFILE_ENV: &str = "TEST_BINARY_PROFILE";
+
+/// The environment variable that sets the target triple for every build,
+/// overriding [`TestBinary::with_target()`](crate::TestBinary::with_target)
+/// and the parent's target.
+pub const TARGET_ENV: &str = "TEST_BINARY_TARGET";
+
+/// The environment variable that makes every build offline when it's set to
+/// anything but `0`, `false` or nothing. See
+/// [`TestBinary::offline()`](crate::TestBinary::offline).
+pub const OFFLINE_ENV: &str = "TEST_BINARY_OFFLINE";
+
+/// The value of an override variable, if it's set to something.
+pub(crate) fn env_override(key: &str) -> Option<String> {
+    std::env::var(key).ok().filter(|value| !value.is_empty())
+}
+
+/// Whether [`OFFLINE_ENV`] asks for offline builds.
+pub(crate) fn offline_override() -> bool {
+    env_override(OFFLINE_ENV).is_some_and(|value| value != "0" && value != "false")
+}
+
+/// Settings that every [`TestBinary`](crate::TestBinary) in the process starts
+/// with, including those created by [`build_test_binary()`] and
+/// [`build_test_binary_once!()`]. Builder methods can still override them.
+///
+/// These can only be set once, so it's best to do it before anything is
+/// built. Since tests run in no particular order, every test that depends on
+/// them can try to set them, and ignore the error if they're already set:
+///
+/// ```rust
+/// # use test_binary::{build_test_binary, BuildDefaults};
+/// let _ = BuildDefaults::new().profile("release").install();
+/// let path = build_test_binary("does-build", "testbins").unwrap();
+/// ```
+///
+/// To change every build without touching the code, eg. to build all the test
+/// binaries with optimisations in CI, set [`PROFILE_ENV`], [`TARGET_ENV`] or
+/// [`OFFLINE_ENV`] instead. These take precedence over both the defaults and
+/// the builder methods.
+///
+/// [`build_test_binary()`]: crate::build_test_binary
+/// [`build_test_binary_once!()`]: crate::build_test_binary_once
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BuildDefaults {
+    pub(crate) profile: Option<String>,
+    pub(crate) target_dir: Option<PathBuf>,
+    pub(crate) offline: bool,
+    pub(crate) jobs: Option<u32>,
+    pub(crate) build_log: Option<PathBuf>,
+}
+
+impl BuildDefaults {
+    /// Creates empty defaults, which change nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds with this profile by default. See
+    /// [`TestBinary::with_profile()`](crate::TestBinary::with_profile).
+    pub fn profile<S: Into<String>>(&mut self, profile: S) -> &mut Self {
+        self.profile = Some(profile.into());
+        self
+    }
+
+    /// Puts build output in this directory by default. See
+    /// [`TestBinary::with_target_dir()`](crate::TestBinary::with_target_dir).
+    pub fn target_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.target_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Builds without network access by default. See
+    /// [`TestBinary::offline()`](crate::TestBinary::offline).
+    pub fn offline(&mut self) -> &mut Self {
+        self.offline = true;
+        self
+    }
+
+    /// Limits the number of parallel jobs by default. See
+    /// [`TestBinary::with_jobs()`](crate::TestBinary::with_jobs).
+    pub fn jobs(&mut self, jobs: u32) -> &mut Self {
+        self.jobs = Some(jobs);
+        self
+    }
+
+    /// Records every build to this file by default. See
+    /// [`TestBinary::record_builds()`](crate::TestBinary::record_builds).
+    pub fn record_builds<P: AsRef<Path>>(&mut self, file: P) -> &mut Self {
+        self.build_log = Some(file.as_ref().to_owned());
+        self
+    }
+
+    /// Makes these the defaults for the rest of the process. If defaults have
+    /// already been installed, they're left alone and this returns the ones
+    /// that are in use.
+    pub fn install(&self) -> Result<(), &'static BuildDefaults> {
+        let mut installed = false;
+        let current = DEFAULTS.get_or_init(|| {
+            installed = true;
+            self.clone()
+        });
+        if installed {
+            Ok(())
+        } else {
+            Err(current)
+        }
+    }
+
+    /// The defaults that have been installed, if any.
+    pub fn current() -> Option<&'static BuildDefaults> {
+        DEFAULTS.get()
+    }
+}
@@ -0,0 +1,127 @@
+//! The resolved dependencies of a test binary, for auditing.
+
+use crate::{export::host_target, ManifestError, TestBinary, TestBinaryError};
+use cargo_metadata::{CargoOpt, DependencyKind, MetadataCommand, PackageId};
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    path::PathBuf,
+};
+
+/// A package that a test binary depends on, directly or indirectly. See
+/// [`Artifact::dependencies()`](crate::Artifact::dependencies).
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Dependency {
+    name: String,
+    version: String,
+    license: Option<String>,
+    source: Option<String>,
+}
+
+impl Dependency {
+    /// The package name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The version that was resolved.
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// The package's license, as the SPDX expression in its manifest. This is
+    /// `None` if the package only has a license file, or doesn't say.
+    pub fn license(&self) -> Option<&str> {
+        self.license.as_deref()
+    }
+
+    /// Where the package came from eg.
+    /// `registry+https://github.com/rust-lang/crates.io-index`. This is
+    /// `None` for path dependencies.
+    pub fn source(&self) -> Option<&str> {
+        self.source.as_deref()
+    }
+}
+
+/// The packages the binary is built with, for its features and target, found
+/// by asking Cargo to resolve its dependencies. Dev-dependencies aren't
+/// included, since they aren't part of the binary. Also returns the path of
+/// the lock file they were resolved from.
+pub(crate) fn resolve(
+    binary: &TestBinary<'_>,
+) -> Result<(Vec<Dependency>, PathBuf), TestBinaryError> {
+    let mut command = MetadataCommand::new();
+    command.manifest_path(&binary.manifest);
+    if let Ok(cargo) = binary.cargo_path() {
+        command.cargo_path(cargo);
+    }
+
+    if !binary.default_features {
+        command.features(CargoOpt::NoDefaultFeatures);
+    }
+    if !binary.features.is_empty() {
+        let features = binary.features.iter().map(|&f| f.to_owned()).collect();
+        command.features(CargoOpt::SomeFeatures(features));
+    }
+
+    let mut options = vec![];
+    let target = match binary.target() {
+        Some(target) => Some(target),
+        None => host_target().ok().map(str::to_owned),
+    };
+    if let Some(target) = target {
+        options.extend(["--filter-platform".to_owned(), target]);
+    }
+    if binary.is_offline() {
+        options.push("--offline".to_owned());
+    }
+    command.other_options(options);
+
+    let metadata = command
+        .exec()
+        .map_err(|e| ManifestError::ReadManifest(binary.manifest.clone(), e.to_string()))?;
+
+    let lock_file = metadata
+        .workspace_root
+        .join("Cargo.lock")
+        .into_std_path_buf();
+    let resolve = match &metadata.resolve {
+        Some(resolve) => resolve,
+        None => return Ok((vec![], lock_file)),
+    };
+    let nodes: HashMap<&PackageId, _> = resolve.nodes.iter().map(|n| (&n.id, n)).collect();
+
+    // Everything reachable from the binary's package, other than through
+    // dev-dependencies.
+    let mut found = BTreeSet::new();
+    let mut queue: VecDeque<_> = resolve.root.iter().collect();
+    while let Some(id) = queue.pop_front() {
+        let deps = nodes
+            .get(id)
+            .map(|node| node.deps.as_slice())
+            .unwrap_or_default();
+        for dep in deps {
+            let needed = dep.dep_kinds.is_empty()
+                || dep
+                    .dep_kinds
+                    .iter()
+                    .any(|info| info.kind != DependencyKind::Development);
+            if needed && found.insert(&dep.pkg) {
+                queue.push_back(&dep.pkg);
+            }
+        }
+    }
+
+    let mut dependencies: Vec<_> = metadata
+        .packages
+        .iter()
+        .filter(|package| found.contains(&package.id))
+        .map(|package| Dependency {
+            name: package.name.clone(),
+            version: package.version.to_string(),
+            license: package.license.clone(),
+            source: package.source.as_ref().map(|source| source.repr.clone()),
+        })
+        .collect();
+    dependencies.sort();
+    Ok((dependencies, lock_file))
+}
@@ -0,0 +1,146 @@
+//! Filtering and capping the compiler messages kept from a build.
+
+use cargo_metadata::diagnostic::DiagnosticLevel as RustcLevel;
+
+/// Which compiler messages to keep from a build, for
+/// [`TestBinary::with_diagnostic_level()`].
+///
+/// [`TestBinary::with_diagnostic_level()`]: crate::TestBinary::with_diagnostic_level
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, serde::Serialize)]
+pub enum DiagnosticLevel {
+    /// Only errors.
+    Errors,
+    /// Errors and warnings.
+    Warnings,
+    /// Everything, including notes and help.
+    #[default]
+    All,
+}
+
+/// How to filter and cap compiler messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub(crate) struct DiagnosticOptions {
+    pub(crate) level: DiagnosticLevel,
+    pub(crate) limit: Option<usize>,
+}
+
+impl DiagnosticOptions {
+    /// Whether to keep a message of the given level.
+    fn includes(&self, level: &RustcLevel) -> bool {
+        match self.level {
+            DiagnosticLevel::All => true,
+            DiagnosticLevel::Warnings => {
+                matches!(
+                    level,
+                    RustcLevel::Ice | RustcLevel::Error | RustcLevel::Warning
+                )
+            }
+            DiagnosticLevel::Errors => matches!(level, RustcLevel::Ice | RustcLevel::Error),
+        }
+    }
+}
+
+/// Gathers compiler messages, as rendered by the compiler.
+#[derive(Debug)]
+pub(crate) struct Collector {
+    options: DiagnosticOptions,
+    text: String,
+    omitted: usize,
+    full: bool,
+}
+
+impl Collector {
+    pub(crate) fn new(options: DiagnosticOptions) -> Self {
+        Self {
+            options,
+            text: String::new(),
+            omitted: 0,
+            full: false,
+        }
+    }
+
+    /// Adds a compiler message, if it passes the filter.
+    pub(crate) fn diagnostic(&mut self, level: &RustcLevel, rendered: &str) {
+        if self.options.includes(level) {
+            self.push(rendered);
+        }
+    }
+
+    /// Adds some other output (eg. a line that isn't a JSON message), which is
+    /// always kept.
+    pub(crate) fn text(&mut self, text: &str) {
+        self.push(text);
+    }
+
+    fn push(&mut self, message: &str) {
+        let limit = self.options.limit.unwrap_or(usize::MAX);
+
+        if self.full {
+            self.omitted += 1;
+            return;
+        }
+
+        self.text.push_str(message);
+        self.text.push('\n');
+
+        if self.text.len() > limit {
+            // Cut it off at the limit, but on a character boundary.
+            let mut end = limit;
+            while !self.text.is_char_boundary(end) {
+                end -= 1;
+            }
+            self.text.truncate(end);
+            self.omitted += 1;
+            self.full = true;
+        }
+    }
+
+    /// The messages, with a marker if any were cut off.
+    pub(crate) fn finish(mut self) -> String {
+        if self.omitted > 0 {
+            self.text.push_str(&format!(
+                "\n[... truncated; {} more messages not shown ...]\n",
+                self.omitted
+            ));
+        }
+        self.text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter() {
+        let options = DiagnosticOptions {
+            level: DiagnosticLevel::Warnings,
+            limit: None,
+        };
+        let mut collector = Collector::new(options);
+        collector.diagnostic(&RustcLevel::Warning, "warning: unused");
+        collector.diagnostic(&RustcLevel::Note, "note: by the way");
+        collector.diagnostic(&RustcLevel::Error, "error: oops");
+        collector.text("some text");
+        assert_eq!(
+            collector.finish(),
+            "warning: unused\nerror: oops\nsome text\n"
+        );
+    }
+
+    #[test]
+    fn limit() {
+        let options = DiagnosticOptions {
+            level: DiagnosticLevel::All,
+            limit: Some(10),
+        };
+        let mut collector = Collector::new(options);
+        collector.text("1234");
+        collector.text("6789🦩");
+        collector.text("more");
+        assert_eq!(
+            collector.finish(),
+            "1234\n6789\n[... truncated; 2 more messages not shown ...]\n"
+        );
+    }
+}
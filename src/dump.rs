@@ -0,0 +1,179 @@
+//! Printing what running test binaries have said when a test panics.
+
+use crate::child::Capture;
+use once_cell::sync::Lazy;
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard, Once,
+    },
+    thread::ThreadId,
+};
+
+/// Whether [`dump_output_on_panic()`] has been called.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// The binaries that are running, by the order they were started in.
+static RUNNING: Lazy<Mutex<HashMap<u64, Running>>> = Lazy::new(Default::default);
+
+static NEXT: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug)]
+struct Running {
+    name: String,
+    id: u32,
+    thread: ThreadId,
+    stdout: Arc<Capture>,
+    stderr: Arc<Capture>,
+}
+
+/// Keeps a binary's output where the panic hook can find it, until the binary
+/// is done with.
+#[derive(Debug)]
+pub(crate) struct Registration(u64);
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        running().remove(&self.0);
+    }
+}
+
+fn running() -> MutexGuard<'static, HashMap<u64, Running>> {
+    RUNNING.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Makes a binary's output available to the panic hook, if it's installed,
+/// on behalf of the current thread.
+pub(crate) fn register(
+    name: &str,
+    id: u32,
+    stdout: &Arc<Capture>,
+    stderr: &Arc<Capture>,
+) -> Option<Registration> {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return None;
+    }
+    let key = NEXT.fetch_add(1, Ordering::Relaxed);
+    running().insert(
+        key,
+        Running {
+            name: name.to_owned(),
+            id,
+            thread: std::thread::current().id(),
+            stdout: Arc::clone(stdout),
+            stderr: Arc::clone(stderr),
+        },
+    );
+    Some(Registration(key))
+}
+
+/// Installs a panic hook that prints everything captured so far from the
+/// binaries the panicking thread has running, before the panic message.
+///
+/// When a test fails, the assertion often isn't enough to tell why, and what
+/// the binary said would be. This shows it, with a header for each stream:
+///
+/// ```rust
+/// test_binary::dump_output_on_panic();
+///
+/// let guard = test_binary::stock::echo_stdin()
+///     .unwrap()
+///     .runner()
+///     .spawn()
+///     .unwrap();
+/// // If this panics, whatever the binary has written is printed first.
+/// assert!(guard.stderr_so_far().is_empty());
+/// ```
+///
+/// Only binaries that are still running, ie. whose [`ChildGuard`] hasn't
+/// been dropped or waited on, and that were started after this is called,
+/// are printed. Each test runs on a thread of its own, so it's only the
+/// binaries the failing test started. Calling this again does nothing.
+///
+/// [`ChildGuard`]: crate::ChildGuard
+pub fn dump_output_on_panic() {
+    static HOOK: Once = Once::new();
+    HOOK.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            let report = report(std::thread::current().id());
+            if !report.is_empty() {
+                eprint!("{}", report);
+            }
+            previous(info);
+        }));
+        ENABLED.store(true, Ordering::Relaxed);
+    });
+}
+
+/// Everything captured from the binaries started on `thread`, in the order
+/// they were started.
+fn report(thread: ThreadId) -> String {
+    let running = running();
+    let mut keys: Vec<_> = running
+        .iter()
+        .filter(|(_, running)| running.thread == thread)
+        .map(|(key, _)| *key)
+        .collect();
+    keys.sort_unstable();
+
+    let mut report = String::new();
+    for key in keys {
+        let running = &running[&key];
+        for (stream, capture) in [("stdout", &running.stdout), ("stderr", &running.stderr)] {
+            let contents = capture.contents();
+            let _ = writeln!(
+                report,
+                "---- {} of {} (pid {}) ----",
+                stream, running.name, running.id
+            );
+            report.push_str(&String::from_utf8_lossy(&contents));
+            if !contents.is_empty() && !contents.ends_with(b"\n") {
+                report.push('\n');
+            }
+        }
+    }
+    report
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::Artifact;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn reports_running_binaries() {
+        dump_output_on_panic();
+
+        let guard = Artifact::from(std::ffi::OsString::from("/bin/sh"))
+            .runner()
+            .args(["-c", "echo fla; printf bar >&2; sleep 60"])
+            .spawn()
+            .unwrap();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while (guard.stdout_so_far().is_empty() || guard.stderr_so_far().is_empty())
+            && Instant::now() < deadline
+        {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let thread = std::thread::current().id();
+        let id = guard.id();
+        assert_eq!(
+            report(thread),
+            format!(
+                "---- stdout of sh (pid {id}) ----\nfla\n---- stderr of sh (pid {id}) ----\nbar\n"
+            )
+        );
+        let other = std::thread::spawn(|| report(std::thread::current().id()))
+            .join()
+            .unwrap();
+        assert_eq!(other, "");
+
+        drop(guard);
+        assert_eq!(report(thread), "");
+    }
+}
@@ -0,0 +1,415 @@
+//! Exporting the paths of built binaries for use by external tools.
+
+use crate::{build_test_binary, hash::hash_file, Artifact, TestBinary, TestBinaryError};
+use once_cell::sync::OnceCell;
+use serde_json::json;
+use std::{
+    collections::BTreeMap,
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+    process::Command,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// The prefix for variable names in `.env` files.
+const ENV_PREFIX: &str = "TEST_BINARY_";
+
+/// A mapping of binary names to the paths of the built binaries, which can be
+/// written to a file for tools outside your tests (shell scripts,
+/// docker-compose etc.) and read back in.
+///
+/// Two formats are supported:
+///
+/// - JSON, as an object with names as the keys and paths as the values
+/// - `.env` style, with one `TEST_BINARY_<NAME>=<path>` line per binary, where
+///   `<NAME>` is the binary name in upper case with anything that isn't a
+///   letter or digit replaced by `_` eg. `TEST_BINARY_DOES_BUILD`
+///
+/// Since the `.env` format doesn't preserve the exact names, lookups with
+/// [`BinaryPaths::get()`] compare names in that normalised form.
+///
+/// ```rust
+/// # use test_binary::BinaryPaths;
+/// let paths = BinaryPaths::build(["does-build", "multiple"], "testbins").unwrap();
+///
+/// let env_file = std::env::temp_dir().join("test-binaries.env");
+/// paths.write_env(&env_file).unwrap();
+///
+/// let loaded = BinaryPaths::load(&env_file).unwrap();
+/// assert_eq!(loaded.get("does-build"), paths.get("does-build"));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BinaryPaths {
+    paths: BTreeMap<String, PathBuf>,
+}
+
+impl BinaryPaths {
+    /// Creates an empty mapping.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds each of the named binaries with [`build_test_binary()`], and
+    /// records their paths.
+    pub fn build<I, S, R>(names: I, directory: R) -> Result<Self, TestBinaryError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+        R: AsRef<Path>,
+    {
+        let mut paths = Self::new();
+        for name in names {
+            let name = name.as_ref();
+            let path = build_test_binary(name, directory.as_ref())?;
+            paths.insert(name, path);
+        }
+        Ok(paths)
+    }
+
+    /// Records the path for a binary.
+    pub fn insert<S: Into<String>, P: Into<PathBuf>>(&mut self, name: S, path: P) -> &mut Self {
+        self.paths.insert(name.into(), path.into());
+        self
+    }
+
+    /// The path of the named binary.
+    pub fn get(&self, name: &str) -> Option<&Path> {
+        let key = env_key(name);
+        self.paths
+            .iter()
+            .find(|(n, _)| env_key(n) == key)
+            .map(|(_, path)| path.as_path())
+    }
+
+    /// All the names and paths, sorted by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Path)> {
+        self.paths.iter().map(|(n, p)| (n.as_str(), p.as_path()))
+    }
+
+    /// Writes the paths as a JSON object. Paths that aren't valid Unicode are
+    /// converted lossily.
+    pub fn write_json<P: AsRef<Path>>(&self, file: P) -> std::io::Result<()> {
+        let object: serde_json::Map<_, _> = self
+            .paths
+            .iter()
+            .map(|(name, path)| (name.clone(), path.to_string_lossy().into()))
+            .collect();
+
+        let json = serde_json::to_string_pretty(&object).map_err(Error::from)?;
+        std::fs::write(file, json + "\n")
+    }
+
+    /// Writes the paths as a `.env` file. Paths that aren't valid Unicode are
+    /// converted lossily.
+    pub fn write_env<P: AsRef<Path>>(&self, file: P) -> std::io::Result<()> {
+        let contents: String = self
+            .paths
+            .iter()
+            .map(|(name, path)| {
+                format!(
+                    "{}{}={}\n",
+                    ENV_PREFIX,
+                    env_key(name),
+                    path.to_string_lossy()
+                )
+            })
+            .collect();
+
+        std::fs::write(file, contents)
+    }
+
+    /// Reads paths written by [`BinaryPaths::write_json()`] (if the file name
+    /// ends in `.json`) or [`BinaryPaths::write_env()`] (otherwise).
+    pub fn load<P: AsRef<Path>>(file: P) -> std::io::Result<Self> {
+        let file = file.as_ref();
+        let contents = std::fs::read_to_string(file)?;
+
+        if file.extension().is_some_and(|ext| ext == "json") {
+            Self::parse_json(&contents)
+        } else {
+            Self::parse_env(&contents)
+        }
+    }
+
+    fn parse_json(contents: &str) -> std::io::Result<Self> {
+        let object: BTreeMap<String, String> =
+            serde_json::from_str(contents).map_err(Error::from)?;
+
+        Ok(Self {
+            paths: object
+                .into_iter()
+                .map(|(name, path)| (name, path.into()))
+                .collect(),
+        })
+    }
+
+    fn parse_env(contents: &str) -> std::io::Result<Self> {
+        let mut paths = Self::new();
+
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (key, path) = line
+                .split_once('=')
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("bad line: {}", line)))?;
+
+            let name = key.strip_prefix(ENV_PREFIX).unwrap_or(key);
+            paths.insert(name, path);
+        }
+
+        Ok(paths)
+    }
+}
+
+/// Information about one binary in an [`ArtifactManifest`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    name: String,
+    path: PathBuf,
+    target: String,
+    profile: String,
+    hash: String,
+    built_at: SystemTime,
+    build_time: Duration,
+}
+
+impl ManifestEntry {
+    /// The binary name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The path to the built binary.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The target triple the binary was built for.
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /// The Cargo profile the binary was built with.
+    pub fn profile(&self) -> &str {
+        &self.profile
+    }
+
+    /// The SHA-256 hash of the binary, as `sha256:<hex>`.
+    pub fn hash(&self) -> &str {
+        &self.hash
+    }
+
+    /// When the build finished.
+    pub fn built_at(&self) -> SystemTime {
+        self.built_at
+    }
+
+    /// How long the build took, including Cargo checking that it was fresh.
+    pub fn build_time(&self) -> Duration {
+        self.build_time
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let built_at = self
+            .built_at
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+
+        json!({
+            "name": self.name,
+            "path": self.path.to_string_lossy(),
+            "target": self.target,
+            "profile": self.profile,
+            "hash": self.hash,
+            "built_at": built_at.as_secs(),
+            "build_time_secs": self.build_time.as_secs_f64(),
+        })
+    }
+}
+
+/// A record of a batch of built binaries, with enough detail for tooling that
+/// needs to provision them elsewhere eg. copying them to test VMs. This can be
+/// written as JSON, which looks like:
+///
+/// ```json
+/// [
+///   {
+///     "name": "does-build",
+///     "path": "/.../testbins/does-build/target/debug/does-build",
+///     "target": "x86_64-unknown-linux-gnu",
+///     "profile": "dev",
+///     "hash": "sha256:...",
+///     "built_at": 1664582400,
+///     "build_time_secs": 0.12
+///   }
+/// ]
+/// ```
+///
+/// `built_at` is in seconds since the Unix epoch.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ArtifactManifest {
+    entries: Vec<ManifestEntry>,
+}
+
+impl ArtifactManifest {
+    /// Creates an empty manifest.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds each of the named binaries like [`build_test_binary()`], and
+    /// records them.
+    pub fn build<I, S, R>(names: I, directory: R) -> Result<Self, TestBinaryError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+        R: AsRef<Path>,
+    {
+        let mut manifest = Self::new();
+        for name in names {
+            let name = name.as_ref();
+            let manifest_path =
+                PathBuf::from_iter([directory.as_ref(), name.as_ref(), "Cargo.toml".as_ref()]);
+            manifest.add(&mut TestBinary::relative_to_parent(name, &manifest_path)?)?;
+        }
+        Ok(manifest)
+    }
+
+    /// Builds a binary and records it.
+    pub fn add(&mut self, binary: &mut TestBinary<'_>) -> Result<Artifact, TestBinaryError> {
+        let started = Instant::now();
+        let artifact = binary.build_artifact()?;
+        let build_time = started.elapsed();
+
+        let hash = hash_file(artifact.path())?;
+
+        self.entries.push(ManifestEntry {
+            name: binary.binary.to_owned(),
+            path: artifact.path().to_owned(),
+            target: match artifact.target() {
+                Some(target) => target.to_owned(),
+                None => host_target()?.to_owned(),
+            },
+            profile: binary.profile().unwrap_or_else(|| "dev".to_owned()),
+            hash,
+            built_at: SystemTime::now(),
+            build_time,
+        });
+
+        Ok(artifact)
+    }
+
+    /// The recorded binaries, in the order they were built.
+    pub fn entries(&self) -> &[ManifestEntry] {
+        &self.entries
+    }
+
+    /// Writes the manifest as JSON. Paths that aren't valid Unicode are
+    /// converted lossily.
+    pub fn write_json<P: AsRef<Path>>(&self, file: P) -> std::io::Result<()> {
+        let entries: Vec<_> = self.entries.iter().map(ManifestEntry::to_json).collect();
+        let json = serde_json::to_string_pretty(&entries).map_err(Error::from)?;
+        std::fs::write(file, json + "\n")
+    }
+}
+
+/// The host target triple, according to Cargo.
+pub(crate) fn host_target() -> Result<&'static str, TestBinaryError> {
+    static HOST: OnceCell<String> = OnceCell::new();
+
+    HOST.get_or_try_init(|| {
+        let cargo = std::env::var_os("CARGO").ok_or_else(|| {
+            TestBinaryError::NonCargoRun("The environment variable 'CARGO' is not set".to_owned())
+        })?;
+        let output = Command::new(cargo).arg("-vV").output()?;
+        let version = String::from_utf8_lossy(&output.stdout);
+
+        version
+            .lines()
+            .find_map(|line| line.strip_prefix("host: "))
+            .map(|host| host.trim().to_owned())
+            .ok_or_else(|| TestBinaryError::CargoFailure(version.into_owned()))
+    })
+    .map(String::as_str)
+}
+
+/// Normalises a binary name into the form used for `.env` variables.
+fn env_key(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut paths = BinaryPaths::new();
+        paths
+            .insert("mock-server", "/target/debug/mock-server")
+            .insert("client", "/target/debug/client");
+
+        let json = dir.path().join("paths.json");
+        paths.write_json(&json).unwrap();
+        assert_eq!(BinaryPaths::load(&json).unwrap(), paths);
+
+        let env = dir.path().join("paths.env");
+        paths.write_env(&env).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&env).unwrap(),
+            "TEST_BINARY_CLIENT=/target/debug/client\n\
+             TEST_BINARY_MOCK_SERVER=/target/debug/mock-server\n"
+        );
+
+        let loaded = BinaryPaths::load(&env).unwrap();
+        assert_eq!(
+            loaded.get("mock-server"),
+            Some(Path::new("/target/debug/mock-server"))
+        );
+        assert_eq!(loaded.get("MOCK_SERVER"), loaded.get("mock-server"));
+        assert_eq!(loaded.get("other"), None);
+    }
+
+    #[test]
+    fn manifest_json() {
+        let entry = ManifestEntry {
+            name: "fla".to_owned(),
+            path: "/target/debug/fla".into(),
+            target: "x86_64-unknown-linux-gnu".to_owned(),
+            profile: "dev".to_owned(),
+            hash: "sha256:00".to_owned(),
+            built_at: SystemTime::UNIX_EPOCH + Duration::from_secs(1_664_582_400),
+            build_time: Duration::from_millis(1500),
+        };
+
+        assert_eq!(
+            entry.to_json(),
+            json!({
+                "name": "fla",
+                "path": "/target/debug/fla",
+                "target": "x86_64-unknown-linux-gnu",
+                "profile": "dev",
+                "hash": "sha256:00",
+                "built_at": 1_664_582_400,
+                "build_time_secs": 1.5,
+            })
+        );
+    }
+
+    #[test]
+    fn bad_env_line() {
+        let err = BinaryPaths::parse_env("# comment\n\nnot a variable\n").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
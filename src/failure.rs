@@ -0,0 +1,183 @@
+//! An error type for tests that return a `Result`, so that they can use `?` on
+//! anything this crate returns and still fail with a useful message.
+
+use std::{
+    error::Error,
+    fmt::{self, Debug, Display},
+    panic::Location,
+};
+
+/// The result of a test that uses `?` on this crate's results. See
+/// [`TestFailure`].
+pub type TestResult<T = ()> = Result<T, TestFailure>;
+
+/// Any error, along with where in the test it came from. This is for tests
+/// that return a `Result`, so that they can use `?` instead of `.expect()`
+/// everywhere. A test like this:
+///
+/// ```rust
+/// use test_binary::{build_test_binary, Artifact, TestResult};
+///
+/// fn test_something() -> TestResult {
+///     let path = build_test_binary("does-build", "testbins")?;
+///     let output = Artifact::from(path).runner().run()?;
+///     assert!(output.status().success());
+///     Ok(())
+/// }
+/// # test_something().unwrap();
+/// ```
+///
+/// The location of the `?` is recorded when the error is converted. If the
+/// test fails, it's reported with the whole error, including all of the
+/// compiler's messages for a [build error](crate::TestBinaryError::BuildError)
+/// and the errors that caused it. When displayed, it fits on one line, unless
+/// the alternate form (`{:#}`) is used to get the same report.
+///
+/// Like `Box<dyn Error>`, this doesn't implement [`Error`] itself, so that it
+/// can be converted from anything that does.
+pub struct TestFailure {
+    error: Box<dyn Error + Send + Sync + 'static>,
+    location: &'static Location<'static>,
+}
+
+impl TestFailure {
+    /// The error.
+    pub fn error(&self) -> &(dyn Error + Send + Sync + 'static) {
+        &*self.error
+    }
+
+    /// Where the error was converted, usually a `?` in a test.
+    pub fn location(&self) -> &'static Location<'static> {
+        self.location
+    }
+
+    /// The error, if it's of type `E`.
+    pub fn downcast_ref<E: Error + 'static>(&self) -> Option<&E> {
+        self.error.downcast_ref()
+    }
+
+    /// The messages of the error and the errors that caused it, outermost
+    /// first. Some of this crate's errors include their cause's message in
+    /// their own, so those causes are left out.
+    fn messages(&self) -> Vec<String> {
+        let first: &(dyn Error + 'static) = &*self.error;
+        let chain = std::iter::successors(Some(first), |&error| error.source());
+        let mut messages: Vec<String> = vec![];
+        for error in chain {
+            let message = error.to_string();
+            match messages.last() {
+                Some(last) if last.contains(&message) => {}
+                _ => messages.push(message),
+            }
+        }
+        messages
+    }
+}
+
+impl<E: Error + Send + Sync + 'static> From<E> for TestFailure {
+    #[track_caller]
+    fn from(error: E) -> Self {
+        Self {
+            error: Box::new(error),
+            location: Location::caller(),
+        }
+    }
+}
+
+impl Display for TestFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            return Debug::fmt(self, f);
+        }
+        let messages: Vec<_> = self
+            .messages()
+            .iter()
+            .map(|message| summary(message))
+            .collect();
+        write!(f, "{} (at {})", messages.join(": "), self.location)
+    }
+}
+
+impl Debug for TestFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let messages = self.messages();
+        writeln!(f, "{}", messages[0].trim_end())?;
+        if messages.len() > 1 {
+            writeln!(f, "\nCaused by:")?;
+            for cause in &messages[1..] {
+                writeln!(f, "    {}", cause.trim_end())?;
+            }
+        }
+        write!(f, "\nat {}", self.location)
+    }
+}
+
+/// The first line of an error's message, along with the next if the first
+/// just introduces the rest eg. `build error:`.
+fn summary(message: &str) -> String {
+    let mut lines = message
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty());
+    let first = lines.next().unwrap_or_default();
+    match lines.next() {
+        Some(next) if first.ends_with(':') => format!("{} {}", first, next),
+        _ => first.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{RunError, TestBinaryError};
+
+    fn fail() -> TestResult {
+        Err(TestBinaryError::BinaryNotBuilt("fla".to_owned()))?;
+        Ok(())
+    }
+
+    #[test]
+    fn rendering() {
+        assert_eq!(fail().unwrap_err().location().file(), file!());
+
+        let error = TestBinaryError::BuildError(
+            "error: unused variable\n --> src/main.rs:1:5\n\nerror: aborting\n".to_owned(),
+        );
+        let (failure, line) = (TestFailure::from(error), line!());
+        assert_eq!(failure.location().line(), line);
+        assert_eq!(
+            failure.to_string(),
+            format!(
+                "build error: error: unused variable (at {})",
+                failure.location()
+            )
+        );
+        assert_eq!(
+            format!("{:#}", failure),
+            format!(
+                "build error:\nerror: unused variable\n --> src/main.rs:1:5\n\n\
+                 error: aborting\n\nat {}",
+                failure.location()
+            )
+        );
+        assert!(failure.downcast_ref::<TestBinaryError>().is_some());
+
+        let io = std::io::Error::other("gone");
+        let failure = TestFailure::from(TestBinaryError::RunError(RunError::SpawnError(io)));
+        assert_eq!(
+            failure.to_string(),
+            format!(
+                "error running test binary: IO error running test binary: gone (at {})",
+                failure.location()
+            )
+        );
+        assert_eq!(
+            format!("{:?}", failure),
+            format!(
+                "error running test binary: IO error running test binary\n\n\
+                 Caused by:\n    gone\n\nat {}",
+                failure.location()
+            )
+        );
+    }
+}
@@ -0,0 +1,246 @@
+//! Noticing when a test binary that depends on the parent crate needs to be
+//! rebuilt.
+//!
+//! Cargo already knows when the child is out of date, but
+//! [`build_test_binary_once!`](crate::build_test_binary_once) only runs it
+//! once per process. In a watch or rerun loop that keeps the process alive
+//! (or when the parent's sources change during a long test run), a child that
+//! depends on the parent by path would be left stale. So we fingerprint the
+//! parent's sources when building such a child, and build again if the
+//! fingerprint changes.
+//!
+//! The same fingerprints, of the child and everything it depends on by path,
+//! decide when a build shared with the rest of the process is out of date.
+
+use crate::{manifest_dir, ManifestError, TestBinary};
+use cargo_metadata::{DependencyKind, MetadataCommand};
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeSet},
+    ffi::OsString,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// Whether the package in the manifest has a path dependency on the package in
+/// `parent_dir`.
+fn depends_on(manifest: &Path, parent_dir: &Path) -> Result<bool, ManifestError> {
+    let metadata = cargo_metadata::MetadataCommand::new()
+        .manifest_path(manifest)
+        .no_deps()
+        .exec()
+        .map_err(|e| ManifestError::ReadManifest(manifest.to_path_buf(), e.to_string()))?;
+
+    let parent_dir = parent_dir.canonicalize().ok();
+
+    Ok(metadata
+        .packages
+        .iter()
+        .flat_map(|package| &package.dependencies)
+        .filter_map(|dep| dep.path.as_ref())
+        .any(|path| path.canonicalize().ok() == parent_dir))
+}
+
+/// A fingerprint of the package sources in `dir`: its `Cargo.toml`, `build.rs`
+/// and everything under `src`. It covers file names, sizes and modification
+/// times, not contents.
+pub(crate) fn source_fingerprint(dir: &Path) -> u64 {
+    let mut files = vec![dir.join("Cargo.toml"), dir.join("build.rs")];
+    collect_files(&dir.join("src"), &mut files);
+    files.sort();
+
+    let mut hasher = DefaultHasher::new();
+    for file in files {
+        // Missing files are fine; their absence is part of the fingerprint.
+        if let Ok(metadata) = std::fs::metadata(&file) {
+            file.hash(&mut hasher);
+            metadata.len().hash(&mut hasher);
+            metadata.modified().ok().hash(&mut hasher);
+        }
+    }
+    hasher.finish()
+}
+
+/// A fingerprint of the binary's package and every package it depends on by
+/// path, directly or not, which is usually what a build in this process might
+/// have changed. Other dependencies come from registries or git, and don't
+/// change without the lock file changing, which the manifest's directory
+/// covers. `None` if any of their manifests can't be read.
+pub(crate) fn build_fingerprint(binary: &TestBinary<'_>) -> Option<u64> {
+    let mut dirs = BTreeSet::new();
+    let mut manifests = vec![binary.manifest.clone()];
+    while let Some(manifest) = manifests.pop() {
+        let dir = manifest.parent()?.canonicalize().ok()?;
+        if !dirs.insert(dir.clone()) {
+            continue;
+        }
+
+        let mut command = MetadataCommand::new();
+        command.manifest_path(&manifest).no_deps();
+        if let Ok(cargo) = binary.cargo_path() {
+            command.cargo_path(cargo);
+        }
+        let metadata = command.exec().ok()?;
+        // A workspace member's metadata has the whole workspace in it.
+        let package = metadata.packages.iter().find(|package| {
+            package
+                .manifest_path
+                .parent()
+                .and_then(|dir| dir.canonicalize().ok())
+                .as_ref()
+                == Some(&dir)
+        })?;
+        manifests.extend(
+            package
+                .dependencies
+                .iter()
+                .filter(|dep| dep.kind != DependencyKind::Development)
+                .filter_map(|dep| dep.path.as_ref())
+                .map(|path| path.join("Cargo.toml").into_std_path_buf()),
+        );
+    }
+
+    let mut hasher = DefaultHasher::new();
+    for dir in dirs {
+        dir.hash(&mut hasher);
+        source_fingerprint(&dir).hash(&mut hasher);
+    }
+    Some(hasher.finish())
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if entry.file_type().is_ok_and(|t| t.is_dir()) {
+            collect_files(&path, files);
+        } else {
+            files.push(path);
+        }
+    }
+}
+
+/// The cached result of a [`build_test_binary_once!`] build.
+///
+/// [`build_test_binary_once!`]: crate::build_test_binary_once
+#[derive(Debug)]
+struct Built {
+    path: OsString,
+    /// The profile it was built with, which can be changed from outside with
+    /// [`PROFILE_ENV`](crate::PROFILE_ENV).
+    profile: Option<String>,
+    /// The parent's fingerprint at build time, if the binary depends on it.
+    parent: Option<u64>,
+}
+
+/// Implementation detail of [`build_test_binary_once!`], which builds the
+/// binary on first use and again if the parent crate it depends on changes.
+///
+/// [`build_test_binary_once!`]: crate::build_test_binary_once
+#[doc(hidden)]
+#[derive(Debug)]
+pub struct OnceBuild {
+    built: Mutex<Option<Built>>,
+}
+
+impl OnceBuild {
+    #[doc(hidden)]
+    #[allow(clippy::new_without_default)]
+    pub const fn new() -> Self {
+        Self {
+            built: Mutex::new(None),
+        }
+    }
+
+    /// The path to the built binary, building it if necessary.
+    #[doc(hidden)]
+    pub fn path<R: AsRef<Path>>(&self, name: &str, directory: R) -> OsString {
+        let mut built = self.built.lock().unwrap_or_else(|e| e.into_inner());
+        let parent_dir = manifest_dir().unwrap();
+
+        let mut binary = TestBinary::in_directory(name, directory.as_ref()).unwrap();
+        let profile = binary.profile();
+
+        if let Some(built) = &*built {
+            match built.parent {
+                _ if built.profile != profile => {}
+                Some(fingerprint) if fingerprint != source_fingerprint(&parent_dir) => {}
+                _ => return built.path.clone(),
+            }
+        }
+
+        // Fingerprint before building, so that changes made during the build
+        // cause another one next time.
+        let parent = if depends_on(&binary.manifest, &parent_dir).unwrap() {
+            Some(source_fingerprint(&parent_dir))
+        } else {
+            None
+        };
+
+        // A build shared with the rest of the process might not have seen the
+        // parent change, so a rebuild has to run Cargo itself.
+        binary.unshared = built.is_some();
+        let path = binary.build().unwrap();
+        *built = Some(Built {
+            path: path.clone(),
+            profile,
+            parent,
+        });
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_package(dir: &Path, manifest: &str) {
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::write(dir.join("Cargo.toml"), manifest).unwrap();
+        std::fs::write(dir.join("src").join("lib.rs"), "").unwrap();
+    }
+
+    #[test]
+    fn path_dependency_on_parent() {
+        let dir = tempfile::tempdir().unwrap();
+        let parent = dir.path();
+        write_package(
+            parent,
+            "[package]\nname = \"parent\"\nversion = \"0.1.0\"\n\n[workspace]\n",
+        );
+
+        let child = parent.join("testbins").join("child");
+        write_package(
+            &child,
+            "[package]\nname = \"child\"\nversion = \"0.1.0\"\n\n\
+             [dependencies]\nparent = { path = \"../..\" }\n\n[workspace]\n",
+        );
+        let other = parent.join("testbins").join("other");
+        write_package(
+            &other,
+            "[package]\nname = \"other\"\nversion = \"0.1.0\"\n\n[workspace]\n",
+        );
+
+        assert!(depends_on(&child.join("Cargo.toml"), parent).unwrap());
+        assert!(!depends_on(&other.join("Cargo.toml"), parent).unwrap());
+    }
+
+    #[test]
+    fn fingerprint_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        write_package(dir.path(), "[package]\n");
+        let before = source_fingerprint(dir.path());
+        assert_eq!(source_fingerprint(dir.path()), before);
+
+        std::fs::write(dir.path().join("src").join("lib.rs"), "pub fn f() {}").unwrap();
+        let after = source_fingerprint(dir.path());
+        assert_ne!(after, before);
+
+        std::fs::write(dir.path().join("src").join("new.rs"), "").unwrap();
+        assert_ne!(source_fingerprint(dir.path()), after);
+    }
+}
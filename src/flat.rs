@@ -0,0 +1,117 @@
+//! Single-file test binaries, which are turned into Cargo packages in a cache
+//! directory before building.
+//!
+//! A single file can declare dependencies in an embedded manifest fragment,
+//! in a doc comment at the top of the file:
+//!
+//! ```none
+//! //! ```cargo
+//! //! [dependencies]
+//! //! signal-hook = "0.3"
+//! //! ```
+//! ```
+
+use crate::{generated, manifest_dir, ManifestError};
+use std::path::{Path, PathBuf};
+
+/// The manifest fragment embedded in the source, if there is one.
+fn embedded_manifest(source: &str) -> Option<String> {
+    let mut lines = source
+        .lines()
+        .map_while(|line| line.trim_start().strip_prefix("//!"))
+        .map(|line| line.strip_prefix(' ').unwrap_or(line));
+
+    lines.find(|line| line.trim() == "```cargo")?;
+
+    let mut fragment = String::new();
+    for line in lines {
+        if line.trim() == "```" {
+            return Some(fragment);
+        }
+        fragment.push_str(line);
+        fragment.push('\n');
+    }
+
+    // Unterminated, so it's probably not meant for us.
+    None
+}
+
+/// The manifest for a single-file binary.
+fn manifest(name: &str, source_path: &Path, source: &str) -> String {
+    let mut manifest = format!(
+        "# Generated by test-binary from {path}\n\
+         \n\
+         [package]\n\
+         name = \"{name}\"\n\
+         version = \"0.1.0\"\n\
+         edition = \"2021\"\n\
+         publish = false\n\
+         \n\
+         [[bin]]\n\
+         name = \"{name}\"\n\
+         path = {path:?}\n\
+         \n\
+         [workspace]\n",
+        name = name,
+        path = source_path.to_string_lossy(),
+    );
+
+    if let Some(fragment) = embedded_manifest(source) {
+        manifest.push('\n');
+        manifest.push_str(&fragment);
+    }
+
+    manifest
+}
+
+/// Creates (or updates) the package for the single-file binary, returning the
+/// path to its manifest. The source path is relative to the parent.
+pub(crate) fn materialize(name: &str, source_path: &Path) -> Result<PathBuf, ManifestError> {
+    let source_path = manifest_dir()?.join(source_path);
+    let source = std::fs::read_to_string(&source_path)
+        .map_err(|e| ManifestError::ReadManifest(source_path.clone(), e.to_string()))?;
+
+    let manifest_path = generated::dir("flat")?.join(name).join("Cargo.toml");
+    generated::write_if_changed(&manifest_path, &manifest(name, &source_path, &source))?;
+    Ok(manifest_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn embedded() {
+        let source = "\
+//! Some docs.
+//!
+//! ```cargo
+//! [dependencies]
+//! signal-hook = \"0.3\"
+//! ```
+
+fn main() {}
+";
+        assert_eq!(
+            embedded_manifest(source).unwrap(),
+            "[dependencies]\nsignal-hook = \"0.3\"\n"
+        );
+
+        let manifest = manifest("fla", Path::new("/testbins/fla.rs"), source);
+        assert!(manifest.contains("path = \"/testbins/fla.rs\"\n"));
+        assert!(manifest.ends_with("\n[dependencies]\nsignal-hook = \"0.3\"\n"));
+    }
+
+    #[test]
+    fn not_embedded() {
+        assert_eq!(embedded_manifest("fn main() {}\n"), None);
+        assert_eq!(
+            embedded_manifest("//! ```cargo\n//! [dependencies]\n"),
+            None
+        );
+        assert_eq!(
+            embedded_manifest("fn main() {}\n//! ```cargo\n//! ```\n"),
+            None
+        );
+    }
+}
@@ -0,0 +1,60 @@
+//! Packages that this crate generates and then builds, eg. for single-file
+//! binaries and scripted mocks.
+
+use crate::{manifest_dir, ManifestError};
+use std::path::{Path, PathBuf};
+
+/// Where generated packages of the given kind go: `test-binary/<kind>` under
+/// the parent's target directory.
+pub(crate) fn dir(kind: &str) -> Result<PathBuf, ManifestError> {
+    let target_dir = match std::env::var_os("CARGO_TARGET_DIR") {
+        Some(dir) => manifest_dir()?.join(dir),
+        None => manifest_dir()?.join("target"),
+    };
+    Ok(target_dir.join("test-binary").join(kind))
+}
+
+/// Writes a generated file, but only if it's changed, so Cargo doesn't think
+/// the package needs rebuilding.
+pub(crate) fn write_if_changed(path: &Path, contents: &str) -> Result<(), ManifestError> {
+    if std::fs::read_to_string(path).ok().as_deref() == Some(contents) {
+        return Ok(());
+    }
+
+    let parent = path
+        .parent()
+        .expect("generated file has no parent directory");
+    std::fs::create_dir_all(parent)
+        .and_then(|_| std::fs::write(path, contents))
+        .map_err(|e| ManifestError::WriteManifest(path.to_owned(), e.to_string()))
+}
+
+/// Generates a package with a single binary of the given name, built from the
+/// source, returning the path to its manifest.
+pub(crate) fn binary_package(
+    kind: &str,
+    name: &str,
+    source: &str,
+) -> Result<PathBuf, ManifestError> {
+    let package_dir = dir(kind)?.join(name);
+    let manifest_path = package_dir.join("Cargo.toml");
+
+    write_if_changed(&package_dir.join("src").join("main.rs"), source)?;
+    write_if_changed(
+        &manifest_path,
+        &format!(
+            "# Generated by test-binary\n\
+             \n\
+             [package]\n\
+             name = \"{}\"\n\
+             version = \"0.1.0\"\n\
+             edition = \"2021\"\n\
+             publish = false\n\
+             \n\
+             [workspace]\n",
+            name
+        ),
+    )?;
+
+    Ok(manifest_path)
+}
@@ -0,0 +1,132 @@
+//! Making test binaries that fail to build stand out in GitHub Actions logs.
+//!
+//! Compiler errors in a test binary otherwise end up somewhere in the middle of
+//! the test output. On GitHub Actions, the compiler's messages are put in a
+//! collapsible group, and each error is also reported as an annotation on the
+//! line of code it's about, using [workflow commands].
+//!
+//! [workflow commands]: https://docs.github.com/en/actions/using-workflows/workflow-commands-for-github-actions
+
+use cargo_metadata::{diagnostic::DiagnosticLevel as RustcLevel, CompilerMessage};
+use std::path::{Path, PathBuf};
+
+/// Whether we're running on GitHub Actions, which sets `GITHUB_ACTIONS`.
+pub(crate) fn detected() -> bool {
+    std::env::var_os("GITHUB_ACTIONS").is_some_and(|value| value == "true")
+}
+
+/// An `::error` command for a compiler error in test binary `name`, pointing
+/// at the code it's about, or `None` if it's not an error. File paths are
+/// relative to `workspace` (the checked out repository), if they're in it.
+pub(crate) fn annotation(
+    name: &str,
+    msg: &CompilerMessage,
+    workspace: Option<&Path>,
+) -> Option<String> {
+    let diagnostic = &msg.message;
+    if !matches!(diagnostic.level, RustcLevel::Error | RustcLevel::Ice) {
+        return None;
+    }
+
+    let mut properties = vec![];
+    if let Some(span) = diagnostic.spans.iter().find(|span| span.is_primary) {
+        let file = resolve(msg.target.src_path.as_std_path(), &span.file_name);
+        let file = match workspace.and_then(|workspace| file.strip_prefix(workspace).ok()) {
+            Some(relative) => relative.to_owned(),
+            None => file,
+        };
+        properties.push(("file", file.to_string_lossy().replace('\\', "/")));
+        properties.push(("line", span.line_start.to_string()));
+        properties.push(("col", span.column_start.to_string()));
+        properties.push(("endLine", span.line_end.to_string()));
+        properties.push(("endColumn", span.column_end.to_string()));
+    }
+    properties.push(("title", format!("test binary {} failed to build", name)));
+
+    let properties: Vec<_> = properties
+        .into_iter()
+        .map(|(key, value)| format!("{}={}", key, escape_property(&value)))
+        .collect();
+    Some(format!(
+        "::error {}::{}",
+        properties.join(","),
+        escape_data(&diagnostic.message)
+    ))
+}
+
+/// Prints the compiler's messages for a failed build of test binary `name` in
+/// a group, followed by the annotations for its errors.
+pub(crate) fn report(name: &str, messages: &str, annotations: &[String]) {
+    eprintln!("::group::Building test binary {}", name);
+    eprint!("{}", messages);
+    eprintln!("::endgroup::");
+    for annotation in annotations {
+        eprintln!("{}", annotation);
+    }
+}
+
+/// The repository that was checked out, which annotations' paths should be
+/// relative to.
+pub(crate) fn workspace() -> Option<PathBuf> {
+    std::env::var_os("GITHUB_WORKSPACE").map(PathBuf::from)
+}
+
+/// The full path of `file`, which the compiler gives relative to the root of
+/// the workspace that the crate with its root at `src_path` belongs to. That's
+/// usually the crate's own package, since test binaries tend to be workspaces
+/// of their own, but otherwise it's somewhere above it.
+fn resolve(src_path: &Path, file: &str) -> PathBuf {
+    let file = Path::new(file);
+    if file.is_absolute() {
+        return file.to_owned();
+    }
+    src_path
+        .ancestors()
+        .skip(1)
+        .map(|dir| dir.join(file))
+        .find(|path| path.exists() || path == src_path)
+        .unwrap_or_else(|| src_path.with_file_name(file))
+}
+
+fn escape_data(data: &str) -> String {
+    data.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+fn escape_property(property: &str) -> String {
+    escape_data(property)
+        .replace(':', "%3A")
+        .replace(',', "%2C")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cargo_metadata::Message;
+
+    const ERROR: &str = r#"{"reason":"compiler-message","package_id":"fla 0.1.0 (path+file:///ws/testbins/fla)","manifest_path":"/ws/testbins/fla/Cargo.toml","target":{"kind":["bin"],"crate_types":["bin"],"name":"fla","src_path":"/ws/testbins/fla/src/main.rs","edition":"2021","doc":true,"doctest":false,"test":true},"message":{"rendered":"error: cannot find value `x`\n","children":[],"code":null,"level":"error","message":"cannot find value `x`: not found, 100%","spans":[{"byte_end":16,"byte_start":12,"column_end":14,"column_start":13,"expansion":null,"file_name":"src/main.rs","is_primary":true,"label":null,"line_end":1,"line_start":1,"suggested_replacement":null,"suggestion_applicability":null,"text":[]}]}}"#;
+
+    fn message(json: &str) -> CompilerMessage {
+        match Message::parse_stream(json.as_bytes()).next() {
+            Some(Ok(Message::CompilerMessage(msg))) => msg,
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn annotations() {
+        let msg = message(ERROR);
+        assert_eq!(
+            annotation("fla", &msg, Some(Path::new("/ws"))).unwrap(),
+            "::error file=testbins/fla/src/main.rs,line=1,col=13,endLine=1,endColumn=14,\
+             title=test binary fla failed to build::cannot find value `x`: not found, 100%25"
+        );
+        assert!(annotation("fla", &msg, None)
+            .unwrap()
+            .starts_with("::error file=/ws/testbins/fla/src/main.rs,"));
+
+        let warning = message(&ERROR.replace(r#""level":"error""#, r#""level":"warning""#));
+        assert_eq!(annotation("fla", &warning, None), None);
+    }
+}
@@ -0,0 +1,194 @@
+//! Running several test binaries together as one scenario.
+
+use crate::{ChildGuard, Graceful, Readiness, RunError, RunOutput, Runner, Signal};
+use std::{
+    ffi::{OsStr, OsString},
+    fmt,
+    time::Duration,
+};
+
+/// Builder for a scenario involving several different test binaries, eg. a
+/// broker and two clients.
+///
+/// Processes are started in the order they're added. Each one can have a
+/// readiness check, and the next process will not be started until it passes.
+/// When the [`RunningHarness`] is dropped (including when a test panics), the
+/// processes are stopped in reverse order, each being given its [grace
+/// period](crate::Runner::grace_period) to exit before it's killed.
+///
+/// ```rust
+/// # use test_binary::{build_test_binary, Artifact, Readiness, TestHarness};
+/// let server = Artifact::from(build_test_binary("actions", "testbins").unwrap());
+/// let client = Artifact::from(build_test_binary("does-build", "testbins").unwrap());
+///
+/// let mut harness = TestHarness::new();
+/// harness
+///     .env("SOCKET", "/tmp/socket")
+///     .process("server", server.runner().args(["print", "listening", "sleep", "10000"]))
+///     .ready_when(Readiness::stdout_line("listening"))
+///     .process("client", &client.runner());
+///
+/// let running = harness.start().unwrap();
+/// // Do things with the running processes, then tear them down.
+/// let outputs = running.stop().unwrap();
+/// assert_eq!(outputs[0].0, "client");
+/// ```
+pub struct TestHarness<'a> {
+    envs: Vec<(OsString, OsString)>,
+    processes: Vec<HarnessProcess<'a>>,
+    ready_timeout: Duration,
+}
+
+struct HarnessProcess<'a> {
+    name: String,
+    runner: Runner<'a>,
+    ready: Option<Readiness<'a>>,
+}
+
+impl<'a> TestHarness<'a> {
+    /// Creates an empty harness.
+    pub fn new() -> Self {
+        Self {
+            envs: vec![],
+            processes: vec![],
+            ready_timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// Sets an environment variable for every process in the harness. Any
+    /// variables set on a process' own runner take precedence.
+    pub fn env<K: AsRef<OsStr>, V: AsRef<OsStr>>(&mut self, key: K, value: V) -> &mut Self {
+        self.envs
+            .push((key.as_ref().to_owned(), value.as_ref().to_owned()));
+        self
+    }
+
+    /// Adds a process to start after any that were previously added. The name
+    /// is used to look it up and in error messages.
+    pub fn process<S: Into<String>>(&mut self, name: S, runner: &Runner<'a>) -> &mut Self {
+        self.processes.push(HarnessProcess {
+            name: name.into(),
+            runner: runner.clone(),
+            ready: None,
+        });
+        self
+    }
+
+    /// Sets a readiness probe for the most recently added process. The next
+    /// process is only started once this one is ready.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no processes have been added yet.
+    pub fn ready_when(&mut self, readiness: Readiness<'a>) -> &mut Self {
+        self.processes
+            .last_mut()
+            .expect("ready_when() called before any processes were added")
+            .ready = Some(readiness);
+        self
+    }
+
+    /// Specifies how long to wait for each process to become ready. The
+    /// default is 10 seconds.
+    pub fn ready_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.ready_timeout = timeout;
+        self
+    }
+
+    /// Starts all the processes in order, waiting for each one to become
+    /// ready. If any process fails to start or become ready, the ones already
+    /// started are torn down again.
+    pub fn start(&mut self) -> Result<RunningHarness, RunError> {
+        let mut running = RunningHarness { processes: vec![] };
+
+        for process in &mut self.processes {
+            let mut runner = process.runner.clone();
+            runner.envs.splice(0..0, self.envs.iter().cloned());
+
+            let mut guard = runner.spawn()?;
+
+            if let Some(readiness) = &mut process.ready {
+                guard
+                    .wait_ready_mut(readiness, self.ready_timeout)
+                    .map_err(|err| match err {
+                        // Report the name used in the harness, not the binary.
+                        RunError::NotReady(_) => RunError::NotReady(process.name.clone()),
+                        other => other,
+                    })?;
+            }
+
+            running.processes.push((process.name.clone(), guard));
+        }
+
+        Ok(running)
+    }
+}
+
+impl<'a> Default for TestHarness<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> fmt::Debug for TestHarness<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TestHarness")
+            .field("envs", &self.envs)
+            .field(
+                "processes",
+                &self.processes.iter().map(|p| &p.name).collect::<Vec<_>>(),
+            )
+            .field("ready_timeout", &self.ready_timeout)
+            .finish()
+    }
+}
+
+/// The processes of a [`TestHarness`] while they're running. Dropping this
+/// stops them all, in the reverse of the order they were started.
+#[derive(Debug)]
+pub struct RunningHarness {
+    processes: Vec<(String, ChildGuard)>,
+}
+
+impl RunningHarness {
+    /// The named process.
+    pub fn process(&self, name: &str) -> Option<&ChildGuard> {
+        self.processes
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, guard)| guard)
+    }
+
+    /// The named process, mutably.
+    pub fn process_mut(&mut self, name: &str) -> Option<&mut ChildGuard> {
+        self.processes
+            .iter_mut()
+            .find(|(n, _)| n == name)
+            .map(|(_, guard)| guard)
+    }
+
+    /// Stops all the processes in reverse order, and returns their names and
+    /// outputs in that order. Each process is sent [`Signal::Term`] and given
+    /// its [grace period](ChildGuard::grace_period) to exit before it's
+    /// killed.
+    pub fn stop(mut self) -> Result<Vec<(String, RunOutput)>, RunError> {
+        let mut outputs = vec![];
+        while let Some((name, guard)) = self.processes.pop() {
+            let graceful = Graceful {
+                signal: Signal::Term,
+                grace_period: guard.grace_period(),
+            };
+            outputs.push((name, guard.shutdown(graceful)?.into_output()));
+        }
+        Ok(outputs)
+    }
+}
+
+impl Drop for RunningHarness {
+    fn drop(&mut self) {
+        // A Vec drops its elements front to back, but we want the opposite.
+        while let Some(process) = self.processes.pop() {
+            drop(process);
+        }
+    }
+}
@@ -0,0 +1,68 @@
+//! Hashing built binaries, for pinning and manifests.
+//!
+//! Hashes are SHA-256, so that they're stable across platforms and Rust
+//! versions (unlike `std`'s hashers), and can be checked with other tools.
+
+use sha2::{Digest, Sha256};
+use std::{fs::File, path::Path};
+
+/// Formats a digest as `sha256:<hex>`.
+fn format_digest(digest: &[u8]) -> String {
+    digest
+        .iter()
+        .fold(String::from("sha256:"), |mut hex, byte| {
+            hex.push_str(&format!("{:02x}", byte));
+            hex
+        })
+}
+
+/// The SHA-256 hash of a file, formatted as `sha256:<hex>`.
+pub(crate) fn hash_file<P: AsRef<Path>>(path: P) -> std::io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(format_digest(&hasher.finalize()))
+}
+
+/// The SHA-256 hash of some bytes, formatted as `sha256:<hex>`.
+pub(crate) fn hash_bytes(data: &[u8]) -> String {
+    format_digest(&Sha256::digest(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sha256(data: &[u8]) -> String {
+        hash_bytes(data)
+    }
+
+    #[test]
+    fn known_digests() {
+        assert_eq!(
+            sha256(b""),
+            "sha256:e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            sha256(b"abc"),
+            "sha256:ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "sha256:248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+        assert_eq!(
+            sha256(&[b'a'; 1000]),
+            "sha256:41edece42d63e8d9bf515a9ba6932e1c20cbc9f5a5d134645adb5db1b9737ea3"
+        );
+    }
+
+    #[test]
+    fn file_in_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data");
+        let data: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+        std::fs::write(&path, &data).unwrap();
+        assert_eq!(hash_file(&path).unwrap(), sha256(&data));
+    }
+}
@@ -0,0 +1,139 @@
+//! Feeding scripted input to test binaries.
+
+use std::{fs::File, io::Write, path::PathBuf, process::ChildStdin, time::Duration};
+
+/// What to write to a test binary's stdin. See
+/// [`Runner::stdin()`](crate::Runner::stdin).
+///
+/// Input is a sequence of steps, which are carried out in order on a thread of
+/// its own while the binary runs. When they're done, the binary's stdin is
+/// closed. Steps that wait between writes make it easy to pace input the way
+/// a person or a slow peer would:
+///
+/// ```rust
+/// # use std::time::Duration;
+/// # use test_binary::Input;
+/// let echo = test_binary::stock::echo_stdin().unwrap();
+/// let output = echo
+///     .runner()
+///     .stdin(
+///         Input::new()
+///             .write_line("one")
+///             .wait(Duration::from_millis(100))
+///             .write_line("two"),
+///     )
+///     .run()
+///     .unwrap();
+/// assert_eq!(output.stdout(), b"one\ntwo\n");
+/// ```
+///
+/// If the binary exits or closes its stdin before reading everything, the
+/// rest of the input is discarded.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Input {
+    steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Step {
+    Write(Vec<u8>),
+    WriteFile(PathBuf),
+    Wait(Duration),
+}
+
+/// A step that's ready to carry out, with any file already opened.
+enum Ready {
+    Write(Vec<u8>),
+    WriteFile(File),
+    Wait(Duration),
+}
+
+/// [`Input`] whose files have all been opened, so that it can't fail to start
+/// once the binary is running.
+pub(crate) struct Feeder {
+    steps: Vec<Ready>,
+}
+
+impl Input {
+    /// Creates empty input, which just closes stdin.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Input that's just these bytes.
+    pub fn bytes<B: Into<Vec<u8>>>(bytes: B) -> Self {
+        let mut input = Self::new();
+        input.write(bytes);
+        input
+    }
+
+    /// Input that's the contents of a file.
+    pub fn file<P: Into<PathBuf>>(path: P) -> Self {
+        let mut input = Self::new();
+        input.write_file(path);
+        input
+    }
+
+    /// Adds a step that writes these bytes.
+    pub fn write<B: Into<Vec<u8>>>(&mut self, bytes: B) -> &mut Self {
+        self.steps.push(Step::Write(bytes.into()));
+        self
+    }
+
+    /// Adds a step that writes a line, adding a newline.
+    pub fn write_line<S: Into<String>>(&mut self, line: S) -> &mut Self {
+        let mut line = line.into();
+        line.push('\n');
+        self.write(line)
+    }
+
+    /// Adds a step that writes the contents of a file. The file is opened
+    /// when the binary is started, and it's an error if it can't be.
+    pub fn write_file<P: Into<PathBuf>>(&mut self, path: P) -> &mut Self {
+        self.steps.push(Step::WriteFile(path.into()));
+        self
+    }
+
+    /// Adds a step that waits before going on to the next one.
+    pub fn wait(&mut self, duration: Duration) -> &mut Self {
+        self.steps.push(Step::Wait(duration));
+        self
+    }
+
+    /// Opens any files the input needs.
+    pub(crate) fn open(&self) -> std::io::Result<Feeder> {
+        let steps = self
+            .steps
+            .iter()
+            .map(|step| {
+                Ok(match step {
+                    Step::Write(bytes) => Ready::Write(bytes.clone()),
+                    Step::WriteFile(path) => Ready::WriteFile(File::open(path)?),
+                    Step::Wait(duration) => Ready::Wait(*duration),
+                })
+            })
+            .collect::<std::io::Result<_>>()?;
+        Ok(Feeder { steps })
+    }
+}
+
+impl Feeder {
+    /// Starts feeding the input to the binary. The thread isn't joined,
+    /// since the binary might never read all of it.
+    pub(crate) fn start(self, mut stdin: ChildStdin) {
+        std::thread::spawn(move || -> std::io::Result<()> {
+            for step in self.steps {
+                match step {
+                    Ready::Write(bytes) => stdin.write_all(&bytes)?,
+                    Ready::WriteFile(mut file) => {
+                        std::io::copy(&mut file, &mut stdin)?;
+                    }
+                    Ready::Wait(duration) => std::thread::sleep(duration),
+                }
+                stdin.flush()?;
+            }
+            // Dropping stdin closes it.
+            Ok(())
+        });
+    }
+}
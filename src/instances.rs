@@ -0,0 +1,187 @@
+//! Running several instances of the same test binary at once.
+
+use crate::{RunError, RunOutput, Runner};
+use std::{ffi::OsString, net::TcpListener, ops::Index, path::Path};
+use tempfile::TempDir;
+
+impl<'a> Runner<'a> {
+    /// Runs `count` instances of the binary concurrently, waits for all of them
+    /// to finish, and returns all of their outputs.
+    ///
+    /// Each instance gets its own temporary sandbox directory, which is its
+    /// working directory unless you've set one with
+    /// [`Runner::current_dir()`]. The directories are removed when the
+    /// returned [`Instances`] is dropped.
+    ///
+    /// The following placeholders are substituted in arguments and environment
+    /// variable values, so that instances don't trip over each other:
+    ///
+    /// - `{instance}` is the index of the instance, starting at 0
+    /// - `{dir}` is the instance's sandbox directory
+    /// - `{socket}` is a path named `socket` in the sandbox directory
+    /// - `{port}` is a TCP port on localhost that was free when the instances
+    ///   were started (and is different for each instance)
+    ///
+    /// ```rust
+    /// # use test_binary::{build_test_binary, Artifact};
+    /// let artifact = Artifact::from(build_test_binary("does-build", "testbins").unwrap());
+    ///
+    /// let instances = artifact
+    ///     .runner()
+    ///     .args(["--listen", "127.0.0.1:{port}"])
+    ///     .run_instances(4)
+    ///     .unwrap();
+    ///
+    /// assert!(instances.success());
+    /// ```
+    pub fn run_instances(&self, count: usize) -> Result<Instances, RunError> {
+        let ports = free_ports(count)?;
+        let sandboxes = (0..count)
+            .map(|_| tempfile::Builder::new().prefix("test-binary-").tempdir())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut runners: Vec<Runner<'a>> = ports
+            .iter()
+            .zip(&sandboxes)
+            .enumerate()
+            .map(|(instance, (port, sandbox))| {
+                let dir = sandbox.path();
+                let socket = dir.join("socket");
+                let vars = [
+                    ("{instance}", instance.to_string()),
+                    ("{port}", port.to_string()),
+                    ("{dir}", dir.to_string_lossy().into_owned()),
+                    ("{socket}", socket.to_string_lossy().into_owned()),
+                ];
+
+                let mut runner = self.clone();
+                runner.args = runner.args.iter().map(|a| substitute(a, &vars)).collect();
+                for (_, value) in &mut runner.envs {
+                    *value = substitute(value, &vars);
+                }
+                if runner.current_dir.is_none() {
+                    runner.current_dir = Some(dir.to_owned());
+                }
+                runner
+            })
+            .collect();
+
+        let outputs = std::thread::scope(|scope| {
+            let handles: Vec<_> = runners
+                .iter_mut()
+                .map(|runner| scope.spawn(move || runner.run()))
+                .collect();
+
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("instance runner thread panicked"))
+                .collect::<Result<Vec<_>, _>>()
+        })?;
+
+        Ok(Instances { outputs, sandboxes })
+    }
+}
+
+/// Finds free ports by binding them all at once before releasing them, so
+/// that we don't get the same port twice.
+fn free_ports(count: usize) -> std::io::Result<Vec<u16>> {
+    let listeners = (0..count)
+        .map(|_| TcpListener::bind(("127.0.0.1", 0)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    listeners
+        .iter()
+        .map(|listener| Ok(listener.local_addr()?.port()))
+        .collect()
+}
+
+/// Replaces placeholders in the argument, if it's valid Unicode. Anything
+/// else is left alone.
+fn substitute(arg: &OsString, vars: &[(&str, String)]) -> OsString {
+    match arg.to_str() {
+        Some(text) => vars
+            .iter()
+            .fold(text.to_owned(), |text, (name, value)| {
+                text.replace(name, value)
+            })
+            .into(),
+        None => arg.clone(),
+    }
+}
+
+/// The outputs of several instances of a test binary. See
+/// [`Runner::run_instances()`].
+#[derive(Debug)]
+pub struct Instances {
+    outputs: Vec<RunOutput>,
+    sandboxes: Vec<TempDir>,
+}
+
+impl Instances {
+    /// Whether every instance exited successfully.
+    pub fn success(&self) -> bool {
+        self.outputs.iter().all(|output| output.status().success())
+    }
+
+    /// The outputs of each instance, in order.
+    pub fn outputs(&self) -> &[RunOutput] {
+        &self.outputs
+    }
+
+    /// The indices and outputs of instances that did not exit successfully.
+    pub fn failures(&self) -> impl Iterator<Item = (usize, &RunOutput)> {
+        self.outputs
+            .iter()
+            .enumerate()
+            .filter(|(_, output)| !output.status().success())
+    }
+
+    /// The sandbox directory of the given instance.
+    pub fn sandbox(&self, instance: usize) -> &Path {
+        self.sandboxes[instance].path()
+    }
+
+    /// The number of instances that were run.
+    pub fn len(&self) -> usize {
+        self.outputs.len()
+    }
+
+    /// Whether no instances were run at all.
+    pub fn is_empty(&self) -> bool {
+        self.outputs.is_empty()
+    }
+}
+
+impl Index<usize> for Instances {
+    type Output = RunOutput;
+
+    fn index(&self, instance: usize) -> &RunOutput {
+        &self.outputs[instance]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn placeholders() {
+        let vars = [
+            ("{port}", "1234".to_owned()),
+            ("{instance}", "2".to_owned()),
+        ];
+        assert_eq!(
+            substitute(&"127.0.0.1:{port}/{instance}/{port}".into(), &vars),
+            OsString::from("127.0.0.1:1234/2/1234")
+        );
+        assert_eq!(substitute(&"{dir}".into(), &vars), OsString::from("{dir}"));
+    }
+
+    #[test]
+    fn ports_are_distinct() {
+        let mut ports = free_ports(8).unwrap();
+        ports.sort_unstable();
+        ports.dedup();
+        assert_eq!(ports.len(), 8);
+    }
+}
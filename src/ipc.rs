@@ -0,0 +1,206 @@
+//! Unique names for IPC endpoints, like Unix sockets and Windows named pipes.
+
+use std::{
+    ffi::OsStr,
+    fmt,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// How many endpoints have been created in this process, to keep them unique.
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// The longest Unix socket path we'll make. The limit is 108 bytes (including
+/// the nul terminator) on Linux, but only 104 on macOS and the BSDs.
+#[cfg(unix)]
+const MAX_LEN: usize = 103;
+
+/// The longest named pipe name we'll make. The limit is 256 characters.
+#[cfg(windows)]
+const MAX_LEN: usize = 255;
+
+/// A unique name for a test binary to listen on: a Unix socket path, or a
+/// named pipe on Windows.
+///
+/// The name includes the process ID, a counter and the name of the current
+/// test (ie. the current thread's name), so endpoints don't collide between
+/// tests or between test processes, and it's clear which test one belongs
+/// to. Unix socket paths are kept short enough to be used on any platform,
+/// which means putting them directly in `/tmp` if the temporary directory's
+/// path is too long (as it is on macOS).
+///
+/// It's passed to a binary like any other argument or environment variable:
+///
+/// ```rust
+/// # use test_binary::IpcEndpoint;
+/// let endpoint = IpcEndpoint::new("server").unwrap();
+/// let output = test_binary::stock::print_env()
+///     .unwrap()
+///     .runner()
+///     .env("SOCKET", &endpoint)
+///     .arg("SOCKET")
+///     .run()
+///     .unwrap();
+/// assert!(String::from_utf8_lossy(output.stdout()).contains("-server"));
+/// ```
+///
+/// When it's dropped, any socket file left at the path is removed. Any stale
+/// file that's already there when it's created is removed too.
+#[derive(Debug)]
+pub struct IpcEndpoint {
+    path: PathBuf,
+}
+
+impl IpcEndpoint {
+    /// Creates a unique endpoint name. `label` is added to the end of the name
+    /// to tell apart endpoints in the same test, as long as there's room.
+    pub fn new(label: &str) -> std::io::Result<Self> {
+        let number = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let test = std::thread::current()
+            .name()
+            .map(sanitize)
+            .unwrap_or_default();
+        let prefix = format!("tb-{}-{}", std::process::id(), number);
+        let suffix = [test, sanitize(label)]
+            .into_iter()
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join("-");
+
+        let dir = base_dir();
+        let extension = if cfg!(unix) { ".sock" } else { "" };
+        // The prefix is what makes it unique, so only the test name and label
+        // are cut short, from the start, since the end is more specific.
+        let room =
+            MAX_LEN.saturating_sub(dir.as_os_str().len() + 1 + prefix.len() + extension.len() + 1);
+        let skip = suffix.chars().count().saturating_sub(room);
+        let suffix: String = suffix.chars().skip(skip).collect();
+        let suffix = suffix.trim_start_matches('-');
+        let name = if suffix.is_empty() {
+            format!("{}{}", prefix, extension)
+        } else {
+            format!("{}-{}{}", prefix, suffix, extension)
+        };
+
+        let endpoint = Self {
+            path: dir.join(name),
+        };
+        endpoint.remove_stale()?;
+        Ok(endpoint)
+    }
+
+    /// The socket path, or the pipe name on Windows.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    #[cfg(unix)]
+    fn remove_stale(&self) -> std::io::Result<()> {
+        match std::fs::remove_file(&self.path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    #[cfg(windows)]
+    fn remove_stale(&self) -> std::io::Result<()> {
+        // Named pipes go away by themselves when they're closed.
+        Ok(())
+    }
+}
+
+/// Where endpoints go.
+#[cfg(unix)]
+fn base_dir() -> PathBuf {
+    let temp_dir = std::env::temp_dir();
+    // Leave room for a reasonable name.
+    if temp_dir.as_os_str().len() <= MAX_LEN / 2 {
+        temp_dir
+    } else {
+        PathBuf::from("/tmp")
+    }
+}
+
+/// Where endpoints go.
+#[cfg(windows)]
+fn base_dir() -> PathBuf {
+    PathBuf::from(r"\\.\pipe")
+}
+
+/// Keeps the characters that are safe in a file or pipe name, so that test
+/// names like `tests::fla` become `tests-fla`.
+fn sanitize(name: &str) -> String {
+    let mut sanitized = String::new();
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            sanitized.push(c);
+        } else if !sanitized.ends_with('-') {
+            sanitized.push('-');
+        }
+    }
+    sanitized.trim_matches('-').to_owned()
+}
+
+impl AsRef<Path> for IpcEndpoint {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<OsStr> for IpcEndpoint {
+    fn as_ref(&self) -> &OsStr {
+        self.path.as_os_str()
+    }
+}
+
+impl fmt::Display for IpcEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path.display())
+    }
+}
+
+impl Drop for IpcEndpoint {
+    fn drop(&mut self) {
+        // There's nothing useful to do with errors while dropping.
+        let _ = self.remove_stale();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        assert_eq!(sanitize("tests::fla mingo"), "tests-fla-mingo");
+        assert_eq!(sanitize("::"), "");
+
+        let first = IpcEndpoint::new("pink").unwrap();
+        let second = IpcEndpoint::new("pink").unwrap();
+        assert_ne!(first.path(), second.path());
+        assert!(first.path().as_os_str().len() <= MAX_LEN);
+
+        let name = first.path().file_name().unwrap().to_string_lossy();
+        assert!(name.contains("names-pink"), "{}", name);
+
+        let long = IpcEndpoint::new(&"fla".repeat(100)).unwrap();
+        assert!(long.path().as_os_str().len() <= MAX_LEN);
+        assert!(long
+            .path()
+            .to_string_lossy()
+            .contains(&std::process::id().to_string()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn cleanup() {
+        let endpoint = IpcEndpoint::new("").unwrap();
+        let path = endpoint.path().to_owned();
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        drop(listener);
+        assert!(path.exists());
+
+        drop(endpoint);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,126 @@
+//! Recording exactly how a test binary was started.
+
+use std::{
+    ffi::{OsStr, OsString},
+    fmt,
+    path::{Path, PathBuf},
+    process::Command,
+    time::SystemTime,
+};
+
+/// How a test binary was started: its command line, the environment
+/// variables that were set or removed for it, its working directory and when
+/// it started. See [`RunOutput::launch()`](crate::RunOutput::launch) and
+/// [`ChildGuard::launch()`](crate::ChildGuard::launch).
+///
+/// Its `Display` implementation is meant for failure messages, to help work
+/// out why a binary misbehaves in one environment and not another.
+#[derive(Debug, Clone)]
+pub struct LaunchContext {
+    program: PathBuf,
+    args: Vec<OsString>,
+    envs: Vec<(OsString, Option<OsString>)>,
+    current_dir: PathBuf,
+    started_at: SystemTime,
+}
+
+impl LaunchContext {
+    /// Records how `command` is about to be started.
+    pub(crate) fn new(command: &Command) -> Self {
+        // A relative working directory is relative to ours.
+        let here = std::env::current_dir().unwrap_or_default();
+        Self {
+            program: command.get_program().into(),
+            args: command.get_args().map(OsStr::to_owned).collect(),
+            envs: command
+                .get_envs()
+                .map(|(key, value)| (key.to_owned(), value.map(OsStr::to_owned)))
+                .collect(),
+            current_dir: match command.get_current_dir() {
+                Some(dir) => here.join(dir),
+                None => here,
+            },
+            started_at: SystemTime::now(),
+        }
+    }
+
+    /// The path to the binary.
+    pub fn program(&self) -> &Path {
+        &self.program
+    }
+
+    /// The arguments passed to the binary, not including the program.
+    pub fn args(&self) -> &[OsString] {
+        &self.args
+    }
+
+    /// The environment variables that were set for the binary, on top of what
+    /// it inherited. Variables that were removed have a value of `None`.
+    pub fn envs(&self) -> &[(OsString, Option<OsString>)] {
+        &self.envs
+    }
+
+    /// The binary's working directory.
+    pub fn current_dir(&self) -> &Path {
+        &self.current_dir
+    }
+
+    /// When the binary was started.
+    pub fn started_at(&self) -> SystemTime {
+        self.started_at
+    }
+}
+
+impl fmt::Display for LaunchContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "command: {:?}", self.program)?;
+        for arg in &self.args {
+            write!(f, " {:?}", arg)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "working directory: {}", self.current_dir.display())?;
+        for (key, value) in &self.envs {
+            match value {
+                Some(value) => writeln!(f, "env: {}={:?}", key.to_string_lossy(), value)?,
+                None => writeln!(f, "env: {} removed", key.to_string_lossy())?,
+            }
+        }
+        let started = self
+            .started_at
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        write!(
+            f,
+            "started: {}.{:03} seconds since the Unix epoch",
+            started.as_secs(),
+            started.subsec_millis()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn display() {
+        let mut command = Command::new("fla");
+        command
+            .args(["mingo", "pink flamingo"])
+            .env("FLA", "1")
+            .env_remove("MINGO")
+            .current_dir("/tmp");
+        let mut launch = LaunchContext::new(&command);
+        launch.started_at = SystemTime::UNIX_EPOCH + std::time::Duration::from_millis(1500);
+
+        assert_eq!(
+            launch.to_string(),
+            "command: \"fla\" \"mingo\" \"pink flamingo\"\n\
+             working directory: /tmp\n\
+             env: FLA=\"1\"\n\
+             env: MINGO removed\n\
+             started: 1.500 seconds since the Unix epoch"
+        );
+    }
+}
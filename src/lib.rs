@@ -86,21 +86,42 @@
 //!
 //!   [cargo-10872]: https://github.com/rust-lang/cargo/issues/10872
 //!
+//! For trivial binaries, a whole package can be overkill. Instead, you can put
+//! a single source file in the directory eg. `testbins/test-something.rs`, and
+//! a package will be created for it when it's built. See
+//! [`TestBinary::from_single_file()`](crate::TestBinary::from_single_file).
+//!
+//! If the tests and the binaries need to agree on something, like the types of
+//! the messages they exchange, put it in a library package alongside them eg.
+//! `testbins/shared-types`, rather than keeping copies in sync by hand. The
+//! binaries depend on it with `shared-types = { path = "../shared-types" }`
+//! and your crate with `shared-types = { path = "testbins/shared-types" }`
+//! under `[dev-dependencies]`. Since the directory isn't a workspace, there's
+//! nothing else to set up, and this crate won't mistake the library for a
+//! binary unless you ask it to build one by that name.
+//!
 //! With this setup, you can now call [`build_test_binary("test-something",
 //! "testbins")`](crate::build_test_binary). See how:
 //!
 //! - `"test-something"` is the binary name you'd pass to Cargo *in the child
 //!   project* eg. if you changed directory to the nested project, you'd run
 //!   `cargo build --bin test-something`; it also has to be the name of the
-//!   subdirectory this project is in
+//!   subdirectory this project is in (if it isn't, see
+//!   [`build_test_binary_in()`](crate::build_test_binary_in))
 //! - `"testbins"` is the directory relative to your real project's manifest
 //!   containing this test binary project (and maybe others); think of it like
-//!   you'd think of the `examples` or `tests` directory
+//!   you'd think of the `examples` or `tests` directory (if they're spread over
+//!   several, see [`build_test_binary_in_dirs()`](crate::build_test_binary_in_dirs))
 //!
-//! If you need to set different profiles or features, or have more control over
-//! the directory structure, there is also [a builder API](crate::TestBinary).
+//! If you need to set different profiles or features, see
+//! [`build_test_binary_with()`](crate::build_test_binary_with), or for more
+//! control over the directory structure, there is also [a builder
+//! API](crate::TestBinary).
 //! Also see [`build_test_binary_once!()`](crate::build_test_binary_once) for a
-//! macro that lazily builds the binary and caches the path.
+//! macro that lazily builds the binary and caches the path, and
+//! [`run_test_binary()`](crate::run_test_binary) for building and running a
+//! binary in one call. To build everything before any tests run, so that no
+//! single test has to wait for it, see [`prewarm()`](crate::prewarm).
 //!
 //! Here's an example of how you might use this crate's API in a test, with a
 //! binary named `does-build`:
@@ -132,25 +153,171 @@
 //! one anyway. Since it is the path provided by Cargo after being invoked in
 //! the current process' working directory, it will be valid as long as you do
 //! not change the working directory between obtaining it and using it.
+//!
+//! This crate doesn't use unsafe code, except where the operating system has
+//! no safe interface for something it needs, eg. sending console events on
+//! Windows. That code is kept to a few modules of its own, which say why they
+//! need it, and is denied everywhere else.
 
-#![forbid(unsafe_code)]
+// Modules that can't do without unsafe code opt in with
+// `#![allow(unsafe_code)]`, and say why at the top.
+#![deny(unsafe_code)]
 #![warn(missing_docs, missing_debug_implementations)]
 #![cfg_attr(docsrs, feature(doc_cfg))]
 
+use once_cell::sync::OnceCell;
 use std::{
-    ffi::OsString,
-    io::{BufReader, Read},
+    cell::RefCell,
+    collections::BTreeMap,
+    ffi::{OsStr, OsString},
     ops::Index,
     path::{Path, PathBuf},
-    process::{Command, Stdio},
+    process::{Command, ExitStatus, Stdio},
+    rc::Rc,
     str::FromStr,
+    sync::mpsc,
+    time::{Duration, Instant, SystemTime},
 };
 
 // For the build_test_binary_once macro.
 pub use once_cell;
 pub use paste;
+// For the assert_output_snapshot macro.
+#[cfg(feature = "insta")]
+#[doc(hidden)]
+pub use insta;
 
+mod artifact_dir;
+mod audit;
+mod bench;
+mod bin_dir;
+mod cancel;
+mod child;
+mod child_tests;
+mod component;
+#[cfg(windows)]
+mod console;
+mod dedup;
+mod defaults;
+mod dependencies;
+mod diagnostics;
+mod dump;
+mod export;
+mod failure;
+mod fingerprint;
+mod flat;
+mod generated;
+mod github;
+mod harness;
+mod hash;
+mod input;
+mod instances;
+mod ipc;
+mod launch;
+mod lint;
+mod lockfile;
+mod log;
+mod log_file;
+mod matrix;
+#[cfg(feature = "libtest-mimic")]
+mod mimic;
+mod mock;
+#[cfg(feature = "async")]
+mod nonblocking;
+mod normalize;
+mod pin;
+#[cfg(feature = "duct")]
+mod pipeline;
+mod platform;
+mod pool;
+mod port;
+mod progress;
+mod ready;
+#[cfg(unix)]
+mod reaper;
+mod record;
+mod resolve;
+mod retry;
+#[cfg(feature = "rpc")]
+mod rpc;
+mod run;
+#[cfg(unix)]
+mod scheduling;
+mod serialize;
+mod session;
+#[cfg(feature = "shared-memory")]
+mod shm;
+mod signal;
+mod size;
+mod spec;
+pub mod stock;
 mod stream;
+mod template;
+mod toolchain;
+#[cfg(feature = "tracing")]
+mod tracing_bridge;
+mod usage;
+#[cfg(feature = "watch")]
+mod watch;
+mod windows_abi;
+mod workspace;
+
+use audit::AuditCallback;
+pub use audit::AuditInput;
+pub use bench::{BenchOptions, BenchStats};
+pub use bin_dir::BinDir;
+pub use cancel::CancelToken;
+pub use child::ChildGuard;
+pub use child_tests::ChildTests;
+pub use component::ComponentRuntime;
+pub use defaults::{BuildDefaults, OFFLINE_ENV, PROFILE_ENV, TARGET_ENV};
+pub use dependencies::Dependency;
+pub use diagnostics::DiagnosticLevel;
+use diagnostics::DiagnosticOptions;
+pub use dump::dump_output_on_panic;
+pub use export::{ArtifactManifest, BinaryPaths, ManifestEntry};
+pub use failure::{TestFailure, TestResult};
+#[doc(hidden)]
+pub use fingerprint::OnceBuild;
+pub use harness::{RunningHarness, TestHarness};
+pub use input::Input;
+pub use instances::Instances;
+pub use ipc::IpcEndpoint;
+pub use launch::LaunchContext;
+pub use lint::Lint;
+pub use lockfile::SyncedDependency;
+#[cfg(feature = "libtest-mimic")]
+pub use mimic::{setup_trials, TestBinaries};
+pub use mock::MockScript;
+#[cfg(feature = "async")]
+pub use nonblocking::build_test_binary_async;
+pub use normalize::Normalizer;
+pub use pin::{PinError, Pinning};
+pub use pool::TestBinaryPool;
+pub use port::Port;
+use progress::ProgressCallback;
+pub use progress::{BuildProgress, Stall};
+pub use ready::Readiness;
+use record::{BuildRecord, BuildStats};
+#[doc(hidden)]
+pub use resolve::artifact_dependency_or_build;
+pub use resolve::resolve_test_binary;
+pub use retry::RetryPolicy;
+#[cfg(feature = "rpc")]
+pub use rpc::RpcClient;
+use run::RunDefaults;
+pub use run::{Artifact, Crash, Panic, RunError, RunOutput, Runner};
+pub use session::Session;
+#[cfg(feature = "shared-memory")]
+pub use shm::SharedMemory;
+pub use signal::{
+    grace_period, set_grace_period, Graceful, Shutdown, ShutdownPath, Signal, GRACE_PERIOD_ENV,
+};
+pub use spec::TestBinarySpec;
+pub use template::BuildTemplate;
+use toolchain::Toolchain;
+pub use usage::ResourceUsage;
+pub use windows_abi::WindowsAbi;
 
 // Internal macros for OsString boilerplate.
 
@@ -188,48 +355,295 @@ macro_rules! push_oss {
 ///     &PathBuf::from_iter(["testbins", "does-build", "Cargo.toml"]),
 /// );
 /// ```
-#[derive(Debug)]
+///
+/// Cloning a `TestBinary` is cheap, and the clone shares any callbacks with
+/// the original. To apply the same settings to many binaries, see
+/// [`BuildTemplate`].
+#[derive(Clone)]
 pub struct TestBinary<'a> {
     binary: &'a str,
     manifest: PathBuf,
     features: Vec<&'a str>,
     default_features: bool,
+    platforms: Option<Vec<&'a str>>,
     profile: Option<&'a str>,
+    /// A profile that has to be used whatever the environment says, while
+    /// building one of several variants.
+    forced_profile: Option<&'a str>,
+    progress: Option<ProgressCallback<'a>>,
+    timeout: Option<Duration>,
+    cancel: Option<CancelToken>,
+    watchdog: Option<Duration>,
+    frozen: bool,
+    pinning: Option<Pinning>,
+    size_budget: Option<size::SizeBudget>,
+    diagnostics: DiagnosticOptions,
+    github: Option<bool>,
+    build_script_envs: Vec<(OsString, OsString)>,
+    target: Option<&'a str>,
+    /// Like `forced_profile`, for building for several targets, or for one
+    /// that the binary has to be built for.
+    forced_target: Option<&'a str>,
+    parent_target: bool,
+    windows_abi: Option<WindowsAbi>,
+    component_runtime: Option<ComponentRuntime>,
+    deny_warnings: bool,
+    rustc_flags: Vec<OsString>,
+    cfgs: Vec<(String, Option<String>)>,
+    run_defaults: RunDefaults,
+    target_dir: Option<PathBuf>,
+    artifact_dir: Option<PathBuf>,
+    future_incompat: bool,
+    offline: bool,
+    jobs: Option<u32>,
+    incremental: Option<bool>,
+    capture_dependencies: bool,
+    audit: Option<AuditCallback<'a>>,
+    build_log: Option<PathBuf>,
+    log_file: Option<log_file::LogFile>,
+    /// Whether to run Cargo even if an identical build has already been done
+    /// in the process, because something it doesn't know about has changed.
+    unshared: bool,
+    cargo: Option<PathBuf>,
+    cargo_from_path: bool,
+    #[cfg(unix)]
+    scheduling: scheduling::Scheduling,
+}
+
+impl std::fmt::Debug for TestBinary<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut debug = f.debug_struct("TestBinary");
+        debug
+            .field("binary", &self.binary)
+            .field("manifest", &self.manifest)
+            .field("features", &self.features)
+            .field("default_features", &self.default_features)
+            .field("platforms", &self.platforms)
+            .field("profile", &self.profile)
+            .field("forced_profile", &self.forced_profile)
+            .field("timeout", &self.timeout)
+            .field("cancel", &self.cancel)
+            .field("watchdog", &self.watchdog)
+            .field("frozen", &self.frozen)
+            .field("pinning", &self.pinning)
+            .field("size_budget", &self.size_budget)
+            .field("diagnostics", &self.diagnostics)
+            .field("github", &self.github)
+            .field("build_script_envs", &self.build_script_envs)
+            .field("target", &self.target)
+            .field("forced_target", &self.forced_target)
+            .field("parent_target", &self.parent_target)
+            .field("windows_abi", &self.windows_abi)
+            .field("component_runtime", &self.component_runtime)
+            .field("deny_warnings", &self.deny_warnings)
+            .field("rustc_flags", &self.rustc_flags)
+            .field("cfgs", &self.cfgs)
+            .field("run_defaults", &self.run_defaults)
+            .field("target_dir", &self.target_dir)
+            .field("artifact_dir", &self.artifact_dir)
+            .field("future_incompat", &self.future_incompat)
+            .field("offline", &self.offline)
+            .field("jobs", &self.jobs)
+            .field("incremental", &self.incremental)
+            .field("capture_dependencies", &self.capture_dependencies)
+            .field("build_log", &self.build_log)
+            .field("log_file", &self.log_file)
+            .field("unshared", &self.unshared)
+            .field("cargo", &self.cargo)
+            .field("cargo_from_path", &self.cargo_from_path);
+        #[cfg(unix)]
+        debug.field("scheduling", &self.scheduling);
+        debug.finish_non_exhaustive()
+    }
 }
 
 impl<'a> TestBinary<'a> {
     /// Creates a new `TestBinary` by specifying the child binary's manifest
     /// relative to the parent.
     pub fn relative_to_parent(name: &'a str, manifest: &'a Path) -> Result<Self, TestBinaryError> {
-        let manifest_path = manifest_dir()?.join(manifest);
-
-        Ok(Self {
-            binary: name,
-            manifest: manifest_path,
-            features: vec![],
-            default_features: true,
-            profile: None,
-        })
+        Ok(Self::with_manifest(name, manifest_dir()?.join(manifest)))
     }
 
     /// Find binary in workspace and create `TestBinary` struct.
+    ///
+    /// The workspace's metadata is cached for the rest of the process, until
+    /// its `Cargo.lock` changes, so looking up more binaries is cheap.
     pub fn from_workspace(name: &'a str) -> Result<Self, TestBinaryError> {
-        let manifest_path = find_package(name)?;
-        Ok(Self {
+        Ok(Self::with_manifest(name, find_package(name)?))
+    }
+
+    /// Creates a new `TestBinary` for the package that provides the binary
+    /// `name`, searching the manifests that match any of the glob `patterns`,
+    /// which are relative to the parent.
+    ///
+    /// This suits helpers that aren't workspace members, but don't all live in
+    /// one directory either:
+    ///
+    /// ```rust
+    /// # use test_binary::TestBinary;
+    /// let path = TestBinary::from_globs(
+    ///     "mock-client",
+    ///     &["tests/helpers/**/Cargo.toml", "testbins/*/Cargo.toml"],
+    /// )
+    /// .unwrap()
+    /// .build()
+    /// .unwrap();
+    /// ```
+    ///
+    /// A package is found by reading its manifest and looking at its files,
+    /// the way Cargo would, without running Cargo. A package with a binary
+    /// target called `name` is preferred over one that's merely called
+    /// `name`, and otherwise the first match wins, taking the patterns in
+    /// order and the matches for each alphabetically. If nothing matches, the
+    /// error is [`ManifestError::PackageNotFound`].
+    pub fn from_globs(name: &'a str, patterns: &[&str]) -> Result<Self, TestBinaryError> {
+        Ok(Self::with_manifest(
+            name,
+            resolve::find_in_globs(name, patterns)?,
+        ))
+    }
+
+    /// Creates a new `TestBinary` from a single source file, relative to the
+    /// parent, rather than a whole Cargo package. A package for it is created
+    /// under the parent's target directory, in `test-binary/flat/<name>`.
+    ///
+    /// The file can declare dependencies in a manifest fragment embedded in
+    /// its top doc comment:
+    ///
+    /// ```none
+    /// //! ```cargo
+    /// //! [dependencies]
+    /// //! signal-hook = "0.3"
+    /// //! ```
+    /// ```
+    pub fn from_single_file(name: &'a str, source: &Path) -> Result<Self, TestBinaryError> {
+        Ok(Self::with_manifest(name, flat::materialize(name, source)?))
+    }
+
+    /// Creates a new `TestBinary` from a [`TestBinarySpec`], as if by calling
+    /// the corresponding builder methods.
+    pub fn from_spec(spec: &'a TestBinarySpec) -> Result<Self, TestBinaryError> {
+        let mut binary = match (&spec.manifest, &spec.dir) {
+            (Some(manifest), _) => Self::relative_to_parent(&spec.name, manifest)?,
+            (None, Some(dir)) => Self::in_directory(&spec.name, dir)?,
+            (None, None) => Self::from_workspace(&spec.name)?,
+        };
+
+        for feature in &spec.features {
+            binary.with_feature(feature);
+        }
+        if !spec.default_features {
+            binary.no_default_features();
+        }
+        if let Some(profile) = &spec.profile {
+            binary.with_profile(profile);
+        }
+        if let Some(target) = &spec.target {
+            binary.with_target(target);
+        }
+        for (key, value) in &spec.env {
+            binary.with_build_script_env(key, value);
+        }
+
+        Ok(binary)
+    }
+
+    /// Finds the binary in `directory` (relative to the parent), either as a
+    /// package in `<directory>/<name>` or a single file `<directory>/<name>.rs`.
+    fn in_directory(name: &'a str, directory: &Path) -> Result<Self, TestBinaryError> {
+        let package = PathBuf::from_iter([directory, name.as_ref(), "Cargo.toml".as_ref()]);
+        let single_file = directory.join(format!("{}.rs", name));
+        let parent = manifest_dir()?;
+
+        if !parent.join(&package).exists() && parent.join(&single_file).exists() {
+            Self::from_single_file(name, &single_file)
+        } else {
+            Ok(Self::with_manifest(name, parent.join(package)))
+        }
+    }
+
+    fn with_manifest(name: &'a str, manifest: PathBuf) -> Self {
+        let defaults = BuildDefaults::current();
+        Self {
             binary: name,
-            manifest: manifest_path,
+            manifest,
             features: vec![],
             default_features: true,
-            profile: None,
-        })
+            platforms: None,
+            profile: defaults.and_then(|d| d.profile.as_deref()),
+            forced_profile: None,
+            progress: None,
+            timeout: None,
+            cancel: None,
+            watchdog: None,
+            frozen: false,
+            pinning: None,
+            size_budget: None,
+            diagnostics: DiagnosticOptions::default(),
+            github: None,
+            build_script_envs: vec![],
+            target: None,
+            forced_target: None,
+            parent_target: true,
+            windows_abi: None,
+            component_runtime: None,
+            deny_warnings: false,
+            rustc_flags: vec![],
+            cfgs: vec![],
+            run_defaults: RunDefaults::default(),
+            target_dir: defaults.and_then(|d| d.target_dir.clone()),
+            artifact_dir: None,
+            future_incompat: false,
+            offline: defaults.is_some_and(|d| d.offline),
+            jobs: defaults.and_then(|d| d.jobs),
+            incremental: None,
+            capture_dependencies: false,
+            audit: None,
+            build_log: defaults.and_then(|d| d.build_log.clone()),
+            log_file: None,
+            unshared: false,
+            cargo: None,
+            cargo_from_path: false,
+            #[cfg(unix)]
+            scheduling: Default::default(),
+        }
     }
 
-    /// Specifies a profile to build the test binary with.
+    /// Builds the binary target named `bin` instead of the one the
+    /// `TestBinary` was created with. This is for packages with more than one
+    /// binary, or whose binary isn't named after the package.
+    pub fn with_bin(&mut self, bin: &'a str) -> &mut Self {
+        self.binary = bin;
+        self
+    }
+
+    /// Specifies a profile to build the test binary with. This can be any
+    /// profile the child package defines, including custom ones.
+    ///
+    /// The binary's path is always the one Cargo reports. Since Cargo puts
+    /// `test` builds in the same directory as `dev` builds, and `bench` builds
+    /// with `release` ones, binaries built with those two profiles are copied
+    /// to `profile-test/` or `profile-bench/` under their [output
+    /// directory](Artifact::output_dir), so that they aren't replaced.
     pub fn with_profile(&mut self, profile: &'a str) -> &mut Self {
         self.profile = Some(profile);
         self
     }
 
+    /// Builds with the profile the parent's tests were built with, so that
+    /// `cargo test --release` builds release binaries without anything else
+    /// being changed. This overrides [`TestBinary::with_profile()`].
+    ///
+    /// The profile is worked out from the directory the running test
+    /// executable is in, so a custom profile is used too, and the child must
+    /// define it. If the test executable isn't where Cargo puts them, it's
+    /// `release` if the parent was built without debug assertions, or the
+    /// default otherwise.
+    pub fn with_profile_matching_parent(&mut self) -> &mut Self {
+        self.profile = parent_profile();
+        self
+    }
+
     /// Specifies not to enable default features.
     pub fn no_default_features(&mut self) -> &mut Self {
         self.default_features = false;
@@ -244,87 +658,951 @@ impl<'a> TestBinary<'a> {
         self
     }
 
+    /// Declares the platforms the binary can be built for, overriding any
+    /// listed in its manifest:
+    ///
+    /// ```toml
+    /// [package.metadata.test-binary]
+    /// platforms = ["unix", "x86_64-pc-windows-msvc"]
+    /// ```
+    ///
+    /// Each is a whole target triple, a part of one eg. `linux`, `x86_64` or
+    /// `musl`, or one of `unix`, `windows`, `macos` or `wasm`. Building for
+    /// a target that none of them cover fails with
+    /// [`TestBinaryError::Unsupported`] before Cargo is run, which a test can
+    /// treat as a reason to skip:
+    ///
+    /// ```rust
+    /// # use test_binary::{TestBinary, TestBinaryError};
+    /// # use std::path::PathBuf;
+    /// # fn test() -> Result<(), TestBinaryError> {
+    /// let manifest = PathBuf::from_iter(["testbins", "does-build", "Cargo.toml"]);
+    /// let result = TestBinary::relative_to_parent("does-build", &manifest)?
+    ///     .with_platforms(["windows"])
+    ///     .build();
+    /// let path = match result {
+    ///     Err(TestBinaryError::Unsupported { .. }) => return Ok(()),
+    ///     result => result?,
+    /// };
+    /// # Ok(())
+    /// # }
+    /// # test().unwrap();
+    /// ```
+    ///
+    /// Binaries can be built for any platform by default.
+    pub fn with_platforms<I: IntoIterator<Item = &'a str>>(&mut self, platforms: I) -> &mut Self {
+        self.platforms = Some(platforms.into_iter().collect());
+        self
+    }
+
+    /// Sets an environment variable for the child's build script eg. to give
+    /// it the path of a test fixture to embed at compile time.
+    ///
+    /// The variable is set for the whole Cargo invocation, since that's the
+    /// only way to reach the build script. But Cargo won't rerun the build
+    /// script when the value changes unless the script asks it to, so the
+    /// build script should print `cargo:rerun-if-env-changed=<key>` for each
+    /// variable it reads.
+    pub fn with_build_script_env<K: AsRef<OsStr>, V: AsRef<OsStr>>(
+        &mut self,
+        key: K,
+        value: V,
+    ) -> &mut Self {
+        self.build_script_envs
+            .push((key.as_ref().to_owned(), value.as_ref().to_owned()));
+        self
+    }
+
+    /// Makes compiler warnings into errors, by adding `-D warnings` to the
+    /// `RUSTFLAGS` Cargo uses (on top of any that are already set). Warnings
+    /// then fail the build with a [`TestBinaryError::BuildError`], so that
+    /// test binaries can be kept free of warnings.
+    ///
+    /// Note that changing `RUSTFLAGS` makes Cargo rebuild everything, so mixing
+    /// builds with and without this can be slow.
+    pub fn deny_warnings(&mut self) -> &mut Self {
+        self.deny_warnings = true;
+        self
+    }
+
+    /// Passes these flags to the compiler for the binary itself, but not its
+    /// dependencies, by building with `cargo rustc --bin testbin -- FLAGS`
+    /// instead of `cargo build`, eg. `--cfg test_mode="slow"` or `-C
+    /// force-frame-pointers`. This is something `RUSTFLAGS` can't do.
+    ///
+    /// The binary is built in a target directory of its own for each set of
+    /// flags, under `variants` in the usual one, so that builds with
+    /// different flags don't replace each other's binaries.
+    ///
+    /// Cargo can only pass flags to one binary at a time, so this doesn't work
+    /// with [`TestBinary::build_all_bins()`]. It doesn't affect
+    /// [`TestBinary::check()`] or [`TestBinary::clippy()`].
+    pub fn with_rustc_flags<I, S>(&mut self, flags: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.rustc_flags
+            .extend(flags.into_iter().map(|flag| flag.as_ref().to_owned()));
+        self
+    }
+
+    /// Sets a configuration option for the binary itself, like `--cfg
+    /// name="value"` (or `--cfg name` without a value), so that one source can
+    /// be built into several variants eg. `with_cfg("mock_mode",
+    /// Some("replay"))` for code under `#[cfg(mock_mode = "replay")]`. This
+    /// is passed on with [`TestBinary::with_rustc_flags()`], so the same
+    /// limitations apply, and each variant is built in a target directory of
+    /// its own, named after its options eg. `variants/mock_mode=replay`.
+    pub fn with_cfg(&mut self, name: &str, value: Option<&str>) -> &mut Self {
+        self.cfgs.push((name.to_owned(), value.map(str::to_owned)));
+        self
+    }
+
+    /// The flags for `cargo rustc`, from [`TestBinary::with_rustc_flags()`]
+    /// and [`TestBinary::with_cfg()`].
+    fn rustc_flags(&self) -> Vec<OsString> {
+        let mut flags = self.rustc_flags.clone();
+        for (name, value) in &self.cfgs {
+            flags.push("--cfg".into());
+            flags.push(match value {
+                Some(value) => format!("{}={:?}", name, value).into(),
+                None => name.into(),
+            });
+        }
+        flags
+    }
+
+    /// The target directory for a build with [rustc
+    /// flags](TestBinary::with_rustc_flags), if there are any, so that each
+    /// variant is cached separately and building one doesn't replace another
+    /// that's in use. It's named after any [options](TestBinary::with_cfg) eg.
+    /// `mock_mode=replay+offline`, and a hash of any other flags.
+    fn variant_target_dir(&self) -> Result<Option<PathBuf>, TestBinaryError> {
+        let mut names: Vec<_> = self
+            .cfgs
+            .iter()
+            .map(|(name, value)| match value {
+                Some(value) => format!("{}={}", name, value.replace(['/', '\\'], "_")),
+                None => name.clone(),
+            })
+            .collect();
+        if !self.rustc_flags.is_empty() {
+            let flags: Vec<_> = self
+                .rustc_flags
+                .iter()
+                .map(|flag| flag.to_string_lossy())
+                .collect();
+            let hash = hash::hash_bytes(flags.join("\u{1f}").as_bytes());
+            let hex = hash.trim_start_matches("sha256:");
+            names.push(format!("flags-{}", &hex[..16]));
+        }
+        if names.is_empty() {
+            return Ok(None);
+        }
+
+        let base = match &self.target_dir {
+            Some(dir) => dir.clone(),
+            None => {
+                let package_dir = self.manifest.parent().unwrap_or(Path::new("."));
+                workspace::metadata(package_dir)?
+                    .target_directory
+                    .clone()
+                    .into_std_path_buf()
+            }
+        };
+        Ok(Some(base.join("variants").join(names.join("+"))))
+    }
+
+    /// Turns incremental compilation on or off for the build, by setting
+    /// `CARGO_INCREMENTAL`. This overrides the profile's `incremental`
+    /// setting. Turning it on speeds up rebuilding a test binary that's being
+    /// worked on; turning it off avoids problems with incremental caches that
+    /// are restored (or corrupted) by CI caching. By default, the profile
+    /// decides.
+    pub fn with_incremental(&mut self, enabled: bool) -> &mut Self {
+        self.incremental = Some(enabled);
+        self
+    }
+
+    /// Records the packages the binary was built with, after a successful
+    /// build, so that they can be audited. See [`Artifact::dependencies()`].
+    /// This takes an extra run of `cargo metadata`.
+    pub fn capture_dependencies(&mut self) -> &mut Self {
+        self.capture_dependencies = true;
+        self
+    }
+
+    /// Checks the binary's dependencies every time it's built, by calling
+    /// `audit` with its lock file and the packages it was built with. This is
+    /// a place to flag vulnerable or yanked dependencies eg. by checking the
+    /// lock file against an advisory database, since test binaries are
+    /// otherwise invisible to `cargo audit`. If it returns an error, the build
+    /// fails with [`TestBinaryError::AuditFailed`].
+    ///
+    /// ```rust
+    /// # use std::path::PathBuf;
+    /// # use test_binary::TestBinary;
+    /// let result = TestBinary::relative_to_parent(
+    ///     "does-build",
+    ///     &PathBuf::from_iter(["testbins", "does-build", "Cargo.toml"]),
+    /// )
+    /// .unwrap()
+    /// .with_audit(|input| {
+    ///     match input.dependencies().iter().find(|dep| dep.name() == "openssl") {
+    ///         Some(dep) => Err(format!("{} {} is not allowed", dep.name(), dep.version())),
+    ///         None => Ok(()),
+    ///     }
+    /// })
+    /// .build();
+    /// assert!(result.is_ok());
+    /// ```
+    pub fn with_audit<F>(&mut self, audit: F) -> &mut Self
+    where
+        F: FnMut(&AuditInput<'_>) -> Result<(), String> + 'a,
+    {
+        self.audit = Some(Rc::new(RefCell::new(audit)));
+        self
+    }
+
+    /// Appends a record of every time Cargo is run to build the binary to
+    /// `file`, one line of JSON each. Records have the time the build
+    /// started, how it was configured, how long it took and how much of that
+    /// was spent waiting for locks, whether anything needed compiling, and
+    /// whether it succeeded. Builds that are shared with an identical one in
+    /// the process are only recorded once.
+    ///
+    /// Processes can safely append to the same file, so this is a way to find
+    /// out where the time goes across a whole test run, eg. in CI. See also
+    /// [`BuildDefaults::record_builds()`].
+    pub fn record_builds<P: AsRef<Path>>(&mut self, file: P) -> &mut Self {
+        self.build_log = Some(file.as_ref().to_owned());
+        self
+    }
+
+    /// Uses this Cargo executable, instead of the one in the `CARGO`
+    /// environment variable (ie. the one running the tests). This is for
+    /// testing against several versions of Cargo, or when `CARGO` can't be
+    /// trusted.
+    pub fn with_cargo<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.cargo = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// Runs `cargo` from the `PATH` if the `CARGO` environment variable isn't
+    /// set, instead of failing with [`TestBinaryError::NonCargoRun`]. This is
+    /// for harnesses that run tests with a cleaned up environment. It makes
+    /// no difference if [`TestBinary::with_cargo()`] is used.
+    pub fn cargo_from_path_fallback(&mut self) -> &mut Self {
+        self.cargo_from_path = true;
+        self
+    }
+
+    /// The toolchain requested by a toolchain file in the binary's package (or
+    /// a directory above it, up to the parent), unless a Cargo has been chosen
+    /// with [`TestBinary::with_cargo()`].
+    fn toolchain(&self) -> Result<Option<Toolchain>, TestBinaryError> {
+        if self.cargo.is_some() {
+            return Ok(None);
+        }
+        match (self.manifest.parent(), manifest_dir()) {
+            (Some(package_dir), Ok(parent_dir)) => Toolchain::find(package_dir, &parent_dir),
+            _ => Ok(None),
+        }
+    }
+
+    /// The Cargo executable to run, not counting toolchain files.
+    fn cargo_path(&self) -> Result<OsString, TestBinaryError> {
+        if let Some(cargo) = &self.cargo {
+            return Ok(cargo.clone().into_os_string());
+        }
+        match std::env::var_os("CARGO") {
+            Some(cargo) => Ok(cargo),
+            None if self.cargo_from_path => Ok("cargo".into()),
+            None => Err(TestBinaryError::NonCargoRun(
+                "The environment variable 'CARGO' is not set".to_owned(),
+            )),
+        }
+    }
+
+    /// Specifies a directory for Cargo's build output, like `cargo build
+    /// --target-dir`. By default, it's the child package's own `target`
+    /// directory.
+    pub fn with_target_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.target_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Puts the built binary in `dir`, at a path that doesn't depend on the
+    /// profile, target or target directory, for tools that need to know where
+    /// to find it in advance.
+    ///
+    /// If the Cargo in use supports it (as nightly Cargo does), it's asked to
+    /// put the binary there itself with `--artifact-dir`. Otherwise the binary
+    /// is copied there after the build, with [`Artifact::install_to()`].
+    /// Either way, the [output directory](Artifact::output_dir) is still the
+    /// one Cargo built the binary in.
+    pub fn with_artifact_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.artifact_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Collects Cargo's report of code in the binary or its dependencies that
+    /// a future version of Rust will reject, like `cargo build
+    /// --future-incompat-report`. The report is available from
+    /// [`Artifact::future_incompat_report()`].
+    ///
+    /// Since test binaries usually aren't part of the parent's workspace,
+    /// these warnings are otherwise easy to miss until an upgrade breaks them.
+    pub fn with_future_incompat_report(&mut self) -> &mut Self {
+        self.future_incompat = true;
+        self
+    }
+
+    /// Builds without accessing the network, like `cargo build --offline`.
+    pub fn offline(&mut self) -> &mut Self {
+        self.offline = true;
+        self
+    }
+
+    /// Limits the number of parallel jobs Cargo runs, like `cargo build
+    /// --jobs`.
+    pub fn with_jobs(&mut self, jobs: u32) -> &mut Self {
+        self.jobs = Some(jobs);
+        self
+    }
+
+    /// Runs Cargo (and so the compiler) at a lower priority, by adding
+    /// `niceness` to ours with `nice`, so that a big build doesn't starve
+    /// timing-sensitive tests running alongside it. Negative values raise the
+    /// priority instead, which usually needs root.
+    #[cfg(unix)]
+    #[cfg_attr(docsrs, doc(cfg(unix)))]
+    pub fn with_niceness(&mut self, niceness: i32) -> &mut Self {
+        self.scheduling.niceness = Some(niceness);
+        self
+    }
+
+    /// Runs Cargo (and so the compiler) on only these CPUs, numbered from
+    /// zero, with `taskset`, to leave the rest for other tests.
+    #[cfg(target_os = "linux")]
+    #[cfg_attr(docsrs, doc(cfg(target_os = "linux")))]
+    pub fn with_cpus<I: IntoIterator<Item = usize>>(&mut self, cpus: I) -> &mut Self {
+        self.scheduling.cpus = Some(cpus.into_iter().collect());
+        self
+    }
+
+    /// Specifies a target triple to build the binary for, like `cargo build
+    /// --target`. This overrides the parent's target (see
+    /// [`TestBinary::ignore_parent_target()`]).
+    pub fn with_target(&mut self, target: &'a str) -> &mut Self {
+        self.target = Some(target);
+        self
+    }
+
+    /// Builds for the host, even if the parent's tests are being run with
+    /// `CARGO_BUILD_TARGET` set. By default, the binary is built for that
+    /// target too, so that it matches the environment the tests run in eg.
+    /// when cross-compiling tests to run under an emulator.
+    pub fn ignore_parent_target(&mut self) -> &mut Self {
+        self.parent_target = false;
+        self
+    }
+
+    /// The profile the binary will be built with, if it's not the default.
+    fn profile(&self) -> Option<String> {
+        if let Some(profile) = self.forced_profile {
+            return Some(profile.to_owned());
+        }
+        defaults::env_override(PROFILE_ENV).or_else(|| self.profile.map(str::to_owned))
+    }
+
+    /// Whether to build without accessing the network.
+    fn is_offline(&self) -> bool {
+        self.offline || defaults::offline_override()
+    }
+
+    /// Builds with this ABI when building for Windows, whether the target is
+    /// the host, the parent's, or set with [`TestBinary::with_target()`], eg.
+    /// to build with `x86_64-pc-windows-gnu` when the parent is built with
+    /// `x86_64-pc-windows-msvc`. The binary then ends up in a directory for
+    /// that target, which is taken care of, but the target must be installed
+    /// eg. with `rustup target add`.
+    ///
+    /// This has no effect when building for anything other than Windows.
+    pub fn with_windows_abi(&mut self, abi: WindowsAbi) -> &mut Self {
+        self.windows_abi = Some(abi);
+        self
+    }
+
+    /// The target the binary will be built for, if it's not the host.
+    fn target(&self) -> Option<String> {
+        let target = self.requested_target();
+        let Some(abi) = self.windows_abi else {
+            return target;
+        };
+        let base = match &target {
+            Some(target) => target.as_str(),
+            None => export::host_target().ok()?,
+        };
+        abi.apply(base).or(target)
+    }
+
+    /// The target asked for by the environment, the builder or the parent,
+    /// before any [ABI](TestBinary::with_windows_abi) is applied.
+    fn requested_target(&self) -> Option<String> {
+        if let Some(target) = self.forced_target {
+            return Some(target.to_owned());
+        }
+        if let Some(target) = defaults::env_override(TARGET_ENV) {
+            return Some(target);
+        }
+        if let Some(target) = self.target {
+            return Some(target.to_owned());
+        }
+        std::env::var("CARGO_BUILD_TARGET")
+            .ok()
+            .filter(|target| self.parent_target && !target.is_empty())
+    }
+
+    /// Specifies which compiler messages to keep, both in
+    /// [`TestBinaryError::BuildError`] and in [`Artifact::diagnostics()`]. By
+    /// default, everything is kept. Lines of output that aren't compiler
+    /// messages are always kept.
+    pub fn with_diagnostic_level(&mut self, level: DiagnosticLevel) -> &mut Self {
+        self.diagnostics.level = level;
+        self
+    }
+
+    /// Specifies the maximum size in bytes of the compiler messages kept. Any
+    /// more are cut off, with a note saying how many messages weren't shown.
+    pub fn with_diagnostic_limit(&mut self, bytes: usize) -> &mut Self {
+        self.diagnostics.limit = Some(bytes);
+        self
+    }
+
+    /// Whether to report a failed build in a way that GitHub Actions picks
+    /// up: the compiler's messages are printed to stderr in a collapsible log
+    /// group, followed by an `::error` annotation for each error, which
+    /// GitHub shows on the line of code it's about. The error returned is the
+    /// same either way.
+    ///
+    /// By default, this is done when running on GitHub Actions ie. when
+    /// `GITHUB_ACTIONS` is `true`.
+    pub fn with_github_annotations(&mut self, enabled: bool) -> &mut Self {
+        self.github = Some(enabled);
+        self
+    }
+
+    /// Specifies that the binary must already be built and up to date, so that
+    /// building it with Cargo should not compile anything. If anything is
+    /// compiled, [`TestBinaryError::NotFresh`] is returned. This is useful for
+    /// pipelines where test binaries are built in an earlier stage, and a
+    /// rebuild indicates a caching problem.
+    pub fn frozen(&mut self) -> &mut Self {
+        self.frozen = true;
+        self
+    }
+
+    /// Specifies that the built binary's hash should be recorded in or checked
+    /// against `test-binaries.lock` in the parent crate's directory. This can
+    /// catch a test binary's behaviour changing without anyone realising which
+    /// tests depend on it.
+    ///
+    /// Hashes are kept separately for each platform, but they also depend on
+    /// the toolchain and sometimes the build directory (eg. through debug
+    /// info), so it makes most sense to verify in the same environment that
+    /// the hashes were recorded in.
+    pub fn pinned(&mut self, pinning: Pinning) -> &mut Self {
+        self.pinning = Some(pinning);
+        self
+    }
+
+    /// Specifies a callback to report progress to while Cargo is building the
+    /// binary eg. when it's waiting for a lock held by the parent `cargo test`.
+    pub fn on_progress<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: FnMut(&BuildProgress) + 'a,
+    {
+        self.progress = Some(Rc::new(RefCell::new(callback)));
+        self
+    }
+
+    /// Specifies how long to wait for Cargo to build the binary. If it takes
+    /// longer, Cargo is killed and [`TestBinaryError::Timeout`] is returned.
+    pub fn with_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Specifies how long Cargo can go without any output before it's
+    /// considered stalled. Each time it stalls, a [`Stall`] describing what it
+    /// was last doing is reported to the [progress
+    /// callback](TestBinary::on_progress) if there is one, or printed to
+    /// stderr otherwise. Unlike [`TestBinary::with_timeout()`], the build
+    /// carries on regardless.
+    pub fn with_watchdog(&mut self, idle: Duration) -> &mut Self {
+        self.watchdog = Some(idle);
+        self
+    }
+
+    /// Adds arguments that every run of the built [`Artifact`] starts with eg.
+    /// flags that a mock server always needs. See
+    /// [`Artifact::with_default_args()`].
+    pub fn with_default_args<I, S>(&mut self, args: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.run_defaults
+            .args
+            .extend(args.into_iter().map(|arg| arg.as_ref().to_owned()));
+        self
+    }
+
+    /// Sets an environment variable for every run of the built [`Artifact`].
+    /// See [`Artifact::with_default_env()`].
+    pub fn with_default_env<K: AsRef<OsStr>, V: AsRef<OsStr>>(
+        &mut self,
+        key: K,
+        value: V,
+    ) -> &mut Self {
+        self.run_defaults
+            .envs
+            .push((key.as_ref().to_owned(), value.as_ref().to_owned()));
+        self
+    }
+
+    /// Sets the working directory for every run of the built [`Artifact`].
+    /// See [`Artifact::with_default_current_dir()`].
+    pub fn with_default_current_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.run_defaults.current_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
     /// Builds the binary crate we've prepared. This goes through Cargo, so it
     /// should function identically to `cargo build --bin testbin` along with
     /// any additional flags from the builder methods.
+    ///
+    /// Identical builds in the same process are only done once: if another
+    /// `TestBinary` with the same settings is building (or has built) the
+    /// binary, this waits for it and returns the same result, as long as the
+    /// binary's sources haven't changed since.
     pub fn build(&mut self) -> Result<OsString, TestBinaryError> {
-        fn get_cargo_env(key: &str) -> Result<OsString, TestBinaryError> {
-            std::env::var_os(key).ok_or_else(|| {
-                TestBinaryError::NonCargoRun(format!(
-                    "{} '{}' {}",
-                    "The environment variable ", key, "is not set",
-                ))
-            })
-        }
+        self.build_artifact().map(Into::into)
+    }
 
-        let cargo_path = get_cargo_env("CARGO")?;
-        let mut cargo_args = vec_oss![
-            "build",
-            "--message-format=json",
-            "-q",
-            "--manifest-path",
-            self.manifest.clone(),
-            "--bin",
-            self.binary,
-        ];
+    /// Builds the binary crate like [`TestBinary::build()`], but returns an
+    /// [`Artifact`] that can be used to run it.
+    pub fn build_artifact(&mut self) -> Result<Artifact, TestBinaryError> {
+        let wanted = stream::Wanted::Bin(self.binary.to_owned());
+        let (_, artifact) = self
+            .build_wanted(wanted)?
+            .pop()
+            .expect("build produced no binary");
+        Ok(artifact)
+    }
 
-        if let Some(prof) = self.profile {
-            push_oss!(cargo_args, "--profile");
-            push_oss!(cargo_args, prof);
+    /// Builds every binary in the package at once, like `cargo build --bins`,
+    /// returning them by name. The name the `TestBinary` was created with (or
+    /// given to [`TestBinary::with_bin()`]) is ignored.
+    pub fn build_all_bins(&mut self) -> Result<BTreeMap<String, Artifact>, TestBinaryError> {
+        Ok(self
+            .build_wanted(stream::Wanted::AllBins)?
+            .into_iter()
+            .collect())
+    }
+
+    /// Builds what we want, sharing the build with any identical one in the
+    /// process.
+    fn build_wanted(
+        &mut self,
+        wanted: stream::Wanted,
+    ) -> Result<Vec<(String, Artifact)>, TestBinaryError> {
+        let command = self.cargo_command("build", Some(&wanted))?;
+        // Whether Cargo itself will put the binaries in the artifact directory.
+        let placed = command.get_args().any(|arg| arg == "-Zunstable-options");
+        let profile = self.profile();
+        let key = dedup::BuildKey {
+            command: format!("{:?}", command),
+            profile: profile.clone(),
+            frozen: self.frozen,
+            diagnostics: self.diagnostics,
+            pinning: self.pinning,
+            dependencies: self.capture_dependencies,
+            rustc_flags: self.rustc_flags(),
+        };
+
+        // A caller that's watching its own build would see nothing of someone
+        // else's, so it gets one of its own.
+        let watched = self.timeout.is_some()
+            || self.watchdog.is_some()
+            || self.cancel.is_some()
+            || self.progress.is_some()
+            || self.log_file.is_some();
+        // The fingerprint is taken before building, so that changes made
+        // during the build cause another one next time.
+        let fingerprint = if watched || self.unshared {
+            None
+        } else {
+            fingerprint::build_fingerprint(self)
+        };
+        let build = || {
+            let artifacts = self.recorded_build(command, wanted)?;
+            match profile.as_deref() {
+                // Keep a copy, or the next build with the profile that really
+                // owns the directory would replace it.
+                Some(profile) if shares_output_dir(profile) => artifacts
+                    .into_iter()
+                    .map(|(name, artifact)| {
+                        let dir = artifact.output_dir().join(format!("profile-{}", profile));
+                        Ok((name, artifact.install_to(dir)?))
+                    })
+                    .collect(),
+                _ => Ok(artifacts),
+            }
+        };
+        let mut artifacts = match fingerprint {
+            Some(fingerprint) => dedup::shared(key, fingerprint, build)?,
+            None => build()?,
+        };
+        // These don't affect the build, so they can differ between callers.
+        for (_, artifact) in &mut artifacts {
+            artifact.defaults = self.run_defaults.clone();
         }
 
-        if !self.default_features {
-            push_oss!(cargo_args, "--no-default-features");
+        if let Some(dir) = &self.artifact_dir {
+            for (_, artifact) in &mut artifacts {
+                *artifact = if placed {
+                    let file_name = artifact.path().file_name().unwrap_or_default();
+                    artifact.clone().relocated(dir.join(file_name))
+                } else {
+                    artifact.install_to(dir)?
+                };
+            }
         }
 
-        for feature in &self.features {
-            push_oss!(cargo_args, "--features");
-            push_oss!(cargo_args, feature);
+        if self.audit.is_some() {
+            let (dependencies, lock_file) = dependencies::resolve(self)?;
+            if let Some(audit) = &self.audit {
+                for (name, _) in &artifacts {
+                    let input = AuditInput {
+                        name,
+                        lock_file: &lock_file,
+                        dependencies: &dependencies,
+                    };
+                    audit::run(audit, &input)?;
+                }
+            }
+        }
+        for (name, artifact) in &artifacts {
+            self.check_size(name, artifact)?;
         }
 
-        let mut cargo_command = Command::new(cargo_path)
-            .args(cargo_args)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()?;
+        Ok(artifacts)
+    }
 
-        let reader = BufReader::new(
-            // The child process' stdout being None is legitimately a
-            // programming error, since we created it ourselves two lines ago.
-            //
-            // Use as_mut() instead of take() here because if we detach
-            // ownership from the subprocess, we risk letting it drop
-            // prematurely, which can make it close before the subprocess is
-            // finished, resulting in a broken pipe error (but in a highly
-            // timing/platform/performance dependent and intermittent way).
-            cargo_command
-                .stdout
-                .as_mut()
-                .expect("Cargo subprocess output has already been claimed"),
-        );
+    /// Runs the build, appending a record of it to the build log if there is
+    /// one.
+    fn recorded_build(
+        &mut self,
+        command: Command,
+        wanted: stream::Wanted,
+    ) -> Result<Vec<(String, Artifact)>, TestBinaryError> {
+        let Some(build_log) = self.build_log.clone() else {
+            return self.run_build(command, wanted, &mut BuildStats::default());
+        };
 
-        let cargo_outcome = stream::process_messages(reader, self.binary);
+        let started_at = SystemTime::now();
+        let started = Instant::now();
+        let command_line = format!("{:?}", command);
+        let binary = wanted.name();
+        let mut stats = BuildStats::default();
+        let result = self.run_build(command, wanted, &mut stats);
 
-        // See above re. stderr being None.
-        let mut error_reader = BufReader::new(
-            cargo_command
-                .stderr
-                .as_mut()
-                .expect("Cargo subprocess error output has already been claimed"),
-        );
+        let record = BuildRecord {
+            timestamp: record::timestamp(started_at),
+            binary: &binary,
+            manifest: &self.manifest,
+            command: command_line,
+            profile: self.profile(),
+            target: self.target(),
+            features: &self.features,
+            duration_secs: started.elapsed().as_secs_f64(),
+            lock_wait_secs: stats.lock_wait.as_secs_f64(),
+            fresh: stats.compiled.as_ref().map(Vec::is_empty),
+            compiled: stats.compiled,
+            outcome: if result.is_ok() { "success" } else { "error" },
+            error: result.as_ref().err().map(ToString::to_string),
+        };
+        record::append(&build_log, &record)?;
+
+        result
+    }
+
+    fn run_build(
+        &mut self,
+        mut command: Command,
+        wanted: stream::Wanted,
+        stats: &mut BuildStats,
+    ) -> Result<Vec<(String, Artifact)>, TestBinaryError> {
+        let started = Instant::now();
+        command
+            .arg("--message-format=json")
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let rustc_flags = self.rustc_flags();
+        if !rustc_flags.is_empty() {
+            command.arg("--").args(rustc_flags);
+        }
+        if self.cancelled() {
+            return Err(TestBinaryError::Cancelled);
+        }
+        let mut log = match &self.log_file {
+            Some(file) => Some(log_file::BuildLog::open(
+                file,
+                &wanted.name(),
+                &format!("{:?}", command),
+            )?),
+            None => None,
+        };
+        // Declared after the child, so that it's dropped first, once the child
+        // has been waited on.
+        let (mut cargo_command, cargo) = cancel::Cargo::spawn(&mut command, self.cancel.is_some())?;
+
+        // Cargo's output is read on other threads, and passed back here so
+        // that we can watch for Cargo waiting on locks or going quiet, and
+        // give up if it takes too long.
+        let options = stream::MessageOptions {
+            frozen: self.frozen,
+            diagnostics: self.diagnostics,
+            github: self.github.unwrap_or_else(github::detected),
+        };
+        let (outputs, messages) = stream::drain(&mut cargo_command, wanted, options);
 
+        // Cargo isn't quiet, because it only reports waiting for locks and
+        // build script warnings when it isn't. Its status lines are left out
+        // of this though.
         let mut error_msg = String::new();
-        error_reader.read_to_string(&mut error_msg)?;
+        let mut build_script_warnings = vec![];
+        let mut build_script_failure: Option<String> = None;
+        let mut future_incompat_id = None;
+        let mut stdout_line = vec![];
+        let mut last_message = None;
+        let mut waiting_for_lock = None;
+        let mut lock_wait_started = None;
+        let mut last_activity = started;
+        let mut stall_reported = started;
+
+        loop {
+            let deadline = self.timeout.map(|timeout| started + timeout);
+            let stall_check = self
+                .watchdog
+                .map(|idle| last_activity.max(stall_reported) + idle);
+            let cancel_check = self
+                .cancel
+                .as_ref()
+                .map(|_| Instant::now() + cancel::POLL_INTERVAL);
+            let wake = [deadline, stall_check, cancel_check]
+                .into_iter()
+                .flatten()
+                .min();
+
+            let received = match wake {
+                Some(wake) => outputs.recv_timeout(wake.saturating_duration_since(Instant::now())),
+                None => outputs
+                    .recv()
+                    .map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+            };
+
+            let output = match received {
+                Ok(output) => output,
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    let now = Instant::now();
+                    if let (Some(timeout), Some(deadline)) = (self.timeout, deadline) {
+                        if now >= deadline {
+                            if let Some(since) = lock_wait_started {
+                                stats.lock_wait += now - since;
+                            }
+                            cargo.kill(&mut cargo_command)?;
+                            if let Some(log) = &mut log {
+                                log.killed(&format!("timed out after {:?}", timeout));
+                            }
+                            return Err(TestBinaryError::Timeout {
+                                timeout,
+                                waiting_for_lock,
+                            });
+                        }
+                    }
+
+                    if self.cancelled() {
+                        cargo.kill(&mut cargo_command)?;
+                        if let Some(log) = &mut log {
+                            log.killed("the build was cancelled");
+                        }
+                        return Err(TestBinaryError::Cancelled);
+                    }
+
+                    if stall_check.is_none_or(|check| now < check) {
+                        continue;
+                    }
+                    stall_reported = now;
+                    let stall = Stall {
+                        elapsed: now - started,
+                        idle: now - last_activity,
+                        command: format!("{:?}", command),
+                        last_message: last_message.clone(),
+                    };
+                    if self.progress.is_some() {
+                        self.report(BuildProgress::Stalled(stall));
+                    } else {
+                        eprintln!("{}", stall);
+                    }
+                    continue;
+                }
+            };
+
+            last_activity = Instant::now();
+
+            match output {
+                stream::Output::Stdout(bytes) => {
+                    for &byte in &bytes {
+                        if byte == b'\n' {
+                            let line = String::from_utf8_lossy(&stdout_line);
+                            last_message = Some(stream::describe_message(&line));
+                            if let Some(log) = &mut log {
+                                log.stdout(&stdout_line);
+                            }
+                            stdout_line.clear();
+                        } else {
+                            stdout_line.push(byte);
+                        }
+                    }
+                }
+                stream::Output::Stderr(line) => {
+                    let line = line?;
+                    last_message = Some(line.trim().to_owned());
+                    if let Some(log) = &mut log {
+                        log.stderr(&line);
+                    }
+
+                    match stream::lock_wait(&line) {
+                        Some(what) => {
+                            self.report(BuildProgress::WaitingForLock(what.clone()));
+                            waiting_for_lock = Some(what);
+                            lock_wait_started.get_or_insert(last_activity);
+                        }
+                        None => {
+                            if waiting_for_lock.take().is_some() {
+                                self.report(BuildProgress::LockAcquired);
+                            }
+                            if let Some(since) = lock_wait_started.take() {
+                                stats.lock_wait += last_activity - since;
+                            }
+                        }
+                    }
+
+                    if let Some(id) = stream::future_incompat_report_id(&line) {
+                        future_incompat_id = Some(id);
+                    }
+
+                    if let Some(warning) = stream::build_script_warning(&line) {
+                        build_script_warnings.push(warning);
+                    }
+
+                    if let Some(failure) = &mut build_script_failure {
+                        failure.push_str(&line);
+                    } else if stream::is_build_script_failure(&line) {
+                        build_script_failure = Some(line.clone());
+                    }
 
-        if cargo_command.wait()?.success() {
+                    if !stream::is_status_line(&line) {
+                        error_msg.push_str(&line);
+                    }
+                }
+            }
+        }
+
+        let cargo_outcome = messages.join().expect("Cargo output thread panicked");
+        if let Some(since) = lock_wait_started {
+            stats.lock_wait += last_activity - since;
+        }
+        if let Some(Ok(built)) = &cargo_outcome {
+            stats.compiled = Some(built.compiled.clone());
+        }
+
+        let status = cargo_command.wait()?;
+        if let Some(log) = &mut log {
+            if !stdout_line.is_empty() {
+                log.stdout(&stdout_line);
+            }
+            log.exited(status);
+        }
+        if let Some(killed) = TestBinaryError::killed(status, &error_msg) {
+            // Whatever was extracted from the JSON output is incomplete.
+            Err(killed)
+        } else if status.success() {
             // The process succeeded. There should be a result from the JSON
             // output above.
-            cargo_outcome
-                .expect("Cargo succeeded but produced no output")
-                .map(Into::into)
+            let built = cargo_outcome.expect("Cargo succeeded but produced no output")?;
+
+            let target = self.target();
+            let dependencies = if self.capture_dependencies {
+                Some(dependencies::resolve(self)?.0)
+            } else {
+                None
+            };
+            let future_incompat_report = match future_incompat_id {
+                Some(id) if self.future_incompat => Some(self.future_incompat_report(id)?),
+                _ => None,
+            };
+            let mut artifacts = vec![];
+            for bin in built.bins {
+                let name = bin.name;
+                let mut artifact = Artifact::new(bin.path.into_std_path_buf());
+                artifact.diagnostics = built.diagnostics.clone();
+                artifact.build_script_warnings = build_script_warnings.clone();
+                artifact.dependencies = dependencies.clone();
+                artifact.future_incompat_report = future_incompat_report.clone();
+                artifact.target = match &target {
+                    Some(target) => Some(target.clone()),
+                    None => export::host_target().ok().map(str::to_owned),
+                };
+                if let Some(script) = bin.build_script {
+                    artifact.out_dir = Some(script.out_dir.into_std_path_buf());
+                    artifact.rustc_env = script.env;
+                }
+
+                if let Some(pinning) = self.pinning {
+                    let lock_file = manifest_dir()?.join(pin::LOCK_FILE);
+                    pin::check(
+                        &lock_file,
+                        &name,
+                        artifact.path(),
+                        target.as_deref(),
+                        pinning,
+                    )?;
+                }
+
+                artifacts.push((name, artifact));
+            }
+
+            Ok(artifacts)
+        } else if let Some(Err(TestBinaryError::BuildError(mut msg))) = cargo_outcome {
+            // The process failed and there are compiler errors we extracted
+            // from the JSON output. If it was the build script that failed
+            // though, there won't be any, so add what Cargo said about it.
+            for warning in &build_script_warnings {
+                msg.push_str(&format!("build script warning: {}\n", warning));
+            }
+            if let Some(failure) = build_script_failure {
+                msg.push_str(&failure);
+            }
+            Err(TestBinaryError::BuildError(msg))
         } else if let Some(Err(err)) = cargo_outcome {
-            // The process failed and there's an error we extracted from the
-            // JSON output. Usually this means a compiler error.
+            // The process failed and there's some other error we extracted
+            // from the JSON output.
             Err(err)
         } else {
             // The process failed but there's no error from the JSON output.
@@ -337,6 +1615,231 @@ impl<'a> TestBinary<'a> {
             Err(TestBinaryError::CargoFailure(error_msg))
         }
     }
+
+    /// Runs Clippy on the binary, like `cargo clippy --bin testbin` along with
+    /// any additional flags from the builder methods, and returns what it
+    /// found. This lets test binaries be linted along with the parent, even
+    /// though they're not in its workspace.
+    ///
+    /// Compiler errors (including lints that are denied) are returned along
+    /// with everything else, so check [`Lint::is_error()`] if the binary needs
+    /// to build. An error is only returned if Clippy couldn't be run at all.
+    pub fn clippy(&mut self) -> Result<Vec<Lint>, TestBinaryError> {
+        let wanted = stream::Wanted::Bin(self.binary.to_owned());
+        let output = self
+            .cargo_command("clippy", Some(&wanted))?
+            .arg("--message-format=json")
+            .output()?;
+
+        lint::parse(&output.stdout).ok_or_else(|| {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            TestBinaryError::killed(output.status, &stderr)
+                .unwrap_or_else(|| TestBinaryError::CargoFailure(stderr.into_owned()))
+        })
+    }
+
+    /// Checks that the binary compiles, like `cargo check --bin testbin` along
+    /// with any additional flags from the builder methods, and returns the
+    /// compiler's messages. No binary is produced, so this is much quicker
+    /// than building, eg. for a test that makes sure every helper still
+    /// compiles.
+    ///
+    /// As with [`TestBinary::clippy()`], compiler errors are returned along
+    /// with warnings, so check [`Lint::is_error()`]. An error is only
+    /// returned if Cargo couldn't be run at all.
+    pub fn check(&mut self) -> Result<Vec<Lint>, TestBinaryError> {
+        let wanted = stream::Wanted::Bin(self.binary.to_owned());
+        let output = self
+            .cargo_command("check", Some(&wanted))?
+            .arg("--message-format=json")
+            .output()?;
+
+        lint::parse(&output.stdout).ok_or_else(|| {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            TestBinaryError::killed(output.status, &stderr)
+                .unwrap_or_else(|| TestBinaryError::CargoFailure(stderr.into_owned()))
+        })
+    }
+
+    /// Runs the test binary's own tests with `cargo test`, for the whole
+    /// package, with any additional flags from the builder methods. This is
+    /// for test binaries complex enough to need tests of their own, since
+    /// they're not in the parent's workspace and won't otherwise be tested.
+    ///
+    /// Test failures (and build failures) are reported in the result rather
+    /// than as an error. An error is only returned if Cargo couldn't be run at
+    /// all.
+    pub fn run_child_tests(&mut self) -> Result<ChildTests, TestBinaryError> {
+        let output = self.cargo_command("test", None)?.output()?;
+        Ok(ChildTests::new(output))
+    }
+
+    /// A Cargo command for the binaries we want (or the whole package), with
+    /// all the flags and environment from the builder methods.
+    fn cargo_command(
+        &self,
+        subcommand: &str,
+        wanted: Option<&stream::Wanted>,
+    ) -> Result<Command, TestBinaryError> {
+        self.check_platform()?;
+        let mut command = self.cargo()?;
+        let building = subcommand == "build";
+        // The flags themselves go at the very end, after `--`.
+        let subcommand = if building && !self.rustc_flags().is_empty() {
+            "rustc"
+        } else {
+            subcommand
+        };
+        let mut cargo_args = vec_oss![subcommand, "--manifest-path", self.manifest.clone()];
+
+        match wanted {
+            Some(stream::Wanted::Bin(name)) => {
+                push_oss!(cargo_args, "--bin");
+                push_oss!(cargo_args, name);
+            }
+            Some(stream::Wanted::AllBins) => push_oss!(cargo_args, "--bins"),
+            None => {}
+        }
+
+        if let Some(prof) = self.profile() {
+            push_oss!(cargo_args, "--profile");
+            push_oss!(cargo_args, prof);
+        }
+
+        if let Some(target) = self.target() {
+            push_oss!(cargo_args, "--target");
+            push_oss!(cargo_args, target);
+        }
+
+        let variant_dir = match building {
+            true => self.variant_target_dir()?,
+            false => None,
+        };
+        if let Some(dir) = variant_dir.as_ref().or(self.target_dir.as_ref()) {
+            push_oss!(cargo_args, "--target-dir");
+            push_oss!(cargo_args, dir);
+        }
+
+        if self.is_offline() {
+            push_oss!(cargo_args, "--offline");
+        }
+
+        if self.future_incompat && building {
+            push_oss!(cargo_args, "--future-incompat-report");
+        }
+
+        if let Some(dir) = self.artifact_dir.as_ref().filter(|_| building) {
+            if let Some(flag) = artifact_dir::flag(command.get_program()) {
+                push_oss!(cargo_args, "-Zunstable-options");
+                push_oss!(cargo_args, flag);
+                push_oss!(cargo_args, dir);
+            }
+        }
+
+        if let Some(jobs) = self.jobs {
+            push_oss!(cargo_args, "--jobs");
+            push_oss!(cargo_args, jobs.to_string());
+        }
+
+        if !self.default_features {
+            push_oss!(cargo_args, "--no-default-features");
+        }
+
+        for feature in &self.features {
+            push_oss!(cargo_args, "--features");
+            push_oss!(cargo_args, feature);
+        }
+
+        command
+            .args(cargo_args)
+            .envs(self.build_script_envs.iter().map(|(k, v)| (k, v)))
+            // Cargo would pick this up itself otherwise.
+            .env_remove("CARGO_BUILD_TARGET");
+        if self.deny_warnings {
+            let (key, flags) = deny_warnings_flags();
+            command.env(key, flags);
+        }
+        if let Some(incremental) = self.incremental {
+            command.env("CARGO_INCREMENTAL", if incremental { "1" } else { "0" });
+        }
+        #[cfg(unix)]
+        let command = self.scheduling.apply(command);
+        Ok(command)
+    }
+
+    /// Fails with [`TestBinaryError::Unsupported`] if the binary doesn't
+    /// support the target it would be built for.
+    fn check_platform(&self) -> Result<(), TestBinaryError> {
+        let platforms = match &self.platforms {
+            Some(platforms) => platforms.iter().map(|p| p.to_string()).collect(),
+            None => match platform::declared(&self.manifest)? {
+                Some(platforms) => platforms,
+                None => return Ok(()),
+            },
+        };
+        let target = match self.target() {
+            Some(target) => target,
+            None => export::host_target()?.to_owned(),
+        };
+        if platforms
+            .iter()
+            .any(|platform| platform::supports(platform, &target))
+        {
+            return Ok(());
+        }
+        Err(TestBinaryError::Unsupported {
+            reason: format!(
+                "{:?} only supports {}, not {}",
+                self.binary,
+                platforms.join(", "),
+                target
+            ),
+        })
+    }
+
+    /// A command to run the right Cargo, with the right toolchain, but no
+    /// arguments yet.
+    fn cargo(&self) -> Result<Command, TestBinaryError> {
+        let Some(toolchain) = self.toolchain()? else {
+            return Ok(Command::new(self.cargo_path()?));
+        };
+        let mut command = Command::new(toolchain.which("cargo")?);
+        // Otherwise Cargo would use whichever rustc the parent's toolchain put
+        // first.
+        command
+            .env("RUSTUP_TOOLCHAIN", &toolchain.channel)
+            .env("RUSTC", toolchain.which("rustc")?);
+        Ok(command)
+    }
+
+    /// Gets the future-incompatibility report that Cargo stored with the given
+    /// ID, from the child's target directory.
+    fn future_incompat_report(&self, id: u32) -> Result<String, TestBinaryError> {
+        let mut command = self.cargo()?;
+        command
+            .args(["report", "future-incompatibilities", "--id"])
+            .arg(id.to_string())
+            // It finds the package from the working directory.
+            .current_dir(self.manifest.parent().unwrap_or(Path::new(".")));
+        if let Some(dir) = &self.target_dir {
+            // Relative to our working directory, not the package's.
+            command.env("CARGO_TARGET_DIR", std::env::current_dir()?.join(dir));
+        }
+
+        let output = command.output()?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(TestBinaryError::killed(output.status, &stderr)
+                .unwrap_or_else(|| TestBinaryError::CargoFailure(stderr.into_owned())));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    pub(crate) fn report(&mut self, progress: BuildProgress) {
+        if let Some(callback) = &self.progress {
+            (callback.borrow_mut())(&progress);
+        }
+    }
 }
 
 /// Simplified function for building a test binary where the binary is in a
@@ -347,15 +1850,228 @@ impl<'a> TestBinary<'a> {
 /// `testbins/does-build`, and the binary is named `does-build` in its
 /// `Cargo.toml`, then you can just call `build_test_binary("does_build",
 /// "testbins")`.
+///
+/// If there's no such subdirectory but there is a single source file named
+/// after the binary eg. `testbins/does-build.rs`, that's built instead, as with
+/// [`TestBinary::from_single_file()`].
 pub fn build_test_binary<R: AsRef<Path>>(
     name: &str,
     directory: R,
 ) -> Result<OsString, TestBinaryError> {
-    TestBinary::relative_to_parent(
-        name,
-        &PathBuf::from_iter([directory.as_ref(), name.as_ref(), "Cargo.toml".as_ref()]),
-    )?
-    .build()
+    TestBinary::in_directory(name, directory.as_ref())?.build()
+}
+
+/// Like [`build_test_binary()`], but lets `configure` set any other options
+/// on the builder first, for when the layout is simple but the defaults aren't
+/// quite right.
+///
+/// ```rust
+/// # use test_binary::build_test_binary_with;
+/// let path = build_test_binary_with("feature-test", "testbins", |b| {
+///     b.no_default_features().with_feature("working")
+/// })
+/// .unwrap();
+/// ```
+pub fn build_test_binary_with<'a, R, F>(
+    name: &'a str,
+    directory: R,
+    configure: F,
+) -> Result<OsString, TestBinaryError>
+where
+    R: AsRef<Path>,
+    F: for<'b> FnOnce(&'b mut TestBinary<'a>) -> &'b mut TestBinary<'a>,
+{
+    configure(&mut TestBinary::in_directory(name, directory.as_ref())?).build()
+}
+
+/// Like [`build_test_binary()`], but for when the package's subdirectory
+/// doesn't have the same name as the binary, or the package has more than one
+/// binary.
+///
+/// For example, if your parent contains the child package in
+/// `testbins/mock_server`, and it has a binary named `mock-server`, then you
+/// can call `build_test_binary_in("testbins", "mock_server", "mock-server")`.
+pub fn build_test_binary_in<R: AsRef<Path>, P: AsRef<Path>>(
+    directory: R,
+    package_dir: P,
+    bin_name: &str,
+) -> Result<OsString, TestBinaryError> {
+    let manifest = PathBuf::from_iter([
+        manifest_dir()?.as_path(),
+        directory.as_ref(),
+        package_dir.as_ref(),
+        "Cargo.toml".as_ref(),
+    ]);
+    TestBinary::with_manifest(bin_name, manifest).build()
+}
+
+/// Like [`build_test_binary()`], but looks for the binary in each of
+/// `directories` in turn, eg. `["testbins", "tests/helpers"]` while moving
+/// test binaries from one to the other. Returns the path to the binary and
+/// the directory it was found in. See [`find_test_binary_dir()`].
+pub fn build_test_binary_in_dirs<I, P>(
+    name: &str,
+    directories: I,
+) -> Result<(OsString, PathBuf), TestBinaryError>
+where
+    I: IntoIterator<Item = P>,
+    P: AsRef<Path>,
+{
+    let directory = find_test_binary_dir(name, directories)?;
+    let path = build_test_binary(name, &directory)?;
+    Ok((path, directory))
+}
+
+/// Finds the first of `directories` (relative to the parent) that has the
+/// binary in it, the way [`build_test_binary()`] expects: either as a package
+/// in `<directory>/<name>` or a single file `<directory>/<name>.rs`. Returns
+/// [`ManifestError::PackageNotFound`] if none of them do.
+pub fn find_test_binary_dir<I, P>(name: &str, directories: I) -> Result<PathBuf, TestBinaryError>
+where
+    I: IntoIterator<Item = P>,
+    P: AsRef<Path>,
+{
+    let parent = manifest_dir()?;
+    directories
+        .into_iter()
+        .map(|directory| directory.as_ref().to_owned())
+        .find(|directory| {
+            let package = directory.join(name).join("Cargo.toml");
+            let single_file = directory.join(format!("{}.rs", name));
+            parent.join(package).exists() || parent.join(single_file).exists()
+        })
+        .ok_or_else(|| ManifestError::PackageNotFound(name.to_owned()).into())
+}
+
+/// The environment variable and flags for Cargo to deny warnings, adding to
+/// whatever flags are already set. Cargo prefers `CARGO_ENCODED_RUSTFLAGS`
+/// (which is separated by `0x1f`) to `RUSTFLAGS` if it's set, so we do too.
+fn deny_warnings_flags() -> (&'static str, OsString) {
+    let (key, separator) = match std::env::var_os("CARGO_ENCODED_RUSTFLAGS") {
+        Some(_) => ("CARGO_ENCODED_RUSTFLAGS", "\u{1f}"),
+        None => ("RUSTFLAGS", " "),
+    };
+
+    let mut flags = std::env::var_os(key).unwrap_or_default();
+    for flag in ["-D", "warnings"] {
+        if !flags.is_empty() {
+            flags.push(separator);
+        }
+        flags.push(flag);
+    }
+    (key, flags)
+}
+
+/// Builds a test binary like [`build_test_binary()`], runs it with the given
+/// arguments, and returns its output. This is for the common case where you
+/// just need to run the binary once and check what it printed or how it
+/// exited.
+///
+/// Like any other build, this is shared with identical ones in the same
+/// process, so the binary is only built again if its sources change. The
+/// output is returned whether or not the binary succeeded; check
+/// [`std::process::Output::status`].
+///
+/// ```rust
+/// # use test_binary::run_test_binary;
+/// let output = run_test_binary("does-build", "testbins", ["--help"]).unwrap();
+/// assert!(output.status.success());
+/// ```
+pub fn run_test_binary<R, I, S>(
+    name: &str,
+    directory: R,
+    args: I,
+) -> Result<std::process::Output, TestBinaryError>
+where
+    R: AsRef<Path>,
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let path = build_test_binary(name, directory)?;
+    Ok(Command::new(path)
+        .args(args)
+        .output()
+        .map_err(RunError::from)?)
+}
+
+/// Builds several test binaries up front, like [`build_test_binary()`],
+/// reporting progress on stderr. This is meant to be called once before any
+/// tests run eg. from the `main` of a custom test harness, so that no test has
+/// to wait (and maybe time out) while a binary is compiled.
+///
+/// Later builds of the same binaries with the same settings in this process,
+/// including through [`build_test_binary()`] and
+/// [`build_test_binary_once!()`], reuse these builds rather than running
+/// Cargo again. The paths are also returned, in case they're useful.
+///
+/// ```rust
+/// # use test_binary::prewarm;
+/// let paths = prewarm(&["does-build", "multiple"], "testbins").unwrap();
+/// assert!(paths.get("multiple").is_some());
+/// ```
+pub fn prewarm<R: AsRef<Path>>(
+    names: &[&str],
+    directory: R,
+) -> Result<BinaryPaths, TestBinaryError> {
+    let mut paths = BinaryPaths::new();
+
+    for (i, name) in names.iter().enumerate() {
+        eprintln!("Building test binary {} ({}/{})", name, i + 1, names.len());
+        let started = Instant::now();
+
+        let path = TestBinary::in_directory(name, directory.as_ref())?
+            .on_progress(|progress| {
+                if let BuildProgress::WaitingForLock(what) = progress {
+                    eprintln!("Waiting for lock on {}", what);
+                }
+            })
+            .build()?;
+
+        eprintln!("Built test binary {} in {:.1?}", name, started.elapsed());
+        paths.insert(*name, path);
+    }
+
+    Ok(paths)
+}
+
+/// Whether Cargo puts binaries built with `profile` in another profile's
+/// directory: `test` shares `debug` with `dev`, and `bench` shares `release`.
+/// Every other profile, including custom ones, has a directory of its own.
+fn shares_output_dir(profile: &str) -> bool {
+    matches!(profile, "test" | "bench")
+}
+
+/// The profile the parent's tests were built with, or `None` for the
+/// default. See [`TestBinary::with_profile_matching_parent()`].
+fn parent_profile() -> Option<&'static str> {
+    static PROFILE: OnceCell<Option<String>> = OnceCell::new();
+    PROFILE
+        .get_or_init(|| {
+            let from_exe = std::env::current_exe()
+                .ok()
+                .and_then(|exe| profile_of_test_exe(&exe));
+            match from_exe {
+                Some(profile) => profile,
+                None if cfg!(debug_assertions) => None,
+                None => Some("release".to_owned()),
+            }
+        })
+        .as_deref()
+}
+
+/// The profile of a test executable from its path, which is
+/// `<target dir>/[<target>/]<profile dir>/deps/<name>-<hash>`. The outer
+/// `None` means it's not in a profile directory at all, and the inner one
+/// means the default profile.
+fn profile_of_test_exe(exe: &Path) -> Option<Option<String>> {
+    let deps = exe.parent()?;
+    if deps.file_name()? != "deps" {
+        return None;
+    }
+    match deps.parent()?.file_name()?.to_str()? {
+        "debug" => Some(None),
+        dir => Some(Some(dir.to_owned())),
+    }
 }
 
 fn manifest_dir() -> Result<PathBuf, ManifestError> {
@@ -367,33 +2083,41 @@ fn manifest_dir() -> Result<PathBuf, ManifestError> {
 }
 
 /// Locates package in current workspace.
-/// Returns path to Cargo.toml defining package that will produce desired binary.
+/// Returns path to Cargo.toml defining package that will produce desired binary,
+/// or failing that, the package with that name.
 fn find_package(bin: &str) -> Result<PathBuf, ManifestError> {
-    let manifest_dir = manifest_dir()?;
-    let manifest_path = manifest_dir.join("Cargo.toml");
-    let manifest = cargo_metadata::MetadataCommand::new()
-        .manifest_path(&manifest_path)
-        .exec()
-        .map_err(|e| ManifestError::ReadManifest(manifest_dir.to_path_buf(), e.to_string()))?;
-
-    let workspace_manifest = manifest.workspace_root.join("Cargo.toml");
-    let workspace = cargo_metadata::MetadataCommand::new()
-        .manifest_path(&workspace_manifest)
-        .exec()
-        .map_err(|e| {
-            ManifestError::ReadManifest(workspace_manifest.into_std_path_buf(), e.to_string())
-        })?;
-
-    for id in &workspace.workspace_members {
-        let package = workspace.index(id);
-        if package.name == bin {
-            return Ok(package.manifest_path.clone().into_std_path_buf());
-        }
+    let workspace = workspace::metadata(&manifest_dir()?)?;
+
+    let members = || {
+        workspace
+            .workspace_members
+            .iter()
+            .map(|id| workspace.index(id))
+    };
+
+    // Prefer a package with a binary target of that name, since the package
+    // itself might be named differently.
+    let package = members()
+        .find(|package| {
+            package
+                .targets
+                .iter()
+                .any(|target| target.name == bin && target.kind.iter().any(|kind| kind == "bin"))
+        })
+        .or_else(|| members().find(|package| package.name == bin));
+
+    if let Some(package) = package {
+        return Ok(package.manifest_path.clone().into_std_path_buf());
     }
     Err(ManifestError::PackageNotFound(bin.to_string()))
 }
 
 /// Error type for build result.
+///
+/// This and the errors it wraps implement `Serialize`, for logging and
+/// aggregating them in structured form: as a map with the variant's name as
+/// `kind`, its fields, its message as `message`, and any wrapped error as
+/// `source`.
 #[derive(thiserror::Error, Debug)]
 pub enum TestBinaryError {
     /// We are not running under Cargo.
@@ -405,6 +2129,17 @@ pub enum TestBinaryError {
     /// Cargo ran but did not succeed.
     #[error("Cargo failed, stderr: {0}")]
     CargoFailure(String),
+    /// Cargo was killed before it finished, usually by a signal eg. from the
+    /// OOM killer when too many jobs are building at once. Unlike a
+    /// [`CargoFailure`](Self::CargoFailure), trying again (perhaps with fewer
+    /// [jobs](TestBinary::with_jobs)) might well succeed.
+    #[error("Cargo was killed ({status}), stderr: {stderr}")]
+    CargoKilled {
+        /// How Cargo exited.
+        status: ExitStatus,
+        /// What Cargo printed before it was killed.
+        stderr: String,
+    },
     /// Cargo ran but there was a compilation error.
     #[error("build error:\n{0}")]
     BuildError(String),
@@ -412,9 +2147,118 @@ pub enum TestBinaryError {
     /// in its build output.
     #[error(r#"could not find binary "{0}" in Cargo output"#)]
     BinaryNotBuilt(String),
+    /// The binary was built in [frozen](TestBinary::frozen) mode, but Cargo
+    /// had to compile something. These are the names of the targets it
+    /// compiled.
+    #[error("test binary was not fresh, Cargo compiled: {}", .0.join(", "))]
+    NotFresh(Vec<String>),
     /// Error processing manifests.
     #[error("manifest error: {0}")]
     ManifestError(#[from] ManifestError),
+    /// Error recording or verifying the binary's pinned hash.
+    #[error("pinning error: {0}")]
+    PinError(#[from] PinError),
+    /// Cargo didn't finish within the timeout given to
+    /// [`TestBinary::with_timeout()`].
+    #[error("Cargo did not finish within {timeout:?}{}", lock_note(.waiting_for_lock))]
+    Timeout {
+        /// The timeout.
+        timeout: Duration,
+        /// If Cargo was blocked waiting for a file lock when it timed out,
+        /// what it was waiting for.
+        waiting_for_lock: Option<String>,
+    },
+    /// The build was cancelled with the token given to
+    /// [`TestBinary::with_cancel_token()`].
+    #[error("build was cancelled")]
+    Cancelled,
+    /// The binary was built, but running it failed.
+    #[error("error running test binary: {0}")]
+    RunError(#[from] RunError),
+    /// The binary's package has a toolchain file, but the toolchain it asks
+    /// for can't be used, usually because it isn't installed.
+    #[error("toolchain {toolchain:?} from {} can't be used: {reason}", .file.display())]
+    ToolchainUnavailable {
+        /// The toolchain that was asked for.
+        toolchain: String,
+        /// The toolchain file.
+        file: PathBuf,
+        /// Why it can't be used.
+        reason: String,
+    },
+    /// The binary was built, but the check given to
+    /// [`TestBinary::with_audit()`] found a problem with its dependencies.
+    #[error(r#"dependencies of test binary "{name}" failed audit: {findings}"#)]
+    AuditFailed {
+        /// The binary name.
+        name: String,
+        /// What the audit found.
+        findings: String,
+    },
+    /// `lipo` failed to combine the binaries built by
+    /// [`TestBinary::build_universal()`].
+    #[error("lipo failed, stderr: {0}")]
+    LipoFailure(String),
+    /// The binary is bigger than the budget given to
+    /// [`TestBinary::with_size_budget()`].
+    #[error(r#"test binary "{name}" is {size} bytes, over its budget of {budget}"#)]
+    OverSizeBudget {
+        /// The binary name.
+        name: String,
+        /// Its size in bytes.
+        size: u64,
+        /// The budget in bytes.
+        budget: u64,
+    },
+    /// The binary doesn't support the platform it would be built for. See
+    /// [`TestBinary::with_platforms()`].
+    #[error("test binary is not supported here: {reason}")]
+    Unsupported {
+        /// Which platforms the binary supports, and which it would have been
+        /// built for.
+        reason: String,
+    },
+    /// Error watching the binary's sources for changes, in
+    /// [`TestBinary::watch()`].
+    #[cfg(feature = "watch")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "watch")))]
+    #[error("error watching for changes: {0}")]
+    WatchError(#[from] notify::Error),
+}
+
+impl TestBinaryError {
+    /// How Cargo exited, if it was [killed](Self::CargoKilled).
+    pub fn cargo_status(&self) -> Option<ExitStatus> {
+        match self {
+            Self::CargoKilled { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    /// The signal that [killed](Self::CargoKilled) Cargo, eg. 9 for
+    /// `SIGKILL`.
+    #[cfg(unix)]
+    #[cfg_attr(docsrs, doc(cfg(unix)))]
+    pub fn cargo_signal(&self) -> Option<i32> {
+        std::os::unix::process::ExitStatusExt::signal(&self.cargo_status()?)
+    }
+
+    /// The error for Cargo exiting with `status` having printed `stderr`, if
+    /// it didn't exit by itself.
+    fn killed(status: ExitStatus, stderr: &str) -> Option<Self> {
+        // Only a signal leaves no exit code.
+        status.code().is_none().then(|| Self::CargoKilled {
+            status,
+            stderr: stderr.to_owned(),
+        })
+    }
+}
+
+fn lock_note(waiting_for_lock: &Option<String>) -> String {
+    match waiting_for_lock {
+        Some(what) => format!(" (blocked waiting for file lock on {})", what),
+        None => String::new(),
+    }
 }
 
 /// Error during reading manifests.
@@ -426,6 +2270,12 @@ pub enum ManifestError {
     /// Error when reading manifest.
     #[error("Error reading manifest: {}. {1}", .0.display())]
     ReadManifest(PathBuf, String),
+    /// Error when writing the manifest for a single-file binary.
+    #[error("Error writing manifest: {}. {1}", .0.display())]
+    WriteManifest(PathBuf, String),
+    /// A pattern given to [`TestBinary::from_globs()`] isn't a valid glob.
+    #[error("Invalid pattern `{0}`: {1}")]
+    InvalidPattern(String, String),
     /// Can't query path to manifest of current crate.
     #[error("ENV variable `CARGO_MANIFEST_DIR` is not set. Error: {0}")]
     EnvNotSet(String),
@@ -486,23 +2336,126 @@ pub enum ManifestError {
 ///     .success());
 /// ```
 ///
+/// If the test binary depends on the parent crate by path, the parent's sources
+/// are fingerprinted when it's built, and it's built again if they have changed
+/// since. This keeps the binary up to date in a process that stays alive while
+/// the parent is edited eg. a watch loop.
+///
+/// Like [`build_test_binary_in_dirs()`], the directory can also be a list of
+/// directories to look in, in order:
+///
+/// ```rust
+/// # use test_binary::build_test_binary_once;
+/// build_test_binary_once!(multiple, ["tests/helpers", "testbins"]);
+/// # path_to_multiple();
+/// ```
+///
 /// If you need to use extra features or a non-default profile, you will need to
 /// go back to using the builder.
 #[macro_export]
 macro_rules! build_test_binary_once {
+    ($name:ident, [$($tests_dir:expr),+ $(,)?]) => {
+        $crate::build_test_binary_once!(
+            $name,
+            $crate::find_test_binary_dir(stringify!($name), [$($tests_dir),+]).unwrap()
+        );
+    };
     ($name:ident, $tests_dir:expr) => {
         $crate::paste::paste! {
             pub fn [<path_to_ $name>]() -> std::ffi::OsString {
-                use $crate::once_cell::sync::Lazy;
-                use std::ffi::OsString;
-
-                static [<LAZY_PATH_TO_ $name>]: Lazy<OsString> =
-                    Lazy::new(|| $crate::build_test_binary(
-                        stringify!($name),
-                        $tests_dir
-                    ).unwrap());
-                [<LAZY_PATH_TO_ $name>].clone()
+                static [<BUILD_ $name:upper>]: $crate::OnceBuild = $crate::OnceBuild::new();
+                [<BUILD_ $name:upper>].path(stringify!($name), $tests_dir)
             }
         }
     };
 }
+
+/// Compares a [`RunOutput`] against an [insta] snapshot, after normalizing it
+/// with [`RunOutput::snapshot()`] so that it doesn't change from run to run.
+/// This is only available with the `insta` feature.
+///
+/// As with insta's own macros, the snapshot can be named, and is stored in
+/// the `snapshots` directory next to the test. A [`Normalizer`] can be given
+/// for extra replacements, otherwise the default one is used.
+///
+/// ```rust,no_run
+/// # use test_binary::{assert_output_snapshot, build_test_binary, Artifact, Normalizer};
+/// let actions = Artifact::from(build_test_binary("actions", "testbins").unwrap());
+/// let output = actions.runner().args(["print", "fla"]).run().unwrap();
+///
+/// assert_output_snapshot!(output);
+/// assert_output_snapshot!("print_fla", output);
+/// assert_output_snapshot!("print_fla_redacted", output, Normalizer::new().replace("fla", "[FLA]"));
+/// ```
+///
+///   [insta]: https://insta.rs
+#[cfg(feature = "insta")]
+#[cfg_attr(docsrs, doc(cfg(feature = "insta")))]
+#[macro_export]
+macro_rules! assert_output_snapshot {
+    ($output:expr $(,)?) => {
+        $crate::assert_output_snapshot!(::std::option::Option::None::<&str>, $output)
+    };
+    ($name:expr, $output:expr $(,)?) => {
+        $crate::assert_output_snapshot!($name, $output, $crate::Normalizer::new())
+    };
+    ($name:expr, $output:expr, $normalizer:expr $(,)?) => {
+        $crate::insta::assert_snapshot!(
+            $name,
+            $crate::RunOutput::snapshot(&$output, &$normalizer),
+            ::std::stringify!($output)
+        )
+    };
+}
+
+/// Get a test binary from an [artifact dependency][bindeps] if Cargo provides
+/// one, or build it like [`build_test_binary()`] otherwise.
+///
+/// Cargo's unstable artifact dependencies (`-Z bindeps`) let a crate depend on
+/// another package's binaries, with Cargo building them beforehand and passing
+/// their paths in `CARGO_BIN_FILE_<DEP>_<name>` environment variables at
+/// compile time. When your tests are built that way, this macro uses the
+/// binary Cargo built rather than invoking a nested build. Otherwise eg. on a
+/// stable toolchain, it falls back to building the binary in the directory
+/// given.
+///
+/// `artifact_dependency!(dep, "name", "tests_dir")` takes:
+///
+/// - `dep`, the name of the dependency (no quotes) with any `-` replaced by
+///   `_`; it's upper-cased to get the environment variable name, as Cargo does
+/// - `"name"`, the name of the binary
+/// - `"tests_dir"`, the directory to find the binary in for a regular build
+///
+/// So with a dependency like this (which needs a nightly toolchain and the
+/// `bindeps` feature):
+///
+/// ```toml
+/// [dev-dependencies]
+/// does-build = { path = "testbins/does-build", artifact = "bin" }
+/// ```
+///
+/// you'd use:
+///
+/// ```rust
+/// # use test_binary::artifact_dependency;
+/// let artifact = artifact_dependency!(does_build, "does-build", "testbins").unwrap();
+/// ```
+///
+///   [bindeps]: https://doc.rust-lang.org/nightly/cargo/reference/unstable.html#artifact-dependencies
+#[macro_export]
+macro_rules! artifact_dependency {
+    ($dep:ident, $name:literal, $tests_dir:expr) => {
+        $crate::paste::paste! {
+            $crate::artifact_dependency_or_build(
+                ::std::option_env!(::std::concat!(
+                    "CARGO_BIN_FILE_",
+                    ::std::stringify!([<$dep:upper>]),
+                    "_",
+                    $name
+                )),
+                $name,
+                $tests_dir,
+            )
+        }
+    };
+}
@@ -0,0 +1,140 @@
+//! Linting test binaries with Clippy.
+
+use cargo_metadata::{
+    diagnostic::{Diagnostic, DiagnosticLevel as RustcLevel},
+    Message,
+};
+use std::{fmt, path::Path};
+
+/// A message from Clippy (or the compiler) about a test binary, from
+/// [`TestBinary::clippy()`](crate::TestBinary::clippy) or
+/// [`TestBinary::check()`](crate::TestBinary::check).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lint {
+    level: String,
+    code: Option<String>,
+    message: String,
+    rendered: String,
+    location: Option<(String, usize)>,
+}
+
+impl Lint {
+    fn new(diagnostic: Diagnostic) -> Self {
+        let location = diagnostic
+            .spans
+            .iter()
+            .find(|span| span.is_primary)
+            .map(|span| (span.file_name.clone(), span.line_start));
+
+        let rendered = diagnostic.to_string();
+
+        Self {
+            // This is how the level is spelled in Cargo's output.
+            level: serde_json::to_value(diagnostic.level)
+                .ok()
+                .and_then(|level| level.as_str().map(str::to_owned))
+                .unwrap_or_default(),
+            code: diagnostic.code.map(|code| code.code),
+            message: diagnostic.message,
+            rendered,
+            location,
+        }
+    }
+
+    /// The level of the message eg. `warning` or `error`.
+    pub fn level(&self) -> &str {
+        &self.level
+    }
+
+    /// Whether the message is an error, which fails the build.
+    pub fn is_error(&self) -> bool {
+        self.level == "error" || self.level == "error: internal compiler error"
+    }
+
+    /// The name of the lint eg. `clippy::len_zero` or `unused_variables`, or
+    /// the error code for compiler errors. Not all messages have one.
+    pub fn code(&self) -> Option<&str> {
+        self.code.as_deref()
+    }
+
+    /// The message, without any context.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The source file the message is about, relative to the child package.
+    pub fn file(&self) -> Option<&Path> {
+        self.location.as_ref().map(|(file, _)| Path::new(file))
+    }
+
+    /// The line (starting from 1) of the source file the message is about.
+    pub fn line(&self) -> Option<usize> {
+        self.location.as_ref().map(|&(_, line)| line)
+    }
+
+    /// The message as Clippy would print it, with its context.
+    pub fn rendered(&self) -> &str {
+        &self.rendered
+    }
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.rendered)
+    }
+}
+
+/// The lints in Clippy's JSON output, or `None` if it didn't get as far as
+/// finishing a build.
+pub(crate) fn parse(output: &[u8]) -> Option<Vec<Lint>> {
+    let mut lints = vec![];
+
+    for message in Message::parse_stream(output).flatten() {
+        match message {
+            Message::CompilerMessage(msg) => {
+                // Summaries like "aborting due to previous error" aren't about
+                // anything in particular.
+                let summary = msg.message.spans.is_empty()
+                    && msg.message.code.is_none()
+                    && matches!(
+                        msg.message.level,
+                        RustcLevel::Error | RustcLevel::Warning | RustcLevel::FailureNote
+                    );
+                if !summary {
+                    lints.push(Lint::new(msg.message));
+                }
+            }
+            Message::BuildFinished(_) => return Some(lints),
+            _ => continue,
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    #[test]
+    fn lints() {
+        let json_output = indoc! {r##"
+{"reason":"compiler-message","package_id":"fla 0.1.0 (path+file:///test-binary/testbins/fla)","manifest_path":"/test-binary/testbins/fla/Cargo.toml","target":{"kind":["bin"],"crate_types":["bin"],"name":"fla","src_path":"/test-binary/testbins/fla/src/main.rs","edition":"2021","doc":true,"doctest":false,"test":true},"message":{"rendered":"warning: length comparison to zero\n","children":[],"code":{"code":"clippy::len_zero","explanation":null},"level":"warning","message":"length comparison to zero","spans":[{"byte_end":60,"byte_start":40,"column_end":30,"column_start":10,"expansion":null,"file_name":"src/main.rs","is_primary":true,"label":null,"line_end":3,"line_start":3,"suggested_replacement":null,"suggestion_applicability":null,"text":[]}]}}
+{"reason":"compiler-message","package_id":"fla 0.1.0 (path+file:///test-binary/testbins/fla)","manifest_path":"/test-binary/testbins/fla/Cargo.toml","target":{"kind":["bin"],"crate_types":["bin"],"name":"fla","src_path":"/test-binary/testbins/fla/src/main.rs","edition":"2021","doc":true,"doctest":false,"test":true},"message":{"rendered":"warning: 1 warning emitted\n\n","children":[],"code":null,"level":"warning","message":"1 warning emitted","spans":[]}}
+{"reason":"build-finished","success":true}
+"##};
+
+        let lints = parse(json_output.as_bytes()).unwrap();
+        assert_eq!(lints.len(), 1);
+        assert_eq!(lints[0].level(), "warning");
+        assert!(!lints[0].is_error());
+        assert_eq!(lints[0].code(), Some("clippy::len_zero"));
+        assert_eq!(lints[0].message(), "length comparison to zero");
+        assert_eq!(lints[0].file(), Some(Path::new("src/main.rs")));
+        assert_eq!(lints[0].line(), Some(3));
+        assert_eq!(lints[0].to_string(), "warning: length comparison to zero\n");
+
+        assert_eq!(parse(b"error: no such command: `clippy`\n"), None);
+    }
+}
@@ -0,0 +1,167 @@
+//! Bringing a test binary's dependencies into line with the parent's, so that
+//! both sides of eg. a protocol are built with the same versions.
+
+use crate::{manifest_dir, workspace, ManifestError, TestBinary, TestBinaryError};
+use cargo_metadata::semver::Version;
+use std::path::{Path, PathBuf};
+
+/// A dependency whose locked version was changed to match the parent's. See
+/// [`TestBinary::sync_lockfile_with_parent()`].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SyncedDependency {
+    name: String,
+    from: String,
+    to: String,
+}
+
+impl SyncedDependency {
+    /// The package name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The version the child had locked before.
+    pub fn from(&self) -> &str {
+        &self.from
+    }
+
+    /// The parent's version, which the child now has locked.
+    pub fn to(&self) -> &str {
+        &self.to
+    }
+}
+
+/// A package from a registry, as it's locked in a `Cargo.lock`.
+#[derive(Debug)]
+struct Locked {
+    name: String,
+    version: Version,
+    source: String,
+}
+
+impl<'a> TestBinary<'a> {
+    /// Changes the versions locked in the child's `Cargo.lock` to the
+    /// parent's, for every registry dependency they have in common where the
+    /// parent's version is semver-compatible with the child's. Returns what
+    /// was changed.
+    ///
+    /// Tests and the binaries they talk to can otherwise end up with
+    /// different versions of a shared crate, eg. one that defines a protocol,
+    /// which can make them disagree in subtle ways.
+    ///
+    /// Each change is made with `cargo update --precise`, so Cargo still
+    /// checks it against the child's requirements, and leaves alone any
+    /// dependency that the child requires a different version of. The child's
+    /// `Cargo.lock` is created if it doesn't exist.
+    pub fn sync_lockfile_with_parent(&mut self) -> Result<Vec<SyncedDependency>, TestBinaryError> {
+        let parent_root = workspace::metadata(&manifest_dir()?)?
+            .workspace_root
+            .clone()
+            .into_std_path_buf();
+        let child_dir = self.manifest.parent().unwrap_or(Path::new("."));
+        // Getting the metadata resolves the child's dependencies, which
+        // creates its lockfile if necessary.
+        let child_root = workspace::metadata(child_dir)?
+            .workspace_root
+            .clone()
+            .into_std_path_buf();
+
+        let parent = read(&parent_root.join("Cargo.lock"))?;
+        let child = read(&child_root.join("Cargo.lock"))?;
+
+        let mut synced = vec![];
+        for package in &child {
+            let newest = parent
+                .iter()
+                .filter(|other| {
+                    other.name == package.name
+                        && other.source == package.source
+                        && other.version != package.version
+                        && compatible(&other.version, &package.version)
+                })
+                .map(|other| &other.version)
+                .max();
+            let Some(version) = newest else {
+                continue;
+            };
+
+            let mut command = self.cargo()?;
+            command
+                .arg("update")
+                .arg("--manifest-path")
+                .arg(&self.manifest)
+                .arg("--package")
+                .arg(format!("{}@{}", package.name, package.version))
+                .arg("--precise")
+                .arg(version.to_string());
+            if self.is_offline() {
+                command.arg("--offline");
+            }
+            // This fails if the child's requirements rule the version out.
+            if command.output()?.status.success() {
+                synced.push(SyncedDependency {
+                    name: package.name.clone(),
+                    from: package.version.to_string(),
+                    to: version.to_string(),
+                });
+            }
+        }
+
+        Ok(synced)
+    }
+}
+
+/// Whether Cargo would consider `a` and `b` compatible: the same major
+/// version, or for `0.x` versions, the same minor version too.
+fn compatible(a: &Version, b: &Version) -> bool {
+    match (a.major, a.minor) {
+        (0, 0) => b.major == 0 && b.minor == 0 && a.patch == b.patch,
+        (0, minor) => b.major == 0 && b.minor == minor,
+        (major, _) => b.major == major,
+    }
+}
+
+/// The registry packages in a lockfile, or none if there isn't one.
+fn read(lockfile: &Path) -> Result<Vec<Locked>, ManifestError> {
+    let read_error = |e: String| ManifestError::ReadManifest(PathBuf::from(lockfile), e);
+    let contents = match std::fs::read_to_string(lockfile) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(read_error(e.to_string())),
+    };
+    let table: toml::Table = contents.parse().map_err(|e| read_error(format!("{}", e)))?;
+
+    Ok(table
+        .get("package")
+        .and_then(|packages| packages.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|package| {
+            let source = package.get("source")?.as_str()?;
+            if !source.starts_with("registry+") && !source.starts_with("sparse+") {
+                return None;
+            }
+            Some(Locked {
+                name: package.get("name")?.as_str()?.to_owned(),
+                version: package.get("version")?.as_str()?.parse().ok()?,
+                source: source.to_owned(),
+            })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compatibility() {
+        let v = |s: &str| s.parse::<Version>().unwrap();
+        assert!(compatible(&v("1.2.3"), &v("1.0.0")));
+        assert!(!compatible(&v("2.0.0"), &v("1.9.9")));
+        assert!(compatible(&v("0.4.1"), &v("0.4.8")));
+        assert!(!compatible(&v("0.5.0"), &v("0.4.8")));
+        assert!(compatible(&v("0.0.3"), &v("0.0.3")));
+        assert!(!compatible(&v("0.0.3"), &v("0.0.4")));
+    }
+}
@@ -0,0 +1,176 @@
+//! Echoing and logging the output of running test binaries, line by line.
+
+use std::{fs::File, io::Write, path::PathBuf, sync::Mutex};
+
+/// Which of the child's pipes some output came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// What to do with each line of a child's output, as configured on a
+/// [`Runner`](crate::Runner).
+#[derive(Debug, Clone, Default)]
+pub(crate) struct OutputOptions {
+    pub(crate) echo: bool,
+    pub(crate) log_dir: Option<PathBuf>,
+    #[cfg(feature = "tracing")]
+    pub(crate) tracing: bool,
+}
+
+impl OutputOptions {
+    /// Whether we need a [`LineSink`] at all.
+    pub(crate) fn enabled(&self) -> bool {
+        #[cfg(feature = "tracing")]
+        if self.tracing {
+            return true;
+        }
+
+        self.echo || self.log_dir.is_some()
+    }
+}
+
+/// Somewhere to send each line of a child's output as it arrives.
+#[derive(Debug)]
+pub(crate) struct LineSink {
+    name: String,
+    echo: bool,
+    log: Option<Mutex<File>>,
+    #[cfg(feature = "tracing")]
+    span: Option<tracing::Span>,
+}
+
+impl LineSink {
+    /// Creates a sink for the named binary. If there's a log directory, a log
+    /// file is created in it and its path returned along with the sink.
+    pub(crate) fn new(
+        name: &str,
+        pid: u32,
+        options: &OutputOptions,
+    ) -> std::io::Result<(Self, Option<PathBuf>)> {
+        let log_path = match &options.log_dir {
+            Some(dir) => {
+                std::fs::create_dir_all(dir)?;
+                Some(dir.join(format!("{}-{}.log", name, pid)))
+            }
+            None => None,
+        };
+
+        let log = match &log_path {
+            Some(path) => Some(Mutex::new(File::create(path)?)),
+            None => None,
+        };
+
+        let sink = Self {
+            name: name.to_owned(),
+            echo: options.echo,
+            log,
+            #[cfg(feature = "tracing")]
+            span: if options.tracing {
+                Some(crate::tracing_bridge::child_span(name, pid))
+            } else {
+                None
+            },
+        };
+
+        Ok((sink, log_path))
+    }
+
+    /// Handles one line of output, without its line ending.
+    pub(crate) fn line(&self, stream: Stream, line: &[u8]) {
+        let line = String::from_utf8_lossy(line);
+        let line = line.strip_suffix('\r').unwrap_or(&line);
+
+        #[cfg(feature = "tracing")]
+        if let (Stream::Stderr, Some(span)) = (stream, &self.span) {
+            if crate::tracing_bridge::forward(span, line) {
+                return;
+            }
+        }
+
+        // These go through the standard print macros, so that the test
+        // harness' output capturing still applies.
+        if self.echo {
+            match stream {
+                Stream::Stdout => println!("[{}] {}", self.name, line),
+                Stream::Stderr => eprintln!("[{}] {}", self.name, line),
+            }
+        }
+
+        if let Some(log) = &self.log {
+            let label = match stream {
+                Stream::Stdout => "stdout",
+                Stream::Stderr => "stderr",
+            };
+            // Logging is best-effort; the output is still captured anyway.
+            let mut file = log.lock().expect("log lock poisoned");
+            let _ = writeln!(file, "[{}] {}", label, line);
+        }
+    }
+}
+
+/// Splits a stream of output chunks into lines for a [`LineSink`].
+#[derive(Debug)]
+pub(crate) struct LineSplitter {
+    stream: Stream,
+    partial: Vec<u8>,
+}
+
+impl LineSplitter {
+    pub(crate) fn new(stream: Stream) -> Self {
+        Self {
+            stream,
+            partial: vec![],
+        }
+    }
+
+    /// Sends any complete lines in the chunk to the sink, and keeps the rest.
+    pub(crate) fn push(&mut self, chunk: &[u8], sink: &LineSink) {
+        self.partial.extend_from_slice(chunk);
+
+        let mut start = 0;
+        while let Some(end) = self.partial[start..].iter().position(|&b| b == b'\n') {
+            sink.line(self.stream, &self.partial[start..start + end]);
+            start += end + 1;
+        }
+
+        self.partial.drain(..start);
+    }
+
+    /// Sends any remaining partial line to the sink.
+    pub(crate) fn finish(&mut self, sink: &LineSink) {
+        if !self.partial.is_empty() {
+            sink.line(self.stream, &self.partial);
+            self.partial.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = OutputOptions {
+            log_dir: Some(dir.path().to_owned()),
+            ..OutputOptions::default()
+        };
+        let (sink, path) = LineSink::new("fla", 42, &options).unwrap();
+        let path = path.unwrap();
+        assert_eq!(path, dir.path().join("fla-42.log"));
+
+        let mut splitter = LineSplitter::new(Stream::Stderr);
+        splitter.push(b"one\ntw", &sink);
+        splitter.push(b"o\r\nthr", &sink);
+        splitter.finish(&sink);
+        drop(sink);
+
+        assert_eq!(
+            std::fs::read_to_string(path).unwrap(),
+            "[stderr] one\n[stderr] two\n[stderr] thr\n"
+        );
+    }
+}
@@ -0,0 +1,140 @@
+//! Keeping everything Cargo said while building a test binary, eg. as a CI
+//! artifact, so that a nested build that failed can be looked into without
+//! reproducing it.
+
+use crate::TestBinary;
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    process::ExitStatus,
+};
+
+/// Where to write Cargo's output, and whether to keep what's already there.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct LogFile {
+    pub(crate) path: PathBuf,
+    /// Whether to add to the file, rather than replacing it for each build.
+    pub(crate) append: bool,
+}
+
+impl<'a> TestBinary<'a> {
+    /// Appends Cargo's output to `file` each time it's run to build the
+    /// binary, whether or not it succeeds: its JSON messages from stdout, a
+    /// line each, and its stderr as is, in the order they came in. Each build
+    /// starts with a line giving the Cargo command, and ends with one giving
+    /// how it exited. A build with a log file is never shared with an
+    /// identical one in the process, so that it's all in the log.
+    pub fn with_log_file<P: AsRef<Path>>(&mut self, file: P) -> &mut Self {
+        self.log_file = Some(LogFile {
+            path: file.as_ref().to_owned(),
+            append: true,
+        });
+        self
+    }
+
+    /// Writes Cargo's output to `file` like [`TestBinary::with_log_file()`],
+    /// but replaces what's there each time the binary is built, so that it
+    /// only has the latest build.
+    pub fn with_log_file_per_build<P: AsRef<Path>>(&mut self, file: P) -> &mut Self {
+        self.log_file = Some(LogFile {
+            path: file.as_ref().to_owned(),
+            append: false,
+        });
+        self
+    }
+}
+
+/// The log of one build.
+#[derive(Debug)]
+pub(crate) struct BuildLog {
+    file: File,
+}
+
+impl BuildLog {
+    /// Opens the log for the build of `name` with `command`, creating the file
+    /// and its directory if need be.
+    pub(crate) fn open(log: &LogFile, name: &str, command: &str) -> std::io::Result<Self> {
+        if let Some(dir) = log.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(log.append)
+            .truncate(!log.append)
+            .open(&log.path)?;
+        let mut log = Self { file };
+        log.note(&format!("building {:?}: {}", name, command));
+        Ok(log)
+    }
+
+    /// A line of Cargo's stdout, without its line ending.
+    pub(crate) fn stdout(&mut self, line: &[u8]) {
+        let mut line = line.to_vec();
+        line.push(b'\n');
+        self.write(&line);
+    }
+
+    /// A line of Cargo's stderr, with its line ending.
+    pub(crate) fn stderr(&mut self, line: &str) {
+        self.write(line.as_bytes());
+    }
+
+    /// Records how Cargo exited.
+    pub(crate) fn exited(&mut self, status: ExitStatus) {
+        self.note(&format!("Cargo exited with {}", status));
+    }
+
+    /// Records that Cargo was killed, and why.
+    pub(crate) fn killed(&mut self, reason: &str) {
+        self.note(&format!("Cargo was killed: {}", reason));
+    }
+
+    /// A line of our own, marked so that it can't be mistaken for Cargo's.
+    fn note(&mut self, note: &str) {
+        self.write(format!("--- test-binary: {}\n", note).as_bytes());
+    }
+
+    /// Writes a whole line at once, so that processes appending to the same
+    /// file don't interleave. A log that can't be written to isn't worth
+    /// failing the build over, once it's been opened.
+    fn write(&mut self, line: &[u8]) {
+        let _ = self.file.write_all(line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn append_or_replace() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("logs").join("cargo.log");
+        let mut log = LogFile {
+            path: path.clone(),
+            append: true,
+        };
+
+        for _ in 0..2 {
+            let mut build = BuildLog::open(&log, "fla", "cargo build").unwrap();
+            build.stdout(br#"{"reason":"build-finished","success":false}"#);
+            build.stderr("error: could not compile `fla`\n");
+            build.killed("timed out");
+        }
+        let logged = std::fs::read_to_string(&path).unwrap();
+        let build = "--- test-binary: building \"fla\": cargo build\n\
+                     {\"reason\":\"build-finished\",\"success\":false}\n\
+                     error: could not compile `fla`\n\
+                     --- test-binary: Cargo was killed: timed out\n";
+        assert_eq!(logged, build.repeat(2));
+
+        log.append = false;
+        BuildLog::open(&log, "mingo", "cargo build").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "--- test-binary: building \"mingo\": cargo build\n"
+        );
+    }
+}
@@ -0,0 +1,208 @@
+//! Building one test binary several ways.
+
+use crate::{Artifact, TestBinary, TestBinaryError};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+    process::Command,
+};
+
+/// The targets a universal macOS binary is made of.
+const UNIVERSAL_TARGETS: [&str; 2] = ["aarch64-apple-darwin", "x86_64-apple-darwin"];
+
+/// The name Apple's tools (and Cargo users) give to universal binaries' target.
+const UNIVERSAL_TARGET: &str = "universal-apple-darwin";
+
+impl<'a> TestBinary<'a> {
+    /// Builds the binary once for each combination of features, returning
+    /// them by the features that were enabled. Each combination is in addition
+    /// to any features added with [`TestBinary::with_feature()`], and to the
+    /// default features unless [`TestBinary::no_default_features()`] was used.
+    ///
+    /// Every build goes in the same target directory, so dependencies that
+    /// don't change between combinations are only compiled once. Since Cargo
+    /// would put every build at the same path, each binary is copied to
+    /// `feature-matrix/<features>/` under its [output
+    /// directory](Artifact::output_dir), where `<features>` is the features
+    /// joined with `+`, or `none`.
+    ///
+    /// ```rust
+    /// # use std::path::PathBuf;
+    /// # use test_binary::TestBinary;
+    /// let manifest = PathBuf::from_iter(["testbins", "capabilities", "Cargo.toml"]);
+    /// let built = TestBinary::relative_to_parent("capabilities", &manifest)
+    ///     .unwrap()
+    ///     .build_feature_matrix([vec![], vec!["tls"], vec!["tls", "compression"]])
+    ///     .unwrap();
+    /// assert_eq!(built.len(), 3);
+    /// ```
+    pub fn build_feature_matrix<I, C>(
+        &mut self,
+        combinations: I,
+    ) -> Result<BTreeMap<BTreeSet<String>, Artifact>, TestBinaryError>
+    where
+        I: IntoIterator<Item = C>,
+        C: IntoIterator<Item = &'a str>,
+    {
+        let base = self.features.clone();
+        let result = self.build_combinations(&base, combinations);
+        self.features = base;
+        result
+    }
+
+    /// Builds the binary with every combination of the given features,
+    /// including none of them, like `cargo hack --feature-powerset`. See
+    /// [`TestBinary::build_feature_matrix()`].
+    pub fn build_feature_powerset(
+        &mut self,
+        features: &[&'a str],
+    ) -> Result<BTreeMap<BTreeSet<String>, Artifact>, TestBinaryError> {
+        let combinations = (0..1_usize << features.len()).map(|mask| {
+            features
+                .iter()
+                .enumerate()
+                .filter(move |(i, _)| mask & (1 << i) != 0)
+                .map(|(_, &feature)| feature)
+                .collect::<Vec<_>>()
+        });
+        self.build_feature_matrix(combinations)
+    }
+
+    /// Builds the binary with both the `dev` and `release` profiles, returning
+    /// `(debug, release)`. This is for tests that compare how optimised and
+    /// unoptimised builds behave. Any profile set with
+    /// [`TestBinary::with_profile()`] or `TEST_BINARY_PROFILE` is ignored.
+    ///
+    /// Cargo can only build one profile at a time, so this still runs it
+    /// twice. The binaries are in different directories, so neither build
+    /// replaces the other.
+    pub fn build_debug_and_release(&mut self) -> Result<(Artifact, Artifact), TestBinaryError> {
+        let result = self.build_with_profile("dev").and_then(|debug| {
+            let release = self.build_with_profile("release")?;
+            Ok((debug, release))
+        });
+        self.forced_profile = None;
+        result
+    }
+
+    /// Builds the binary for each of the target triples, returning the result
+    /// for each one. A build failing for one target doesn't stop the others
+    /// from being built. Any target set with [`TestBinary::with_target()`] or
+    /// `TEST_BINARY_TARGET` is ignored.
+    ///
+    /// Cargo puts each target's build in its own directory, so they can all
+    /// be used at the same time.
+    pub fn build_matrix<I>(
+        &mut self,
+        targets: I,
+    ) -> BTreeMap<String, Result<Artifact, TestBinaryError>>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let built = targets
+            .into_iter()
+            .map(|triple| {
+                self.forced_target = Some(triple);
+                (triple.to_owned(), self.build_artifact())
+            })
+            .collect();
+        self.forced_target = None;
+        built
+    }
+
+    /// Builds the binary for both `aarch64-apple-darwin` and
+    /// `x86_64-apple-darwin`, and combines them into a universal binary with
+    /// `lipo`, eg. for testing how a binary behaves under Rosetta. Any target
+    /// set with [`TestBinary::with_target()`] or `TEST_BINARY_TARGET` is
+    /// ignored.
+    ///
+    /// The universal binary goes in `universal-apple-darwin/<profile>` in the
+    /// target directory, alongside the directories for each target, and its
+    /// [target](Artifact::target) is `universal-apple-darwin`. Both targets
+    /// must be installed eg. with `rustup target add`, and `lipo` must be on
+    /// the `PATH`, as it is on macOS with Xcode's command line tools.
+    pub fn build_universal(&mut self) -> Result<Artifact, TestBinaryError> {
+        let built: Result<Vec<_>, _> = UNIVERSAL_TARGETS
+            .into_iter()
+            .map(|triple| {
+                self.forced_target = Some(triple);
+                self.build_artifact()
+            })
+            .collect();
+        self.forced_target = None;
+        let built = built?;
+
+        // Cargo put each one in <target dir>/<triple>/<profile>.
+        let first = &built[0];
+        let profile_dir = first.output_dir();
+        let target_dir = profile_dir
+            .parent()
+            .and_then(Path::parent)
+            .unwrap_or(Path::new(""));
+        let dir = target_dir
+            .join(UNIVERSAL_TARGET)
+            .join(profile_dir.file_name().unwrap_or_default());
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join(first.path().file_name().unwrap_or_default());
+
+        let output = Command::new("lipo")
+            .arg("-create")
+            .arg("-output")
+            .arg(&path)
+            .args(built.iter().map(Artifact::path))
+            .output()?;
+        if !output.status.success() {
+            return Err(TestBinaryError::LipoFailure(
+                String::from_utf8_lossy(&output.stderr).into_owned(),
+            ));
+        }
+
+        let mut universal = first.clone().relocated(path);
+        universal.target = Some(UNIVERSAL_TARGET.to_owned());
+        Ok(universal)
+    }
+
+    fn build_with_profile(&mut self, profile: &'a str) -> Result<Artifact, TestBinaryError> {
+        self.forced_profile = Some(profile);
+        self.build_artifact()
+    }
+
+    fn build_combinations<I, C>(
+        &mut self,
+        base: &[&'a str],
+        combinations: I,
+    ) -> Result<BTreeMap<BTreeSet<String>, Artifact>, TestBinaryError>
+    where
+        I: IntoIterator<Item = C>,
+        C: IntoIterator<Item = &'a str>,
+    {
+        let mut built = BTreeMap::new();
+
+        for combination in combinations {
+            let combination: BTreeSet<&'a str> = combination.into_iter().collect();
+            self.features = base.iter().chain(&combination).copied().collect();
+
+            let artifact = self.build_artifact()?;
+            let dir_name = if combination.is_empty() {
+                "none".to_owned()
+            } else {
+                // Features of dependencies are written as "dep/feature".
+                combination
+                    .iter()
+                    .map(|feature| feature.replace('/', "_"))
+                    .collect::<Vec<_>>()
+                    .join("+")
+            };
+            let dir = artifact.output_dir().join("feature-matrix").join(dir_name);
+            // Keep a copy, so that it isn't replaced by the next build.
+            let artifact = artifact.install_to(&dir)?;
+
+            built.insert(
+                combination.into_iter().map(str::to_owned).collect(),
+                artifact,
+            );
+        }
+
+        Ok(built)
+    }
+}
@@ -0,0 +1,106 @@
+//! Setting up test binaries for custom test harnesses built with
+//! `libtest-mimic`.
+
+use crate::{Artifact, TestBinary};
+use libtest_mimic::{Failed, Trial};
+use std::{collections::BTreeMap, path::Path, sync::Arc};
+
+/// The test binaries built by [`setup_trials()`], which is passed to each
+/// test.
+#[cfg_attr(docsrs, doc(cfg(feature = "libtest-mimic")))]
+#[derive(Debug)]
+pub struct TestBinaries {
+    artifacts: BTreeMap<String, Artifact>,
+}
+
+impl TestBinaries {
+    /// The built binary with the given name.
+    ///
+    /// # Panics
+    ///
+    /// If the binary wasn't one of those given to [`setup_trials()`]. Every
+    /// binary that was is guaranteed to have been built.
+    pub fn artifact(&self, name: &str) -> &Artifact {
+        self.artifacts
+            .get(name)
+            .unwrap_or_else(|| panic!("test binary {} was not set up", name))
+    }
+
+    /// The path to the built binary with the given name. This panics in the
+    /// same way as [`TestBinaries::artifact()`].
+    pub fn path(&self, name: &str) -> &Path {
+        self.artifact(name).path()
+    }
+
+    /// Creates a test that's passed these binaries when it runs.
+    pub fn test<S, F>(self: &Arc<Self>, name: S, test: F) -> Trial
+    where
+        S: Into<String>,
+        F: FnOnce(&TestBinaries) -> Result<(), Failed> + Send + 'static,
+    {
+        let binaries = Arc::clone(self);
+        Trial::test(name, move || test(&binaries))
+    }
+}
+
+/// Builds the named test binaries in `directory`, like
+/// [`prewarm()`](crate::prewarm), and then calls `make_trials` to create the
+/// tests that use them. This is meant for the `main` of a custom test harness
+/// (with `harness = false`), so that binaries are built before any test runs
+/// and build errors don't turn up as panics in whichever tests happen to run
+/// first.
+///
+/// Every binary gets a `setup::<name>` test as well, which fails with the
+/// build error if it didn't build. If any didn't, `make_trials` isn't called,
+/// since its tests couldn't run anyway.
+///
+/// ```rust,no_run
+/// # use test_binary::setup_trials;
+/// let args = libtest_mimic::Arguments::from_args();
+/// let trials = setup_trials(&["actions"], "testbins", |binaries| {
+///     vec![binaries.test("prints", |binaries| {
+///         let output = binaries
+///             .artifact("actions")
+///             .runner()
+///             .args(["print", "fla"])
+///             .run()?;
+///         assert_eq!(output.stdout(), b"fla\n");
+///         Ok(())
+///     })]
+/// });
+/// libtest_mimic::run(&args, trials).exit();
+/// ```
+#[cfg_attr(docsrs, doc(cfg(feature = "libtest-mimic")))]
+pub fn setup_trials<R, F>(names: &[&str], directory: R, make_trials: F) -> Vec<Trial>
+where
+    R: AsRef<Path>,
+    F: FnOnce(&Arc<TestBinaries>) -> Vec<Trial>,
+{
+    let mut trials = vec![];
+    let mut artifacts = BTreeMap::new();
+    let mut failed = false;
+
+    for &name in names {
+        let built = TestBinary::in_directory(name, directory.as_ref())
+            .and_then(|mut binary| binary.build_artifact());
+        let setup = format!("setup::{}", name);
+
+        match built {
+            Ok(artifact) => {
+                artifacts.insert(name.to_owned(), artifact);
+                trials.push(Trial::test(setup, || Ok(())));
+            }
+            Err(error) => {
+                failed = true;
+                let message = error.to_string();
+                trials.push(Trial::test(setup, move || Err(message.into())));
+            }
+        }
+    }
+
+    if !failed {
+        trials.extend(make_trials(&Arc::new(TestBinaries { artifacts })));
+    }
+
+    trials
+}
@@ -0,0 +1,294 @@
+//! Generating mock binaries from declarative scripts.
+
+use crate::{generated, Artifact, TestBinary, TestBinaryError};
+use serde_json::Value;
+use std::{
+    fmt::Write as _,
+    io::{Error, ErrorKind},
+    path::Path,
+    time::Duration,
+};
+
+/// One step of a [`MockScript`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Step {
+    Expect(String),
+    SkipLine,
+    Send(String),
+    SendStderr(String),
+    Sleep(Duration),
+    Exit(i32),
+}
+
+/// A script for a mock binary, which is turned into Rust code and built with
+/// [`MockScript::build()`]. The mock performs each step in order, then exits
+/// with the script's exit code.
+///
+/// If the mock reads a line from stdin that doesn't match what the script
+/// expects (or stdin ends), it prints what it expected and what it got to
+/// stderr, and exits with [`MockScript::MISMATCH_EXIT_CODE`].
+///
+/// Scripts can be written in Rust:
+///
+/// ```rust
+/// # use test_binary::MockScript;
+/// let mock = MockScript::new()
+///     .send_line("220 ready")
+///     .expect_line("HELO test")
+///     .send_line("250 hello")
+///     .exit_code(0)
+///     .build("smtp-mock")
+///     .unwrap();
+/// ```
+///
+/// Or loaded from JSON, with each step an object with a single key:
+///
+/// ```json
+/// {
+///   "steps": [
+///     { "send": "220 ready" },
+///     { "expect": "HELO test" },
+///     { "sleep_ms": 100 },
+///     { "send": "250 hello" },
+///     { "skip_line": true },
+///     { "send_stderr": "done" }
+///   ],
+///   "exit_code": 0
+/// }
+/// ```
+///
+/// An `{ "exit": <code> }` step exits straight away.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MockScript {
+    steps: Vec<Step>,
+    exit_code: i32,
+}
+
+impl MockScript {
+    /// The exit code of a mock when its input doesn't match the script.
+    pub const MISMATCH_EXIT_CODE: i32 = 2;
+
+    /// Creates an empty script, for a mock that exits successfully.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses a script from JSON.
+    pub fn from_json(json: &str) -> std::io::Result<Self> {
+        let invalid = |msg: String| Error::new(ErrorKind::InvalidData, msg);
+
+        let value: Value = serde_json::from_str(json).map_err(Error::from)?;
+        let mut script = Self::new();
+
+        if let Some(code) = value.get("exit_code") {
+            script.exit_code =
+                as_exit_code(code).ok_or_else(|| invalid(format!("bad exit code: {}", code)))?;
+        }
+
+        let steps = match value.get("steps") {
+            Some(Value::Array(steps)) => steps.as_slice(),
+            Some(other) => return Err(invalid(format!("steps should be an array: {}", other))),
+            None => &[],
+        };
+
+        for step in steps {
+            let bad_step = || invalid(format!("bad step: {}", step));
+            let (kind, param) = match step.as_object() {
+                Some(object) if object.len() == 1 => object.iter().next().unwrap(),
+                _ => return Err(bad_step()),
+            };
+
+            let text = || param.as_str().map(str::to_owned).ok_or_else(bad_step);
+            script.steps.push(match kind.as_str() {
+                "expect" => Step::Expect(text()?),
+                "skip_line" => Step::SkipLine,
+                "send" => Step::Send(text()?),
+                "send_stderr" => Step::SendStderr(text()?),
+                "sleep_ms" => {
+                    Step::Sleep(Duration::from_millis(param.as_u64().ok_or_else(bad_step)?))
+                }
+                "exit" => Step::Exit(as_exit_code(param).ok_or_else(bad_step)?),
+                _ => return Err(bad_step()),
+            });
+        }
+
+        Ok(script)
+    }
+
+    /// Reads a JSON script from a file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        Self::from_json(&std::fs::read_to_string(path)?)
+    }
+
+    /// Adds a step that reads a line from stdin, which must match exactly
+    /// (without the line ending).
+    pub fn expect_line<S: Into<String>>(&mut self, line: S) -> &mut Self {
+        self.steps.push(Step::Expect(line.into()));
+        self
+    }
+
+    /// Adds a step that reads and ignores a line from stdin.
+    pub fn skip_line(&mut self) -> &mut Self {
+        self.steps.push(Step::SkipLine);
+        self
+    }
+
+    /// Adds a step that writes a line to stdout.
+    pub fn send_line<S: Into<String>>(&mut self, line: S) -> &mut Self {
+        self.steps.push(Step::Send(line.into()));
+        self
+    }
+
+    /// Adds a step that writes a line to stderr.
+    pub fn send_stderr_line<S: Into<String>>(&mut self, line: S) -> &mut Self {
+        self.steps.push(Step::SendStderr(line.into()));
+        self
+    }
+
+    /// Adds a step that sleeps.
+    pub fn sleep(&mut self, duration: Duration) -> &mut Self {
+        self.steps.push(Step::Sleep(duration));
+        self
+    }
+
+    /// Adds a step that exits straight away with the given code.
+    pub fn exit(&mut self, code: i32) -> &mut Self {
+        self.steps.push(Step::Exit(code));
+        self
+    }
+
+    /// Sets the code to exit with after all the steps.
+    pub fn exit_code(&mut self, code: i32) -> &mut Self {
+        self.exit_code = code;
+        self
+    }
+
+    /// Generates a package for the mock under the parent's target directory,
+    /// in `test-binary/mock/<name>`, and builds it. Each mock needs a
+    /// different name.
+    pub fn build(&self, name: &str) -> Result<Artifact, TestBinaryError> {
+        let manifest_path = generated::binary_package("mock", name, &self.to_source())?;
+        TestBinary::with_manifest(name, manifest_path).build_artifact()
+    }
+
+    /// The Rust source for the mock.
+    fn to_source(&self) -> String {
+        let mut source =
+            PRELUDE.replace("MISMATCH_EXIT_CODE", &Self::MISMATCH_EXIT_CODE.to_string());
+
+        for step in &self.steps {
+            // Debug formatting for strings gives us valid Rust literals.
+            match step {
+                Step::Expect(line) => writeln!(source, "    expect(&mut lines, Some({:?}));", line),
+                Step::SkipLine => writeln!(source, "    expect(&mut lines, None);"),
+                Step::Send(line) => writeln!(source, "    send({:?});", line),
+                Step::SendStderr(line) => writeln!(source, "    eprintln!(\"{{}}\", {:?});", line),
+                Step::Sleep(duration) => writeln!(
+                    source,
+                    "    std::thread::sleep(std::time::Duration::from_nanos({}));",
+                    duration.as_nanos()
+                ),
+                Step::Exit(code) => writeln!(source, "    std::process::exit({});", code),
+            }
+            .expect("error writing to String");
+        }
+
+        writeln!(source, "    std::process::exit({});\n}}", self.exit_code)
+            .expect("error writing to String");
+        source
+    }
+}
+
+fn as_exit_code(value: &Value) -> Option<i32> {
+    value.as_i64().and_then(|code| i32::try_from(code).ok())
+}
+
+const PRELUDE: &str = r#"//! Generated by test-binary from a mock script.
+
+use std::io::{BufRead, Write};
+
+#[allow(dead_code)]
+fn expect(lines: &mut impl Iterator<Item = std::io::Result<String>>, expected: Option<&str>) {
+    let line = lines.next().and_then(Result::ok);
+    let matches = match (&line, expected) {
+        (Some(line), Some(expected)) => line == expected,
+        (Some(_), None) => true,
+        (None, _) => false,
+    };
+    if !matches {
+        eprintln!("mock expected {:?}, got {:?}", expected, line);
+        std::process::exit(MISMATCH_EXIT_CODE);
+    }
+}
+
+#[allow(dead_code)]
+fn send(line: &str) {
+    let mut stdout = std::io::stdout();
+    writeln!(stdout, "{}", line).unwrap();
+    stdout.flush().unwrap();
+}
+
+fn main() {
+    let stdin = std::io::stdin();
+    let mut lines = stdin.lock().lines();
+    let _ = &mut lines;
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_json() {
+        let script = MockScript::from_json(
+            r#"{
+                "steps": [
+                    { "send": "ready" },
+                    { "expect": "ping" },
+                    { "skip_line": true },
+                    { "sleep_ms": 5 },
+                    { "send_stderr": "bye" },
+                    { "exit": 4 }
+                ],
+                "exit_code": 3
+            }"#,
+        )
+        .unwrap();
+
+        let mut expected = MockScript::new();
+        expected
+            .send_line("ready")
+            .expect_line("ping")
+            .skip_line()
+            .sleep(Duration::from_millis(5))
+            .send_stderr_line("bye")
+            .exit(4)
+            .exit_code(3);
+
+        assert_eq!(script, expected);
+    }
+
+    #[test]
+    fn bad_json() {
+        for json in [
+            r#"{"steps": {}}"#,
+            r#"{"steps": [{"send": 1}]}"#,
+            r#"{"steps": [{"send": "a", "expect": "b"}]}"#,
+            r#"{"steps": [{"dance": true}]}"#,
+            r#"{"exit_code": "zero"}"#,
+        ] {
+            let err = MockScript::from_json(json).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData, "{}", json);
+        }
+    }
+
+    #[test]
+    fn source() {
+        let source = MockScript::new()
+            .send_line("say \"hi\"")
+            .exit_code(1)
+            .to_source();
+        assert!(source.contains("    send(\"say \\\"hi\\\"\");\n"));
+        assert!(source.ends_with("    std::process::exit(1);\n}\n"));
+    }
+}
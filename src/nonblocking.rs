@@ -0,0 +1,84 @@
+//! Building and running test binaries from async code.
+//!
+//! Cargo and the binaries are still run the usual way, but on the `blocking`
+//! crate's thread pool, so that waiting for them doesn't hold up the async
+//! runtime. Nothing here depends on a particular runtime, so it works the same
+//! with tokio, async-std, smol or anything else.
+
+use crate::{
+    build_test_binary, Artifact, CancelToken, RunError, RunOutput, TestBinary, TestBinaryError,
+    TestBinarySpec,
+};
+use blocking::unblock;
+use std::{
+    ffi::{OsStr, OsString},
+    future::Future,
+    path::Path,
+};
+
+/// Builds the test binary like [`build_test_binary()`], but in the background,
+/// returning a future that completes when it's built.
+///
+/// The arguments are copied before this returns, so the future can be spawned
+/// onto any runtime.
+#[cfg_attr(docsrs, doc(cfg(feature = "async")))]
+pub fn build_test_binary_async<R: AsRef<Path>>(
+    name: &str,
+    directory: R,
+) -> impl Future<Output = Result<OsString, TestBinaryError>> + Send + 'static {
+    let name = name.to_owned();
+    let directory = directory.as_ref().to_owned();
+    unblock(move || build_test_binary(&name, directory))
+}
+
+impl TestBinarySpec {
+    /// Builds the binary this describes, like
+    /// [`TestBinary::from_spec()`] followed by
+    /// [`TestBinary::build_artifact()`], but in the background. This is the
+    /// way to build with non-default settings from async code.
+    #[cfg_attr(docsrs, doc(cfg(feature = "async")))]
+    pub fn build_async(
+        &self,
+    ) -> impl Future<Output = Result<Artifact, TestBinaryError>> + Send + 'static {
+        let spec = self.clone();
+        unblock(move || TestBinary::from_spec(&spec)?.build_artifact())
+    }
+
+    /// Builds the binary like [`TestBinarySpec::build_async()`], but stops
+    /// when `token` is cancelled, as with
+    /// [`TestBinary::with_cancel_token()`]. Dropping the future doesn't stop
+    /// the build by itself, since it carries on in the background.
+    #[cfg_attr(docsrs, doc(cfg(feature = "async")))]
+    pub fn build_async_cancellable(
+        &self,
+        token: &CancelToken,
+    ) -> impl Future<Output = Result<Artifact, TestBinaryError>> + Send + 'static {
+        let spec = self.clone();
+        let token = token.clone();
+        unblock(move || {
+            TestBinary::from_spec(&spec)?
+                .with_cancel_token(&token)
+                .build_artifact()
+        })
+    }
+}
+
+impl Artifact {
+    /// Runs the binary with `args` to completion like [`Runner::run()`], but in
+    /// the background, returning a future that completes when it exits.
+    ///
+    /// [`Runner::run()`]: crate::Runner::run
+    #[cfg_attr(docsrs, doc(cfg(feature = "async")))]
+    pub fn run_async<I, S>(
+        &self,
+        args: I,
+    ) -> impl Future<Output = Result<RunOutput, RunError>> + Send + 'static
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let artifact = self.clone();
+        let args: Vec<OsString> = args.into_iter().map(|a| a.as_ref().to_owned()).collect();
+        unblock(move || artifact.runner().args(args).run())
+    }
+}
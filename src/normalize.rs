@@ -0,0 +1,257 @@
+//! Scrubbing test binaries' output of things that change from run to run, so
+//! that it can be compared against snapshots.
+
+use std::path::Path;
+
+/// Hosts whose port numbers are replaced, since they're usually ephemeral.
+const HOSTS: [&str; 5] = ["localhost:", "127.0.0.1:", "0.0.0.0:", "[::1]:", "[::]:"];
+
+/// Normalizes a test binary's output so that it's the same from run to run
+/// and platform to platform. By default:
+///
+/// - ANSI escape codes (colours etc.) are removed
+/// - `\r\n` line endings become `\n`
+/// - paths in the system's temporary directory become `[TEMP]`, along with
+///   the randomly named directory under it if it was made by `tempfile`
+/// - port numbers on local addresses become `[PORT]` eg. `127.0.0.1:[PORT]`
+///
+/// Anything else can be replaced with [`Normalizer::replace()`]. See
+/// [`RunOutput::snapshot()`](crate::RunOutput::snapshot) for the most common
+/// use.
+///
+/// ```rust
+/// # use test_binary::Normalizer;
+/// let text = "\x1b[32mlistening\x1b[0m on 127.0.0.1:40123 as job 17\r\n";
+/// let normalized = Normalizer::new().replace("job 17", "job [JOB]").normalize(text);
+/// assert_eq!(normalized, "listening on 127.0.0.1:[PORT] as job [JOB]\n");
+/// ```
+#[derive(Debug, Clone)]
+pub struct Normalizer {
+    ansi: bool,
+    line_endings: bool,
+    temp_paths: bool,
+    ports: bool,
+    replacements: Vec<(String, String)>,
+}
+
+impl Default for Normalizer {
+    fn default() -> Self {
+        Self {
+            ansi: true,
+            line_endings: true,
+            temp_paths: true,
+            ports: true,
+            replacements: vec![],
+        }
+    }
+}
+
+impl Normalizer {
+    /// Creates a normalizer that does all of the default normalizations.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Leaves ANSI escape codes in.
+    pub fn keep_ansi(&mut self) -> &mut Self {
+        self.ansi = false;
+        self
+    }
+
+    /// Leaves `\r\n` line endings as they are.
+    pub fn keep_line_endings(&mut self) -> &mut Self {
+        self.line_endings = false;
+        self
+    }
+
+    /// Leaves paths in the temporary directory as they are.
+    pub fn keep_temp_paths(&mut self) -> &mut Self {
+        self.temp_paths = false;
+        self
+    }
+
+    /// Leaves port numbers as they are.
+    pub fn keep_ports(&mut self) -> &mut Self {
+        self.ports = false;
+        self
+    }
+
+    /// Replaces every occurrence of `text` with `placeholder`. Replacements
+    /// are made after the default normalizations, in the order they're added.
+    pub fn replace<S: Into<String>, P: Into<String>>(
+        &mut self,
+        text: S,
+        placeholder: P,
+    ) -> &mut Self {
+        self.replacements.push((text.into(), placeholder.into()));
+        self
+    }
+
+    /// Normalizes `text`.
+    pub fn normalize(&self, text: &str) -> String {
+        let mut text = text.to_owned();
+
+        if self.ansi {
+            text = strip_ansi(&text);
+        }
+        if self.line_endings {
+            text = text.replace("\r\n", "\n");
+        }
+        if self.temp_paths {
+            let temp_dir = std::env::temp_dir();
+            // The temporary directory is often a symlink eg. on macOS, and the
+            // binary might have been given either version.
+            if let Ok(canonical) = temp_dir.canonicalize() {
+                text = replace_temp_paths(&text, &canonical);
+            }
+            text = replace_temp_paths(&text, &temp_dir);
+        }
+        if self.ports {
+            text = replace_ports(&text);
+        }
+        for (from, to) in &self.replacements {
+            text = text.replace(from, to);
+        }
+
+        text
+    }
+}
+
+/// Removes ANSI escape sequences: CSI sequences like colours, OSC sequences
+/// like hyperlinks and window titles, character set designations, and any
+/// other two character escapes.
+fn strip_ansi(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            stripped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            // Parameters, then a final byte in '@'..='~'.
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            // Anything up to BEL or ESC \.
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' {
+                        break;
+                    }
+                    if c == '\x1b' && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            // Character set designations, which take one more character.
+            Some('(' | ')') => {
+                chars.next();
+            }
+            _ => {}
+        }
+    }
+
+    stripped
+}
+
+/// Replaces `temp_dir` at the start of paths with `[TEMP]`. If the next
+/// component looks like one of `tempfile`'s random names, it's replaced too.
+fn replace_temp_paths(text: &str, temp_dir: &Path) -> String {
+    let temp_dir = temp_dir.to_string_lossy();
+    let temp_dir = temp_dir.trim_end_matches(['/', '\\']);
+    if temp_dir.is_empty() {
+        return text.to_owned();
+    }
+
+    let mut replaced = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find(temp_dir) {
+        replaced.push_str(&rest[..start]);
+        replaced.push_str("[TEMP]");
+        rest = &rest[start + temp_dir.len()..];
+
+        let random = rest
+            .strip_prefix(['/', '\\'])
+            .and_then(|path| path.strip_prefix(".tmp"));
+        if let Some(path) = random {
+            let name_len = path
+                .find(|c: char| !c.is_ascii_alphanumeric())
+                .unwrap_or(path.len());
+            if name_len > 0 {
+                rest = &path[name_len..];
+            }
+        }
+    }
+
+    replaced.push_str(rest);
+    replaced
+}
+
+/// Replaces port numbers after local host names and addresses with `[PORT]`.
+fn replace_ports(text: &str) -> String {
+    let mut replaced = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some((start, host)) = HOSTS
+        .iter()
+        .filter_map(|host| rest.find(host).map(|start| (start, host)))
+        .min()
+    {
+        let end = start + host.len();
+        replaced.push_str(&rest[..end]);
+        rest = &rest[end..];
+
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        if digits > 0 {
+            replaced.push_str("[PORT]");
+            rest = &rest[digits..];
+        }
+    }
+
+    replaced.push_str(rest);
+    replaced
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ansi() {
+        assert_eq!(
+            strip_ansi(
+                "\x1b[1;31merror\x1b[0m: \x1b]8;;http://fla\x07link\x1b]8;;\x1b\\ \x1b(Bdone"
+            ),
+            "error: link done"
+        );
+    }
+
+    #[test]
+    fn temp_paths() {
+        let temp_dir = Path::new("/tmp");
+        assert_eq!(
+            replace_temp_paths("wrote /tmp/.tmpAb3dE9/out.txt and /tmp/fla", temp_dir),
+            "wrote [TEMP]/out.txt and [TEMP]/fla"
+        );
+        assert_eq!(replace_temp_paths("/tmp/.tmp", temp_dir), "[TEMP]/.tmp");
+    }
+
+    #[test]
+    fn ports() {
+        assert_eq!(
+            replace_ports("on localhost:8080, [::1]:443 and 127.0.0.1:; 10.0.0.1:80"),
+            "on localhost:[PORT], [::1]:[PORT] and 127.0.0.1:; 10.0.0.1:80"
+        );
+    }
+}
@@ -0,0 +1,215 @@
+//! Pinning the hashes of built binaries in a checked-in lock file.
+//!
+//! The lock file has a section for each platform, since the same source
+//! doesn't build to the same binary everywhere. Binaries that are
+//! cross-compiled go in a section for their target triple instead, so that
+//! building the same binary for several targets doesn't mix them up:
+//!
+//! ```none
+//! [linux-x86_64]
+//! does-build = "sha256:..."
+//!
+//! [x86_64-unknown-linux-musl]
+//! does-build = "sha256:..."
+//! ```
+
+use crate::hash::hash_file;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// The name of the lock file, in the parent crate's directory.
+pub(crate) const LOCK_FILE: &str = "test-binaries.lock";
+
+const HEADER: &str = "\
+# Hashes of test binaries, pinned by the test-binary crate.
+# Regenerate by building with `Pinning::Record`.
+";
+
+/// Serialises updates to the lock file from within this process.
+static LOCK: Mutex<()> = Mutex::new(());
+
+/// What to do with a binary's entry in `test-binaries.lock`, for
+/// [`TestBinary::pinned()`](crate::TestBinary::pinned).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Pinning {
+    /// Record the binary's hash, replacing any existing entry.
+    Record,
+    /// Check that the binary's hash matches its entry, and return
+    /// [`PinError`] if it doesn't or there isn't one.
+    Verify,
+}
+
+/// Error pinning a binary's hash.
+#[derive(thiserror::Error, Debug)]
+pub enum PinError {
+    /// Error reading or writing a file.
+    #[error("error accessing {}: {1}", .0.display())]
+    Io(PathBuf, #[source] std::io::Error),
+    /// The lock file couldn't be parsed.
+    #[error("error parsing {}: {1}", .0.display())]
+    Parse(PathBuf, String),
+    /// There's no entry for the binary on this platform.
+    #[error(r#"test binary "{0}" is not pinned for {1}"#)]
+    Missing(String, String),
+    /// The binary's hash doesn't match its entry.
+    #[error(r#"test binary "{name}" has changed: pinned {expected}, built {actual}"#)]
+    Mismatch {
+        /// The binary name.
+        name: String,
+        /// The pinned hash.
+        expected: String,
+        /// The hash of the binary that was built.
+        actual: String,
+    },
+}
+
+type Sections = BTreeMap<String, BTreeMap<String, String>>;
+
+/// The lock file section for the platform we're running on.
+fn platform() -> String {
+    format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH)
+}
+
+fn parse(lock_file: &Path, contents: &str) -> Result<Sections, PinError> {
+    let mut sections = Sections::new();
+    let mut section = None;
+
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = Some(sections.entry(name.to_owned()).or_default());
+            continue;
+        }
+
+        let bad_line = || PinError::Parse(lock_file.to_owned(), format!("bad line: {}", line));
+        let (name, hash) = line.split_once('=').ok_or_else(bad_line)?;
+        let hash = hash
+            .trim()
+            .strip_prefix('"')
+            .and_then(|h| h.strip_suffix('"'))
+            .ok_or_else(bad_line)?;
+        section
+            .as_mut()
+            .ok_or_else(bad_line)?
+            .insert(name.trim().to_owned(), hash.to_owned());
+    }
+
+    Ok(sections)
+}
+
+fn render(sections: &Sections) -> String {
+    let mut contents = HEADER.to_owned();
+    for (platform, entries) in sections {
+        contents.push_str(&format!("\n[{}]\n", platform));
+        for (name, hash) in entries {
+            contents.push_str(&format!("{} = \"{}\"\n", name, hash));
+        }
+    }
+    contents
+}
+
+/// Records or verifies the hash of the binary at `path` in the lock file. The
+/// target is the one it was cross-compiled for, if any.
+pub(crate) fn check(
+    lock_file: &Path,
+    name: &str,
+    path: &Path,
+    target: Option<&str>,
+    pinning: Pinning,
+) -> Result<(), PinError> {
+    let actual = hash_file(path).map_err(|e| PinError::Io(path.to_owned(), e))?;
+    let platform = target.map_or_else(platform, str::to_owned);
+
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    let mut sections = match std::fs::read_to_string(lock_file) {
+        Ok(contents) => parse(lock_file, &contents)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Sections::new(),
+        Err(e) => return Err(PinError::Io(lock_file.to_owned(), e)),
+    };
+
+    match pinning {
+        Pinning::Record => {
+            let entries = sections.entry(platform).or_default();
+            if entries.get(name) != Some(&actual) {
+                entries.insert(name.to_owned(), actual);
+                std::fs::write(lock_file, render(&sections))
+                    .map_err(|e| PinError::Io(lock_file.to_owned(), e))?;
+            }
+            Ok(())
+        }
+        Pinning::Verify => {
+            match sections
+                .get(&platform)
+                .and_then(|entries| entries.get(name))
+            {
+                Some(expected) if *expected == actual => Ok(()),
+                Some(expected) => Err(PinError::Mismatch {
+                    name: name.to_owned(),
+                    expected: expected.clone(),
+                    actual,
+                }),
+                None => Err(PinError::Missing(name.to_owned(), platform)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_and_verify() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock_file = dir.path().join(LOCK_FILE);
+        let binary = dir.path().join("fla");
+        std::fs::write(&binary, "version 1").unwrap();
+
+        assert!(matches!(
+            check(&lock_file, "fla", &binary, None, Pinning::Verify),
+            Err(PinError::Missing(..))
+        ));
+
+        check(&lock_file, "fla", &binary, None, Pinning::Record).unwrap();
+        check(&lock_file, "fla", &binary, None, Pinning::Verify).unwrap();
+
+        let contents = std::fs::read_to_string(&lock_file).unwrap();
+        let sections = parse(&lock_file, &contents).unwrap();
+        assert_eq!(sections[&platform()]["fla"], hash_file(&binary).unwrap());
+
+        // The same binary built for another target is pinned separately.
+        let musl = "x86_64-unknown-linux-musl";
+        assert!(matches!(
+            check(&lock_file, "fla", &binary, Some(musl), Pinning::Verify),
+            Err(PinError::Missing(_, section)) if section == musl
+        ));
+        std::fs::write(&binary, "version 2").unwrap();
+        check(&lock_file, "fla", &binary, Some(musl), Pinning::Record).unwrap();
+
+        match check(&lock_file, "fla", &binary, None, Pinning::Verify) {
+            Err(PinError::Mismatch { name, .. }) => assert_eq!(name, "fla"),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        let path = Path::new(LOCK_FILE);
+        assert!(parse(path, "fla = \"sha256:00\"").is_err());
+        assert!(parse(path, "[linux-x86_64]\nfla = sha256:00").is_err());
+        assert!(parse(path, "[linux-x86_64]\nfla").is_err());
+
+        let sections = parse(path, "# comment\n[linux-x86_64]\nfla = \"sha256:00\"\n").unwrap();
+        assert_eq!(
+            render(&sections),
+            format!("{}\n[linux-x86_64]\nfla = \"sha256:00\"\n", HEADER)
+        );
+    }
+}
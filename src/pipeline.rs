@@ -0,0 +1,46 @@
+//! Composing test binaries into pipelines with [`duct`].
+
+use crate::Artifact;
+use std::ffi::{OsStr, OsString};
+
+impl Artifact {
+    /// Creates a [`duct::Expression`] that runs the binary with `args`, after
+    /// any default arguments, and with the artifact's default environment and
+    /// working directory. This can then be piped to and from other commands
+    /// and redirected, with duct's usual methods:
+    ///
+    /// ```rust
+    /// # use test_binary::build_test_binary;
+    /// # use test_binary::Artifact;
+    /// let actions = Artifact::from(build_test_binary("actions", "testbins").unwrap());
+    /// let output = actions
+    ///     .expression(["print", "fla", "print", "mingo"])
+    ///     .pipe(duct::cmd!("grep", "fla"))
+    ///     .read()
+    ///     .unwrap();
+    /// assert_eq!(output, "fla");
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "duct")))]
+    pub fn expression<I, S>(&self, args: I) -> duct::Expression
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let args = self
+            .defaults
+            .args
+            .iter()
+            .cloned()
+            .chain(args.into_iter().map(|arg| arg.as_ref().to_owned()))
+            .collect::<Vec<OsString>>();
+
+        let mut expression = duct::cmd(self.path(), args);
+        for (key, value) in &self.defaults.envs {
+            expression = expression.env(key, value);
+        }
+        if let Some(dir) = &self.defaults.current_dir {
+            expression = expression.dir(dir);
+        }
+        expression
+    }
+}
@@ -0,0 +1,129 @@
+//! Which platforms a test binary can be built for, so that tests can be
+//! skipped on the others rather than failing to build.
+
+use crate::ManifestError;
+use std::path::Path;
+
+/// Operating systems in target triples that count as `unix`.
+const UNIX: [&str; 12] = [
+    "linux",
+    "darwin",
+    "ios",
+    "android",
+    "freebsd",
+    "netbsd",
+    "openbsd",
+    "dragonfly",
+    "solaris",
+    "illumos",
+    "haiku",
+    "aix",
+];
+
+/// The platforms listed in the package's manifest, as
+///
+/// ```toml
+/// [package.metadata.test-binary]
+/// platforms = ["unix", "x86_64-pc-windows-msvc"]
+/// ```
+///
+/// or `None` if there's no list. A missing or malformed manifest is left for
+/// Cargo to complain about.
+pub(crate) fn declared(manifest: &Path) -> Result<Option<Vec<String>>, ManifestError> {
+    let read_error = |e: String| ManifestError::ReadManifest(manifest.to_path_buf(), e);
+    let contents = match std::fs::read_to_string(manifest) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(read_error(e.to_string())),
+    };
+    let Ok(table) = contents.parse::<toml::Table>() else {
+        return Ok(None);
+    };
+
+    let Some(platforms) = table
+        .get("package")
+        .and_then(|package| package.get("metadata"))
+        .and_then(|metadata| metadata.get("test-binary"))
+        .and_then(|metadata| metadata.get("platforms"))
+    else {
+        return Ok(None);
+    };
+    platforms
+        .as_array()
+        .and_then(|platforms| {
+            platforms
+                .iter()
+                .map(|platform| platform.as_str().map(str::to_owned))
+                .collect()
+        })
+        .map(Some)
+        .ok_or_else(|| read_error("test-binary platforms should be a list of strings".to_owned()))
+}
+
+/// Whether `platform` covers the target `triple`. It does if it's the whole
+/// triple, one of its parts eg. `linux`, `x86_64` or `musl`, or one of the
+/// families `unix`, `windows`, `macos` or `wasm`.
+pub(crate) fn supports(platform: &str, triple: &str) -> bool {
+    let parts: Vec<_> = triple.split('-').collect();
+    let arch = parts.first().copied().unwrap_or_default();
+    platform == triple
+        || parts.contains(&platform)
+        || match platform {
+            "unix" => parts.iter().any(|part| UNIX.contains(part)),
+            "macos" => triple.ends_with("apple-darwin"),
+            "wasm" => arch.starts_with("wasm"),
+            _ => false,
+        }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn platforms() {
+        let linux = "x86_64-unknown-linux-gnu";
+        for platform in [linux, "linux", "unix", "x86_64", "gnu"] {
+            assert!(supports(platform, linux), "{}", platform);
+        }
+        for platform in ["windows", "macos", "wasm", "aarch64", "lin"] {
+            assert!(!supports(platform, linux), "{}", platform);
+        }
+
+        assert!(supports("windows", "x86_64-pc-windows-msvc"));
+        assert!(!supports("unix", "x86_64-pc-windows-msvc"));
+        assert!(supports("macos", "aarch64-apple-darwin"));
+        assert!(supports("unix", "aarch64-apple-darwin"));
+        assert!(!supports("macos", "aarch64-apple-ios"));
+        assert!(supports("wasm", "wasm32-wasip1"));
+        assert!(!supports("unix", "wasm32-wasip1"));
+    }
+
+    #[test]
+    fn manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = dir.path().join("Cargo.toml");
+        assert_eq!(declared(&manifest).unwrap(), None);
+
+        std::fs::write(&manifest, "[package]\nname = \"fla\"\n").unwrap();
+        assert_eq!(declared(&manifest).unwrap(), None);
+
+        std::fs::write(
+            &manifest,
+            "[package]\nname = \"fla\"\n\n\
+             [package.metadata.test-binary]\nplatforms = [\"unix\", \"wasm\"]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            declared(&manifest).unwrap(),
+            Some(vec!["unix".to_owned(), "wasm".to_owned()])
+        );
+
+        std::fs::write(
+            &manifest,
+            "[package]\nname = \"fla\"\n\n[package.metadata.test-binary]\nplatforms = \"unix\"\n",
+        )
+        .unwrap();
+        assert!(declared(&manifest).is_err());
+    }
+}
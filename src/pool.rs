@@ -0,0 +1,175 @@
+//! Building test binaries in the background while tests run.
+
+use crate::{Artifact, TestBinary, TestBinaryError};
+use std::{
+    collections::{HashMap, VecDeque},
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex},
+};
+
+/// Builds test binaries on background threads, so that tests that don't need
+/// them can get started while they're compiled, and those that do only wait
+/// for the ones they need. Binaries are found in a directory, as with
+/// [`build_test_binary()`](crate::build_test_binary).
+///
+/// ```rust
+/// # use test_binary::TestBinaryPool;
+/// use once_cell::sync::Lazy;
+///
+/// static POOL: Lazy<TestBinaryPool> = Lazy::new(|| {
+///     let pool = TestBinaryPool::new("testbins", 2);
+///     pool.request_all(["does-build", "multiple"]);
+///     pool
+/// });
+///
+/// // In a test:
+/// let artifact = POOL.get("multiple").unwrap();
+/// assert!(artifact.runner().run().unwrap().status().success());
+/// ```
+///
+/// At most the given number of builds run at once, on threads that finish
+/// once there's nothing left to build. A failed build isn't kept: the next
+/// [`get()`](TestBinaryPool::get) tries again, and returns the error.
+#[derive(Debug)]
+pub struct TestBinaryPool {
+    directory: PathBuf,
+    threads: usize,
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    state: Mutex<State>,
+    /// Notified whenever a build finishes.
+    finished: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    queue: VecDeque<String>,
+    builds: HashMap<String, Build>,
+    workers: usize,
+}
+
+#[derive(Debug)]
+enum Build {
+    Queued,
+    Building,
+    Built(Box<Artifact>),
+}
+
+impl TestBinaryPool {
+    /// Creates a pool for the binaries in `directory` (relative to the
+    /// parent), that builds up to `threads` of them at once.
+    pub fn new<R: AsRef<Path>>(directory: R, threads: usize) -> Self {
+        Self {
+            directory: directory.as_ref().to_owned(),
+            threads: threads.max(1),
+            shared: Default::default(),
+        }
+    }
+
+    /// Starts building the binary `name` in the background, unless it's
+    /// already built or on its way.
+    pub fn request(&self, name: &str) -> &Self {
+        let mut state = self.lock();
+        if state.builds.contains_key(name) {
+            return self;
+        }
+        state.builds.insert(name.to_owned(), Build::Queued);
+        state.queue.push_back(name.to_owned());
+
+        if state.workers < self.threads {
+            state.workers += 1;
+            let directory = self.directory.clone();
+            let shared = self.shared.clone();
+            std::thread::spawn(move || work(&directory, &shared));
+        }
+        self
+    }
+
+    /// Starts building each of the binaries `names` in the background, in
+    /// order.
+    pub fn request_all<I, S>(&self, names: I) -> &Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        for name in names {
+            self.request(name.as_ref());
+        }
+        self
+    }
+
+    /// The binary `name`, once it's built. This blocks while it's being
+    /// built in the background. If it hasn't been started yet (including if
+    /// it was never [requested](TestBinaryPool::request)), it's built on this
+    /// thread instead.
+    pub fn get(&self, name: &str) -> Result<Artifact, TestBinaryError> {
+        let mut state = self.lock();
+        loop {
+            match state.builds.get(name) {
+                Some(Build::Built(artifact)) => return Ok(Artifact::clone(artifact)),
+                Some(Build::Building) => {
+                    state = self
+                        .shared
+                        .finished
+                        .wait(state)
+                        .unwrap_or_else(|e| e.into_inner());
+                }
+                Some(Build::Queued) | None => break,
+            }
+        }
+
+        // Take it off the queue and build it here, rather than waiting.
+        state.queue.retain(|queued| queued != name);
+        state.builds.insert(name.to_owned(), Build::Building);
+        drop(state);
+        finish(&self.shared, name, build(&self.directory, name))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.shared.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Builds what's queued until there's nothing left.
+fn work(directory: &Path, shared: &Shared) {
+    loop {
+        let name = {
+            let mut state = shared.state.lock().unwrap_or_else(|e| e.into_inner());
+            match state.queue.pop_front() {
+                Some(name) => {
+                    state.builds.insert(name.clone(), Build::Building);
+                    name
+                }
+                None => {
+                    state.workers -= 1;
+                    return;
+                }
+            }
+        };
+        let _ = finish(shared, &name, build(directory, &name));
+    }
+}
+
+fn build(directory: &Path, name: &str) -> Result<Artifact, TestBinaryError> {
+    TestBinary::in_directory(name, directory)?.build_artifact()
+}
+
+/// Records the result of building `name`, and wakes up anyone waiting for it.
+fn finish(
+    shared: &Shared,
+    name: &str,
+    result: Result<Artifact, TestBinaryError>,
+) -> Result<Artifact, TestBinaryError> {
+    let mut state = shared.state.lock().unwrap_or_else(|e| e.into_inner());
+    match &result {
+        Ok(artifact) => state
+            .builds
+            .insert(name.to_owned(), Build::Built(Box::new(artifact.clone()))),
+        Err(_) => state.builds.remove(name),
+    };
+    shared.finished.notify_all();
+    result
+}
@@ -0,0 +1,182 @@
+//! Reserving ports for test binaries to listen on.
+
+use once_cell::sync::Lazy;
+use std::{
+    collections::HashSet,
+    fmt,
+    net::{Ipv4Addr, SocketAddr, TcpListener, UdpSocket},
+    sync::{Arc, Mutex},
+};
+
+/// How many times to ask the OS for a port before giving up.
+const ATTEMPTS: usize = 100;
+
+/// Ports that have been handed out in this process and are still in use.
+static RESERVED: Lazy<Mutex<HashSet<(Protocol, u16)>>> = Lazy::new(Default::default);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Protocol {
+    Tcp,
+    Udp,
+}
+
+/// A bound socket, which is only kept to hold on to its port.
+#[derive(Debug)]
+enum Socket {
+    Tcp(#[allow(dead_code)] TcpListener),
+    Udp(#[allow(dead_code)] UdpSocket),
+}
+
+/// A free port on localhost for a test binary to listen on.
+///
+/// The port is found by binding to port 0 and letting the OS pick, and stays
+/// bound until it's released, so nothing else can take it in the meantime.
+/// Passing it to a [`Runner`](crate::Runner) with
+/// [`Runner::port_arg()`](crate::Runner::port_arg) or
+/// [`Runner::port_env()`](crate::Runner::port_env) releases it just before
+/// the binary starts, which leaves as small a window as possible for another
+/// process to grab it.
+///
+/// No other `Port` in this process will get the same number until every
+/// clone of this one has been dropped, so tests running in parallel won't
+/// collide with each other even after the port is released.
+///
+/// ```rust
+/// # use test_binary::Port;
+/// let port = Port::tcp().unwrap();
+/// let output = test_binary::stock::print_env()
+///     .unwrap()
+///     .runner()
+///     .port_env("PORT", &port)
+///     .arg("PORT")
+///     .run()
+///     .unwrap();
+/// assert_eq!(output.stdout(), format!("PORT={}\n", port).as_bytes());
+/// ```
+#[derive(Debug, Clone)]
+pub struct Port {
+    reservation: Arc<Reservation>,
+}
+
+#[derive(Debug)]
+struct Reservation {
+    protocol: Protocol,
+    number: u16,
+    socket: Mutex<Option<Socket>>,
+}
+
+impl Port {
+    /// Reserves a TCP port.
+    pub fn tcp() -> std::io::Result<Self> {
+        Self::reserve(Protocol::Tcp)
+    }
+
+    /// Reserves a UDP port.
+    pub fn udp() -> std::io::Result<Self> {
+        Self::reserve(Protocol::Udp)
+    }
+
+    fn reserve(protocol: Protocol) -> std::io::Result<Self> {
+        let localhost = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+        // Ports that are already taken in this process are kept bound until
+        // we've found one, so the OS doesn't offer them again.
+        let mut taken = vec![];
+
+        for _ in 0..ATTEMPTS {
+            let (socket, number) = match protocol {
+                Protocol::Tcp => {
+                    let listener = TcpListener::bind(localhost)?;
+                    let number = listener.local_addr()?.port();
+                    (Socket::Tcp(listener), number)
+                }
+                Protocol::Udp => {
+                    let socket = UdpSocket::bind(localhost)?;
+                    let number = socket.local_addr()?.port();
+                    (Socket::Udp(socket), number)
+                }
+            };
+
+            let mut reserved = RESERVED.lock().unwrap_or_else(|e| e.into_inner());
+            if reserved.insert((protocol, number)) {
+                return Ok(Self {
+                    reservation: Arc::new(Reservation {
+                        protocol,
+                        number,
+                        socket: Mutex::new(Some(socket)),
+                    }),
+                });
+            }
+            taken.push(socket);
+        }
+
+        Err(std::io::Error::new(
+            std::io::ErrorKind::AddrInUse,
+            "no free port found",
+        ))
+    }
+
+    /// The port number.
+    pub fn number(&self) -> u16 {
+        self.reservation.number
+    }
+
+    /// The address to connect to, on localhost.
+    pub fn addr(&self) -> SocketAddr {
+        SocketAddr::from((Ipv4Addr::LOCALHOST, self.number()))
+    }
+
+    /// Stops holding the port, so that a test binary can listen on it. This is
+    /// done for you by [`Runner`](crate::Runner) if the port was given to it,
+    /// but is needed if the port is passed some other way.
+    pub fn release(&self) {
+        self.reservation
+            .socket
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+    }
+}
+
+impl fmt::Display for Port {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.number())
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        RESERVED
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&(self.protocol, self.number));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reserve() {
+        let port = Port::tcp().unwrap();
+        // It's still bound, so nobody else can have it.
+        assert!(TcpListener::bind(port.addr()).is_err());
+        port.release();
+        let listener = TcpListener::bind(port.addr()).unwrap();
+        drop(listener);
+
+        let ports: Vec<_> = (0..20).map(|_| Port::udp().unwrap()).collect();
+        for port in &ports {
+            port.release();
+        }
+        let numbers: HashSet<_> = ports.iter().map(Port::number).collect();
+        assert_eq!(numbers.len(), ports.len());
+
+        let number = port.number();
+        let clone = port.clone();
+        drop(port);
+        assert!(RESERVED.lock().unwrap().contains(&(Protocol::Tcp, number)));
+        drop(clone);
+        assert!(!RESERVED.lock().unwrap().contains(&(Protocol::Tcp, number)));
+    }
+}
@@ -0,0 +1,90 @@
+//! Reporting what Cargo is doing while it builds a test binary.
+
+use std::{cell::RefCell, rc::Rc, time::Duration};
+
+/// Something that happened while Cargo was building a test binary, reported
+/// to the callback given to [`TestBinary::on_progress()`].
+///
+/// [`TestBinary::on_progress()`]: crate::TestBinary::on_progress
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BuildProgress {
+    /// Cargo is blocked waiting for a file lock held by another Cargo process
+    /// eg. the `cargo test` that's running your tests. This is normal lock
+    /// contention, not a hung build. The string is what Cargo says it's
+    /// waiting for, like `build directory` or `package cache`.
+    WaitingForLock(String),
+    /// Cargo has carried on after waiting for a lock.
+    LockAcquired,
+    /// Cargo hasn't output anything for longer than the period given to
+    /// [`TestBinary::with_watchdog()`].
+    ///
+    /// [`TestBinary::with_watchdog()`]: crate::TestBinary::with_watchdog
+    Stalled(Stall),
+    /// The binary was built, but it's bigger than the size given to
+    /// [`TestBinary::with_size_warning()`].
+    ///
+    /// [`TestBinary::with_size_warning()`]: crate::TestBinary::with_size_warning
+    OverSizeBudget {
+        /// The binary name.
+        name: String,
+        /// Its size in bytes.
+        size: u64,
+        /// The budget in bytes.
+        budget: u64,
+    },
+}
+
+/// Diagnostics for a build that has gone quiet, from the watchdog set up with
+/// [`TestBinary::with_watchdog()`].
+///
+/// [`TestBinary::with_watchdog()`]: crate::TestBinary::with_watchdog
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stall {
+    pub(crate) elapsed: Duration,
+    pub(crate) idle: Duration,
+    pub(crate) command: String,
+    pub(crate) last_message: Option<String>,
+}
+
+impl Stall {
+    /// How long it's been since Cargo was started.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// How long it's been since Cargo last output anything.
+    pub fn idle(&self) -> Duration {
+        self.idle
+    }
+
+    /// The Cargo command line.
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    /// The last thing Cargo output, if anything. For JSON messages this is
+    /// a summary eg. `compiler-artifact (does-build)`.
+    pub fn last_message(&self) -> Option<&str> {
+        self.last_message.as_deref()
+    }
+}
+
+impl std::fmt::Display for Stall {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Cargo has been quiet for {:?} ({:?} since it started): {}; last output: {}",
+            self.idle,
+            self.elapsed,
+            self.command,
+            self.last_message.as_deref().unwrap_or("none"),
+        )
+    }
+}
+
+/// A callback given to [`TestBinary::on_progress()`].
+///
+/// [`TestBinary::on_progress()`]: crate::TestBinary::on_progress
+/// Shared, so that clones of a builder all report to the same callback.
+pub(crate) type ProgressCallback<'a> = Rc<RefCell<dyn FnMut(&BuildProgress) + 'a>>;
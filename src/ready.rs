@@ -0,0 +1,168 @@
+//! Readiness probes for running test binaries.
+
+use crate::{run::POLL_INTERVAL, ChildGuard, RunError};
+use std::{
+    fmt,
+    net::{SocketAddr, TcpStream},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+/// How long to wait for each attempt to connect to a TCP port.
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// A way of telling when a running test binary is ready to be used, eg. that a
+/// mock server is listening. See [`ChildGuard::wait_ready()`].
+pub struct Readiness<'a> {
+    probe: Probe<'a>,
+}
+
+enum Probe<'a> {
+    Tcp(SocketAddr),
+    FileExists(PathBuf),
+    StdoutLine(String),
+    Custom(Box<dyn FnMut(&ChildGuard) -> bool + 'a>),
+}
+
+impl<'a> Readiness<'a> {
+    /// Ready when a TCP connection to the given port on localhost succeeds.
+    pub fn tcp_port(port: u16) -> Self {
+        Self::tcp(SocketAddr::from(([127, 0, 0, 1], port)))
+    }
+
+    /// Ready when a TCP connection to the given address succeeds.
+    pub fn tcp(addr: SocketAddr) -> Self {
+        Self {
+            probe: Probe::Tcp(addr),
+        }
+    }
+
+    /// Ready when the given path exists.
+    pub fn file_exists<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            probe: Probe::FileExists(path.into()),
+        }
+    }
+
+    /// Ready when the binary prints exactly this line (without the line
+    /// ending) to stdout. The line has to be finished, ie. followed by a line
+    /// ending, so that eg. `ready` doesn't match the start of `ready soon`.
+    pub fn stdout_line<S: Into<String>>(line: S) -> Self {
+        Self {
+            probe: Probe::StdoutLine(line.into()),
+        }
+    }
+
+    /// Ready when the closure returns true. It is called repeatedly until then.
+    pub fn custom<F: FnMut(&ChildGuard) -> bool + 'a>(check: F) -> Self {
+        Self {
+            probe: Probe::Custom(Box::new(check)),
+        }
+    }
+
+    /// Checks once whether the binary is ready.
+    fn check(&mut self, guard: &ChildGuard) -> bool {
+        match &mut self.probe {
+            Probe::Tcp(addr) => TcpStream::connect_timeout(addr, CONNECT_TIMEOUT).is_ok(),
+            Probe::FileExists(path) => path.exists(),
+            // This is waited for on the capture itself instead.
+            Probe::StdoutLine(_) => false,
+            Probe::Custom(check) => check(guard),
+        }
+    }
+}
+
+impl<'a> fmt::Debug for Readiness<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.probe {
+            Probe::Tcp(addr) => f.debug_tuple("Tcp").field(addr).finish(),
+            Probe::FileExists(path) => f.debug_tuple("FileExists").field(path).finish(),
+            Probe::StdoutLine(line) => f.debug_tuple("StdoutLine").field(line).finish(),
+            Probe::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+/// Whether the output contains the given line, ignoring line endings. Only
+/// finished lines count.
+fn has_line(output: &[u8], line: &str) -> bool {
+    output
+        .split_inclusive(|&byte| byte == b'\n')
+        .filter_map(|l| l.strip_suffix(b"\n"))
+        .any(|l| l.strip_suffix(b"\r").unwrap_or(l) == line.as_bytes())
+}
+
+impl ChildGuard {
+    /// Waits until the binary is ready according to the given probe. This is
+    /// an error if it doesn't become ready within `timeout`, or if it exits
+    /// first.
+    ///
+    /// ```rust
+    /// # use std::time::Duration;
+    /// # use test_binary::{build_test_binary, Artifact, Readiness};
+    /// let artifact = Artifact::from(build_test_binary("actions", "testbins").unwrap());
+    /// let mut server = artifact
+    ///     .runner()
+    ///     .args(["print", "listening", "sleep", "10000"])
+    ///     .spawn()
+    ///     .unwrap();
+    ///
+    /// server
+    ///     .wait_ready(Readiness::stdout_line("listening"), Duration::from_secs(10))
+    ///     .unwrap();
+    /// ```
+    pub fn wait_ready(
+        &mut self,
+        mut readiness: Readiness<'_>,
+        timeout: Duration,
+    ) -> Result<(), RunError> {
+        self.wait_ready_mut(&mut readiness, timeout)
+    }
+
+    pub(crate) fn wait_ready_mut(
+        &mut self,
+        readiness: &mut Readiness<'_>,
+        timeout: Duration,
+    ) -> Result<(), RunError> {
+        let deadline = Instant::now() + timeout;
+
+        if let Probe::StdoutLine(line) = &readiness.probe {
+            // Only what's been printed since the last check needs looking at,
+            // starting from the last unfinished line.
+            let mut start = 0;
+            let found = self.stdout_capture().wait_for(deadline, |data| {
+                let found = has_line(&data[start..], line);
+                start += data[start..]
+                    .iter()
+                    .rposition(|&byte| byte == b'\n')
+                    .map_or(0, |end| end + 1);
+                found.then_some(())
+            });
+            return found.ok_or_else(|| RunError::NotReady(self.name().to_owned()));
+        }
+
+        while !readiness.check(self) {
+            if Instant::now() >= deadline || self.try_wait()?.is_some() {
+                return Err(RunError::NotReady(self.name().to_owned()));
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines() {
+        assert!(has_line(b"starting\nready\n", "ready"));
+        assert!(has_line(b"starting\r\nready\r\n", "ready"));
+        assert!(!has_line(b"ready", "ready"));
+        assert!(has_line(b"ready\n", "ready"));
+        assert!(!has_line(b"not ready\n", "ready"));
+        assert!(!has_line(b"", "ready"));
+    }
+}
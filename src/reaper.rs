@@ -0,0 +1,119 @@
+//! Killing test binaries that are still running when the process exits.
+//!
+//! A [`ChildGuard`](crate::ChildGuard) kills its binary when it's dropped, but
+//! a guard that's leaked, or kept in a `static`, never is. So every binary is
+//! also registered here while it runs, and whatever's left when the process
+//! exits is killed, rather than being left to interfere with whatever runs
+//! next on the same machine.
+//!
+//! There's no safe way to run code as the process exits, so this needs unsafe
+//! code. It's only needed on Unix, since on Windows each binary is in a job
+//! object that's killed when its last handle is closed, which happens at exit
+//! anyway.
+
+#![allow(unsafe_code)]
+
+use nix::{
+    sys::signal::{killpg, Signal},
+    unistd::Pid,
+};
+use once_cell::sync::Lazy;
+use std::{
+    collections::HashSet,
+    sync::{Mutex, Once},
+};
+
+/// The process groups of the binaries that are running.
+#[derive(Debug, Default)]
+struct Registry {
+    groups: HashSet<u32>,
+}
+
+impl Registry {
+    /// Kills every process group still registered, and forgets them.
+    fn kill_all(&mut self) {
+        for group in self.groups.drain() {
+            // It might have exited since, and there's no one to tell anyway.
+            let _ = killpg(Pid::from_raw(group as i32), Signal::SIGKILL);
+        }
+    }
+}
+
+static REGISTRY: Lazy<Mutex<Registry>> = Lazy::new(Default::default);
+
+/// Registers a binary, whose process group has the same ID as the binary
+/// itself. The first time, this also sets up killing them all at exit.
+pub(crate) fn register(group: u32) {
+    static HOOKS: Once = Once::new();
+    HOOKS.call_once(install_hooks);
+    registry().groups.insert(group);
+}
+
+/// Forgets a binary once it's been waited on. Until then, it can't have been
+/// replaced by another process with the same ID.
+pub(crate) fn unregister(group: u32) {
+    registry().groups.remove(&group);
+}
+
+fn registry() -> std::sync::MutexGuard<'static, Registry> {
+    REGISTRY.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+extern "C" fn kill_survivors() {
+    registry().kill_all();
+}
+
+fn install_hooks() {
+    // SAFETY: the handler doesn't unwind, and only touches statics, which
+    // are still there while exit handlers run.
+    unsafe {
+        nix::libc::atexit(kill_survivors);
+    }
+
+    // When panics abort, exit handlers don't run, so this is the last chance.
+    // When they unwind, a panic only ends one test, and the rest of the
+    // process might still need its binaries.
+    #[cfg(panic = "abort")]
+    {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            previous(info);
+            kill_survivors();
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use command_group::CommandGroup;
+    use std::process::{Command, Stdio};
+
+    #[test]
+    fn kills_what_is_left() {
+        let spawn = || {
+            Command::new("sleep")
+                .arg("60")
+                .stdout(Stdio::null())
+                .group_spawn()
+                .unwrap()
+        };
+        let mut reaped = spawn();
+        let mut left = spawn();
+
+        let mut registry = Registry::default();
+        registry.groups.insert(reaped.id());
+        registry.groups.insert(left.id());
+        reaped.kill().unwrap();
+        reaped.wait().unwrap();
+        registry.groups.remove(&reaped.id());
+
+        registry.kill_all();
+        assert!(registry.groups.is_empty());
+        let status = left.wait().unwrap();
+        assert_eq!(
+            std::os::unix::process::ExitStatusExt::signal(&status),
+            Some(Signal::SIGKILL as i32)
+        );
+    }
+}
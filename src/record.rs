@@ -0,0 +1,103 @@
+//! Recording every build attempt to a file, for tracing slow or flaky builds
+//! across test processes.
+
+use serde::Serialize;
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+/// What we found out about a Cargo run while it was going, whether or not it
+/// succeeded.
+#[derive(Debug, Default)]
+pub(crate) struct BuildStats {
+    /// How long Cargo spent waiting for locks, in total.
+    pub(crate) lock_wait: Duration,
+    /// The targets Cargo compiled, if the build got far enough to know.
+    pub(crate) compiled: Option<Vec<String>>,
+}
+
+/// One line of a build record file.
+#[derive(Debug, Serialize)]
+pub(crate) struct BuildRecord<'a> {
+    /// Seconds since the Unix epoch, when the build started.
+    pub(crate) timestamp: f64,
+    pub(crate) binary: &'a str,
+    pub(crate) manifest: &'a Path,
+    pub(crate) command: String,
+    pub(crate) profile: Option<String>,
+    pub(crate) target: Option<String>,
+    pub(crate) features: &'a [&'a str],
+    pub(crate) duration_secs: f64,
+    pub(crate) lock_wait_secs: f64,
+    /// Whether everything was already built, or `None` if the build didn't
+    /// get far enough to know.
+    pub(crate) fresh: Option<bool>,
+    pub(crate) compiled: Option<Vec<String>>,
+    pub(crate) outcome: &'static str,
+    pub(crate) error: Option<String>,
+}
+
+/// Seconds since the Unix epoch.
+pub(crate) fn timestamp(time: SystemTime) -> f64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+/// Appends `record` to the file as a line of JSON. The line is written in one
+/// go, so that processes appending to the same file don't interleave.
+pub(crate) fn append(file: &Path, record: &BuildRecord) -> std::io::Result<()> {
+    let mut line = serde_json::to_vec(record)?;
+    line.push(b'\n');
+    if let Some(dir) = file.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)?;
+    }
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(file)?
+        .write_all(&line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn appends_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("builds").join("record.jsonl");
+        let record = BuildRecord {
+            timestamp: 1.5,
+            binary: "fla",
+            manifest: Path::new("testbins/fla/Cargo.toml"),
+            command: "cargo build".to_owned(),
+            profile: None,
+            target: None,
+            features: &["pink"],
+            duration_secs: 2.0,
+            lock_wait_secs: 0.0,
+            fresh: Some(false),
+            compiled: Some(vec!["fla".to_owned()]),
+            outcome: "success",
+            error: None,
+        };
+
+        append(&file, &record).unwrap();
+        append(&file, &record).unwrap();
+
+        let contents = std::fs::read_to_string(&file).unwrap();
+        let lines: Vec<serde_json::Value> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["binary"], "fla");
+        assert_eq!(lines[0]["features"][0], "pink");
+        assert_eq!(lines[0]["fresh"], false);
+        assert_eq!(lines[1]["error"], serde_json::Value::Null);
+    }
+}
@@ -0,0 +1,168 @@
+//! Finding a helper binary wherever it lives.
+
+use crate::{find_package, Artifact, ManifestError, TestBinary, TestBinaryError};
+use std::path::{Path, PathBuf};
+
+/// Locates the named binary, building it if necessary, by checking in order:
+///
+/// 1. the `CARGO_BIN_EXE_<name>` environment variable, which Cargo sets when
+///    running integration tests and benchmarks for binaries that are real
+///    `[[bin]]` targets of your crate (no build needed)
+/// 2. packages in the current workspace, as with
+///    [`TestBinary::from_workspace()`]
+/// 3. the `directory` relative to the parent, as with
+///    [`build_test_binary()`](crate::build_test_binary)
+///
+/// This gives test code one way to find any helper, and means helpers can be
+/// moved between these layouts without changing the tests that use them.
+///
+/// If the binary isn't found anywhere, the error is
+/// [`ManifestError::PackageNotFound`].
+pub fn resolve_test_binary<R: AsRef<Path>>(
+    name: &str,
+    directory: R,
+) -> Result<Artifact, TestBinaryError> {
+    if let Some(path) = std::env::var_os(format!("CARGO_BIN_EXE_{}", name)) {
+        return Ok(Artifact::new(path));
+    }
+
+    match find_package(name) {
+        Ok(manifest) => return TestBinary::with_manifest(name, manifest).build_artifact(),
+        Err(ManifestError::PackageNotFound(_)) => {}
+        Err(err) => return Err(err.into()),
+    }
+
+    let directory = directory.as_ref();
+    let parent = crate::manifest_dir()?;
+    let package = PathBuf::from_iter([directory, name.as_ref(), "Cargo.toml".as_ref()]);
+    let single_file = directory.join(format!("{}.rs", name));
+
+    if parent.join(package).exists() || parent.join(single_file).exists() {
+        TestBinary::in_directory(name, directory)?.build_artifact()
+    } else {
+        Err(ManifestError::PackageNotFound(name.to_owned()).into())
+    }
+}
+
+/// Finds the package that provides the binary `bin`, among the manifests
+/// matching any of `patterns`, which are relative to the parent. A package
+/// with a binary target of that name is preferred, then one with that name.
+/// Where several match, the first one wins, taking the patterns in order and
+/// the matches of each in alphabetical order.
+pub(crate) fn find_in_globs(bin: &str, patterns: &[&str]) -> Result<PathBuf, ManifestError> {
+    let parent = crate::manifest_dir()?;
+
+    let mut manifests = vec![];
+    for pattern in patterns {
+        let full = parent.join(pattern);
+        let paths = glob::glob(&full.to_string_lossy())
+            .map_err(|e| ManifestError::InvalidPattern(pattern.to_string(), e.to_string()))?;
+        // Unreadable directories can't hold the package we're looking for.
+        manifests.extend(paths.filter_map(Result::ok));
+    }
+
+    let mut named = None;
+    for manifest in manifests {
+        let package = PackageTargets::read(&manifest)?;
+        if package.has_bin(bin) {
+            return Ok(manifest);
+        }
+        if named.is_none() && package.name.as_deref() == Some(bin) {
+            named = Some(manifest);
+        }
+    }
+    named.ok_or_else(|| ManifestError::PackageNotFound(bin.to_owned()))
+}
+
+/// Just enough of a manifest to know what binaries its package provides,
+/// without running Cargo.
+#[derive(Debug, Default)]
+struct PackageTargets {
+    dir: PathBuf,
+    name: Option<String>,
+    bins: Vec<String>,
+}
+
+impl PackageTargets {
+    fn read(manifest: &Path) -> Result<Self, ManifestError> {
+        let read_error = |e: String| ManifestError::ReadManifest(manifest.to_path_buf(), e);
+        let contents = std::fs::read_to_string(manifest).map_err(|e| read_error(e.to_string()))?;
+        let table: toml::Table = contents.parse().map_err(|e| read_error(format!("{}", e)))?;
+
+        let name = table
+            .get("package")
+            .and_then(|package| package.get("name"))
+            .and_then(|name| name.as_str())
+            .map(str::to_owned);
+        let bins = table
+            .get("bin")
+            .and_then(|bins| bins.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|bin| bin.get("name")?.as_str().map(str::to_owned))
+            .collect();
+
+        Ok(Self {
+            dir: manifest.parent().unwrap_or(Path::new("")).to_path_buf(),
+            name,
+            bins,
+        })
+    }
+
+    /// Whether the package has a binary target named `bin`, either declared
+    /// or found by Cargo's automatic target discovery.
+    fn has_bin(&self, bin: &str) -> bool {
+        let src = self.dir.join("src");
+        self.bins.iter().any(|name| name == bin)
+            || (self.name.as_deref() == Some(bin) && src.join("main.rs").exists())
+            || src.join("bin").join(format!("{}.rs", bin)).exists()
+            || src.join("bin").join(bin).join("main.rs").exists()
+    }
+}
+
+/// Implementation detail of [`artifact_dependency!`], which uses the path Cargo
+/// gave for an artifact dependency if there is one, or builds the binary
+/// otherwise.
+///
+/// [`artifact_dependency!`]: crate::artifact_dependency
+#[doc(hidden)]
+pub fn artifact_dependency_or_build<R: AsRef<Path>>(
+    artifact_path: Option<&str>,
+    name: &str,
+    directory: R,
+) -> Result<Artifact, TestBinaryError> {
+    match artifact_path {
+        Some(path) if Path::new(path).exists() => Ok(Artifact::new(path)),
+        _ => TestBinary::in_directory(name, directory.as_ref())?.build_artifact(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn package_targets() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("testbins");
+
+        let package = PackageTargets::read(&dir.join("mock_server").join("Cargo.toml")).unwrap();
+        assert_eq!(package.name.as_deref(), Some("mock-server"));
+        assert!(package.has_bin("mock-server"));
+        assert!(package.has_bin("mock-client"));
+        assert!(!package.has_bin("mock_server"));
+
+        let package = PackageTargets::read(&dir.join("does-build").join("Cargo.toml")).unwrap();
+        assert!(package.has_bin("does-build"));
+        assert!(!package.has_bin("mock-client"));
+    }
+
+    #[test]
+    fn artifact_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fla");
+        std::fs::write(&path, "").unwrap();
+
+        let artifact = artifact_dependency_or_build(path.to_str(), "fla", "testbins").unwrap();
+        assert_eq!(artifact.path(), path);
+    }
+}
@@ -0,0 +1,101 @@
+//! Retrying flaky test binary runs.
+
+use crate::RunOutput;
+use std::time::Duration;
+
+/// When and how often to retry a run of a test binary. See
+/// [`Runner::retry()`](crate::Runner::retry).
+///
+/// By default, any unsuccessful run (including a timeout) is retried. If you
+/// specify exit codes with [`RetryPolicy::on_exit_code()`] or
+/// [`RetryPolicy::on_timeout()`], only those outcomes will be retried.
+///
+/// ```
+/// # use std::time::Duration;
+/// # use test_binary::RetryPolicy;
+/// // Retry up to twice more if the mock server fails to bind (exit code 98),
+/// // waiting 100ms and then 200ms.
+/// RetryPolicy::new(3)
+///     .with_backoff(Duration::from_millis(100))
+///     .on_exit_code(98);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    attempts: usize,
+    backoff: Duration,
+    exit_codes: Vec<i32>,
+    timeouts: bool,
+}
+
+impl RetryPolicy {
+    /// Creates a policy that runs the binary up to `attempts` times in total.
+    pub fn new(attempts: usize) -> Self {
+        Self {
+            attempts,
+            backoff: Duration::ZERO,
+            exit_codes: vec![],
+            timeouts: false,
+        }
+    }
+
+    /// Specifies how long to wait before the first retry. The delay doubles
+    /// after each subsequent attempt.
+    pub fn with_backoff(&mut self, initial: Duration) -> &mut Self {
+        self.backoff = initial;
+        self
+    }
+
+    /// Specifies an exit code that should trigger a retry. These are
+    /// additive, so you can call this multiple times.
+    pub fn on_exit_code(&mut self, code: i32) -> &mut Self {
+        self.exit_codes.push(code);
+        self
+    }
+
+    /// Specifies that a run which exceeded its
+    /// [timeout](crate::Runner::timeout) should be retried.
+    pub fn on_timeout(&mut self) -> &mut Self {
+        self.timeouts = true;
+        self
+    }
+
+    /// The total number of attempts allowed.
+    pub(crate) fn attempts(&self) -> usize {
+        self.attempts
+    }
+
+    /// How long to wait after the given (zero-based) failed attempt.
+    pub(crate) fn delay(&self, attempt: usize) -> Duration {
+        self.backoff
+            .checked_mul(1 << attempt.min(31))
+            .unwrap_or(Duration::MAX)
+    }
+
+    /// Whether the run's outcome is one we should retry.
+    pub(crate) fn should_retry(&self, output: &RunOutput) -> bool {
+        if self.exit_codes.is_empty() && !self.timeouts {
+            return !output.status().success() || output.timed_out();
+        }
+
+        (self.timeouts && output.timed_out())
+            || output
+                .status()
+                .code()
+                .is_some_and(|code| self.exit_codes.contains(&code))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles() {
+        let mut policy = RetryPolicy::new(4);
+        policy.with_backoff(Duration::from_millis(50));
+        assert_eq!(policy.delay(0), Duration::from_millis(50));
+        assert_eq!(policy.delay(1), Duration::from_millis(100));
+        assert_eq!(policy.delay(2), Duration::from_millis(200));
+        assert!(policy.delay(200) > Duration::from_secs(86_400));
+    }
+}
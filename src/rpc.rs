@@ -0,0 +1,145 @@
+//! Driving test binaries over a request/response protocol on their stdio.
+
+use crate::{child::Capture, ChildGuard, RunError, RunOutput};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    io::Write,
+    process::ChildStdin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use test_binary_rpc::{decode_frame, encode, Request, Response};
+
+/// How long to wait for each response, unless told otherwise.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The test side of a conversation with a binary that serves requests using
+/// the [`test-binary-rpc`](https://docs.rs/test-binary-rpc) crate. Start with
+/// [`Runner::rpc()`](crate::Runner::rpc).
+///
+/// Each [`RpcClient::call()`] sends a request to the binary's stdin and waits
+/// for the response on its stdout, so a single mock binary can be told what
+/// to do step by step by the test, instead of following a fixed script.
+///
+/// ```rust
+/// let server = test_binary::TestBinary::relative_to_parent(
+///     "rpc-server",
+///     &std::path::PathBuf::from_iter(["testbins", "rpc-server", "Cargo.toml"]),
+/// )
+/// .unwrap()
+/// .build_artifact()
+/// .unwrap();
+///
+/// let mut client = server.runner().rpc().unwrap();
+/// let sum: i64 = client.call("add", (1, 2)).unwrap();
+/// assert_eq!(sum, 3);
+/// assert!(client.finish().unwrap().status().success());
+/// ```
+///
+/// Like a [`ChildGuard`], the binary is killed if the client is dropped
+/// before it exits.
+#[cfg_attr(docsrs, doc(cfg(feature = "rpc")))]
+#[derive(Debug)]
+pub struct RpcClient {
+    guard: ChildGuard,
+    input: Option<ChildStdin>,
+    output: Arc<Capture>,
+    /// How much of the output has been read as responses.
+    position: usize,
+    next_id: u64,
+    timeout: Duration,
+}
+
+impl RpcClient {
+    pub(crate) fn new(mut guard: ChildGuard) -> Self {
+        let input = guard.child_mut().stdin.take();
+        let output = Arc::clone(guard.stdout_capture());
+        Self {
+            guard,
+            input,
+            output,
+            position: 0,
+            next_id: 1,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Sets how long to wait for each response. The default is 10 seconds.
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Calls `method` in the binary with `params`, and waits for the result.
+    ///
+    /// If the binary responds with an error, doesn't respond in time, or
+    /// responds with something that isn't an `R`, this returns
+    /// [`RunError::RpcFailed`].
+    pub fn call<P, R>(&mut self, method: &str, params: P) -> Result<R, RunError>
+    where
+        P: Serialize,
+        R: DeserializeOwned,
+    {
+        let failed = |reason: String| RunError::RpcFailed {
+            method: method.to_owned(),
+            reason,
+        };
+
+        let id = self.next_id;
+        self.next_id += 1;
+        let params = serde_json::to_value(params)
+            .map_err(|err| failed(format!("invalid parameters: {}", err)))?;
+        let request = encode(&Request {
+            id,
+            method: method.to_owned(),
+            params,
+        })?;
+
+        let input = self.input.as_mut().ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::BrokenPipe, "input already closed")
+        })?;
+        input.write_all(&request)?;
+        input.flush()?;
+
+        let deadline = Instant::now() + self.timeout;
+        let position = self.position;
+        let (json, length) = self
+            .output
+            .wait_for(deadline, |data| {
+                decode_frame(&data[position..]).map(|(json, length)| (json.to_vec(), length))
+            })
+            .ok_or_else(|| failed("the binary exited or didn't respond in time".to_owned()))?;
+        self.position += length;
+
+        let response: Response = serde_json::from_slice(&json)
+            .map_err(|err| failed(format!("invalid response: {}", err)))?;
+        if response.id != id {
+            return Err(failed(format!(
+                "response was for request {}, not {}",
+                response.id, id
+            )));
+        }
+        if let Some(error) = response.error {
+            return Err(failed(error));
+        }
+        serde_json::from_value(response.result.unwrap_or_default())
+            .map_err(|err| failed(format!("invalid result: {}", err)))
+    }
+
+    /// The running binary.
+    pub fn guard(&mut self) -> &mut ChildGuard {
+        &mut self.guard
+    }
+
+    /// Closes the binary's stdin, which tells it there are no more requests,
+    /// and waits for it to exit.
+    pub fn finish(mut self) -> Result<RunOutput, RunError> {
+        self.input.take();
+        self.guard.wait()
+    }
+
+    /// Kills the binary and waits for it to exit.
+    pub fn kill(self) -> Result<RunOutput, RunError> {
+        self.guard.kill()
+    }
+}
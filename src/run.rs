@@ -0,0 +1,1218 @@
+//! Helpers for running test binaries once they're built.
+
+use command_group::CommandGroup;
+use std::{
+    ffi::{OsStr, OsString},
+    io::Read,
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Stdio},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tempfile::TempPath;
+
+use crate::{
+    child::Capturing,
+    launch::LaunchContext,
+    log::{LineSink, OutputOptions},
+    usage::{ResourceUsage, Sampler},
+    ChildGuard, ComponentRuntime, Dependency, Input, Normalizer, Port, RetryPolicy, Session,
+};
+
+/// How often to check on a running binary when we can't just block on it.
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A built test binary.
+///
+/// This is mostly a wrapper around the path to the binary, but it also
+/// provides a way to run the binary with a few test-oriented extras via
+/// [`Artifact::runner()`]. You can get one from
+/// [`TestBinary::build_artifact()`](crate::TestBinary::build_artifact), or from
+/// any path eg. the result of [`build_test_binary()`](crate::build_test_binary)
+/// by using [`Artifact::from()`].
+#[derive(Debug, Clone)]
+pub struct Artifact {
+    path: PathBuf,
+    output_dir: PathBuf,
+    pub(crate) diagnostics: String,
+    pub(crate) out_dir: Option<PathBuf>,
+    pub(crate) rustc_env: Vec<(String, String)>,
+    pub(crate) build_script_warnings: Vec<String>,
+    pub(crate) target: Option<String>,
+    pub(crate) dependencies: Option<Vec<Dependency>>,
+    pub(crate) future_incompat_report: Option<String>,
+    pub(crate) defaults: RunDefaults,
+    pub(crate) runtime: Option<ComponentRuntime>,
+}
+
+/// Arguments, environment and working directory that every run of an
+/// [`Artifact`] starts with.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RunDefaults {
+    pub(crate) args: Vec<OsString>,
+    pub(crate) envs: Vec<(OsString, OsString)>,
+    pub(crate) current_dir: Option<PathBuf>,
+}
+
+impl Artifact {
+    /// Creates an artifact for the binary at the given path.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        let path = path.into();
+        let output_dir = path.parent().map(Path::to_owned).unwrap_or_default();
+        Self {
+            path,
+            output_dir,
+            diagnostics: String::new(),
+            out_dir: None,
+            rustc_env: vec![],
+            build_script_warnings: vec![],
+            target: None,
+            dependencies: None,
+            future_incompat_report: None,
+            defaults: RunDefaults::default(),
+            runtime: None,
+        }
+    }
+
+    /// The path of the built binary.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The size of the binary in bytes, as it is now.
+    pub fn size(&self) -> std::io::Result<u64> {
+        Ok(std::fs::metadata(&self.path)?.len())
+    }
+
+    /// The same artifact, for a copy of the binary at `path`. The output
+    /// directory is still the one Cargo used.
+    pub(crate) fn relocated(mut self, path: PathBuf) -> Self {
+        self.path = path;
+        self
+    }
+
+    /// Copies the binary into `dir`, creating it if necessary, and returns an
+    /// artifact for the copy. Any previous copy is replaced atomically, so a
+    /// copy that's running isn't disturbed. The [output
+    /// directory](Artifact::output_dir) is still the one Cargo used.
+    pub fn install_to<P: AsRef<Path>>(&self, dir: P) -> std::io::Result<Artifact> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+        let destination = dir.join(self.path.file_name().unwrap_or_default());
+
+        let temp = tempfile::NamedTempFile::new_in(dir)?;
+        std::fs::copy(&self.path, temp.path())?;
+        temp.persist(&destination).map_err(|e| e.error)?;
+
+        Ok(self.clone().relocated(destination))
+    }
+
+    /// The directory Cargo put the binary in eg. `target/debug`, for the
+    /// target and profile it was built with. Anything else the build placed
+    /// alongside the binary (eg. dynamic libraries, or files copied there by
+    /// a build script) can be found here.
+    pub fn output_dir(&self) -> &Path {
+        &self.output_dir
+    }
+
+    /// Compiler messages from building the binary eg. warnings, as configured
+    /// with [`TestBinary::with_diagnostic_level()`]. This is empty if the
+    /// artifact wasn't built by this crate.
+    ///
+    /// [`TestBinary::with_diagnostic_level()`]: crate::TestBinary::with_diagnostic_level
+    pub fn diagnostics(&self) -> &str {
+        &self.diagnostics
+    }
+
+    /// The `OUT_DIR` of the binary's build script, if its package has one and
+    /// the artifact was built by this crate. This is where to find anything the
+    /// build script generated.
+    pub fn out_dir(&self) -> Option<&Path> {
+        self.out_dir.as_deref()
+    }
+
+    /// Environment variables the binary's build script set for compiling it,
+    /// with `cargo::rustc-env=KEY=VALUE`. This is empty if there's no build
+    /// script or the artifact wasn't built by this crate.
+    pub fn rustc_env(&self) -> &[(String, String)] {
+        &self.rustc_env
+    }
+
+    /// Cargo's report of code in the binary or its dependencies that a future
+    /// version of Rust will reject, if it was asked for with
+    /// [`TestBinary::with_future_incompat_report()`] and there is any such
+    /// code.
+    ///
+    /// [`TestBinary::with_future_incompat_report()`]: crate::TestBinary::with_future_incompat_report
+    pub fn future_incompat_report(&self) -> Option<&str> {
+        self.future_incompat_report.as_deref()
+    }
+
+    /// Warnings printed by build scripts with `cargo::warning=MESSAGE`, while
+    /// building the binary. Cargo only reports these for packages that are
+    /// local to the child eg. path dependencies, not for those from a
+    /// registry.
+    pub fn build_script_warnings(&self) -> &[String] {
+        &self.build_script_warnings
+    }
+
+    /// The target triple the binary was built for eg.
+    /// `x86_64-unknown-linux-musl`. This is the host's triple unless it was
+    /// cross-compiled. It's `None` if the artifact wasn't built by this crate,
+    /// or if Cargo couldn't tell us the host's triple.
+    pub fn target(&self) -> Option<&str> {
+        self.target.as_deref()
+    }
+
+    /// The packages the binary was built with, if that was asked for with
+    /// [`TestBinary::capture_dependencies()`]. These are sorted by name and
+    /// version, and don't include the binary's own package.
+    ///
+    /// [`TestBinary::capture_dependencies()`]: crate::TestBinary::capture_dependencies
+    pub fn dependencies(&self) -> Option<&[Dependency]> {
+        self.dependencies.as_deref()
+    }
+
+    /// Adds arguments that every run of the binary starts with, before any
+    /// given to the [`Runner`]. Also see [`TestBinary::with_default_args()`].
+    ///
+    /// [`TestBinary::with_default_args()`]: crate::TestBinary::with_default_args
+    pub fn with_default_args<I, S>(&mut self, args: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.defaults
+            .args
+            .extend(args.into_iter().map(|arg| arg.as_ref().to_owned()));
+        self
+    }
+
+    /// Sets an environment variable for every run of the binary. The
+    /// [`Runner`] can override it.
+    pub fn with_default_env<K: AsRef<OsStr>, V: AsRef<OsStr>>(
+        &mut self,
+        key: K,
+        value: V,
+    ) -> &mut Self {
+        self.defaults
+            .envs
+            .push((key.as_ref().to_owned(), value.as_ref().to_owned()));
+        self
+    }
+
+    /// Sets the working directory for every run of the binary. The [`Runner`]
+    /// can override it.
+    pub fn with_default_current_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.defaults.current_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Runs the binary with `runtime`, as a WebAssembly component. This is
+    /// set up by [`TestBinary::build_component()`], but can be used for any
+    /// component.
+    ///
+    /// [`TestBinary::build_component()`]: crate::TestBinary::build_component
+    pub fn with_runtime(&mut self, runtime: ComponentRuntime) -> &mut Self {
+        self.runtime = Some(runtime);
+        self
+    }
+
+    /// The runtime the binary is run with, if it's a component.
+    pub fn runtime(&self) -> Option<&ComponentRuntime> {
+        self.runtime.as_ref()
+    }
+
+    /// Creates a [`std::process::Command`] for the binary, for when you need
+    /// more control than [`Artifact::runner()`] gives you. It has the
+    /// artifact's default arguments, environment and working directory, if
+    /// there are any. For a component, the command runs its
+    /// [runtime](Artifact::runtime).
+    pub fn command(&self) -> Command {
+        let mut command = match &self.runtime {
+            Some(runtime) => runtime.command(&self.path),
+            None => Command::new(&self.path),
+        };
+        command
+            .args(&self.defaults.args)
+            .envs(self.defaults.envs.iter().map(|(k, v)| (k, v)));
+        if let Some(dir) = &self.defaults.current_dir {
+            command.current_dir(dir);
+        }
+        command
+    }
+
+    /// Starts configuring a run of the binary.
+    pub fn runner(&self) -> Runner<'_> {
+        Runner {
+            artifact: self,
+            args: vec![],
+            envs: vec![],
+            current_dir: None,
+            capture_backtrace: false,
+            capture_panic: false,
+            sample_usage: false,
+            timeout: None,
+            retry: None,
+            output: OutputOptions::default(),
+            ports: vec![],
+            grace_period: None,
+            input: None,
+            spill_threshold: None,
+            #[cfg(unix)]
+            user: None,
+            #[cfg(unix)]
+            scheduling: Default::default(),
+        }
+    }
+}
+
+impl From<OsString> for Artifact {
+    fn from(path: OsString) -> Self {
+        Self::new(path)
+    }
+}
+
+impl From<PathBuf> for Artifact {
+    fn from(path: PathBuf) -> Self {
+        Self::new(path)
+    }
+}
+
+impl From<Artifact> for OsString {
+    fn from(artifact: Artifact) -> Self {
+        artifact.path.into_os_string()
+    }
+}
+
+impl AsRef<Path> for Artifact {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<OsStr> for Artifact {
+    fn as_ref(&self) -> &OsStr {
+        self.path.as_os_str()
+    }
+}
+
+/// Builder for a single run of a test binary. Start with
+/// [`Artifact::runner()`].
+#[derive(Debug, Clone)]
+pub struct Runner<'a> {
+    artifact: &'a Artifact,
+    pub(crate) args: Vec<OsString>,
+    pub(crate) envs: Vec<(OsString, OsString)>,
+    pub(crate) current_dir: Option<PathBuf>,
+    capture_backtrace: bool,
+    capture_panic: bool,
+    sample_usage: bool,
+    timeout: Option<Duration>,
+    retry: Option<RetryPolicy>,
+    output: OutputOptions,
+    ports: Vec<Port>,
+    grace_period: Option<Duration>,
+    input: Option<Input>,
+    spill_threshold: Option<usize>,
+    #[cfg(unix)]
+    user: Option<(u32, u32)>,
+    #[cfg(unix)]
+    scheduling: crate::scheduling::Scheduling,
+}
+
+impl<'a> Runner<'a> {
+    /// Adds an argument to pass to the binary.
+    pub fn arg<S: AsRef<OsStr>>(&mut self, arg: S) -> &mut Self {
+        self.args.push(arg.as_ref().to_owned());
+        self
+    }
+
+    /// Adds several arguments to pass to the binary.
+    pub fn args<I, S>(&mut self, args: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        for arg in args {
+            self.arg(arg);
+        }
+        self
+    }
+
+    /// Sets an environment variable for the binary.
+    pub fn env<K: AsRef<OsStr>, V: AsRef<OsStr>>(&mut self, key: K, value: V) -> &mut Self {
+        self.envs
+            .push((key.as_ref().to_owned(), value.as_ref().to_owned()));
+        self
+    }
+
+    /// Passes a reserved port to the binary as an argument. The port is
+    /// released just before the binary starts, so that it can listen on it.
+    pub fn port_arg(&mut self, port: &Port) -> &mut Self {
+        self.ports.push(port.clone());
+        self.arg(port.to_string())
+    }
+
+    /// Passes a reserved port to the binary in an environment variable. The
+    /// port is released just before the binary starts, so that it can listen
+    /// on it.
+    pub fn port_env<K: AsRef<OsStr>>(&mut self, key: K, port: &Port) -> &mut Self {
+        self.ports.push(port.clone());
+        self.env(key, port.to_string())
+    }
+
+    /// Feeds the binary's stdin from `input`, instead of leaving it empty.
+    /// This applies to [`Runner::run()`] and [`Runner::spawn()`]; interactive
+    /// sessions have input of their own.
+    pub fn stdin(&mut self, input: &Input) -> &mut Self {
+        self.input = Some(input.clone());
+        self
+    }
+
+    /// Keeps at most `threshold` bytes of each of stdout and stderr in
+    /// memory. If the binary writes more than that, all of it is written to
+    /// a temporary file instead, and only the start is kept in memory, for
+    /// binaries whose output would otherwise use up the test's memory. See
+    /// [`RunOutput::stdout_path()`] and [`RunOutput::stdout_reader()`].
+    pub fn spill_output(&mut self, threshold: usize) -> &mut Self {
+        self.spill_threshold = Some(threshold);
+        self
+    }
+
+    /// Sets how long the binary gets to exit after being sent
+    /// [`Signal::Term`](crate::Signal::Term) when its [`ChildGuard`] is
+    /// dropped or its [`RunningHarness`](crate::RunningHarness) is stopped,
+    /// before it's killed. Zero means it's killed straight away. This takes
+    /// precedence over the default [`grace_period()`](crate::grace_period),
+    /// including the environment variable.
+    pub fn grace_period(&mut self, grace_period: Duration) -> &mut Self {
+        self.grace_period = Some(grace_period);
+        self
+    }
+
+    /// Runs the binary as another user and group, for testing how it handles
+    /// permissions. This needs the test to be running as root, as it often is
+    /// in a container; otherwise starting the binary fails with
+    /// [`RunError::CannotSwitchUser`], unless `uid` and `gid` are our own.
+    ///
+    /// The binary must be somewhere the user can get to, which a target
+    /// directory under a root-only home directory isn't.
+    #[cfg(unix)]
+    #[cfg_attr(docsrs, doc(cfg(unix)))]
+    pub fn run_as(&mut self, uid: u32, gid: u32) -> &mut Self {
+        self.user = Some((uid, gid));
+        self
+    }
+
+    /// Runs the binary at a lower priority, by adding `niceness` to ours with
+    /// `nice`, eg. for a helper that does a lot of work in the background.
+    /// Negative values raise the priority instead, which usually needs root.
+    #[cfg(unix)]
+    #[cfg_attr(docsrs, doc(cfg(unix)))]
+    pub fn niceness(&mut self, niceness: i32) -> &mut Self {
+        self.scheduling.niceness = Some(niceness);
+        self
+    }
+
+    /// Runs the binary on only these CPUs, numbered from zero, with
+    /// `taskset`.
+    #[cfg(target_os = "linux")]
+    #[cfg_attr(docsrs, doc(cfg(target_os = "linux")))]
+    pub fn cpus<I: IntoIterator<Item = usize>>(&mut self, cpus: I) -> &mut Self {
+        self.scheduling.cpus = Some(cpus.into_iter().collect());
+        self
+    }
+
+    /// Sets the working directory for the binary.
+    pub fn current_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.current_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Runs the binary with `RUST_BACKTRACE=full` (unless you've set
+    /// `RUST_BACKTRACE` yourself) and, if it crashes, attaches the backtrace
+    /// it printed to the [`RunOutput`]. See [`RunOutput::crash()`].
+    pub fn capture_crash_backtrace(&mut self) -> &mut Self {
+        self.capture_backtrace = true;
+        self
+    }
+
+    /// Runs the binary with `RUST_BACKTRACE=full` (unless you've set
+    /// `RUST_BACKTRACE` yourself) and, if it fails, picks out the panic
+    /// message and backtrace it printed. They're in [`RunOutput::panic()`],
+    /// and the [failure summary](RunOutput::failure_summary), so that a
+    /// helper's panic can be debugged straight from the test's output.
+    ///
+    /// Unlike [`Runner::capture_crash_backtrace()`], this works with panics
+    /// that unwind, which is the default.
+    pub fn capture_panics(&mut self) -> &mut Self {
+        self.capture_panic = true;
+        self
+    }
+
+    /// Records the binary's peak memory usage and CPU time. See
+    /// [`RunOutput::resource_usage()`].
+    ///
+    /// This is supported on Unix and Windows. On other platforms the
+    /// [`ResourceUsage`] will be empty.
+    pub fn sample_resource_usage(&mut self) -> &mut Self {
+        self.sample_usage = true;
+        self
+    }
+
+    /// Kills the binary if it runs for longer than `timeout`. See
+    /// [`RunOutput::timed_out()`].
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Runs the binary again if it fails, according to the given policy. The
+    /// result of the last attempt is returned, with any earlier attempts
+    /// available via [`RunOutput::previous_attempts()`].
+    pub fn retry(&mut self, policy: &RetryPolicy) -> &mut Self {
+        self.retry = Some(policy.clone());
+        self
+    }
+
+    /// Streams the binary's stdout and stderr into the test's output as it
+    /// runs, line by line, with each line prefixed by `[name]` where `name` is
+    /// the binary's name. This makes it possible to tell apart the output of
+    /// several binaries running at once.
+    pub fn echo_output(&mut self) -> &mut Self {
+        self.output.echo = true;
+        self
+    }
+
+    /// Writes the binary's stdout and stderr to a log file in `dir` as it
+    /// runs. The file is named after the binary and its process ID, and is
+    /// deleted if the binary exits successfully. Otherwise its path is
+    /// available from [`RunOutput::log_file()`].
+    pub fn keep_log_on_failure<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.output.log_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Forwards structured logs from the binary to [`tracing`] as they arrive.
+    /// Each line the binary writes to stderr that is a JSON log record, in the
+    /// format of `tracing-subscriber`'s JSON formatter, is emitted as an event
+    /// with the target `test_binary::child`. Events are emitted within a
+    /// `child` span that has the binary's name and process ID.
+    ///
+    /// Forwarded lines are still captured, but aren't echoed or logged by
+    /// [`Runner::echo_output()`] or [`Runner::keep_log_on_failure()`].
+    #[cfg(feature = "tracing")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "tracing")))]
+    pub fn forward_tracing(&mut self) -> &mut Self {
+        self.output.tracing = true;
+        self
+    }
+
+    fn command(&self) -> Command {
+        let mut command = self.artifact.command();
+        command.args(&self.args);
+
+        let backtrace_set = self
+            .envs
+            .iter()
+            .chain(&self.artifact.defaults.envs)
+            .any(|(k, _)| k == "RUST_BACKTRACE");
+        if (self.capture_backtrace || self.capture_panic) && !backtrace_set {
+            command.env("RUST_BACKTRACE", "full");
+        }
+
+        command.envs(self.envs.iter().map(|(k, v)| (k, v)));
+
+        if let Some(dir) = &self.current_dir {
+            command.current_dir(dir);
+        }
+
+        #[cfg(unix)]
+        let mut command = self.scheduling.apply(command);
+        #[cfg(unix)]
+        if let Some((uid, gid)) = self.user {
+            use std::os::unix::process::CommandExt;
+            command.uid(uid).gid(gid);
+        }
+
+        command
+    }
+
+    /// Checks that we're allowed to start the binary as the user it's
+    /// configured to run as, since the error from spawning it otherwise is
+    /// just "operation not permitted".
+    #[cfg(unix)]
+    fn check_user(&self) -> Result<(), RunError> {
+        use nix::unistd::{getegid, geteuid};
+
+        match self.user {
+            Some((uid, gid))
+                if !geteuid().is_root()
+                    && (uid != geteuid().as_raw() || gid != getegid().as_raw()) =>
+            {
+                Err(RunError::CannotSwitchUser { uid, gid })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Runs the binary to completion, capturing its output.
+    pub fn run(&mut self) -> Result<RunOutput, RunError> {
+        let mut previous_attempts = vec![];
+
+        loop {
+            let mut output = self.run_once()?;
+
+            match &self.retry {
+                Some(policy)
+                    if previous_attempts.len() + 1 < policy.attempts()
+                        && policy.should_retry(&output) =>
+                {
+                    std::thread::sleep(policy.delay(previous_attempts.len()));
+                    previous_attempts.push(output);
+                }
+                _ => {
+                    output.previous_attempts = previous_attempts;
+                    return Ok(output);
+                }
+            }
+        }
+    }
+
+    /// Starts the binary in the background, capturing its output. See
+    /// [`ChildGuard`].
+    ///
+    /// Note that the [timeout](Runner::timeout) and [retry
+    /// policy](Runner::retry) only apply to [`Runner::run()`].
+    pub fn spawn(&self) -> Result<ChildGuard, RunError> {
+        let feeder = match &self.input {
+            Some(input) => Some(input.open()?),
+            None => None,
+        };
+        let stdin = if feeder.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        };
+
+        let mut guard = self.spawn_with(stdin, Stdio::piped(), Stdio::piped())?;
+        if let (Some(feeder), Some(stdin)) = (feeder, guard.child_mut().stdin.take()) {
+            feeder.start(stdin);
+        }
+        Ok(guard)
+    }
+
+    /// Starts the binary in the background with its stdin connected to the
+    /// returned [`Session`], for tests of interactive programs.
+    pub fn interact(&self) -> Result<Session, RunError> {
+        let guard = self.spawn_with(Stdio::piped(), Stdio::piped(), Stdio::piped())?;
+        Ok(Session::new(guard))
+    }
+
+    /// Starts a binary that serves requests with the
+    /// [`test-binary-rpc`](https://docs.rs/test-binary-rpc) crate, and
+    /// returns an [`RpcClient`](crate::RpcClient) for calling it. Its stdin
+    /// and stdout carry the requests and responses; its stderr is captured
+    /// as usual.
+    #[cfg(feature = "rpc")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "rpc")))]
+    pub fn rpc(&self) -> Result<crate::RpcClient, RunError> {
+        let guard = self.spawn_with(Stdio::piped(), Stdio::piped(), Stdio::piped())?;
+        Ok(crate::RpcClient::new(guard))
+    }
+
+    /// Starts the binary in the background with a pseudoterminal as its
+    /// stdin, stdout and stderr, connected to the returned [`Session`]. This
+    /// is for binaries that only prompt, colour their output or read
+    /// passwords when they're talking to a terminal.
+    ///
+    /// The terminal echoes input and turns `\n` into `\r\n` in output, as
+    /// terminals do, so bear that in mind when writing expectations. Output
+    /// only appears in the session's transcript, not in the [`RunOutput`].
+    #[cfg(unix)]
+    #[cfg_attr(docsrs, doc(cfg(unix)))]
+    pub fn interact_pty(&self) -> Result<Session, RunError> {
+        let pty = nix::pty::openpty(None, None).map_err(std::io::Error::from)?;
+        let guard = self.spawn_with(
+            Stdio::from(pty.slave.try_clone()?),
+            Stdio::from(pty.slave.try_clone()?),
+            Stdio::from(pty.slave),
+        )?;
+        Ok(Session::with_pty(guard, pty.master)?)
+    }
+
+    fn spawn_with(
+        &self,
+        stdin: Stdio,
+        stdout: Stdio,
+        stderr: Stdio,
+    ) -> Result<ChildGuard, RunError> {
+        #[cfg(unix)]
+        self.check_user()?;
+
+        let mut command = self.command();
+        command.stdin(stdin).stdout(stdout).stderr(stderr);
+
+        let launch = LaunchContext::new(&command);
+        let mut group = command.group();
+        // On Windows, the job object is killed along with everything in it
+        // if we go away without cleaning up, and the binary gets a console
+        // process group of its own, so that it can be sent Ctrl-Break alone.
+        #[cfg(windows)]
+        group
+            .kill_on_drop(true)
+            .creation_flags(crate::console::CREATE_NEW_PROCESS_GROUP);
+        for port in &self.ports {
+            port.release();
+        }
+        let child = group.spawn()?;
+
+        let name = self
+            .artifact
+            .path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+
+        let mut capturing = Capturing {
+            spill_threshold: self.spill_threshold,
+            panic: self.capture_panic,
+            ..Capturing::default()
+        };
+        if self.output.enabled() {
+            let (sink, log_file) = LineSink::new(&name, child.id(), &self.output)?;
+            capturing.sink = Some(sink);
+            capturing.log_file = log_file;
+        }
+
+        Ok(ChildGuard::new(
+            child,
+            name,
+            self.capture_backtrace,
+            capturing,
+            launch,
+            self.grace_period.unwrap_or_else(crate::grace_period),
+        ))
+    }
+
+    fn run_once(&self) -> Result<RunOutput, RunError> {
+        let mut guard = self.spawn()?;
+        let (usage, timed_out) = self.wait_for_exit(&mut guard)?;
+        let mut output = guard.finish()?;
+
+        output.usage = usage;
+        output.timed_out = timed_out;
+        if timed_out {
+            // If we killed it, it didn't crash.
+            output.crash = None;
+        }
+
+        Ok(output)
+    }
+
+    /// Waits until the child exits, is killed due to a timeout, or (if we
+    /// don't need to poll it) can be blocked on. Returns any resource usage
+    /// sampled and whether the child was killed.
+    fn wait_for_exit(
+        &self,
+        guard: &mut ChildGuard,
+    ) -> Result<(Option<ResourceUsage>, bool), RunError> {
+        if !self.sample_usage && self.timeout.is_none() {
+            return Ok((None, false));
+        }
+
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let mut sampler = self.sample_usage.then(|| Sampler::start(guard));
+        let mut timed_out = false;
+
+        loop {
+            let exited = if let Some(sampler) = &mut sampler {
+                sampler.poll(guard)?
+            } else {
+                guard.child_mut().try_wait()?.is_some()
+            };
+
+            if exited {
+                break;
+            }
+
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                guard.kill_group()?;
+                timed_out = true;
+                break;
+            }
+
+            std::thread::sleep(POLL_INTERVAL);
+        }
+
+        let usage = match sampler {
+            Some(sampler) => Some(sampler.finish(guard)?),
+            None => None,
+        };
+
+        Ok((usage, timed_out))
+    }
+}
+
+/// The outcome of running a test binary to completion.
+#[derive(Debug, Clone)]
+pub struct RunOutput {
+    pub(crate) status: ExitStatus,
+    pub(crate) stdout: Vec<u8>,
+    pub(crate) stderr: Vec<u8>,
+    pub(crate) stdout_spill: Option<Arc<TempPath>>,
+    pub(crate) stderr_spill: Option<Arc<TempPath>>,
+    pub(crate) crash: Option<Crash>,
+    pub(crate) panic: Option<Panic>,
+    pub(crate) log_file: Option<PathBuf>,
+    pub(crate) usage: Option<ResourceUsage>,
+    pub(crate) timed_out: bool,
+    pub(crate) previous_attempts: Vec<RunOutput>,
+    pub(crate) launch: LaunchContext,
+}
+
+impl RunOutput {
+    /// The exit status of the binary.
+    pub fn status(&self) -> ExitStatus {
+        self.status
+    }
+
+    /// Everything the binary wrote to stdout, or if it was spilled to a file
+    /// (see [`Runner::spill_output()`]), the start of it.
+    pub fn stdout(&self) -> &[u8] {
+        &self.stdout
+    }
+
+    /// Everything the binary wrote to stderr, or if it was spilled to a file
+    /// (see [`Runner::spill_output()`]), the start of it.
+    pub fn stderr(&self) -> &[u8] {
+        &self.stderr
+    }
+
+    /// The file holding everything the binary wrote to stdout, if it was
+    /// spilled to one. See [`Runner::spill_output()`]. The file is deleted
+    /// when the last clone of this output is dropped.
+    pub fn stdout_path(&self) -> Option<&Path> {
+        self.stdout_spill.as_deref().map(AsRef::as_ref)
+    }
+
+    /// The file holding everything the binary wrote to stderr, if it was
+    /// spilled to one. See [`RunOutput::stdout_path()`].
+    pub fn stderr_path(&self) -> Option<&Path> {
+        self.stderr_spill.as_deref().map(AsRef::as_ref)
+    }
+
+    /// Reads everything the binary wrote to stdout, whether it was spilled to
+    /// a file or not.
+    pub fn stdout_reader(&self) -> std::io::Result<Box<dyn Read + '_>> {
+        reader(&self.stdout, self.stdout_path())
+    }
+
+    /// Reads everything the binary wrote to stderr, whether it was spilled to
+    /// a file or not.
+    pub fn stderr_reader(&self) -> std::io::Result<Box<dyn Read + '_>> {
+        reader(&self.stderr, self.stderr_path())
+    }
+
+    /// Details of how the binary crashed, if it did and if
+    /// [`Runner::capture_crash_backtrace()`] was used.
+    pub fn crash(&self) -> Option<&Crash> {
+        self.crash.as_ref()
+    }
+
+    /// The panic the binary failed with, if it did and if
+    /// [`Runner::capture_panics()`] was used.
+    pub fn panic(&self) -> Option<&Panic> {
+        self.panic.as_ref()
+    }
+
+    /// The resource usage of the binary, if
+    /// [`Runner::sample_resource_usage()`] was used.
+    pub fn resource_usage(&self) -> Option<&ResourceUsage> {
+        self.usage.as_ref()
+    }
+
+    /// The log file of a failed run, if
+    /// [`Runner::keep_log_on_failure()`] was used.
+    pub fn log_file(&self) -> Option<&Path> {
+        self.log_file.as_deref()
+    }
+
+    /// Whether the binary was killed because it exceeded the
+    /// [timeout](Runner::timeout).
+    pub fn timed_out(&self) -> bool {
+        self.timed_out
+    }
+
+    /// How the binary was started.
+    pub fn launch(&self) -> &LaunchContext {
+        &self.launch
+    }
+
+    /// Panics if the binary didn't exit successfully, with a message that has
+    /// its exit status, how it was started, and what it wrote to stderr.
+    #[track_caller]
+    pub fn assert_success(&self) -> &Self {
+        if let Some(summary) = self.failure_summary() {
+            panic!("{}", summary);
+        }
+        self
+    }
+
+    /// What went wrong, if the binary didn't exit successfully: its exit
+    /// status, how it was started, the [panic](RunOutput::panic) if one was
+    /// captured, and what it wrote to stderr.
+    pub fn failure_summary(&self) -> Option<String> {
+        if self.status.success() {
+            return None;
+        }
+        let mut summary = format!("test binary failed: {}\n{}\n", self.status, self.launch);
+        if let Some(panic) = &self.panic {
+            summary.push_str("--- panic ---\n");
+            summary.push_str(&panic.to_string());
+        }
+        summary.push_str("--- stderr ---\n");
+        summary.push_str(&String::from_utf8_lossy(&self.stderr));
+        Some(summary)
+    }
+
+    /// The outputs of any earlier attempts that were retried according to the
+    /// [retry policy](Runner::retry), oldest first.
+    pub fn previous_attempts(&self) -> &[RunOutput] {
+        &self.previous_attempts
+    }
+
+    /// Stdout, converted lossily to UTF-8 and normalized with a default
+    /// [`Normalizer`].
+    pub fn stdout_normalized(&self) -> String {
+        Normalizer::new().normalize(&String::from_utf8_lossy(&self.stdout))
+    }
+
+    /// Stderr, converted lossily to UTF-8 and normalized with a default
+    /// [`Normalizer`].
+    pub fn stderr_normalized(&self) -> String {
+        Normalizer::new().normalize(&String::from_utf8_lossy(&self.stderr))
+    }
+
+    /// The exit code, stdout and stderr together, normalized with
+    /// `normalizer`, in a form that's suitable for snapshot testing:
+    ///
+    /// ```none
+    /// exit code: 0
+    /// --- stdout ---
+    /// listening on 127.0.0.1:[PORT]
+    /// --- stderr ---
+    /// ```
+    ///
+    /// With the `insta` feature, [`assert_output_snapshot!()`] compares this
+    /// against a snapshot.
+    ///
+    /// [`assert_output_snapshot!()`]: crate::assert_output_snapshot
+    pub fn snapshot(&self, normalizer: &Normalizer) -> String {
+        let code = match self.status.code() {
+            Some(code) => code.to_string(),
+            None => "none".to_owned(),
+        };
+        // Each section ends with a newline, even if the output didn't.
+        let section = |output: &[u8]| {
+            let mut text = normalizer.normalize(&String::from_utf8_lossy(output));
+            if !text.is_empty() && !text.ends_with('\n') {
+                text.push('\n');
+            }
+            text
+        };
+        format!(
+            "exit code: {}\n--- stdout ---\n{}--- stderr ---\n{}",
+            code,
+            section(&self.stdout),
+            section(&self.stderr)
+        )
+    }
+}
+
+/// Details of a test binary that was killed by a signal or aborted.
+#[derive(Debug, Clone)]
+pub struct Crash {
+    signal: Option<i32>,
+    backtrace: Option<String>,
+}
+
+impl Crash {
+    /// Checks whether the exit status looks like a crash, and if so, extracts
+    /// any backtrace Rust's panic handler wrote to stderr.
+    pub(crate) fn detect(status: ExitStatus, stderr: &[u8]) -> Option<Self> {
+        let signal = crash_signal(status)?;
+        Some(Self {
+            signal,
+            backtrace: extract_backtrace(&String::from_utf8_lossy(stderr)),
+        })
+    }
+
+    /// The signal that killed the binary. This is always `None` on platforms
+    /// without signals, where a crash is detected from the exit code instead.
+    pub fn signal(&self) -> Option<i32> {
+        self.signal
+    }
+
+    /// The symbolized backtrace the binary printed before it died, if any.
+    /// This will only be present if the binary panicked with `panic = "abort"`
+    /// or printed a backtrace some other way, since a plain
+    /// [`std::process::abort()`] or a segfault leaves nothing behind.
+    pub fn backtrace(&self) -> Option<&str> {
+        self.backtrace.as_deref()
+    }
+}
+
+/// A panic that a test binary printed to stderr before it failed. This is
+/// found by [`Runner::capture_panics()`].
+///
+/// Its `Display` form is the panic as Rust prints it, with the backtrace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Panic {
+    thread: String,
+    location: Option<String>,
+    message: String,
+    backtrace: Option<String>,
+}
+
+impl Panic {
+    /// Finds the first panic in `stderr`, in the form Rust prints them:
+    ///
+    /// ```none
+    /// thread 'main' (1234) panicked at src/main.rs:2:5:
+    /// the message, which might be several lines
+    /// stack backtrace:
+    /// ```
+    ///
+    /// Before Rust 1.73, the message was on the first line instead, in
+    /// quotes, before the location.
+    pub(crate) fn detect(stderr: &str) -> Option<Self> {
+        let mut lines = stderr
+            .lines()
+            .skip_while(|line| !(line.starts_with("thread '") && line.contains(" panicked at ")));
+        let (thread, rest) = lines.next()?["thread '".len()..].split_once("' ")?;
+        // Newer versions of Rust put the thread's ID after its name.
+        let rest = match rest.strip_prefix('(') {
+            Some(rest) => rest.split_once(") ")?.1,
+            None => rest,
+        };
+        let rest = rest.strip_prefix("panicked at ")?;
+
+        let (location, message) = match rest.strip_suffix(':') {
+            Some(location) => {
+                let message: Vec<_> = lines
+                    .take_while(|line| !line.starts_with("note: ") && *line != "stack backtrace:")
+                    .collect();
+                (Some(location.to_owned()), message.join("\n"))
+            }
+            None => match rest.rsplit_once("', ") {
+                Some((message, location)) => (
+                    Some(location.to_owned()),
+                    message.trim_start_matches('\'').to_owned(),
+                ),
+                None => (None, rest.to_owned()),
+            },
+        };
+
+        Some(Self {
+            thread: thread.to_owned(),
+            location,
+            message,
+            backtrace: extract_backtrace(stderr),
+        })
+    }
+
+    /// The name of the thread that panicked eg. `main`.
+    pub fn thread(&self) -> &str {
+        &self.thread
+    }
+
+    /// Where the panic happened eg. `src/main.rs:2:5`, if it was printed.
+    pub fn location(&self) -> Option<&str> {
+        self.location.as_deref()
+    }
+
+    /// The panic message.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The backtrace printed after the panic, if there was one.
+    pub fn backtrace(&self) -> Option<&str> {
+        self.backtrace.as_deref()
+    }
+}
+
+impl std::fmt::Display for Panic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.location {
+            Some(location) => writeln!(f, "thread '{}' panicked at {}:", self.thread, location)?,
+            None => writeln!(f, "thread '{}' panicked:", self.thread)?,
+        }
+        writeln!(f, "{}", self.message)?;
+        if let Some(backtrace) = &self.backtrace {
+            writeln!(f, "stack backtrace:")?;
+            f.write_str(backtrace)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads captured output from the file it was spilled to, or from memory.
+fn reader<'a>(data: &'a [u8], path: Option<&Path>) -> std::io::Result<Box<dyn Read + 'a>> {
+    Ok(match path {
+        Some(path) => Box::new(std::io::BufReader::new(std::fs::File::open(path)?)),
+        None => Box::new(data),
+    })
+}
+
+/// Returns `Some(signal)` if the status represents a crash. The inner value is
+/// `None` if the platform has no notion of signals.
+#[cfg(unix)]
+fn crash_signal(status: ExitStatus) -> Option<Option<i32>> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal().map(Some)
+}
+
+/// Returns `Some(signal)` if the status represents a crash. The inner value is
+/// `None` if the platform has no notion of signals.
+#[cfg(not(unix))]
+fn crash_signal(status: ExitStatus) -> Option<Option<i32>> {
+    // On Windows, abort() exits with code 3, and fatal exceptions (access
+    // violations, fail-fast etc.) use NTSTATUS codes with the high bits set.
+    match status.code() {
+        Some(3) => Some(None),
+        Some(code) if (code as u32) & 0xC000_0000 == 0xC000_0000 => Some(None),
+        _ => None,
+    }
+}
+
+/// Pulls the "stack backtrace:" section out of a panic message on stderr.
+fn extract_backtrace(stderr: &str) -> Option<String> {
+    let mut lines = stderr
+        .lines()
+        .skip_while(|line| *line != "stack backtrace:");
+    lines.next()?;
+
+    let mut backtrace = String::new();
+    for line in lines.take_while(|line| line.starts_with(' ')) {
+        backtrace.push_str(line);
+        backtrace.push('\n');
+    }
+
+    Some(backtrace)
+}
+
+/// Error type for running a test binary.
+#[derive(thiserror::Error, Debug)]
+pub enum RunError {
+    /// An error spawning or communicating with the test binary.
+    #[error("IO error running test binary")]
+    SpawnError(#[from] std::io::Error),
+    /// The test binary ran but did not succeed.
+    #[error("test binary failed: {0}")]
+    BinaryFailure(ExitStatus),
+    /// A test binary exited or timed out before it was ready.
+    #[error(r#"test binary "{0}" did not become ready"#)]
+    NotReady(String),
+    /// The signal can't be delivered on this platform. See
+    /// [`Signal`](crate::Signal).
+    #[error("{0:?} can't be sent to a test binary on this platform")]
+    UnsupportedSignal(crate::Signal),
+    /// A test binary in a [`Session`](crate::Session) didn't print what was
+    /// expected before it closed its output or the timeout passed.
+    #[error("test binary did not print {pattern:?}, transcript:\n{transcript}")]
+    ExpectFailed {
+        /// What was expected.
+        pattern: String,
+        /// Everything sent and received in the session.
+        transcript: String,
+    },
+    /// A call made with an [`RpcClient`](crate::RpcClient) failed, either
+    /// because the binary returned an error or because it didn't respond
+    /// properly.
+    #[cfg(feature = "rpc")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "rpc")))]
+    #[error("RPC call {method:?} to test binary failed: {reason}")]
+    RpcFailed {
+        /// The method that was called.
+        method: String,
+        /// Why it failed.
+        reason: String,
+    },
+    /// [`Runner::run_as()`] asked for another user, but the test isn't
+    /// running as root.
+    #[error("can't run test binary as uid {uid}, gid {gid} without root privileges")]
+    CannotSwitchUser {
+        /// The user the binary was to run as.
+        uid: u32,
+        /// The group the binary was to run as.
+        gid: u32,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    #[test]
+    fn backtrace_from_panic() {
+        let stderr = indoc! {"
+            thread 'main' panicked at src/main.rs:1:73:
+            boom
+            stack backtrace:
+               0:     0x55e0842f4f9a - std::backtrace_rs::backtrace::libunwind::trace
+                                       at /rustc/library/std/src/../../backtrace/src/backtrace/libunwind.rs:117:9
+               1:     0x55e0842f4f9a - crashes::main
+            note: Some details are omitted, run with `RUST_BACKTRACE=full` for a verbose backtrace.
+        "};
+
+        // Not using indoc here, since it would strip the frame indentation.
+        let expected = concat!(
+            "   0:     0x55e0842f4f9a - std::backtrace_rs::backtrace::libunwind::trace\n",
+            "                           at /rustc/library/std/src/../../backtrace/src/backtrace/libunwind.rs:117:9\n",
+            "   1:     0x55e0842f4f9a - crashes::main\n",
+        );
+
+        assert_eq!(extract_backtrace(stderr).as_deref(), Some(expected));
+    }
+
+    #[test]
+    fn panics() {
+        let stderr = indoc! {"
+            starting
+            thread 'worker' panicked at src/main.rs:4:9:
+            something broke
+            over two lines
+            note: run with `RUST_BACKTRACE=1` environment variable to display a backtrace
+        "};
+        let panic = Panic::detect(stderr).unwrap();
+        assert_eq!(panic.thread(), "worker");
+        assert_eq!(panic.location(), Some("src/main.rs:4:9"));
+        assert_eq!(panic.message(), "something broke\nover two lines");
+        assert_eq!(panic.backtrace(), None);
+        assert_eq!(
+            panic.to_string(),
+            "thread 'worker' panicked at src/main.rs:4:9:\nsomething broke\nover two lines\n"
+        );
+
+        let panic =
+            Panic::detect("thread 'main' (42) panicked at src/main.rs:2:5:\nboom\n").unwrap();
+        assert_eq!(panic.thread(), "main");
+        assert_eq!(panic.message(), "boom");
+
+        let panic = Panic::detect("thread 'main' panicked at 'boom', src/main.rs:1:13\n").unwrap();
+        assert_eq!(panic.location(), Some("src/main.rs:1:13"));
+        assert_eq!(panic.message(), "boom");
+
+        assert_eq!(Panic::detect("error: no panic here\n"), None);
+    }
+
+    #[test]
+    fn no_backtrace() {
+        assert_eq!(extract_backtrace("thread 'main' panicked\nboom\n"), None);
+    }
+}
@@ -0,0 +1,105 @@
+//! Running Cargo and test binaries at a lower priority, or on only some CPUs.
+//!
+//! This is done by starting them through `nice` and (on Linux) `taskset`,
+//! which set up the process and then replace themselves with it. So it's
+//! still the same process, with everything it starts inheriting the same
+//! settings, but without needing any unsafe code to set it up between forking
+//! and executing.
+
+use std::process::Command;
+
+/// How to schedule a process, if not the same way as the parent.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Scheduling {
+    /// How much to add to the parent's niceness. Higher is lower priority.
+    pub(crate) niceness: Option<i32>,
+    /// The CPUs the process may run on.
+    #[cfg(target_os = "linux")]
+    pub(crate) cpus: Option<Vec<usize>>,
+}
+
+impl Scheduling {
+    /// Returns a command that runs `command` with these settings. Stdio and
+    /// anything else that can't be read back from a command must be set
+    /// afterwards.
+    pub(crate) fn apply(&self, command: Command) -> Command {
+        let mut wrappers: Vec<Vec<String>> = vec![];
+        #[cfg(target_os = "linux")]
+        if let Some(cpus) = &self.cpus {
+            let list: Vec<_> = cpus.iter().map(ToString::to_string).collect();
+            wrappers.push(vec!["taskset".into(), "-c".into(), list.join(",")]);
+        }
+        if let Some(niceness) = self.niceness {
+            wrappers.push(vec!["nice".into(), "-n".into(), niceness.to_string()]);
+        }
+        if wrappers.is_empty() {
+            return command;
+        }
+
+        let mut words = wrappers.into_iter().flatten();
+        // There's always at least a program and one argument.
+        let mut wrapped = Command::new(words.next().unwrap_or_default());
+        wrapped
+            .args(words)
+            .arg(command.get_program())
+            .args(command.get_args());
+        for (key, value) in command.get_envs() {
+            match value {
+                Some(value) => wrapped.env(key, value),
+                None => wrapped.env_remove(key),
+            };
+        }
+        if let Some(dir) = command.get_current_dir() {
+            wrapped.current_dir(dir);
+        }
+        wrapped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps() {
+        let mut command = Command::new("fla");
+        command
+            .arg("--bar")
+            .env("FLA", "1")
+            .env_remove("BAR")
+            .current_dir("/tmp");
+
+        let unchanged = Scheduling::default().apply(command);
+        assert_eq!(unchanged.get_program(), "fla");
+
+        let scheduling = Scheduling {
+            niceness: Some(10),
+            ..Scheduling::default()
+        };
+        let wrapped = scheduling.apply(unchanged);
+        assert_eq!(wrapped.get_program(), "nice");
+        assert_eq!(
+            wrapped.get_args().collect::<Vec<_>>(),
+            ["-n", "10", "fla", "--bar"]
+        );
+        assert_eq!(
+            wrapped.get_envs().collect::<Vec<_>>(),
+            [("BAR".as_ref(), None), ("FLA".as_ref(), Some("1".as_ref()))]
+        );
+        assert_eq!(wrapped.get_current_dir(), Some("/tmp".as_ref()));
+
+        #[cfg(target_os = "linux")]
+        {
+            let scheduling = Scheduling {
+                niceness: Some(5),
+                cpus: Some(vec![0, 2]),
+            };
+            let wrapped = scheduling.apply(Command::new("fla"));
+            assert_eq!(wrapped.get_program(), "taskset");
+            assert_eq!(
+                wrapped.get_args().collect::<Vec<_>>(),
+                ["-c", "0,2", "nice", "-n", "5", "fla"]
+            );
+        }
+    }
+}
@@ -0,0 +1,354 @@
+//! Serializing errors, so that they can be logged and aggregated in structured
+//! form.
+//!
+//! Every error is a map with the variant's name as `kind`, its fields, and its
+//! `Display` text as `message`. An error that wraps another has it as
+//! `source`, in the same form. Compiler messages are also serializable, as
+//! their parts.
+
+use crate::{Lint, ManifestError, PinError, RunError, TestBinaryError};
+use serde::{
+    ser::{SerializeMap, Serializer},
+    Serialize,
+};
+use std::{fmt::Display, process::ExitStatus};
+
+/// Starts the map for an error.
+fn begin<S: Serializer>(serializer: S, kind: &str) -> Result<S::SerializeMap, S::Error> {
+    let mut map = serializer.serialize_map(None)?;
+    map.serialize_entry("kind", kind)?;
+    Ok(map)
+}
+
+/// Finishes the map for an error, adding its message.
+fn end<M: SerializeMap, E: Display>(mut map: M, error: &E) -> Result<M::Ok, M::Error> {
+    map.serialize_entry("message", &error.to_string())?;
+    map.end()
+}
+
+/// An error from outside this crate, which only has a message.
+#[cfg(feature = "watch")]
+struct Foreign<'a, E>(&'a str, &'a E);
+
+#[cfg(feature = "watch")]
+impl<E: Display> Serialize for Foreign<'_, E> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        end(begin(serializer, self.0)?, self.1)
+    }
+}
+
+/// An IO error, with its kind as `io_kind`.
+struct Io<'a>(&'a std::io::Error);
+
+impl Serialize for Io<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = begin(serializer, "Io")?;
+        map.serialize_entry("io_kind", &format!("{:?}", self.0.kind()))?;
+        end(map, self.0)
+    }
+}
+
+/// An exit status, as its code and (on Unix) the signal that ended it.
+struct Status<'a>(&'a ExitStatus);
+
+impl Serialize for Status<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("code", &self.0.code())?;
+        #[cfg(unix)]
+        map.serialize_entry(
+            "signal",
+            &std::os::unix::process::ExitStatusExt::signal(self.0),
+        )?;
+        map.end()
+    }
+}
+
+impl Serialize for TestBinaryError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let map = match self {
+            Self::NonCargoRun(reason) => {
+                let mut map = begin(serializer, "NonCargoRun")?;
+                map.serialize_entry("reason", reason)?;
+                map
+            }
+            Self::CargoRunError(err) => {
+                let mut map = begin(serializer, "CargoRunError")?;
+                map.serialize_entry("source", &Io(err))?;
+                map
+            }
+            Self::CargoFailure(stderr) => {
+                let mut map = begin(serializer, "CargoFailure")?;
+                map.serialize_entry("stderr", stderr)?;
+                map
+            }
+            Self::CargoKilled { status, stderr } => {
+                let mut map = begin(serializer, "CargoKilled")?;
+                map.serialize_entry("status", &Status(status))?;
+                map.serialize_entry("stderr", stderr)?;
+                map
+            }
+            Self::BuildError(diagnostics) => {
+                let mut map = begin(serializer, "BuildError")?;
+                map.serialize_entry("diagnostics", diagnostics)?;
+                map
+            }
+            Self::BinaryNotBuilt(name) => {
+                let mut map = begin(serializer, "BinaryNotBuilt")?;
+                map.serialize_entry("name", name)?;
+                map
+            }
+            Self::NotFresh(compiled) => {
+                let mut map = begin(serializer, "NotFresh")?;
+                map.serialize_entry("compiled", compiled)?;
+                map
+            }
+            Self::ManifestError(err) => {
+                let mut map = begin(serializer, "ManifestError")?;
+                map.serialize_entry("source", err)?;
+                map
+            }
+            Self::PinError(err) => {
+                let mut map = begin(serializer, "PinError")?;
+                map.serialize_entry("source", err)?;
+                map
+            }
+            Self::Timeout {
+                timeout,
+                waiting_for_lock,
+            } => {
+                let mut map = begin(serializer, "Timeout")?;
+                map.serialize_entry("timeout_secs", &timeout.as_secs_f64())?;
+                map.serialize_entry("waiting_for_lock", waiting_for_lock)?;
+                map
+            }
+            Self::Cancelled => begin(serializer, "Cancelled")?,
+            Self::RunError(err) => {
+                let mut map = begin(serializer, "RunError")?;
+                map.serialize_entry("source", err)?;
+                map
+            }
+            Self::ToolchainUnavailable {
+                toolchain,
+                file,
+                reason,
+            } => {
+                let mut map = begin(serializer, "ToolchainUnavailable")?;
+                map.serialize_entry("toolchain", toolchain)?;
+                map.serialize_entry("file", file)?;
+                map.serialize_entry("reason", reason)?;
+                map
+            }
+            Self::AuditFailed { name, findings } => {
+                let mut map = begin(serializer, "AuditFailed")?;
+                map.serialize_entry("name", name)?;
+                map.serialize_entry("findings", findings)?;
+                map
+            }
+            Self::LipoFailure(stderr) => {
+                let mut map = begin(serializer, "LipoFailure")?;
+                map.serialize_entry("stderr", stderr)?;
+                map
+            }
+            Self::OverSizeBudget { name, size, budget } => {
+                let mut map = begin(serializer, "OverSizeBudget")?;
+                map.serialize_entry("name", name)?;
+                map.serialize_entry("size", size)?;
+                map.serialize_entry("budget", budget)?;
+                map
+            }
+            Self::Unsupported { reason } => {
+                let mut map = begin(serializer, "Unsupported")?;
+                map.serialize_entry("reason", reason)?;
+                map
+            }
+            #[cfg(feature = "watch")]
+            Self::WatchError(err) => {
+                let mut map = begin(serializer, "WatchError")?;
+                map.serialize_entry("source", &Foreign("Notify", err))?;
+                map
+            }
+        };
+        end(map, self)
+    }
+}
+
+impl Serialize for ManifestError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let map = match self {
+            Self::PackageNotFound(name) => {
+                let mut map = begin(serializer, "PackageNotFound")?;
+                map.serialize_entry("name", name)?;
+                map
+            }
+            Self::ReadManifest(path, reason) => {
+                let mut map = begin(serializer, "ReadManifest")?;
+                map.serialize_entry("path", path)?;
+                map.serialize_entry("reason", reason)?;
+                map
+            }
+            Self::WriteManifest(path, reason) => {
+                let mut map = begin(serializer, "WriteManifest")?;
+                map.serialize_entry("path", path)?;
+                map.serialize_entry("reason", reason)?;
+                map
+            }
+            Self::InvalidPattern(pattern, reason) => {
+                let mut map = begin(serializer, "InvalidPattern")?;
+                map.serialize_entry("pattern", pattern)?;
+                map.serialize_entry("reason", reason)?;
+                map
+            }
+            Self::EnvNotSet(reason) => {
+                let mut map = begin(serializer, "EnvNotSet")?;
+                map.serialize_entry("reason", reason)?;
+                map
+            }
+        };
+        end(map, self)
+    }
+}
+
+impl Serialize for PinError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let map = match self {
+            Self::Io(path, err) => {
+                let mut map = begin(serializer, "Io")?;
+                map.serialize_entry("path", path)?;
+                map.serialize_entry("source", &Io(err))?;
+                map
+            }
+            Self::Parse(path, reason) => {
+                let mut map = begin(serializer, "Parse")?;
+                map.serialize_entry("path", path)?;
+                map.serialize_entry("reason", reason)?;
+                map
+            }
+            Self::Missing(name, platform) => {
+                let mut map = begin(serializer, "Missing")?;
+                map.serialize_entry("name", name)?;
+                map.serialize_entry("platform", platform)?;
+                map
+            }
+            Self::Mismatch {
+                name,
+                expected,
+                actual,
+            } => {
+                let mut map = begin(serializer, "Mismatch")?;
+                map.serialize_entry("name", name)?;
+                map.serialize_entry("expected", expected)?;
+                map.serialize_entry("actual", actual)?;
+                map
+            }
+        };
+        end(map, self)
+    }
+}
+
+impl Serialize for RunError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let map = match self {
+            Self::SpawnError(err) => {
+                let mut map = begin(serializer, "SpawnError")?;
+                map.serialize_entry("source", &Io(err))?;
+                map
+            }
+            Self::BinaryFailure(status) => {
+                let mut map = begin(serializer, "BinaryFailure")?;
+                map.serialize_entry("status", &Status(status))?;
+                map
+            }
+            Self::NotReady(name) => {
+                let mut map = begin(serializer, "NotReady")?;
+                map.serialize_entry("name", name)?;
+                map
+            }
+            Self::UnsupportedSignal(signal) => {
+                let mut map = begin(serializer, "UnsupportedSignal")?;
+                map.serialize_entry("signal", &format!("{:?}", signal))?;
+                map
+            }
+            Self::ExpectFailed {
+                pattern,
+                transcript,
+            } => {
+                let mut map = begin(serializer, "ExpectFailed")?;
+                map.serialize_entry("pattern", pattern)?;
+                map.serialize_entry("transcript", transcript)?;
+                map
+            }
+            #[cfg(feature = "rpc")]
+            Self::RpcFailed { method, reason } => {
+                let mut map = begin(serializer, "RpcFailed")?;
+                map.serialize_entry("method", method)?;
+                map.serialize_entry("reason", reason)?;
+                map
+            }
+            Self::CannotSwitchUser { uid, gid } => {
+                let mut map = begin(serializer, "CannotSwitchUser")?;
+                map.serialize_entry("uid", uid)?;
+                map.serialize_entry("gid", gid)?;
+                map
+            }
+        };
+        end(map, self)
+    }
+}
+
+impl Serialize for Lint {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("level", self.level())?;
+        map.serialize_entry("code", &self.code())?;
+        map.serialize_entry("message", self.message())?;
+        map.serialize_entry("file", &self.file())?;
+        map.serialize_entry("line", &self.line())?;
+        map.serialize_entry("rendered", self.rendered())?;
+        map.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[test]
+    fn nested() {
+        let err = TestBinaryError::from(ManifestError::PackageNotFound("fla".to_owned()));
+        assert_eq!(
+            serde_json::to_value(&err).unwrap(),
+            json!({
+                "kind": "ManifestError",
+                "source": {
+                    "kind": "PackageNotFound",
+                    "name": "fla",
+                    "message": "Package fla not found",
+                },
+                "message": "manifest error: Package fla not found",
+            })
+        );
+
+        let err = TestBinaryError::from(RunError::from(std::io::Error::from(
+            std::io::ErrorKind::NotFound,
+        )));
+        let value = serde_json::to_value(&err).unwrap();
+        assert_eq!(value["source"]["kind"], "SpawnError");
+        assert_eq!(value["source"]["source"]["io_kind"], "NotFound");
+    }
+
+    #[test]
+    fn fields() {
+        let err = TestBinaryError::Timeout {
+            timeout: Duration::from_millis(1500),
+            waiting_for_lock: Some("build directory".to_owned()),
+        };
+        let value = serde_json::to_value(&err).unwrap();
+        assert_eq!(value["kind"], "Timeout");
+        assert_eq!(value["timeout_secs"], 1.5);
+        assert_eq!(value["waiting_for_lock"], "build directory");
+        assert_eq!(value["message"], err.to_string());
+    }
+}
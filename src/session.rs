@@ -0,0 +1,202 @@
+//! Interactive sessions with running test binaries, expect-style.
+
+use crate::{child::Capture, log::Stream, ChildGuard, RunError, RunOutput};
+use std::{
+    io::Write,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// A conversation with a running test binary: send it input, and wait for
+/// what it prints in response. Start with
+/// [`Runner::interact()`](crate::Runner::interact) (or
+/// [`Runner::interact_pty()`](crate::Runner::interact_pty) on Unix, for
+/// binaries that behave differently when talking to a terminal).
+///
+/// Everything sent and received is kept in a transcript, which is included in
+/// the error if [`Session::expect()`] fails, so that it's clear where the
+/// conversation went wrong.
+///
+/// ```rust
+/// # use std::time::Duration;
+/// let echo = test_binary::stock::echo_stdin().unwrap();
+/// let mut session = echo.runner().interact().unwrap();
+///
+/// session.send_line("hello").unwrap();
+/// session.expect("hello", Duration::from_secs(5)).unwrap();
+/// assert!(session.wait().unwrap().status().success());
+/// ```
+///
+/// Like a [`ChildGuard`], the binary is killed if the session is dropped
+/// before it exits.
+#[derive(Debug)]
+pub struct Session {
+    guard: ChildGuard,
+    input: Option<Box<dyn Input>>,
+    output: Arc<Capture>,
+    /// How much of the output has been consumed by expect().
+    position: usize,
+    /// How much of the output has been added to the transcript.
+    transcribed: usize,
+    transcript: String,
+}
+
+/// Somewhere to send input to the binary.
+trait Input: Write + Send + std::fmt::Debug {}
+
+impl<W: Write + Send + std::fmt::Debug> Input for W {}
+
+impl Session {
+    /// Starts a session over the binary's stdin and stdout.
+    pub(crate) fn new(mut guard: ChildGuard) -> Self {
+        let input = guard
+            .child_mut()
+            .stdin
+            .take()
+            .map(|stdin| Box::new(stdin) as Box<dyn Input>);
+        let output = Arc::clone(guard.stdout_capture());
+        Self::with_io(guard, input, output)
+    }
+
+    /// Starts a session over a pseudoterminal's master side.
+    #[cfg(unix)]
+    pub(crate) fn with_pty(
+        guard: ChildGuard,
+        master: std::os::fd::OwnedFd,
+    ) -> std::io::Result<Self> {
+        let reader = std::fs::File::from(master.try_clone()?);
+        // The reader thread isn't joined; it finishes when the binary (and
+        // anything else holding the terminal) closes it.
+        let (output, _) = Capture::start(Some(reader), Stream::Stdout, None, None);
+        let input = Box::new(std::fs::File::from(master)) as Box<dyn Input>;
+        Ok(Self::with_io(guard, Some(input), output))
+    }
+
+    fn with_io(guard: ChildGuard, input: Option<Box<dyn Input>>, output: Arc<Capture>) -> Self {
+        Self {
+            guard,
+            input,
+            output,
+            position: 0,
+            transcribed: 0,
+            transcript: String::new(),
+        }
+    }
+
+    /// Waits up to `timeout` for the binary to print `pattern`, and returns
+    /// everything it printed before that since the last match. The next call
+    /// only looks at what comes after the match.
+    ///
+    /// If the binary closes its output or the timeout passes first, this
+    /// returns [`RunError::ExpectFailed`] with the transcript so far.
+    pub fn expect(&mut self, pattern: &str, timeout: Duration) -> Result<String, RunError> {
+        let deadline = Instant::now() + timeout;
+        let position = self.position;
+        let found = self.output.wait_for(deadline, |data| {
+            find(&data[position..], pattern.as_bytes()).map(|start| position + start)
+        });
+        self.update_transcript();
+
+        match found {
+            Some(start) => {
+                let data = self.output.contents();
+                let before = String::from_utf8_lossy(&data[self.position..start]).into_owned();
+                self.position = start + pattern.len();
+                Ok(before)
+            }
+            None => Err(RunError::ExpectFailed {
+                pattern: pattern.to_owned(),
+                transcript: self.transcript.clone(),
+            }),
+        }
+    }
+
+    /// Sends `text` to the binary, as is.
+    pub fn send(&mut self, text: &str) -> Result<(), RunError> {
+        self.note(&format!("[sent] {:?}", text));
+
+        let input = self.input.as_mut().ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::BrokenPipe, "input already closed")
+        })?;
+        input.write_all(text.as_bytes())?;
+        input.flush()?;
+        Ok(())
+    }
+
+    /// Sends `line` to the binary, followed by a newline.
+    pub fn send_line(&mut self, line: &str) -> Result<(), RunError> {
+        self.send(&format!("{}\n", line))
+    }
+
+    /// Closes the binary's input, so that it sees the end of its input.
+    ///
+    /// For a session over a pseudoterminal, this closes our side of it.
+    pub fn close_input(&mut self) {
+        if self.input.take().is_some() {
+            self.note("[closed input]");
+        }
+    }
+
+    /// Everything sent and received so far. Input is shown on lines of its
+    /// own, starting with `[sent]`.
+    pub fn transcript(&mut self) -> &str {
+        self.update_transcript();
+        &self.transcript
+    }
+
+    /// The running binary.
+    pub fn guard(&mut self) -> &mut ChildGuard {
+        &mut self.guard
+    }
+
+    /// Closes the binary's input and waits for it to exit.
+    pub fn wait(mut self) -> Result<RunOutput, RunError> {
+        self.close_input();
+        self.guard.wait()
+    }
+
+    /// Kills the binary and waits for it to exit.
+    pub fn kill(self) -> Result<RunOutput, RunError> {
+        self.guard.kill()
+    }
+
+    /// Adds a line of our own to the transcript, after anything received.
+    fn note(&mut self, line: &str) {
+        self.update_transcript();
+        if !self.transcript.is_empty() && !self.transcript.ends_with('\n') {
+            self.transcript.push('\n');
+        }
+        self.transcript.push_str(line);
+        self.transcript.push('\n');
+    }
+
+    /// Adds anything received since the last time to the transcript.
+    fn update_transcript(&mut self) {
+        let data = self.output.contents();
+        self.transcript
+            .push_str(&String::from_utf8_lossy(&data[self.transcribed..]));
+        self.transcribed = data.len();
+    }
+}
+
+/// The position of `pattern` in `data`, if it's there.
+fn find(data: &[u8], pattern: &[u8]) -> Option<usize> {
+    if pattern.is_empty() {
+        return Some(0);
+    }
+    data.windows(pattern.len())
+        .position(|window| window == pattern)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds() {
+        assert_eq!(find(b"fla mingo", b"mingo"), Some(4));
+        assert_eq!(find(b"fla mingo", b"pink"), None);
+        assert_eq!(find(b"fla", b""), Some(0));
+        assert_eq!(find(b"", b"fla"), None);
+    }
+}
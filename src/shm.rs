@@ -0,0 +1,170 @@
+//! Shared memory regions for test binaries to map.
+//!
+//! Mapping memory needs unsafe code. Memory that another process can write to
+//! at any time can't be safely treated as a plain slice, so it's only ever
+//! accessed through atomics.
+
+#![allow(unsafe_code)]
+
+use memmap2::MmapRaw;
+use std::{
+    ffi::OsStr,
+    path::Path,
+    sync::atomic::{AtomicU8, Ordering},
+};
+use tempfile::NamedTempFile;
+
+/// A region of memory shared with a test binary.
+///
+/// The region is a file, which the test maps into memory and the binary can
+/// map too, by opening it by name. It's created in `/dev/shm` on Linux, so it
+/// never touches the disk, or the temporary directory elsewhere. Every
+/// platform's file mapping APIs can share memory this way, and the binary
+/// doesn't need anything more than the path (passed like any other argument
+/// or environment variable) to get at it. The file is deleted when the region
+/// is dropped.
+///
+/// ```rust
+/// # use test_binary::{build_test_binary, Artifact, SharedMemory};
+/// let region = SharedMemory::new(4096).unwrap();
+/// region.write_at(0, b"ping");
+///
+/// let binary = Artifact::from(build_test_binary("actions", "testbins").unwrap());
+/// let mut param = region.path().as_os_str().to_owned();
+/// param.push("=pong");
+/// binary.runner().arg("write").arg(param).run().unwrap();
+///
+/// assert_eq!(region.read_vec(0, 4), b"pong");
+/// ```
+///
+/// The test side is accessed through atomic bytes, since the binary can write
+/// to it at any time. [`SharedMemory::bytes()`] gives direct access, for
+/// building whatever synchronisation the protocol under test uses.
+#[cfg_attr(docsrs, doc(cfg(feature = "shared-memory")))]
+#[derive(Debug)]
+pub struct SharedMemory {
+    file: NamedTempFile,
+    map: MmapRaw,
+}
+
+impl SharedMemory {
+    /// Creates a zeroed region of `len` bytes.
+    pub fn new(len: usize) -> std::io::Result<Self> {
+        let shm = Path::new("/dev/shm");
+        let dir = if cfg!(target_os = "linux") && shm.is_dir() {
+            shm.to_owned()
+        } else {
+            std::env::temp_dir()
+        };
+
+        let file = tempfile::Builder::new()
+            .prefix("test-binary-shm-")
+            .tempfile_in(dir)?;
+        file.as_file().set_len(len as u64)?;
+        let map = MmapRaw::map_raw(file.as_file())?;
+
+        Ok(Self { file, map })
+    }
+
+    /// The path to the file to map, which is what to pass to the binary.
+    pub fn path(&self) -> &Path {
+        self.file.path()
+    }
+
+    /// The size of the region in bytes.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Whether the region is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The region, as bytes that can be accessed concurrently with the
+    /// binary.
+    pub fn bytes(&self) -> &[AtomicU8] {
+        if self.map.len() == 0 {
+            return &[];
+        }
+        // SAFETY: AtomicU8 has the same size and alignment as u8. The mapping
+        // is valid for its whole length for as long as we hold it, and since
+        // everything goes through atomics, concurrent writes by other
+        // processes or threads are not data races.
+        unsafe { std::slice::from_raw_parts(self.map.as_ptr().cast::<AtomicU8>(), self.map.len()) }
+    }
+
+    /// Copies bytes from the region, starting at `offset`, into `buffer`.
+    ///
+    /// # Panics
+    ///
+    /// If the bytes don't all fit in the region.
+    pub fn read_at(&self, offset: usize, buffer: &mut [u8]) {
+        let bytes = &self.bytes()[offset..offset + buffer.len()];
+        for (byte, atomic) in buffer.iter_mut().zip(bytes) {
+            *byte = atomic.load(Ordering::Acquire);
+        }
+    }
+
+    /// Copies `len` bytes from the region, starting at `offset`. This panics
+    /// in the same way as [`SharedMemory::read_at()`].
+    pub fn read_vec(&self, offset: usize, len: usize) -> Vec<u8> {
+        let mut buffer = vec![0; len];
+        self.read_at(offset, &mut buffer);
+        buffer
+    }
+
+    /// Copies `data` into the region, starting at `offset`.
+    ///
+    /// # Panics
+    ///
+    /// If the bytes don't all fit in the region.
+    pub fn write_at(&self, offset: usize, data: &[u8]) {
+        let bytes = &self.bytes()[offset..offset + data.len()];
+        for (byte, atomic) in data.iter().zip(bytes) {
+            atomic.store(*byte, Ordering::Release);
+        }
+    }
+
+    /// Writes any changes made through the mapping back to the file, so that
+    /// a binary reading the file directly rather than mapping it sees them.
+    pub fn flush(&self) -> std::io::Result<()> {
+        self.map.flush()
+    }
+}
+
+impl AsRef<Path> for SharedMemory {
+    fn as_ref(&self) -> &Path {
+        self.path()
+    }
+}
+
+impl AsRef<OsStr> for SharedMemory {
+    fn as_ref(&self) -> &OsStr {
+        self.path().as_os_str()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_and_write() {
+        let region = SharedMemory::new(16).unwrap();
+        assert_eq!(region.len(), 16);
+        assert_eq!(region.read_vec(0, 16), [0; 16]);
+
+        region.write_at(4, b"fla");
+        assert_eq!(region.read_vec(3, 5), b"\0fla\0");
+
+        region.flush().unwrap();
+        assert_eq!(&std::fs::read(region.path()).unwrap()[4..7], b"fla");
+
+        let path = region.path().to_owned();
+        drop(region);
+        assert!(!path.exists());
+
+        assert!(SharedMemory::new(0).unwrap().bytes().is_empty());
+    }
+}
@@ -0,0 +1,217 @@
+//! Signalling and shutting down running test binaries.
+
+use crate::{ChildGuard, RunError, RunOutput};
+use std::{
+    sync::{Mutex, Once},
+    time::Duration,
+};
+
+/// The environment variable that overrides the default grace period, as a
+/// number of seconds, eg. `TEST_BINARY_GRACE_PERIOD=30` or `0.5`.
+pub const GRACE_PERIOD_ENV: &str = "TEST_BINARY_GRACE_PERIOD";
+
+/// The grace period to use when nothing else says otherwise.
+const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// The grace period set with [`set_grace_period()`].
+static GRACE_PERIOD: Mutex<Option<Duration>> = Mutex::new(None);
+
+/// Sets the default grace period for the rest of the process: how long a
+/// binary gets to exit after being asked to, before it's killed. This is used
+/// when a [`ChildGuard`] is dropped or a
+/// [`RunningHarness`](crate::RunningHarness) is stopped, and by
+/// [`Graceful::default()`], unless
+/// [`Runner::grace_period()`](crate::Runner::grace_period) says otherwise.
+/// It starts at 5 seconds.
+///
+/// The [`GRACE_PERIOD_ENV`] environment variable overrides this, so that
+/// slow CI machines can be given longer without changing any tests.
+pub fn set_grace_period(grace_period: Duration) {
+    *GRACE_PERIOD.lock().unwrap_or_else(|e| e.into_inner()) = Some(grace_period);
+}
+
+/// The default grace period. See [`set_grace_period()`].
+///
+/// If [`GRACE_PERIOD_ENV`] is set to something other than a number of
+/// seconds, it's ignored, with a warning on stderr the first time.
+pub fn grace_period() -> Duration {
+    if let Some(value) = std::env::var_os(GRACE_PERIOD_ENV) {
+        let parsed = value
+            .to_str()
+            .and_then(|value| value.trim().parse().ok())
+            .and_then(|secs| Duration::try_from_secs_f64(secs).ok());
+        match parsed {
+            Some(grace_period) => return grace_period,
+            None => {
+                static WARNING: Once = Once::new();
+                WARNING.call_once(|| {
+                    eprintln!(
+                        "warning: ignoring {}={:?}, which isn't a number of seconds",
+                        GRACE_PERIOD_ENV, value
+                    )
+                });
+            }
+        }
+    }
+    GRACE_PERIOD
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .unwrap_or(DEFAULT_GRACE_PERIOD)
+}
+
+/// A signal to send to a running test binary.
+///
+/// Windows doesn't have signals, so [`Signal::Term`] and [`Signal::Int`] are
+/// both delivered as a `CTRL_BREAK_EVENT` there, which by default also makes
+/// the binary exit, and [`Signal::Kill`] is `TerminateProcess()`. On platforms
+/// that have neither, only [`Signal::Kill`] can be sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    /// A request to terminate (`SIGTERM`).
+    Term,
+    /// An interrupt, like pressing Ctrl-C (`SIGINT`).
+    Int,
+    /// Immediate termination, which can't be handled (`SIGKILL`).
+    Kill,
+}
+
+/// Options for [`ChildGuard::shutdown()`]. The default is [`Signal::Term`]
+/// and the default [`grace_period()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Graceful {
+    /// The signal to send first.
+    pub signal: Signal,
+    /// How long to wait for the binary to exit after sending the signal,
+    /// before killing it.
+    pub grace_period: Duration,
+}
+
+impl Default for Graceful {
+    fn default() -> Self {
+        Self {
+            signal: Signal::Term,
+            grace_period: grace_period(),
+        }
+    }
+}
+
+/// How a binary was stopped by [`ChildGuard::shutdown()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownPath {
+    /// The binary had already exited before it was signalled.
+    AlreadyExited,
+    /// The binary exited within the grace period after being signalled.
+    Graceful,
+    /// The binary had to be killed.
+    Killed,
+}
+
+/// The result of [`ChildGuard::shutdown()`].
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    path: ShutdownPath,
+    output: RunOutput,
+}
+
+impl Shutdown {
+    /// How the binary was stopped.
+    pub fn path(&self) -> ShutdownPath {
+        self.path
+    }
+
+    /// The binary's output.
+    pub fn output(&self) -> &RunOutput {
+        &self.output
+    }
+
+    /// Consumes this, returning the binary's output.
+    pub fn into_output(self) -> RunOutput {
+        self.output
+    }
+}
+
+impl ChildGuard {
+    /// Asks the binary to exit by sending it a signal, and kills it if it
+    /// hasn't exited by the end of the grace period.
+    ///
+    /// The signal is sent with [`ChildGuard::signal()`]. If it can't be sent,
+    /// eg. on Windows when the tests aren't running in a console, the binary
+    /// is killed straight away and the path will be [`ShutdownPath::Killed`].
+    /// The same goes for [`Signal::Kill`], of course, and for a grace period
+    /// of zero.
+    pub fn shutdown(mut self, graceful: Graceful) -> Result<Shutdown, RunError> {
+        if self.try_wait()?.is_some() {
+            return Ok(Shutdown {
+                path: ShutdownPath::AlreadyExited,
+                output: self.wait()?,
+            });
+        }
+
+        let sent = graceful.signal != Signal::Kill
+            && !graceful.grace_period.is_zero()
+            && self.signal(graceful.signal).is_ok();
+        let (path, mut output) = if sent {
+            match self.wait_timeout(graceful.grace_period)? {
+                Ok(output) => (ShutdownPath::Graceful, output),
+                Err(guard) => (ShutdownPath::Killed, guard.kill()?),
+            }
+        } else {
+            (ShutdownPath::Killed, self.kill()?)
+        };
+
+        // We signalled or killed it, so it didn't crash.
+        output.crash = None;
+
+        Ok(Shutdown { path, output })
+    }
+
+    /// Sends a signal to the binary (but not any processes it started), using
+    /// whatever the platform has that means the same thing. See [`Signal`].
+    /// If there's nothing that does, this returns
+    /// [`RunError::UnsupportedSignal`].
+    pub fn signal(&mut self, signal: Signal) -> Result<(), RunError> {
+        match signal {
+            // This is SIGKILL or TerminateProcess(), as appropriate.
+            Signal::Kill => Ok(self.child_mut().kill()?),
+            _ => send_signal(self, signal),
+        }
+    }
+
+    /// Sends `CTRL_BREAK_EVENT` to the binary, like pressing Ctrl-Break in its
+    /// console. Binaries are started in a console process group of their own,
+    /// so nothing else gets it. This is the closest thing Windows has to
+    /// `SIGTERM`, for testing how a binary handles being asked to exit.
+    #[cfg(windows)]
+    #[cfg_attr(docsrs, doc(cfg(windows)))]
+    pub fn ctrl_break(&self) -> Result<(), RunError> {
+        Ok(crate::console::ctrl_break(self.id())?)
+    }
+}
+
+/// Sends a signal other than [`Signal::Kill`] to the child.
+#[cfg(unix)]
+fn send_signal(guard: &ChildGuard, signal: Signal) -> Result<(), RunError> {
+    use nix::{sys::signal, unistd::Pid};
+
+    let signal = match signal {
+        Signal::Term => signal::Signal::SIGTERM,
+        Signal::Int => signal::Signal::SIGINT,
+        Signal::Kill => signal::Signal::SIGKILL,
+    };
+
+    signal::kill(Pid::from_raw(guard.id() as i32), signal).map_err(std::io::Error::from)?;
+    Ok(())
+}
+
+/// Sends a signal other than [`Signal::Kill`] to the child.
+#[cfg(windows)]
+fn send_signal(guard: &ChildGuard, _signal: Signal) -> Result<(), RunError> {
+    // This fails if we don't have a console to share with the binary.
+    guard.ctrl_break()
+}
+
+/// Sends a signal other than [`Signal::Kill`] to the child.
+#[cfg(not(any(unix, windows)))]
+fn send_signal(_guard: &ChildGuard, signal: Signal) -> Result<(), RunError> {
+    Err(RunError::UnsupportedSignal(signal))
+}
@@ -0,0 +1,73 @@
+//! Keeping test binaries within a size budget, eg. for binaries that have to
+//! fit in a test image along with everything else.
+
+use crate::{Artifact, BuildProgress, TestBinary, TestBinaryError};
+
+/// The most a test binary should take up, and what to do if it's bigger.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SizeBudget {
+    pub(crate) bytes: u64,
+    /// Whether going over is only worth a warning, rather than an error.
+    pub(crate) warn_only: bool,
+}
+
+impl<'a> TestBinary<'a> {
+    /// Fails the build with [`TestBinaryError::OverSizeBudget`] if the binary
+    /// is bigger than this many bytes. Also see [`Artifact::size()`].
+    pub fn with_size_budget(&mut self, bytes: u64) -> &mut Self {
+        self.size_budget = Some(SizeBudget {
+            bytes,
+            warn_only: false,
+        });
+        self
+    }
+
+    /// Warns if the binary is bigger than this many bytes, as
+    /// [`BuildProgress::OverSizeBudget`] if there's a [progress
+    /// callback](TestBinary::on_progress) and on stderr otherwise, but still
+    /// returns it.
+    pub fn with_size_warning(&mut self, bytes: u64) -> &mut Self {
+        self.size_budget = Some(SizeBudget {
+            bytes,
+            warn_only: true,
+        });
+        self
+    }
+
+    /// Checks a newly built binary against the budget, if there is one.
+    pub(crate) fn check_size(
+        &mut self,
+        name: &str,
+        artifact: &Artifact,
+    ) -> Result<(), TestBinaryError> {
+        let Some(budget) = self.size_budget else {
+            return Ok(());
+        };
+        let size = artifact.size()?;
+        if size <= budget.bytes {
+            return Ok(());
+        }
+
+        let name = name.to_owned();
+        if !budget.warn_only {
+            return Err(TestBinaryError::OverSizeBudget {
+                name,
+                size,
+                budget: budget.bytes,
+            });
+        }
+        if self.progress.is_some() {
+            self.report(BuildProgress::OverSizeBudget {
+                name,
+                size,
+                budget: budget.bytes,
+            });
+        } else {
+            eprintln!(
+                r#"warning: test binary "{}" is {} bytes, over its budget of {}"#,
+                name, size, budget.bytes
+            );
+        }
+        Ok(())
+    }
+}
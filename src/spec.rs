@@ -0,0 +1,103 @@
+//! Build specifications that can be loaded from configuration files.
+
+use serde::Deserialize;
+use std::{collections::BTreeMap, path::PathBuf};
+
+/// A description of how to build a test binary, which can be deserialized
+/// from eg. TOML or JSON and turned into a builder with
+/// [`TestBinary::from_spec()`](crate::TestBinary::from_spec).
+///
+/// Field names are in kebab case, like in Cargo manifests. Only `name` is
+/// required:
+///
+/// ```toml
+/// name = "mock-server"
+/// dir = "testbins"
+/// features = ["tls"]
+/// default-features = false
+/// profile = "release"
+/// target = "x86_64-unknown-linux-musl"
+///
+/// [env]
+/// FIXTURE_PATH = "fixtures/server.json"
+/// ```
+///
+/// The binary is found the same way as by
+/// [`build_test_binary()`](crate::build_test_binary) if `dir` is given, or
+/// [`TestBinary::relative_to_parent()`](crate::TestBinary::relative_to_parent)
+/// if `manifest` is given instead, or
+/// [`TestBinary::from_workspace()`](crate::TestBinary::from_workspace) if
+/// neither is. `env` is passed to the binary's build script with
+/// [`TestBinary::with_build_script_env()`](crate::TestBinary::with_build_script_env).
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct TestBinarySpec {
+    /// The name of the binary.
+    pub name: String,
+    /// The directory containing the binary's package (or single source file),
+    /// relative to the parent.
+    pub dir: Option<PathBuf>,
+    /// The binary's manifest, relative to the parent.
+    pub manifest: Option<PathBuf>,
+    /// Features to enable.
+    pub features: Vec<String>,
+    /// Whether to enable the default features.
+    pub default_features: bool,
+    /// The profile to build with.
+    pub profile: Option<String>,
+    /// The target triple to build for.
+    pub target: Option<String>,
+    /// Environment variables for the binary's build script.
+    pub env: BTreeMap<String, String>,
+}
+
+impl Default for TestBinarySpec {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            dir: None,
+            manifest: None,
+            features: vec![],
+            default_features: true,
+            profile: None,
+            target: None,
+            env: BTreeMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deserialize() {
+        let spec: TestBinarySpec = serde_json::from_str(
+            r#"{
+                "name": "fla",
+                "dir": "testbins",
+                "features": ["pink"],
+                "default-features": false,
+                "env": { "FLA": "mingo" }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            spec,
+            TestBinarySpec {
+                name: "fla".to_owned(),
+                dir: Some("testbins".into()),
+                features: vec!["pink".to_owned()],
+                default_features: false,
+                env: [("FLA".to_owned(), "mingo".to_owned())].into(),
+                ..TestBinarySpec::default()
+            }
+        );
+
+        let spec: TestBinarySpec = serde_json::from_str(r#"{"name": "fla"}"#).unwrap();
+        assert!(spec.default_features);
+
+        assert!(serde_json::from_str::<TestBinarySpec>(r#"{"nmae": "fla"}"#).is_err());
+    }
+}
@@ -0,0 +1,94 @@
+//! Ready-made helper binaries for common subprocess testing needs.
+//!
+//! Each function here builds its binary on first use (as a package generated
+//! under the parent's target directory, in `test-binary/stock/<name>`) and
+//! returns it, so you don't need to write your own for these:
+//!
+//! ```rust
+//! let output = test_binary::stock::exit_with_code()
+//!     .unwrap()
+//!     .runner()
+//!     .arg("3")
+//!     .run()
+//!     .unwrap();
+//! assert_eq!(output.status().code(), Some(3));
+//! ```
+//!
+//! The binaries don't have any dependencies, so they're quick to build.
+
+use crate::{generated, Artifact, TestBinary, TestBinaryError};
+use once_cell::sync::OnceCell;
+
+/// Generates and builds a stock binary, caching the result.
+fn build(
+    cell: &'static OnceCell<Artifact>,
+    name: &str,
+    source: &str,
+) -> Result<Artifact, TestBinaryError> {
+    cell.get_or_try_init(|| {
+        let manifest_path = generated::binary_package("stock", name, source)?;
+        TestBinary::with_manifest(name, manifest_path).build_artifact()
+    })
+    .cloned()
+}
+
+macro_rules! stock {
+    ($(#[$doc:meta])* $function:ident, $name:literal, $file:literal) => {
+        $(#[$doc])*
+        pub fn $function() -> Result<Artifact, TestBinaryError> {
+            static ARTIFACT: OnceCell<Artifact> = OnceCell::new();
+            build(&ARTIFACT, $name, include_str!(concat!("stock/", $file)))
+        }
+    };
+}
+
+stock!(
+    /// A binary that exits with the code given as its first argument, or 0 if
+    /// there isn't one.
+    exit_with_code,
+    "exit-with-code",
+    "exit_with_code.rs"
+);
+
+stock!(
+    /// A binary that copies its stdin to its stdout until stdin is closed.
+    echo_stdin,
+    "echo-stdin",
+    "echo_stdin.rs"
+);
+
+stock!(
+    /// A binary that sleeps for the number of milliseconds given as its first
+    /// argument, then exits with the code given as its second argument, or 0 if
+    /// there isn't one.
+    sleep_then_exit,
+    "sleep-then-exit",
+    "sleep_then_exit.rs"
+);
+
+stock!(
+    /// A binary that ignores `SIGTERM`, prints `ready` to stdout, and then
+    /// sleeps for the number of milliseconds given as its first argument, or
+    /// forever if there isn't one. Useful for testing shutdown escalation. On
+    /// platforms without `SIGTERM`, it just prints and sleeps.
+    ignore_sigterm,
+    "ignore-sigterm",
+    "ignore_sigterm.rs"
+);
+
+stock!(
+    /// A binary that prints environment variables to stdout as `KEY=value`
+    /// lines: the ones named as its arguments (skipping any that aren't set),
+    /// or all of them if there are no arguments.
+    print_env,
+    "print-env",
+    "print_env.rs"
+);
+
+stock!(
+    /// A binary that keeps a CPU busy for the number of milliseconds given as
+    /// its first argument, or forever if there isn't one.
+    busy_loop,
+    "busy-loop",
+    "busy_loop.rs"
+);
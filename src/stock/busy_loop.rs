@@ -0,0 +1,15 @@
+//! Keeps a CPU busy for the number of milliseconds given as the first argument,
+//! or forever if there isn't one.
+
+use std::time::{Duration, Instant};
+
+fn main() {
+    let deadline = std::env::args().nth(1).map(|millis| {
+        Instant::now() + Duration::from_millis(millis.parse().expect("duration should be a number"))
+    });
+
+    let mut counter = 0u64;
+    while deadline.map_or(true, |deadline| Instant::now() < deadline) {
+        counter = std::hint::black_box(counter.wrapping_add(1));
+    }
+}
@@ -0,0 +1,20 @@
+//! Copies stdin to stdout until stdin is closed.
+
+use std::io::Write;
+
+fn main() {
+    let stdin = std::io::stdin();
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    let mut input = stdin.lock();
+    let mut buffer = [0; 8192];
+
+    loop {
+        let n = std::io::Read::read(&mut input, &mut buffer).expect("error reading stdin");
+        if n == 0 {
+            break;
+        }
+        stdout.write_all(&buffer[..n]).expect("error writing stdout");
+        stdout.flush().expect("error writing stdout");
+    }
+}
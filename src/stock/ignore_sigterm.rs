@@ -0,0 +1,34 @@
+//! Ignores SIGTERM, prints "ready" to stdout, then sleeps for the number of
+//! milliseconds given as the first argument, or forever if there isn't one. On
+//! platforms without SIGTERM, it just prints and sleeps.
+
+#[cfg(unix)]
+fn ignore_sigterm() {
+    extern "C" {
+        fn signal(signum: i32, handler: usize) -> usize;
+    }
+    const SIGTERM: i32 = 15;
+    const SIG_IGN: usize = 1;
+
+    // SAFETY: ignoring a signal doesn't involve any handler code running.
+    unsafe {
+        signal(SIGTERM, SIG_IGN);
+    }
+}
+
+#[cfg(not(unix))]
+fn ignore_sigterm() {}
+
+fn main() {
+    ignore_sigterm();
+    println!("ready");
+
+    match std::env::args().nth(1) {
+        Some(millis) => std::thread::sleep(std::time::Duration::from_millis(
+            millis.parse().expect("duration should be a number"),
+        )),
+        None => loop {
+            std::thread::park();
+        },
+    }
+}
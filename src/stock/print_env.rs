@@ -0,0 +1,19 @@
+//! Prints environment variables to stdout as `KEY=value` lines: the ones named
+//! as arguments (skipping any that aren't set), or all of them if there are no
+//! arguments.
+
+fn main() {
+    let names: Vec<String> = std::env::args().skip(1).collect();
+
+    if names.is_empty() {
+        for (key, value) in std::env::vars_os() {
+            println!("{}={}", key.to_string_lossy(), value.to_string_lossy());
+        }
+    } else {
+        for name in names {
+            if let Some(value) = std::env::var_os(&name) {
+                println!("{}={}", name, value.to_string_lossy());
+            }
+        }
+    }
+}
@@ -0,0 +1,17 @@
+//! Sleeps for the number of milliseconds given as the first argument, then
+//! exits with the code given as the second argument, or 0 if there isn't one.
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let millis = args
+        .next()
+        .map(|millis| millis.parse().expect("duration should be a number"))
+        .unwrap_or(0);
+    let code = args
+        .next()
+        .map(|code| code.parse().expect("exit code should be a number"))
+        .unwrap_or(0);
+
+    std::thread::sleep(std::time::Duration::from_millis(millis));
+    std::process::exit(code);
+}
@@ -1,60 +1,172 @@
 //! Stream handling and parsing code. This is the more "pure, functional" aspect
 //! of the test binary code.
 
-use crate::TestBinaryError;
+use crate::{
+    diagnostics::{Collector, DiagnosticOptions},
+    github, TestBinaryError,
+};
 use camino::Utf8PathBuf;
-use cargo_metadata::Message;
-use std::{fmt::Write as _, io::BufRead};
+use cargo_metadata::{BuildScript, Message, PackageId};
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Read},
+    process::Child,
+    sync::mpsc::{self, Receiver, Sender},
+    thread::JoinHandle,
+};
+
+/// Options for [`process_messages()`].
+#[derive(Debug, Clone, Copy, Default)]
+pub(super) struct MessageOptions {
+    /// Whether it's an error for Cargo to have compiled anything.
+    pub(super) frozen: bool,
+    /// Which compiler messages to keep.
+    pub(super) diagnostics: DiagnosticOptions,
+    /// Whether to report a failed build with GitHub Actions' workflow
+    /// commands.
+    pub(super) github: bool,
+}
+
+/// Which binaries we want from a build.
+#[derive(Debug, Clone)]
+pub(super) enum Wanted {
+    /// The binary target with this name.
+    Bin(String),
+    /// Every binary target in the package.
+    AllBins,
+}
+
+impl Wanted {
+    fn matches(&self, target_name: &str) -> bool {
+        match self {
+            Wanted::Bin(name) => name == target_name,
+            Wanted::AllBins => true,
+        }
+    }
+
+    /// The name to report if the binary isn't in the build output.
+    pub(super) fn name(&self) -> String {
+        match self {
+            Wanted::Bin(name) => name.clone(),
+            Wanted::AllBins => "*".to_owned(),
+        }
+    }
+}
+
+/// A binary found in Cargo's output.
+#[derive(Debug, PartialEq, Eq)]
+pub(super) struct BuiltBin {
+    /// The name of the binary target.
+    pub(super) name: String,
+    pub(super) path: Utf8PathBuf,
+    /// What the package's build script produced, if it has one.
+    pub(super) build_script: Option<BuildScript>,
+}
+
+/// What we found out about a successful build.
+#[derive(Debug)]
+pub(super) struct Built {
+    pub(super) bins: Vec<BuiltBin>,
+    /// Compiler messages, filtered and capped.
+    pub(super) diagnostics: String,
+    /// Targets that weren't fresh ie. that Cargo actually compiled.
+    pub(super) compiled: Vec<String>,
+}
 
-/// Process a stream of messages from Cargo's output, searching for the binary
-/// name we want or gathering information for a useful error.
+/// Process a stream of messages from Cargo's output, searching for the
+/// binaries we want or gathering information for a useful error.
 pub(super) fn process_messages<R: BufRead>(
     reader: R,
-    binary_name: &str,
-) -> Option<Result<Utf8PathBuf, TestBinaryError>> {
+    wanted: &Wanted,
+    options: &MessageOptions,
+) -> Option<Result<Built, TestBinaryError>> {
     // Parse messages with cargo_metadata.
     let messages = Message::parse_stream(reader);
 
-    // The actual outcome is we either find the path and return it, or generate
-    // an error.
-    let mut cargo_outcome = None;
-
     // Keep these in case the build fails.
-    let mut compiler_messages = String::new();
+    let mut compiler_messages = Collector::new(options.diagnostics);
+
+    // Targets that weren't fresh, for frozen mode and build records.
+    let mut compiled = vec![];
+
+    // The binaries we've found so far.
+    let mut bins = vec![];
+
+    // Build script output, which comes before the artifacts of its package.
+    let mut build_scripts: HashMap<PackageId, BuildScript> = HashMap::new();
+
+    // Annotations for GitHub Actions, if they're wanted.
+    let mut annotations = vec![];
+    let workspace = options.github.then(github::workspace).flatten();
 
     for message in messages.flatten() {
         match message {
-            // Hooray we found it!
+            // Hooray we found one!
             Message::CompilerArtifact(artf)
-                if (artf.target.name == binary_name
+                if (wanted.matches(&artf.target.name)
                     && artf.target.kind.contains(&"bin".to_string())) =>
             {
-                cargo_outcome = Some(artf.executable.ok_or_else(|| {
+                if !artf.fresh {
+                    compiled.push(artf.target.name.clone());
+                }
+                if options.frozen && !compiled.is_empty() {
+                    // It's built, but it shouldn't have needed to be.
+                    return Some(Err(TestBinaryError::NotFresh(compiled)));
+                }
+
+                match artf.executable {
+                    Some(path) => bins.push(BuiltBin {
+                        name: artf.target.name,
+                        path,
+                        build_script: build_scripts.get(&artf.package_id).cloned(),
+                    }),
                     // Wait no we didn't.
-                    TestBinaryError::BinaryNotBuilt(binary_name.to_owned())
-                }));
-                break;
+                    None => return Some(Err(TestBinaryError::BinaryNotBuilt(artf.target.name))),
+                }
+
+                // If we only want one, we don't need to wait for the rest.
+                if let Wanted::Bin(_) = wanted {
+                    break;
+                }
+            }
+
+            Message::CompilerArtifact(artf) if !artf.fresh => {
+                compiled.push(artf.target.name);
+            }
+
+            Message::BuildScriptExecuted(script) => {
+                build_scripts.insert(script.package_id.clone(), script);
             }
 
             // Let's keep these just in case.
             Message::CompilerMessage(msg) => {
-                writeln!(compiler_messages, "{}", msg).expect("error writing to String");
+                compiler_messages.diagnostic(&msg.message.level, &msg.to_string());
+                if options.github {
+                    annotations.extend(github::annotation(
+                        &wanted.name(),
+                        &msg,
+                        workspace.as_deref(),
+                    ));
+                }
             }
             Message::TextLine(text) => {
-                writeln!(compiler_messages, "{}", text).expect("error writing to String");
+                compiler_messages.text(&text);
             }
 
             // Hooray it's finished!
             Message::BuildFinished(build_result) => {
-                cargo_outcome = if build_result.success {
-                    cargo_outcome.or_else(|| {
-                        // Wait our binary isn't there.
-                        Some(Err(TestBinaryError::BinaryNotBuilt(binary_name.to_owned())))
-                    })
-                } else {
+                if !build_result.success {
                     // Wait it failed.
-                    Some(Err(TestBinaryError::BuildError(compiler_messages)))
-                };
+                    let messages = compiler_messages.finish();
+                    if options.github {
+                        github::report(&wanted.name(), &messages, &annotations);
+                    }
+                    return Some(Err(TestBinaryError::BuildError(messages)));
+                }
+                if bins.is_empty() {
+                    // Wait our binary isn't there.
+                    return Some(Err(TestBinaryError::BinaryNotBuilt(wanted.name())));
+                }
                 break;
             }
 
@@ -62,7 +174,197 @@ pub(super) fn process_messages<R: BufRead>(
         }
     }
 
-    cargo_outcome
+    if bins.is_empty() {
+        None
+    } else {
+        Some(Ok(Built {
+            bins,
+            diagnostics: compiler_messages.finish(),
+            compiled,
+        }))
+    }
+}
+
+/// Output from Cargo, as it arrives.
+#[derive(Debug)]
+pub(super) enum Output {
+    /// Some bytes from stdout, not necessarily a whole line.
+    Stdout(Vec<u8>),
+    /// A line from stderr.
+    Stderr(std::io::Result<String>),
+}
+
+/// Wraps Cargo's stdout, sending a copy of everything read from it.
+#[derive(Debug)]
+struct Tee<R> {
+    inner: R,
+    sender: Sender<Output>,
+}
+
+impl<R> Tee<R> {
+    fn new(inner: R, sender: Sender<Output>) -> Self {
+        Self { inner, sender }
+    }
+}
+
+impl<R: Read> Read for Tee<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n > 0 {
+            // Nobody listening is fine; reading still works.
+            let _ = self.sender.send(Output::Stdout(buf[..n].to_vec()));
+        }
+        Ok(n)
+    }
+}
+
+/// Reads Cargo's stdout and stderr at the same time, each on a thread of its
+/// own, so that Cargo can't fill up one pipe and block while we're still
+/// waiting on the other. Everything read is sent on as [`Output`] as it
+/// arrives, until both are closed. Stdout is also parsed with
+/// [`process_messages()`], whose outcome the thread returns.
+pub(super) fn drain(
+    cargo: &mut Child,
+    wanted: Wanted,
+    options: MessageOptions,
+) -> (
+    Receiver<Output>,
+    JoinHandle<Option<Result<Built, TestBinaryError>>>,
+) {
+    let (sender, outputs) = mpsc::channel();
+
+    // The child process' stdout and stderr being None is legitimately a
+    // programming error, since they're always piped.
+    let stdout = cargo
+        .stdout
+        .take()
+        .expect("Cargo subprocess output has already been claimed");
+    let stdout_sender = sender.clone();
+    let messages = std::thread::spawn(move || {
+        let mut reader = BufReader::new(Tee::new(stdout, stdout_sender));
+        let outcome = process_messages(&mut reader, &wanted, &options);
+        // Keep reading until Cargo is finished. If we close its stdout
+        // early, it can fail with a broken pipe error (but in a highly
+        // timing/platform/performance dependent and intermittent way).
+        let _ = std::io::copy(&mut reader, &mut std::io::sink());
+        outcome
+    });
+
+    let stderr = cargo
+        .stderr
+        .take()
+        .expect("Cargo subprocess error output has already been claimed");
+    std::thread::spawn(move || {
+        let mut reader = BufReader::new(stderr);
+        let mut line = vec![];
+        loop {
+            line.clear();
+            let result = match reader.read_until(b'\n', &mut line) {
+                Ok(0) => break,
+                Ok(_) => Ok(String::from_utf8_lossy(&line).into_owned()),
+                Err(e) => Err(e),
+            };
+            if sender.send(Output::Stderr(result)).is_err() {
+                break;
+            }
+        }
+    });
+
+    (outputs, messages)
+}
+
+/// A short description of a line of Cargo's JSON output, for diagnostics.
+pub(super) fn describe_message(line: &str) -> String {
+    let value = match serde_json::from_str::<serde_json::Value>(line) {
+        Ok(value) => value,
+        Err(_) => return line.trim().to_owned(),
+    };
+
+    let reason = value["reason"].as_str().unwrap_or("unknown message");
+    match value["target"]["name"].as_str() {
+        Some(target) => format!("{} ({})", reason, target),
+        None => reason.to_owned(),
+    }
+}
+
+/// If the line from Cargo's stderr says it's blocked waiting for a file lock,
+/// returns what it's waiting for.
+pub(super) fn lock_wait(line: &str) -> Option<String> {
+    let line = strip_ansi(line);
+    line.trim()
+        .strip_prefix("Blocking waiting for file lock on ")
+        .map(str::to_owned)
+}
+
+/// Whether the line from Cargo's stderr is one of its progress updates eg.
+/// `   Compiling fla v0.1.0`, which have a capitalised word right-aligned to
+/// the twelfth column.
+pub(super) fn is_status_line(line: &str) -> bool {
+    let line = strip_ansi(line);
+    match (line.get(..12), line.get(12..13)) {
+        (Some(status), Some(" ")) => {
+            let word = status.trim_start();
+            word.len() < status.len()
+                && word.starts_with(|c: char| c.is_ascii_uppercase())
+                && word.chars().all(|c| c.is_ascii_alphabetic())
+        }
+        _ => false,
+    }
+}
+
+/// If the line from Cargo's stderr is a warning from a build script, as
+/// reported by Cargo eg. `warning: fla@0.1.0: look out`, returns the warning.
+pub(super) fn build_script_warning(line: &str) -> Option<String> {
+    let line = strip_ansi(line);
+    let (package, warning) = line
+        .trim_end()
+        .strip_prefix("warning: ")?
+        .split_once(": ")?;
+    if package.contains('@') && !package.contains(' ') {
+        Some(warning.to_owned())
+    } else {
+        None
+    }
+}
+
+/// Whether the line from Cargo's stderr starts the report of a build script
+/// failing. Everything after it is about the failure.
+pub(super) fn is_build_script_failure(line: &str) -> bool {
+    strip_ansi(line).starts_with("error: failed to run custom build command")
+}
+
+/// If the line from Cargo's stderr says where to find a future-incompatibility
+/// report eg. ``note: this report can be shown with `cargo report
+/// future-incompatibilities --id 1` ``, returns the report's ID.
+pub(super) fn future_incompat_report_id(line: &str) -> Option<u32> {
+    let line = strip_ansi(line);
+    let (_, rest) = line.split_once("cargo report future-incompatibilities --id ")?;
+    rest.split(|c: char| !c.is_ascii_digit())
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// Removes terminal colour codes, which Cargo uses when told to with
+/// `CARGO_TERM_COLOR`.
+fn strip_ansi(line: &str) -> String {
+    let mut stripped = String::with_capacity(line.len());
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            // Skip to the end of the escape sequence.
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            stripped.push(c);
+        }
+    }
+
+    stripped
 }
 
 #[cfg(test)]
@@ -95,7 +397,11 @@ error: aborting due to previous error
 
 "#};
 
-        let outcome = process_messages(std::io::Cursor::new(json_output), binary);
+        let outcome = process_messages(
+            std::io::Cursor::new(json_output),
+            &Wanted::Bin(binary.to_owned()),
+            &MessageOptions::default(),
+        );
 
         if let Some(Err(TestBinaryError::BuildError(msg))) = outcome {
             assert_eq!(msg, expected_msg);
@@ -128,7 +434,11 @@ error: aborting due to previous error
 
 "#};
 
-        let outcome = process_messages(std::io::Cursor::new(json_output), binary);
+        let outcome = process_messages(
+            std::io::Cursor::new(json_output),
+            &Wanted::Bin(binary.to_owned()),
+            &MessageOptions::default(),
+        );
 
         if let Some(Err(TestBinaryError::BuildError(msg))) = outcome {
             assert_eq!(msg, expected_msg);
@@ -145,7 +455,11 @@ error: aborting due to previous error
 {"reason":"build-finished","success":true}
 "##};
 
-        let outcome = process_messages(std::io::Cursor::new(json_output), binary);
+        let outcome = process_messages(
+            std::io::Cursor::new(json_output),
+            &Wanted::Bin(binary.to_owned()),
+            &MessageOptions::default(),
+        );
 
         if let Some(Err(TestBinaryError::BinaryNotBuilt(name))) = outcome {
             assert_eq!(name, binary);
@@ -161,7 +475,11 @@ error: aborting due to previous error
 {"reason":"build-finished","success":true}
 "##};
 
-        let outcome = process_messages(std::io::Cursor::new(json_output), binary);
+        let outcome = process_messages(
+            std::io::Cursor::new(json_output),
+            &Wanted::Bin(binary.to_owned()),
+            &MessageOptions::default(),
+        );
 
         if let Some(Err(TestBinaryError::BinaryNotBuilt(name))) = outcome {
             assert_eq!(name, binary);
@@ -169,4 +487,218 @@ error: aborting due to previous error
             panic!("{:#?}", outcome);
         }
     }
+
+    #[test]
+    fn frozen() {
+        let wanted = Wanted::Bin("fla".to_owned());
+        let json_output = indoc! {r##"
+{"reason":"compiler-artifact","package_id":"dep 0.1.0 (registry+https://github.com/rust-lang/crates.io-index)","manifest_path":"/registry/dep/Cargo.toml","target":{"kind":["lib"],"crate_types":["lib"],"name":"dep","src_path":"/registry/dep/src/lib.rs","edition":"2021","doc":true,"doctest":true,"test":true},"profile":{"opt_level":"0","debuginfo":2,"debug_assertions":true,"overflow_checks":true,"test":false},"features":[],"filenames":["/test-binary/testbins/fla/target/debug/deps/libdep.rlib"],"executable":null,"fresh":true}
+{"reason":"compiler-artifact","package_id":"fla 0.1.0 (path+file:///test-binary/testbins/fla)","manifest_path":"/test-binary/testbins/fla/Cargo.toml","target":{"kind":["bin"],"crate_types":["bin"],"name":"fla","src_path":"/test-binary/testbins/fla/src/main.rs","edition":"2021","doc":true,"doctest":false,"test":true},"profile":{"opt_level":"0","debuginfo":2,"debug_assertions":true,"overflow_checks":true,"test":false},"features":[],"filenames":["/test-binary/testbins/fla/target/debug/fla"],"executable":"/test-binary/testbins/fla/target/debug/fla","fresh":false}
+{"reason":"build-finished","success":true}
+"##};
+
+        let outcome = process_messages(
+            std::io::Cursor::new(json_output),
+            &wanted,
+            &MessageOptions::default(),
+        );
+        assert_eq!(
+            outcome.unwrap().unwrap().bins[0].path,
+            "/test-binary/testbins/fla/target/debug/fla"
+        );
+
+        let frozen = MessageOptions {
+            frozen: true,
+            ..MessageOptions::default()
+        };
+        let outcome = process_messages(std::io::Cursor::new(json_output), &wanted, &frozen);
+        if let Some(Err(TestBinaryError::NotFresh(compiled))) = outcome {
+            assert_eq!(compiled, ["fla"]);
+        } else {
+            panic!("{:#?}", outcome);
+        }
+
+        let fresh = json_output.replace(r#""fresh":false"#, r#""fresh":true"#);
+        let outcome = process_messages(std::io::Cursor::new(fresh), &wanted, &frozen);
+        assert!(matches!(outcome, Some(Ok(_))));
+    }
+
+    #[test]
+    fn all_bins() {
+        let json_output = indoc! {r##"
+{"reason":"compiler-artifact","package_id":"dep 0.1.0 (registry+https://github.com/rust-lang/crates.io-index)","manifest_path":"/registry/dep/Cargo.toml","target":{"kind":["lib"],"crate_types":["lib"],"name":"dep","src_path":"/registry/dep/src/lib.rs","edition":"2021","doc":true,"doctest":true,"test":true},"profile":{"opt_level":"0","debuginfo":2,"debug_assertions":true,"overflow_checks":true,"test":false},"features":[],"filenames":["/test-binary/testbins/fla/target/debug/deps/libdep.rlib"],"executable":null,"fresh":true}
+{"reason":"compiler-artifact","package_id":"fla 0.1.0 (path+file:///test-binary/testbins/fla)","manifest_path":"/test-binary/testbins/fla/Cargo.toml","target":{"kind":["bin"],"crate_types":["bin"],"name":"fla","src_path":"/test-binary/testbins/fla/src/main.rs","edition":"2021","doc":true,"doctest":false,"test":true},"profile":{"opt_level":"0","debuginfo":2,"debug_assertions":true,"overflow_checks":true,"test":false},"features":[],"filenames":["/test-binary/testbins/fla/target/debug/fla"],"executable":"/test-binary/testbins/fla/target/debug/fla","fresh":false}
+{"reason":"compiler-artifact","package_id":"fla 0.1.0 (path+file:///test-binary/testbins/fla)","manifest_path":"/test-binary/testbins/fla/Cargo.toml","target":{"kind":["bin"],"crate_types":["bin"],"name":"mingo","src_path":"/test-binary/testbins/fla/src/bin/mingo.rs","edition":"2021","doc":true,"doctest":false,"test":true},"profile":{"opt_level":"0","debuginfo":2,"debug_assertions":true,"overflow_checks":true,"test":false},"features":[],"filenames":["/test-binary/testbins/fla/target/debug/mingo"],"executable":"/test-binary/testbins/fla/target/debug/mingo","fresh":false}
+{"reason":"build-finished","success":true}
+"##};
+
+        let outcome = process_messages(
+            std::io::Cursor::new(json_output),
+            &Wanted::AllBins,
+            &MessageOptions::default(),
+        );
+        let bins = outcome.unwrap().unwrap().bins;
+        let names_and_paths: Vec<_> = bins
+            .iter()
+            .map(|bin| (bin.name.as_str(), bin.path.as_str()))
+            .collect();
+        assert_eq!(
+            names_and_paths,
+            [
+                ("fla", "/test-binary/testbins/fla/target/debug/fla"),
+                ("mingo", "/test-binary/testbins/fla/target/debug/mingo")
+            ]
+        );
+
+        let outcome = process_messages(
+            std::io::Cursor::new(json_output),
+            &Wanted::Bin("mingo".to_owned()),
+            &MessageOptions::default(),
+        );
+        assert_eq!(outcome.unwrap().unwrap().bins[0].name, "mingo");
+    }
+
+    #[test]
+    fn build_script() {
+        let json_output = indoc! {r##"
+{"reason":"build-script-executed","package_id":"dep 0.1.0 (registry+https://github.com/rust-lang/crates.io-index)","linked_libs":[],"linked_paths":[],"cfgs":[],"env":[],"out_dir":"/test-binary/testbins/fla/target/debug/build/dep-0123/out"}
+{"reason":"build-script-executed","package_id":"fla 0.1.0 (path+file:///test-binary/testbins/fla)","linked_libs":[],"linked_paths":[],"cfgs":[],"env":[["GREETING","hello"]],"out_dir":"/test-binary/testbins/fla/target/debug/build/fla-4567/out"}
+{"reason":"compiler-artifact","package_id":"fla 0.1.0 (path+file:///test-binary/testbins/fla)","manifest_path":"/test-binary/testbins/fla/Cargo.toml","target":{"kind":["bin"],"crate_types":["bin"],"name":"fla","src_path":"/test-binary/testbins/fla/src/main.rs","edition":"2021","doc":true,"doctest":false,"test":true},"profile":{"opt_level":"0","debuginfo":2,"debug_assertions":true,"overflow_checks":true,"test":false},"features":[],"filenames":["/test-binary/testbins/fla/target/debug/fla"],"executable":"/test-binary/testbins/fla/target/debug/fla","fresh":false}
+{"reason":"build-finished","success":true}
+"##};
+
+        let outcome = process_messages(
+            std::io::Cursor::new(json_output),
+            &Wanted::Bin("fla".to_owned()),
+            &MessageOptions::default(),
+        );
+        let script = outcome
+            .unwrap()
+            .unwrap()
+            .bins
+            .remove(0)
+            .build_script
+            .unwrap();
+        assert_eq!(
+            script.out_dir,
+            "/test-binary/testbins/fla/target/debug/build/fla-4567/out"
+        );
+        assert_eq!(script.env, [("GREETING".to_owned(), "hello".to_owned())]);
+    }
+
+    #[test]
+    fn status_lines() {
+        assert!(is_status_line(
+            "   Compiling fla v0.1.0 (/test-binary/testbins/fla)\n"
+        ));
+        assert!(is_status_line(
+            "\u{1b}[1m\u{1b}[32m    Finished\u{1b}[0m `dev` profile"
+        ));
+        assert!(!is_status_line(
+            "error: failed to run custom build command\n"
+        ));
+        assert!(!is_status_line(
+            "  process didn't exit successfully: `fla`\n"
+        ));
+        assert!(!is_status_line("Caused by:\n"));
+    }
+
+    #[test]
+    fn build_script_warnings() {
+        assert_eq!(
+            build_script_warning("warning: fla@0.1.0: look out: a flamingo\n"),
+            Some("look out: a flamingo".to_owned())
+        );
+        assert_eq!(
+            build_script_warning("warning: unused manifest key: package.fla\n"),
+            None
+        );
+        assert_eq!(build_script_warning("   Compiling fla v0.1.0\n"), None);
+        assert!(is_build_script_failure(
+            "error: failed to run custom build command for `fla v0.1.0`\n"
+        ));
+    }
+
+    #[test]
+    fn future_incompat_report_ids() {
+        assert_eq!(
+            future_incompat_report_id(
+                "note: this report can be shown with `cargo report future-incompatibilities --id 12`\n"
+            ),
+            Some(12)
+        );
+        assert_eq!(
+            future_incompat_report_id(
+                "warning: the following packages contain code that will be rejected by a future \
+                 version of Rust: fla v0.1.0\n"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn describe_messages() {
+        assert_eq!(
+            describe_message(
+                r#"{"reason":"compiler-artifact","target":{"name":"fla"},"fresh":true}"#
+            ),
+            "compiler-artifact (fla)"
+        );
+        assert_eq!(
+            describe_message(r#"{"reason":"build-finished","success":true}"#),
+            "build-finished"
+        );
+        assert_eq!(describe_message("   Compiling fla\n"), "Compiling fla");
+    }
+
+    #[test]
+    fn lock_wait_lines() {
+        assert_eq!(
+            lock_wait("    Blocking waiting for file lock on build directory"),
+            Some("build directory".to_owned())
+        );
+        assert_eq!(
+            lock_wait(
+                "\u{1b}[1m\u{1b}[36m    Blocking\u{1b}[0m waiting for file lock on package cache"
+            ),
+            Some("package cache".to_owned())
+        );
+        assert_eq!(lock_wait("   Compiling does-build v0.1.0"), None);
+    }
+
+    // Both streams are drained even when the other is full, and nothing is
+    // lost along the way.
+    #[cfg(unix)]
+    #[test]
+    fn drains_both_streams() {
+        use std::process::{Command, Stdio};
+
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(
+                "yes 'on stderr' | head -n 50000 >&2\n\
+                 yes 'on stdout' | head -n 50000\n\
+                 yes 'on stderr' | head -n 50000 >&2\n",
+            )
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let (outputs, messages) = drain(&mut child, Wanted::AllBins, MessageOptions::default());
+
+        let (mut stdout, mut stderr_lines) = (0, 0);
+        for output in outputs {
+            match output {
+                Output::Stdout(bytes) => stdout += bytes.len(),
+                Output::Stderr(line) => {
+                    assert_eq!(line.unwrap(), "on stderr\n");
+                    stderr_lines += 1;
+                }
+            }
+        }
+        assert_eq!(stdout, 50_000 * "on stdout\n".len());
+        assert_eq!(stderr_lines, 100_000);
+        messages.join().unwrap();
+        assert!(child.wait().unwrap().success());
+    }
 }
@@ -0,0 +1,94 @@
+//! Building many test binaries with the same settings.
+
+use crate::{Artifact, TestBinary, TestBinaryError};
+use std::{
+    ffi::OsString,
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+type Setting<'a> = Arc<dyn Fn(&mut TestBinary<'a>) + Send + Sync + 'a>;
+
+/// Settings to build any number of test binaries with, for suites where many
+/// helpers are built the same way.
+///
+/// A template finds binaries the way
+/// [`build_test_binary()`](crate::build_test_binary) does, in a directory
+/// relative to the parent, and configures each one's builder as it's told:
+///
+/// ```rust
+/// # use test_binary::BuildTemplate;
+/// let mut template = BuildTemplate::new("testbins");
+/// template.configure(|b| b.with_profile("release").with_incremental(true));
+///
+/// let does_build = template.build("does-build").unwrap();
+/// let multiple = template.build("multiple").unwrap();
+/// ```
+///
+/// Templates are cheap to clone, and can be shared between threads, so each
+/// test can build what it needs from one template in a `static`.
+#[derive(Clone)]
+pub struct BuildTemplate<'a> {
+    directory: PathBuf,
+    settings: Vec<Setting<'a>>,
+}
+
+impl<'a> BuildTemplate<'a> {
+    /// Creates a template for binaries in `directory`, relative to the
+    /// parent, with no settings of its own.
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Self {
+        Self {
+            directory: directory.into(),
+            settings: vec![],
+        }
+    }
+
+    /// Adds `configure` to the settings. It's called with the builder for
+    /// each binary, after any settings added before it.
+    pub fn configure<F>(&mut self, configure: F) -> &mut Self
+    where
+        F: for<'b> Fn(&'b mut TestBinary<'a>) -> &'b mut TestBinary<'a> + Send + Sync + 'a,
+    {
+        self.settings.push(Arc::new(move |binary| {
+            configure(binary);
+        }));
+        self
+    }
+
+    /// The directory binaries are found in, relative to the parent.
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// The builder for the binary `name`, with the template's settings, for
+    /// when one binary needs something more.
+    pub fn binary(&self, name: &'a str) -> Result<TestBinary<'a>, TestBinaryError> {
+        let mut binary = TestBinary::in_directory(name, &self.directory)?;
+        for setting in &self.settings {
+            setting(&mut binary);
+        }
+        Ok(binary)
+    }
+
+    /// Builds the binary `name` with the template's settings, returning its
+    /// path like [`TestBinary::build()`].
+    pub fn build(&self, name: &'a str) -> Result<OsString, TestBinaryError> {
+        self.binary(name)?.build()
+    }
+
+    /// Builds the binary `name` with the template's settings, like
+    /// [`TestBinary::build_artifact()`].
+    pub fn build_artifact(&self, name: &'a str) -> Result<Artifact, TestBinaryError> {
+        self.binary(name)?.build_artifact()
+    }
+}
+
+impl fmt::Debug for BuildTemplate<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BuildTemplate")
+            .field("directory", &self.directory)
+            .field("settings", &self.settings.len())
+            .finish()
+    }
+}
@@ -0,0 +1,142 @@
+//! Building test binaries with the toolchain their own toolchain file asks
+//! for.
+
+use crate::TestBinaryError;
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// The names rustup looks for, in the order it prefers them.
+const FILE_NAMES: [&str; 2] = ["rust-toolchain", "rust-toolchain.toml"];
+
+/// A toolchain requested by a toolchain file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Toolchain {
+    pub(crate) channel: String,
+    pub(crate) file: PathBuf,
+}
+
+impl Toolchain {
+    /// Finds the toolchain file that applies to the package in `package_dir`,
+    /// if it's not one that applies to the parent (in `parent_dir`) too, since
+    /// that toolchain is already in use.
+    pub(crate) fn find(
+        package_dir: &Path,
+        parent_dir: &Path,
+    ) -> Result<Option<Self>, TestBinaryError> {
+        for dir in package_dir.ancestors() {
+            if dir == parent_dir {
+                break;
+            }
+
+            for name in FILE_NAMES {
+                let file = dir.join(name);
+                let contents = match std::fs::read_to_string(&file) {
+                    Ok(contents) => contents,
+                    Err(_) => continue,
+                };
+                let channel =
+                    parse(&contents).ok_or_else(|| TestBinaryError::ToolchainUnavailable {
+                        toolchain: String::new(),
+                        file: file.clone(),
+                        reason: "couldn't find a channel in the toolchain file".to_owned(),
+                    })?;
+                return Ok(Some(Self { channel, file }));
+            }
+
+            // Outside the parent, only the package's own directory counts.
+            if !dir.starts_with(parent_dir) {
+                break;
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// The path to one of the toolchain's tools eg. `cargo`, as found by
+    /// rustup. It's an error if rustup isn't installed, or the toolchain
+    /// isn't.
+    pub(crate) fn which(&self, tool: &str) -> Result<PathBuf, TestBinaryError> {
+        let unavailable = |reason: String| TestBinaryError::ToolchainUnavailable {
+            toolchain: self.channel.clone(),
+            file: self.file.clone(),
+            reason,
+        };
+
+        let output = Command::new("rustup")
+            .args(["which", tool, "--toolchain", &self.channel])
+            .env_remove("RUST_BACKTRACE")
+            .output()
+            .map_err(|err| unavailable(format!("couldn't run rustup: {}", err)))?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let reason = stderr.lines().next().unwrap_or_default();
+            let reason = reason.strip_prefix("error: ").unwrap_or(reason);
+            return Err(unavailable(reason.to_owned()));
+        }
+
+        Ok(PathBuf::from(
+            String::from_utf8_lossy(&output.stdout).trim_end(),
+        ))
+    }
+}
+
+/// Gets the channel from a toolchain file, which is either TOML or (in the
+/// legacy format) just the channel name.
+fn parse(contents: &str) -> Option<String> {
+    match contents.parse::<toml::Table>() {
+        Ok(table) => table
+            .get("toolchain")?
+            .get("channel")?
+            .as_str()
+            .map(str::to_owned),
+        Err(_) => {
+            let channel = contents.trim();
+            let legacy = !channel.is_empty() && !channel.contains(char::is_whitespace);
+            legacy.then(|| channel.to_owned())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses() {
+        assert_eq!(
+            parse("[toolchain]\nchannel = \"1.70\"\ncomponents = [\"clippy\"]\n").as_deref(),
+            Some("1.70")
+        );
+        assert_eq!(
+            parse("nightly-2024-01-01\n").as_deref(),
+            Some("nightly-2024-01-01")
+        );
+        assert_eq!(parse("[toolchain]\npath = \"/opt/rust\"\n"), None);
+        assert_eq!(parse(""), None);
+    }
+
+    #[test]
+    fn finds() {
+        let parent = tempfile::tempdir().unwrap();
+        let package = parent.path().join("testbins").join("fla");
+        std::fs::create_dir_all(&package).unwrap();
+        assert_eq!(Toolchain::find(&package, parent.path()).unwrap(), None);
+
+        // The parent's own toolchain file doesn't count.
+        std::fs::write(parent.path().join("rust-toolchain"), "stable").unwrap();
+        assert_eq!(Toolchain::find(&package, parent.path()).unwrap(), None);
+
+        let file = parent.path().join("testbins").join("rust-toolchain.toml");
+        std::fs::write(&file, "[toolchain]\nchannel = \"beta\"\n").unwrap();
+        assert_eq!(
+            Toolchain::find(&package, parent.path()).unwrap(),
+            Some(Toolchain {
+                channel: "beta".to_owned(),
+                file,
+            })
+        );
+    }
+}
@@ -0,0 +1,154 @@
+//! Forwarding structured logs from test binaries to `tracing`.
+//!
+//! The test binary writes one JSON object per line to stderr, in the format
+//! produced by `tracing-subscriber`'s JSON formatter (either with fields nested
+//! under `"fields"` or flattened). Lines that aren't JSON log records are left
+//! alone.
+
+use serde_json::{Map, Value};
+use tracing::Level;
+
+/// The target that forwarded events are emitted under.
+const TARGET: &str = "test_binary::child";
+
+/// A log record parsed from a test binary's output.
+#[derive(Debug, PartialEq)]
+struct Record {
+    level: Level,
+    target: String,
+    message: String,
+    fields: Map<String, Value>,
+}
+
+/// Keys in a flattened record that aren't event fields.
+const METADATA_KEYS: &[&str] = &[
+    "timestamp",
+    "level",
+    "target",
+    "span",
+    "spans",
+    "threadName",
+    "threadId",
+    "filename",
+    "line_number",
+];
+
+fn parse(line: &str) -> Option<Record> {
+    let mut object = match serde_json::from_str::<Value>(line).ok()? {
+        Value::Object(object) => object,
+        _ => return None,
+    };
+
+    let level = object.get("level")?.as_str()?.parse().ok()?;
+    let target = object
+        .get("target")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_owned();
+
+    let mut fields = match object.remove("fields") {
+        Some(Value::Object(fields)) => fields,
+        _ => {
+            for key in METADATA_KEYS {
+                object.remove(*key);
+            }
+            object
+        }
+    };
+
+    let message = match fields.remove("message") {
+        Some(Value::String(message)) => message,
+        Some(other) => other.to_string(),
+        None => String::new(),
+    };
+
+    Some(Record {
+        level,
+        target,
+        message,
+        fields,
+    })
+}
+
+/// Emits the line as an event within the span, if it's a log record. Returns
+/// whether it was.
+pub(crate) fn forward(span: &tracing::Span, line: &str) -> bool {
+    let record = match parse(line) {
+        Some(record) => record,
+        None => return false,
+    };
+
+    let target = record.target.as_str();
+    let message = record.message.as_str();
+    let fields = Value::Object(record.fields);
+
+    // The level has to be known at compile time for the macro.
+    macro_rules! forward_at {
+        ($level:expr) => {
+            tracing::event!(
+                target: TARGET,
+                parent: span,
+                $level,
+                child_target = target,
+                fields = %fields,
+                "{}",
+                message
+            )
+        };
+    }
+
+    match record.level {
+        Level::ERROR => forward_at!(Level::ERROR),
+        Level::WARN => forward_at!(Level::WARN),
+        Level::INFO => forward_at!(Level::INFO),
+        Level::DEBUG => forward_at!(Level::DEBUG),
+        Level::TRACE => forward_at!(Level::TRACE),
+    }
+
+    true
+}
+
+/// Creates the span that identifies events from a particular child.
+pub(crate) fn child_span(name: &str, pid: u32) -> tracing::Span {
+    tracing::info_span!(target: TARGET, "child", name, pid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nested_fields() {
+        let line = r#"{"timestamp":"2022-10-01T00:00:00.000000Z","level":"WARN","fields":{"message":"disk full","free":0},"target":"mock_server"}"#;
+
+        let mut fields = Map::new();
+        fields.insert("free".to_owned(), Value::from(0));
+
+        assert_eq!(
+            parse(line),
+            Some(Record {
+                level: Level::WARN,
+                target: "mock_server".to_owned(),
+                message: "disk full".to_owned(),
+                fields,
+            })
+        );
+    }
+
+    #[test]
+    fn flattened_fields() {
+        let line = r#"{"timestamp":"2022-10-01T00:00:00.000000Z","level":"DEBUG","message":"accepted","peer":"127.0.0.1","target":"mock_server"}"#;
+        let record = parse(line).unwrap();
+        assert_eq!(record.level, Level::DEBUG);
+        assert_eq!(record.message, "accepted");
+        assert_eq!(record.fields.len(), 1);
+        assert_eq!(record.fields["peer"], "127.0.0.1");
+    }
+
+    #[test]
+    fn not_a_record() {
+        assert_eq!(parse("thread 'main' panicked"), None);
+        assert_eq!(parse(r#"{"level":"LOUD"}"#), None);
+        assert_eq!(parse("[1, 2, 3]"), None);
+    }
+}
@@ -0,0 +1,279 @@
+//! Resource usage of test binary runs.
+//!
+//! On Unix, the binary is reaped with `wait4()`, which also returns what the
+//! kernel kept count of for it. On Windows, the binary is put in a job object
+//! of its own, and the job's accounting is queried once it exits. Neither has
+//! a safe interface, so this needs unsafe code. Other platforms don't report
+//! anything.
+
+#![allow(unsafe_code)]
+
+use crate::ChildGuard;
+use std::time::Duration;
+
+/// Resource usage of a test binary run. See
+/// [`Runner::sample_resource_usage()`](crate::Runner::sample_resource_usage).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResourceUsage {
+    peak_rss: Option<u64>,
+    cpu_time: Option<Duration>,
+}
+
+impl ResourceUsage {
+    /// The peak resident set size of the binary, in bytes. On Windows, this
+    /// is the peak memory committed by the binary and anything it started,
+    /// which is what Windows keeps count of.
+    pub fn peak_rss(&self) -> Option<u64> {
+        self.peak_rss
+    }
+
+    /// The total user and system CPU time used by the binary, including any
+    /// processes it started and waited for (or on Windows, any it started at
+    /// all).
+    pub fn cpu_time(&self) -> Option<Duration> {
+        self.cpu_time
+    }
+}
+
+/// Keeps track of a binary's resource usage until it exits.
+#[derive(Debug, Default)]
+pub(crate) struct Sampler {
+    /// The usage reported when the binary was reaped, if it has been.
+    #[cfg(unix)]
+    reaped: Option<ResourceUsage>,
+    /// The binary's job, unless it couldn't be put in one.
+    #[cfg(windows)]
+    job: Option<windows::Job>,
+}
+
+impl Sampler {
+    /// Starts keeping track of the binary's resource usage.
+    #[cfg_attr(not(windows), allow(unused_variables))]
+    pub(crate) fn start(guard: &mut ChildGuard) -> Self {
+        #[cfg(windows)]
+        return Self {
+            // Nested jobs need Windows 8. Without one, there's just nothing
+            // to report.
+            job: windows::Job::new(guard.child_mut()).ok(),
+        };
+        #[cfg(not(windows))]
+        Self::default()
+    }
+
+    /// Checks whether the binary has exited, without blocking. On Unix, this
+    /// reaps it if it has, and records how it exited in the guard.
+    #[cfg(unix)]
+    pub(crate) fn poll(&mut self, guard: &mut ChildGuard) -> std::io::Result<bool> {
+        if self.reaped.is_none() {
+            if let Some((status, usage)) = unix::wait4(guard.id(), false)? {
+                guard.set_reaped(status);
+                self.reaped = Some(usage);
+            }
+        }
+        Ok(self.reaped.is_some())
+    }
+
+    /// Checks whether the binary has exited, without blocking.
+    #[cfg(not(unix))]
+    pub(crate) fn poll(&mut self, guard: &mut ChildGuard) -> std::io::Result<bool> {
+        Ok(guard.child_mut().try_wait()?.is_some())
+    }
+
+    /// Waits for the binary to exit (or finish being killed), and returns what
+    /// it used.
+    #[cfg(unix)]
+    pub(crate) fn finish(self, guard: &mut ChildGuard) -> std::io::Result<ResourceUsage> {
+        if let Some(usage) = self.reaped {
+            return Ok(usage);
+        }
+        let (status, usage) =
+            unix::wait4(guard.id(), true)?.expect("blocking wait4() returned without a child");
+        guard.set_reaped(status);
+        Ok(usage)
+    }
+
+    /// Waits for the binary to exit (or finish being killed), and returns what
+    /// it used.
+    #[cfg(not(unix))]
+    pub(crate) fn finish(self, guard: &mut ChildGuard) -> std::io::Result<ResourceUsage> {
+        guard.child_mut().wait()?;
+        #[cfg(windows)]
+        if let Some(job) = &self.job {
+            return job.usage();
+        }
+        Ok(ResourceUsage::default())
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use super::ResourceUsage;
+    use nix::libc;
+    use std::{os::unix::process::ExitStatusExt, process::ExitStatus, time::Duration};
+
+    /// Reaps the process `pid` if it's exited, or waits until it does if
+    /// `block` is set, returning how it exited and what it used.
+    pub(super) fn wait4(
+        pid: u32,
+        block: bool,
+    ) -> std::io::Result<Option<(ExitStatus, ResourceUsage)>> {
+        let flags = if block { 0 } else { libc::WNOHANG };
+        let mut status = 0;
+        // SAFETY: `rusage` is plain old data, for which all zeroes is valid.
+        let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+        loop {
+            // SAFETY: both pointers are to locals that outlive the call, and
+            // are of the types it expects.
+            let reaped =
+                unsafe { libc::wait4(pid as libc::pid_t, &mut status, flags, &mut rusage) };
+            match reaped {
+                0 => return Ok(None),
+                -1 => {
+                    let error = std::io::Error::last_os_error();
+                    if error.kind() != std::io::ErrorKind::Interrupted {
+                        return Err(error);
+                    }
+                }
+                _ => break,
+            }
+        }
+
+        let usage = ResourceUsage {
+            peak_rss: peak_rss(rusage.ru_maxrss as i64),
+            cpu_time: Some(duration(rusage.ru_utime) + duration(rusage.ru_stime)),
+        };
+        Ok(Some((ExitStatus::from_raw(status), usage)))
+    }
+
+    /// Converts `ru_maxrss` to bytes. Apple platforms give it in bytes, and
+    /// everything else in kilobytes.
+    fn peak_rss(maxrss: i64) -> Option<u64> {
+        let unit = if cfg!(target_vendor = "apple") {
+            1
+        } else {
+            1024
+        };
+        u64::try_from(maxrss)
+            .ok()
+            .filter(|&maxrss| maxrss > 0)
+            .map(|maxrss| maxrss * unit)
+    }
+
+    fn duration(time: libc::timeval) -> Duration {
+        Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn conversions() {
+            let unit = if cfg!(target_vendor = "apple") {
+                1
+            } else {
+                1024
+            };
+            assert_eq!(peak_rss(1896), Some(1896 * unit));
+            assert_eq!(peak_rss(0), None);
+
+            let time = libc::timeval {
+                tv_sec: 2,
+                tv_usec: 420_000,
+            };
+            assert_eq!(duration(time), Duration::from_millis(2420));
+        }
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use super::ResourceUsage;
+    use std::{
+        os::windows::io::AsRawHandle,
+        process::Child,
+        ptr::{null, null_mut},
+        time::Duration,
+    };
+    use winapi::{
+        shared::minwindef::{DWORD, LPVOID},
+        um::{
+            handleapi::CloseHandle,
+            jobapi2::{AssignProcessToJobObject, CreateJobObjectW, QueryInformationJobObject},
+            winnt::{
+                JobObjectBasicAccountingInformation, JobObjectExtendedLimitInformation, HANDLE,
+                JOBOBJECTINFOCLASS, JOBOBJECT_BASIC_ACCOUNTING_INFORMATION,
+                JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+            },
+        },
+    };
+
+    /// A job object with just the binary in it, to keep count of what it
+    /// uses. Unlike the job it's started in, this has no limits, so closing
+    /// it leaves the binary alone.
+    #[derive(Debug)]
+    pub(super) struct Job(HANDLE);
+
+    impl Job {
+        pub(super) fn new(child: &Child) -> std::io::Result<Self> {
+            // SAFETY: both arguments may be null, for an unnamed job with the
+            // default security attributes.
+            let handle = unsafe { CreateJobObjectW(null_mut(), null()) };
+            if handle.is_null() {
+                return Err(std::io::Error::last_os_error());
+            }
+            let job = Self(handle);
+            // SAFETY: both handles are open for as long as the call.
+            if unsafe { AssignProcessToJobObject(job.0, child.as_raw_handle() as HANDLE) } == 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(job)
+        }
+
+        /// What the binary (and anything it started) used.
+        pub(super) fn usage(&self) -> std::io::Result<ResourceUsage> {
+            let accounting: JOBOBJECT_BASIC_ACCOUNTING_INFORMATION =
+                self.query(JobObjectBasicAccountingInformation)?;
+            let limits: JOBOBJECT_EXTENDED_LIMIT_INFORMATION =
+                self.query(JobObjectExtendedLimitInformation)?;
+
+            // SAFETY: these are always set as 64 bit integers, of 100ns each.
+            let ticks = unsafe {
+                *accounting.TotalUserTime.QuadPart() + *accounting.TotalKernelTime.QuadPart()
+            };
+            Ok(ResourceUsage {
+                peak_rss: Some(limits.PeakJobMemoryUsed as u64),
+                cpu_time: Some(Duration::from_nanos(ticks as u64 * 100)),
+            })
+        }
+
+        fn query<T>(&self, class: JOBOBJECTINFOCLASS) -> std::io::Result<T> {
+            // SAFETY: `T` is only ever one of the plain old data structs the
+            // query fills in, for which all zeroes is valid, and its size is
+            // passed along with it.
+            unsafe {
+                let mut info: T = std::mem::zeroed();
+                let queried = QueryInformationJobObject(
+                    self.0,
+                    class,
+                    &mut info as *mut T as LPVOID,
+                    std::mem::size_of::<T>() as DWORD,
+                    null_mut(),
+                );
+                if queried == 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(info)
+            }
+        }
+    }
+
+    impl Drop for Job {
+        fn drop(&mut self) {
+            // SAFETY: the handle is open, and only closed here.
+            unsafe {
+                CloseHandle(self.0);
+            }
+        }
+    }
+}
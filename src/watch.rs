@@ -0,0 +1,99 @@
+//! Rebuilding a test binary whenever its sources change.
+
+use crate::{fingerprint::source_fingerprint, Artifact, TestBinary, TestBinaryError};
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::{
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+    sync::mpsc,
+    time::Duration,
+};
+
+/// How long to wait for things to settle after a change, so that saving
+/// several files at once only causes one build.
+const SETTLE_TIME: Duration = Duration::from_millis(200);
+
+impl TestBinary<'_> {
+    /// Builds the binary, and then builds it again whenever its sources
+    /// change, calling `on_build` with the result each time. This keeps going
+    /// until `on_build` returns [`ControlFlow::Break`].
+    ///
+    /// The sources are the package's `Cargo.toml`, `build.rs` and everything
+    /// under `src`, the same as for
+    /// [`build_test_binary_once!()`](crate::build_test_binary_once). This is
+    /// for working on a test binary alongside the tests that use it eg. by
+    /// running them from `on_build`.
+    ///
+    /// ```rust,no_run
+    /// # use std::{ops::ControlFlow, path::PathBuf};
+    /// # use test_binary::TestBinary;
+    /// TestBinary::relative_to_parent(
+    ///     "mock-server",
+    ///     &PathBuf::from_iter(["testbins", "mock_server", "Cargo.toml"]),
+    /// )
+    /// .unwrap()
+    /// .watch(|result| {
+    ///     match result {
+    ///         Ok(artifact) => println!("rebuilt {}", artifact.path().display()),
+    ///         Err(err) => println!("{}", err),
+    ///     }
+    ///     ControlFlow::Continue(())
+    /// })
+    /// .unwrap();
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "watch")))]
+    pub fn watch<F>(&mut self, mut on_build: F) -> Result<(), TestBinaryError>
+    where
+        F: FnMut(Result<Artifact, TestBinaryError>) -> ControlFlow<()>,
+    {
+        let package_dir = self.manifest.parent().unwrap_or(Path::new(".")).to_owned();
+
+        // Start watching before the first build, so no changes are missed.
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        watcher.watch(&package_dir, RecursiveMode::NonRecursive)?;
+        let src = package_dir.join("src");
+        if src.exists() {
+            watcher.watch(&src, RecursiveMode::Recursive)?;
+        }
+
+        loop {
+            let fingerprint = source_fingerprint(&package_dir);
+            if on_build(self.build_artifact()).is_break() {
+                return Ok(());
+            }
+
+            // Events can be for things other than the sources (eg. the target
+            // directory, or editors' temporary files) or for changes that
+            // were already built, so keep waiting until there's a real one.
+            loop {
+                wait_for_change(&events, &package_dir)?;
+                while events.recv_timeout(SETTLE_TIME).is_ok() {}
+                if source_fingerprint(&package_dir) != fingerprint {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Waits for an event that could be a change to the sources in `package_dir`.
+fn wait_for_change(
+    events: &mpsc::Receiver<notify::Result<notify::Event>>,
+    package_dir: &Path,
+) -> Result<(), TestBinaryError> {
+    let is_source = |path: &PathBuf| {
+        let relative = path.strip_prefix(package_dir).unwrap_or(path);
+        relative.starts_with("src")
+            || relative == Path::new("Cargo.toml")
+            || relative == Path::new("build.rs")
+    };
+
+    loop {
+        // The watcher is only dropped once we've returned.
+        let event = events.recv().expect("file watcher stopped")?;
+        if !matches!(event.kind, EventKind::Access(_)) && event.paths.iter().any(is_source) {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,62 @@
+//! Building for Windows with a different ABI than the parent.
+
+/// The ABI (and so the toolchain) to build a test binary with when it's built
+/// for Windows. See
+/// [`TestBinary::with_windows_abi()`](crate::TestBinary::with_windows_abi).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WindowsAbi {
+    /// Microsoft's ABI, linked with the MSVC tools eg.
+    /// `x86_64-pc-windows-msvc`.
+    Msvc,
+    /// The MinGW ABI, linked with the GNU tools eg. `x86_64-pc-windows-gnu`.
+    Gnu,
+    /// The MinGW ABI, linked with LLVM's tools eg.
+    /// `x86_64-pc-windows-gnullvm`.
+    GnuLlvm,
+}
+
+impl WindowsAbi {
+    /// The last part of a Windows target triple with this ABI.
+    fn env(self) -> &'static str {
+        match self {
+            Self::Msvc => "msvc",
+            Self::Gnu => "gnu",
+            Self::GnuLlvm => "gnullvm",
+        }
+    }
+
+    /// `triple` with this ABI instead of its own, or `None` if it's not a
+    /// Windows triple or already has this ABI.
+    pub(crate) fn apply(self, triple: &str) -> Option<String> {
+        let (start, env) = triple.rsplit_once("-windows-")?;
+        if env == self.env() || !["msvc", "gnu", "gnullvm"].contains(&env) {
+            return None;
+        }
+        Some(format!("{}-windows-{}", start, self.env()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn triples() {
+        assert_eq!(
+            WindowsAbi::Gnu.apply("x86_64-pc-windows-msvc").as_deref(),
+            Some("x86_64-pc-windows-gnu")
+        );
+        assert_eq!(
+            WindowsAbi::Msvc.apply("i686-pc-windows-gnu").as_deref(),
+            Some("i686-pc-windows-msvc")
+        );
+        assert_eq!(
+            WindowsAbi::GnuLlvm
+                .apply("aarch64-pc-windows-msvc")
+                .as_deref(),
+            Some("aarch64-pc-windows-gnullvm")
+        );
+        assert_eq!(WindowsAbi::Msvc.apply("x86_64-pc-windows-msvc"), None);
+        assert_eq!(WindowsAbi::Gnu.apply("x86_64-unknown-linux-gnu"), None);
+    }
+}
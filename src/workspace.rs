@@ -0,0 +1,138 @@
+//! Caching what `cargo metadata` says about the parent's workspace.
+//!
+//! Looking up a package with [`TestBinary::from_workspace()`](crate::TestBinary::from_workspace)
+//! needs the workspace's metadata, which can take seconds to get in a big
+//! workspace. So it's fetched once per process and reused, until the
+//! workspace's lockfile changes, which it does whenever its members or their
+//! dependencies do.
+
+use crate::ManifestError;
+use cargo_metadata::{Metadata, MetadataCommand};
+use once_cell::sync::Lazy;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+#[derive(Debug, Default)]
+struct Cache {
+    /// The workspace root for each package directory.
+    roots: HashMap<PathBuf, PathBuf>,
+    /// The metadata for each workspace root.
+    workspaces: HashMap<PathBuf, Cached>,
+}
+
+#[derive(Debug)]
+struct Cached {
+    /// When the lockfile was last modified, if there is one.
+    lockfile_modified: Option<SystemTime>,
+    metadata: Arc<Metadata>,
+}
+
+static CACHE: Lazy<Mutex<Cache>> = Lazy::new(Default::default);
+
+/// The metadata of the workspace that the package in `manifest_dir` belongs
+/// to.
+pub(crate) fn metadata(manifest_dir: &Path) -> Result<Arc<Metadata>, ManifestError> {
+    // Held throughout, so that callers don't all run Cargo at once.
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+
+    let root = match cache.roots.get(manifest_dir) {
+        Some(root) => root.clone(),
+        None => {
+            let manifest = MetadataCommand::new()
+                .manifest_path(manifest_dir.join("Cargo.toml"))
+                .no_deps()
+                .exec()
+                .map_err(|e| {
+                    ManifestError::ReadManifest(manifest_dir.to_path_buf(), e.to_string())
+                })?;
+            let root = manifest.workspace_root.into_std_path_buf();
+            cache.roots.insert(manifest_dir.to_path_buf(), root.clone());
+            root
+        }
+    };
+
+    cached(&mut cache, &root, || {
+        let workspace_manifest = root.join("Cargo.toml");
+        MetadataCommand::new()
+            .manifest_path(&workspace_manifest)
+            .exec()
+            .map_err(|e| ManifestError::ReadManifest(workspace_manifest, e.to_string()))
+    })
+}
+
+/// Returns the cached metadata for the workspace at `root` if its lockfile
+/// hasn't changed since, or else runs `load` and caches the result.
+fn cached<F>(cache: &mut Cache, root: &Path, load: F) -> Result<Arc<Metadata>, ManifestError>
+where
+    F: FnOnce() -> Result<Metadata, ManifestError>,
+{
+    let lockfile_modified = || {
+        std::fs::metadata(root.join("Cargo.lock"))
+            .and_then(|m| m.modified())
+            .ok()
+    };
+
+    if let Some(cached) = cache.workspaces.get(root) {
+        if cached.lockfile_modified == lockfile_modified() {
+            return Ok(Arc::clone(&cached.metadata));
+        }
+    }
+
+    let metadata = Arc::new(load()?);
+    // Cargo might have just written the lockfile, so check it afterwards.
+    cache.workspaces.insert(
+        root.to_path_buf(),
+        Cached {
+            lockfile_modified: lockfile_modified(),
+            metadata: Arc::clone(&metadata),
+        },
+    );
+    Ok(metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(root: &Path) -> Result<Metadata, ManifestError> {
+        MetadataCommand::new()
+            .manifest_path(root.join("Cargo.toml"))
+            .no_deps()
+            .exec()
+            .map_err(|e| ManifestError::ReadManifest(root.to_path_buf(), e.to_string()))
+    }
+
+    #[test]
+    fn reloads_when_lockfile_changes() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testbins")
+            .join("does-build");
+        let dir = tempfile::tempdir().unwrap();
+        let lockfile = dir.path().join("Cargo.lock");
+        let mut cache = Cache::default();
+        let loads = std::cell::Cell::new(0);
+
+        let get = |cache: &mut Cache| {
+            cached(cache, dir.path(), || {
+                loads.set(loads.get() + 1);
+                load(&root)
+            })
+            .unwrap()
+        };
+
+        let first = get(&mut cache);
+        let second = get(&mut cache);
+        assert!(Arc::ptr_eq(&first, &second));
+
+        std::fs::write(&lockfile, "").unwrap();
+        let third = get(&mut cache);
+        assert!(!Arc::ptr_eq(&second, &third));
+        let fourth = get(&mut cache);
+        assert!(Arc::ptr_eq(&third, &fourth));
+        assert_eq!(loads.get(), 2);
+    }
+}
@@ -0,0 +1,259 @@
+//! The test binary side of [test-binary]'s stdio RPC protocol.
+//!
+//! A test binary that depends on this crate can be driven from a test with
+//! `test_binary::RpcClient`: the test sends requests to the binary's stdin,
+//! and the binary answers them on its stdout. This makes it possible to write
+//! one flexible mock whose behaviour each test decides as it goes, rather than
+//! a mock for every scripted exchange.
+//!
+//! ```rust,no_run
+//! use test_binary_rpc::Server;
+//!
+//! fn main() {
+//!     Server::new()
+//!         .handle("add", |(a, b): (i64, i64)| Ok(a + b))
+//!         .handle("greet", |name: String| Ok(format!("hello, {}", name)))
+//!         .serve()
+//!         .unwrap();
+//! }
+//! ```
+//!
+//! Since stdout carries the protocol, anything else the binary wants to print
+//! has to go to stderr.
+//!
+//! # Protocol
+//!
+//! Every message is a 4 byte, big-endian length followed by that many bytes
+//! of JSON. Requests look like `{"id": 1, "method": "add", "params": [1, 2]}`,
+//! and each one gets a response with the same ID, either
+//! `{"id": 1, "result": 3}` or `{"id": 1, "error": "what went wrong"}`. The
+//! binary serves requests one at a time, in order, until its stdin is closed.
+//!
+//! [test-binary]: https://crates.io/crates/test-binary
+
+#![warn(missing_docs, missing_debug_implementations)]
+#![deny(unsafe_code)]
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::{Read, Write},
+};
+
+/// The number of bytes in a message's length prefix.
+pub const LENGTH_PREFIX: usize = 4;
+
+/// A request from the test to the binary.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Request {
+    /// Identifies the request, so its response can be matched up with it.
+    pub id: u64,
+    /// What the binary should do.
+    pub method: String,
+    /// The method's parameters.
+    #[serde(default)]
+    pub params: Value,
+}
+
+/// A response from the binary to the test.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Response {
+    /// The ID of the request this answers.
+    pub id: u64,
+    /// What the method returned, if it succeeded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    /// Why the method failed, if it did.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Encodes a message, including its length prefix.
+pub fn encode<T: Serialize>(message: &T) -> std::io::Result<Vec<u8>> {
+    let json = serde_json::to_vec(message)?;
+    let length = u32::try_from(json.len())
+        .map_err(|_| invalid_data("message too long"))?
+        .to_be_bytes();
+
+    let mut encoded = Vec::with_capacity(LENGTH_PREFIX + json.len());
+    encoded.extend_from_slice(&length);
+    encoded.extend_from_slice(&json);
+    Ok(encoded)
+}
+
+/// Finds the first complete message at the start of `data`. Returns the
+/// message's JSON and the total number of bytes it takes up, or `None` if
+/// there isn't a complete message yet.
+pub fn decode_frame(data: &[u8]) -> Option<(&[u8], usize)> {
+    let prefix: [u8; LENGTH_PREFIX] = data.get(..LENGTH_PREFIX)?.try_into().ok()?;
+    let end = LENGTH_PREFIX + u32::from_be_bytes(prefix) as usize;
+    Some((data.get(LENGTH_PREFIX..end)?, end))
+}
+
+/// Writes a message, including its length prefix.
+pub fn write_message<W: Write, T: Serialize>(mut writer: W, message: &T) -> std::io::Result<()> {
+    writer.write_all(&encode(message)?)?;
+    writer.flush()
+}
+
+/// Reads a message. Returns `None` if the reader ends before the message
+/// starts.
+pub fn read_message<R: Read, T: DeserializeOwned>(mut reader: R) -> std::io::Result<Option<T>> {
+    let mut prefix = [0; LENGTH_PREFIX];
+    let mut filled = 0;
+    while filled < LENGTH_PREFIX {
+        match reader.read(&mut prefix[filled..])? {
+            0 if filled == 0 => return Ok(None),
+            0 => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            count => filled += count,
+        }
+    }
+
+    let mut json = vec![0; u32::from_be_bytes(prefix) as usize];
+    reader.read_exact(&mut json)?;
+    Ok(Some(serde_json::from_slice(&json)?))
+}
+
+fn invalid_data(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+/// A method handler, which takes the parameters as JSON.
+type Handler = Box<dyn FnMut(Value) -> Result<Value, String>>;
+
+/// Serves requests from a test, calling a handler for each one according to
+/// its method.
+#[derive(Default)]
+pub struct Server {
+    handlers: BTreeMap<String, Handler>,
+}
+
+impl Server {
+    /// Creates a server with no methods.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Handles requests for `method` with `handler`. The request's parameters
+    /// are deserialized as `P`, and if that fails, the test gets an error
+    /// without the handler being called.
+    pub fn handle<P, R, F>(&mut self, method: &str, mut handler: F) -> &mut Self
+    where
+        P: DeserializeOwned,
+        R: Serialize,
+        F: FnMut(P) -> Result<R, String> + 'static,
+    {
+        let handler = move |params: Value| {
+            let params = serde_json::from_value(params)
+                .map_err(|err| format!("invalid parameters: {}", err))?;
+            let result = handler(params)?;
+            serde_json::to_value(result).map_err(|err| format!("invalid result: {}", err))
+        };
+        self.handlers.insert(method.to_owned(), Box::new(handler));
+        self
+    }
+
+    /// Serves requests on stdin and stdout until stdin is closed.
+    pub fn serve(&mut self) -> std::io::Result<()> {
+        self.serve_on(std::io::stdin().lock(), std::io::stdout().lock())
+    }
+
+    /// Serves requests read from `reader`, writing responses to `writer`,
+    /// until the reader ends.
+    pub fn serve_on<R: Read, W: Write>(
+        &mut self,
+        mut reader: R,
+        mut writer: W,
+    ) -> std::io::Result<()> {
+        while let Some(request) = read_message::<_, Request>(&mut reader)? {
+            let outcome = match self.handlers.get_mut(&request.method) {
+                Some(handler) => handler(request.params),
+                None => Err(format!("unknown method: {}", request.method)),
+            };
+            let response = match outcome {
+                Ok(result) => Response {
+                    id: request.id,
+                    result: Some(result),
+                    error: None,
+                },
+                Err(error) => Response {
+                    id: request.id,
+                    result: None,
+                    error: Some(error),
+                },
+            };
+            write_message(&mut writer, &response)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Server {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Server")
+            .field("methods", &self.handlers.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(id: u64, method: &str, params: Value) -> Vec<u8> {
+        encode(&Request {
+            id,
+            method: method.to_owned(),
+            params,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn frames() {
+        let encoded = encode(&"fla").unwrap();
+        assert_eq!(encoded, b"\0\0\0\x05\"fla\"");
+        assert_eq!(decode_frame(&encoded), Some((&b"\"fla\""[..], 9)));
+        assert_eq!(decode_frame(&encoded[..6]), None);
+        assert_eq!(decode_frame(&encoded[..2]), None);
+
+        let mut reader = &encoded[..];
+        assert_eq!(
+            read_message::<_, String>(&mut reader).unwrap().unwrap(),
+            "fla"
+        );
+        assert!(read_message::<_, String>(&mut reader).unwrap().is_none());
+        assert!(read_message::<_, String>(&encoded[..2]).is_err());
+    }
+
+    #[test]
+    fn serves() {
+        let mut input = request(1, "add", serde_json::json!([1, 2]));
+        input.extend(request(2, "add", serde_json::json!("mingo")));
+        input.extend(request(3, "pink", Value::Null));
+
+        let mut output = vec![];
+        Server::new()
+            .handle("add", |(a, b): (i64, i64)| Ok(a + b))
+            .serve_on(&input[..], &mut output)
+            .unwrap();
+
+        let mut output = &output[..];
+        let mut responses = vec![];
+        while let Some(response) = read_message::<_, Response>(&mut output).unwrap() {
+            responses.push(response);
+        }
+
+        assert_eq!(responses.len(), 3);
+        assert_eq!(responses[0].result, Some(serde_json::json!(3)));
+        assert!(responses[1]
+            .error
+            .as_ref()
+            .unwrap()
+            .starts_with("invalid parameters"));
+        assert_eq!(responses[2].id, 3);
+        assert_eq!(responses[2].error.as_deref(), Some("unknown method: pink"));
+    }
+}
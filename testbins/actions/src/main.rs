@@ -0,0 +1,74 @@
+//! Test binary for test-binary crate. This binary performs the actions given
+//! as arguments, in order:
+//!
+//! - `print <text>` prints a line to stdout
+//! - `eprint <text>` prints a line to stderr
+//! - `sleep <millis>` sleeps
+//! - `exit <code>` exits with the given code
+//! - `spawn <millis>` starts another copy of this binary that sleeps, and prints
+//!   its process ID
+//! - `ignore <signal>` ignores `SIGTERM` or `SIGINT` (Unix only)
+//! - `write <path>=<text>` writes the text at the start of an existing file,
+//!   without truncating it
+//!
+//! If it runs out of actions, it exits successfully.
+
+use std::{process::exit, thread::sleep, time::Duration};
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+
+    while let Some(action) = args.next() {
+        let param = args.next().expect("missing parameter for action");
+
+        match action.as_str() {
+            "print" => println!("{}", param),
+            "eprint" => eprintln!("{}", param),
+            "sleep" => sleep(Duration::from_millis(param.parse().unwrap())),
+            "exit" => exit(param.parse().unwrap()),
+            "spawn" => spawn(&param),
+            "write" => write(&param),
+            #[cfg(unix)]
+            "ignore" => ignore(&param),
+            other => panic!("unknown action: {}", other),
+        }
+    }
+}
+
+/// Starts a copy of this binary in the background, which outlives us unless
+/// it's killed along with us.
+fn spawn(millis: &str) {
+    let child = std::process::Command::new(std::env::current_exe().unwrap())
+        .args(["sleep", millis])
+        .spawn()
+        .unwrap();
+    println!("{}", child.id());
+}
+
+/// Overwrites the start of a file, which might be mapped by someone else.
+fn write(param: &str) {
+    use std::io::Write;
+
+    let (path, text) = param.split_once('=').expect("missing = in write");
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(path)
+        .unwrap()
+        .write_all(text.as_bytes())
+        .unwrap();
+}
+
+/// Replaces the default handler for the signal, so it doesn't terminate us.
+#[cfg(unix)]
+fn ignore(signal: &str) {
+    use signal_hook::consts::{SIGINT, SIGTERM};
+    use std::sync::{atomic::AtomicBool, Arc};
+
+    let signal = match signal {
+        "SIGTERM" => SIGTERM,
+        "SIGINT" => SIGINT,
+        other => panic!("unknown signal: {}", other),
+    };
+
+    signal_hook::flag::register(signal, Arc::new(AtomicBool::new(false))).unwrap();
+}
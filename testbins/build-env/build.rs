@@ -0,0 +1,8 @@
+//! Build script for the build-env test binary. It embeds the fixture path it's
+//! given in `BUILD_ENV_FIXTURE` into the binary.
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=BUILD_ENV_FIXTURE");
+    let fixture = std::env::var("BUILD_ENV_FIXTURE").unwrap_or_default();
+    println!("cargo:rustc-env=FIXTURE={}", fixture);
+}
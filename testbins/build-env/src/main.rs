@@ -0,0 +1,6 @@
+//! Test binary for test-binary crate. This binary prints the fixture path its
+//! build script was given.
+
+fn main() {
+    println!("{}", env!("FIXTURE"));
+}
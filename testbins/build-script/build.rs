@@ -0,0 +1,18 @@
+//! Build script for the build-script test binary. It generates a file in
+//! `OUT_DIR`, sets a variable for compiling the binary, and warns about it. It
+//! fails if `BUILD_SCRIPT_FAIL` is set.
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=BUILD_SCRIPT_FAIL");
+
+    let out_dir = std::env::var_os("OUT_DIR").unwrap();
+    std::fs::write(std::path::Path::new(&out_dir).join("generated.txt"), "generated\n").unwrap();
+    println!("cargo:rustc-env=GREETING=hello");
+    println!("cargo:warning=generated a file");
+
+    if std::env::var_os("BUILD_SCRIPT_FAIL").is_some() {
+        eprintln!("told to fail");
+        std::process::exit(1);
+    }
+}
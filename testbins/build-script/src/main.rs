@@ -0,0 +1,5 @@
+//! Test binary for test-binary crate. This binary has a build script.
+
+fn main() {
+    println!("{}", env!("GREETING"));
+}
@@ -0,0 +1,11 @@
+//! Test binary for test-binary crate. This binary prints the features it was
+//! built with, one per line.
+
+fn main() {
+    if cfg!(feature = "compression") {
+        println!("compression");
+    }
+    if cfg!(feature = "tls") {
+        println!("tls");
+    }
+}
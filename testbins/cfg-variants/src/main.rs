@@ -0,0 +1,13 @@
+fn main() {
+    if cfg!(test_mode = "slow") {
+        println!("slow");
+    } else {
+        println!("normal");
+    }
+    if cfg!(mock_mode = "replay") {
+        println!("replaying");
+    }
+    if cfg!(offline) {
+        println!("offline");
+    }
+}
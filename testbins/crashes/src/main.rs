@@ -0,0 +1,8 @@
+//! Test binary for test-binary crate. This binary panics with `panic = "abort"`
+//! when given the argument "panic", and exits normally otherwise.
+
+fn main() {
+    if std::env::args().nth(1).as_deref() == Some("panic") {
+        panic!("deliberate crash");
+    }
+}
@@ -0,0 +1,10 @@
+//! Test binary for test-binary crate. This binary prints whether it was built
+//! with debug assertions, so tests can tell which profile it was built with.
+
+fn main() {
+    if cfg!(debug_assertions) {
+        println!("debug assertions");
+    } else {
+        println!("no debug assertions");
+    }
+}
@@ -0,0 +1,11 @@
+//! Test binary for test-binary crate. This binary builds, but with code that
+//! a future version of Rust will reject.
+
+// This is deliberate, and allowing it doesn't stop Cargo reporting it.
+#![allow(invalid_type_param_default)]
+
+fn fla<T = u8>() {}
+
+fn main() {
+    fla::<u8>();
+}
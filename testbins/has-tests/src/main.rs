@@ -0,0 +1,29 @@
+//! Test binary for test-binary crate. This binary has tests of its own, one
+//! of which fails with the "fail" feature.
+
+fn double(x: i32) -> i32 {
+    if cfg!(feature = "fail") {
+        x * 3
+    } else {
+        x * 2
+    }
+}
+
+fn main() {
+    println!("{}", double(1));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn doubles() {
+        assert_eq!(double(2), 4);
+    }
+
+    #[test]
+    fn zero() {
+        assert_eq!(double(0), 0);
+    }
+}
@@ -0,0 +1,7 @@
+//! Test binary for test-binary crate. This binary builds, but Clippy has
+//! something to say about it.
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    println!("{}", args.len() == 0);
+}
@@ -0,0 +1,6 @@
+//! Test binary for test-binary crate. This is the second binary in the
+//! mock_server package.
+
+fn main() {
+    println!("mock client");
+}
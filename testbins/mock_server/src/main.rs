@@ -0,0 +1,6 @@
+//! Test binary for test-binary crate. This binary's package directory has a
+//! different name to the binary, and the package has more than one binary.
+
+fn main() {
+    println!("mock server");
+}
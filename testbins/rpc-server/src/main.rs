@@ -0,0 +1,29 @@
+//! Test binary for test-binary crate. This binary serves requests from an
+//! `RpcClient`:
+//!
+//! - `add [a, b]` returns the sum
+//! - `eprint <text>` prints a line to stderr
+//! - `fail <message>` returns an error with the message
+//! - `incr` adds one to a counter and returns it
+//!
+//! It exits successfully when its stdin is closed.
+
+use test_binary_rpc::Server;
+
+fn main() {
+    let mut count = 0_u64;
+
+    Server::new()
+        .handle("add", |(a, b): (i64, i64)| Ok(a + b))
+        .handle("eprint", |text: String| {
+            eprintln!("{}", text);
+            Ok(())
+        })
+        .handle("fail", |message: String| Err::<(), _>(message))
+        .handle("incr", move |()| {
+            count += 1;
+            Ok(count)
+        })
+        .serve()
+        .unwrap();
+}
@@ -0,0 +1,6 @@
+//! Test binary for test-binary crate. This is built from just this file,
+//! without a package of its own.
+
+fn main() {
+    println!("single file");
+}
@@ -0,0 +1,5 @@
+//! Test binary for test-binary crate. This binary builds, but with a warning.
+
+fn main() {
+    let unused = 1;
+}
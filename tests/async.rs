@@ -0,0 +1,28 @@
+//! Tests for the async API, which is behind the `async` feature.
+
+use futures_lite::future::{block_on, zip};
+use std::path::Path;
+use test_binary::{build_test_binary_async, TestBinarySpec};
+
+// Test building two binaries concurrently and running one, without a runtime
+// other than a minimal executor.
+#[test]
+fn test_build_and_run_async() {
+    let (built, actions) = block_on(zip(
+        build_test_binary_async("does-build", "testbins"),
+        TestBinarySpec {
+            name: "actions".to_owned(),
+            dir: Some("testbins".into()),
+            ..TestBinarySpec::default()
+        }
+        .build_async(),
+    ));
+    assert!(Path::new(&built.unwrap()).ends_with("does-build"));
+
+    let output = block_on(actions.unwrap().run_async(["print", "fla"])).unwrap();
+    assert!(output.status().success());
+    assert_eq!(output.stdout(), b"fla\n");
+
+    let failed = block_on(build_test_binary_async("doesnt-build", "testbins"));
+    assert!(failed.is_err());
+}
@@ -0,0 +1,22 @@
+//! This creates a separate test binary so we can remove `CARGO` from the
+//! environment without affecting other tests.
+
+use std::{env::remove_var, path::Path};
+use test_binary::{TestBinary, TestBinaryError};
+
+// Test that the builder only finds Cargo itself if it's allowed to.
+#[test]
+fn test_cargo_from_path_fallback() {
+    remove_var("CARGO");
+    let manifest = Path::new("testbins/does-build/Cargo.toml");
+    let result = TestBinary::relative_to_parent("does-build", manifest)
+        .unwrap()
+        .build();
+    assert!(matches!(result, Err(TestBinaryError::NonCargoRun(_))));
+
+    TestBinary::relative_to_parent("does-build", manifest)
+        .unwrap()
+        .cargo_from_path_fallback()
+        .build()
+        .unwrap();
+}
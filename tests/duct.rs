@@ -0,0 +1,37 @@
+//! Tests for turning binaries into duct expressions, which is behind the
+//! `duct` feature.
+
+use test_binary::{build_test_binary, Artifact};
+
+// Test piping a binary's output through another command and into a file.
+#[test]
+fn test_expression() {
+    let mut actions = Artifact::from(build_test_binary("actions", "testbins").unwrap());
+    actions.with_default_args(["print", "fla"]);
+
+    let output = actions
+        .expression(["print", "mingo", "eprint", "pink"])
+        .pipe(duct::cmd!("tr", "a-z", "A-Z"))
+        .stderr_null()
+        .read()
+        .unwrap();
+    assert_eq!(output, "FLA\nMINGO");
+
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("out.txt");
+    actions
+        .expression(["eprint", "pink"])
+        .stderr_to_stdout()
+        .stdout_path(&file)
+        .run()
+        .unwrap();
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), "fla\npink\n");
+
+    let failed = actions
+        .expression(["exit", "3"])
+        .stdout_null()
+        .unchecked()
+        .run()
+        .unwrap();
+    assert_eq!(failed.status.code(), Some(3));
+}
@@ -0,0 +1,477 @@
+//! This creates a separate test binary so we can point `CARGO` at a fake Cargo
+//! without affecting other tests.
+
+#![cfg(unix)]
+
+use std::{
+    cell::RefCell,
+    os::unix::fs::PermissionsExt,
+    path::Path,
+    time::{Duration, Instant},
+};
+use test_binary::{
+    BuildDefaults, BuildProgress, CancelToken, OnceBuild, TestBinary, TestBinaryError,
+};
+
+fn fake_cargo(dir: &Path, name: &str, script: &str) {
+    let path = dir.join(name);
+    std::fs::write(&path, format!("#!/bin/sh\n{}", script)).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    std::env::set_var("CARGO", path);
+}
+
+// These all share one test, since they each need a different fake Cargo.
+#[test]
+fn test_progress() {
+    lock_wait();
+    watchdog();
+    killed();
+    cancelled();
+    large_output();
+    build_target();
+    incremental();
+    env_overrides();
+    variant_overrides();
+    once_rebuild();
+    // This has to be last, since the defaults can't be changed once they're
+    // installed.
+    build_defaults();
+}
+
+// Test that Cargo waiting on a lock is reported, both as progress and in
+// timeout errors.
+fn lock_wait() {
+    let dir = tempfile::tempdir().unwrap();
+    let manifest = Path::new("testbins/does-build/Cargo.toml");
+
+    fake_cargo(
+        dir.path(),
+        "blocked",
+        "echo '    Blocking waiting for file lock on build directory' >&2\nexec sleep 30\n",
+    );
+    let progress = RefCell::new(vec![]);
+    let result = TestBinary::relative_to_parent("does-build", manifest)
+        .unwrap()
+        .on_progress(|p| progress.borrow_mut().push(p.clone()))
+        .with_timeout(Duration::from_millis(500))
+        .build();
+
+    assert_eq!(
+        progress.take(),
+        [BuildProgress::WaitingForLock("build directory".to_owned())]
+    );
+    match result {
+        Err(err @ TestBinaryError::Timeout { .. }) => assert_eq!(
+            err.to_string(),
+            "Cargo did not finish within 500ms (blocked waiting for file lock on build directory)"
+        ),
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    fake_cargo(
+        dir.path(),
+        "unblocked",
+        "echo '    Blocking waiting for file lock on package cache' >&2\n\
+         echo '   Compiling does-build v0.1.0' >&2\n\
+         exit 101\n",
+    );
+    let result = TestBinary::relative_to_parent("does-build", manifest)
+        .unwrap()
+        .on_progress(|p| progress.borrow_mut().push(p.clone()))
+        .build();
+
+    assert_eq!(
+        progress.take(),
+        [
+            BuildProgress::WaitingForLock("package cache".to_owned()),
+            BuildProgress::LockAcquired
+        ]
+    );
+    assert!(matches!(result, Err(TestBinaryError::CargoFailure(_))));
+}
+
+// Test that the watchdog reports Cargo going quiet, without stopping the build.
+fn watchdog() {
+    let dir = tempfile::tempdir().unwrap();
+    let manifest = Path::new("testbins/does-build/Cargo.toml");
+
+    fake_cargo(
+        dir.path(),
+        "quiet",
+        "echo '{\"reason\":\"compiler-artifact\",\"target\":{\"name\":\"dep\"}}'\n\
+         sleep 1\n\
+         exit 101\n",
+    );
+    let progress = RefCell::new(vec![]);
+    let result = TestBinary::relative_to_parent("does-build", manifest)
+        .unwrap()
+        .on_progress(|p| progress.borrow_mut().push(p.clone()))
+        .with_watchdog(Duration::from_millis(300))
+        .build();
+
+    assert!(matches!(result, Err(TestBinaryError::CargoFailure(_))));
+
+    let progress = progress.take();
+    assert!(!progress.is_empty());
+    for p in progress {
+        match p {
+            BuildProgress::Stalled(stall) => {
+                assert!(stall.idle() >= Duration::from_millis(300));
+                assert!(stall.elapsed() >= stall.idle());
+                assert!(stall.command().contains("does-build"));
+                assert_eq!(stall.last_message(), Some("compiler-artifact (dep)"));
+            }
+            other => panic!("unexpected progress: {:?}", other),
+        }
+    }
+}
+
+// Test that Cargo being killed is told apart from it failing.
+fn killed() {
+    let dir = tempfile::tempdir().unwrap();
+    let manifest = Path::new("testbins/does-build/Cargo.toml");
+
+    fake_cargo(
+        dir.path(),
+        "killed",
+        "echo '   Compiling does-build v0.1.0' >&2\nkill -9 $$\n",
+    );
+    let result = TestBinary::relative_to_parent("does-build", manifest)
+        .unwrap()
+        .build();
+
+    match result {
+        Err(err @ TestBinaryError::CargoKilled { .. }) => {
+            assert_eq!(err.cargo_signal(), Some(9));
+            assert_eq!(err.cargo_status().and_then(|s| s.code()), None);
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+// Test that cancelling a build kills Cargo, and what it started, promptly.
+fn cancelled() {
+    let dir = tempfile::tempdir().unwrap();
+    let manifest = Path::new("testbins/does-build/Cargo.toml");
+    let compiled = dir.path().join("compiled");
+
+    // The background job stands in for rustc.
+    fake_cargo(
+        dir.path(),
+        "cancelled",
+        &format!(
+            "(sleep 1 && touch '{}') &\nexec sleep 30\n",
+            compiled.display()
+        ),
+    );
+    let token = CancelToken::new();
+    let canceller = {
+        let token = token.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            token.cancel();
+        })
+    };
+    let started = Instant::now();
+    let result = TestBinary::relative_to_parent("does-build", manifest)
+        .unwrap()
+        .with_cancel_token(&token)
+        .build();
+    canceller.join().unwrap();
+
+    assert!(matches!(result, Err(TestBinaryError::Cancelled)));
+    assert!(started.elapsed() < Duration::from_secs(5));
+    std::thread::sleep(Duration::from_millis(1500));
+    assert!(!compiled.exists());
+
+    // Once it's cancelled, nothing more is started.
+    let result = TestBinary::relative_to_parent("does-build", manifest)
+        .unwrap()
+        .with_cancel_token(&token)
+        .build();
+    assert!(matches!(result, Err(TestBinaryError::Cancelled)));
+}
+
+// Test that a build doesn't deadlock when Cargo writes more than a pipe buffer's
+// worth to either stream while we're still waiting on the other.
+fn large_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let manifest = Path::new("testbins/does-build/Cargo.toml");
+
+    fake_cargo(
+        dir.path(),
+        "noisy",
+        "yes 'warning: lots of output' | head -n 100000 >&2\n\
+         yes 'not a message' | head -n 100000\n\
+         yes 'warning: even more output' | head -n 100000 >&2\n\
+         echo 'error: done' >&2\n\
+         exit 101\n",
+    );
+    // The timeout turns a deadlock into a test failure instead of a hang.
+    let result = TestBinary::relative_to_parent("does-build", manifest)
+        .unwrap()
+        .with_timeout(Duration::from_secs(60))
+        .build();
+
+    match result {
+        Err(TestBinaryError::CargoFailure(stderr)) => {
+            assert_eq!(stderr.lines().count(), 200_001);
+            assert!(stderr.ends_with("error: done\n"));
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+// Test that the parent's build target is passed on, unless it's ignored.
+fn build_target() {
+    let dir = tempfile::tempdir().unwrap();
+    let manifest = Path::new("testbins/does-build/Cargo.toml");
+
+    fake_cargo(
+        dir.path(),
+        "args",
+        "echo \"$@\" >&2\n\
+         echo \"env: $CARGO_BUILD_TARGET\" >&2\n\
+         exit 101\n",
+    );
+    std::env::set_var("CARGO_BUILD_TARGET", "riscv64gc-unknown-linux-gnu");
+
+    let result = TestBinary::relative_to_parent("does-build", manifest)
+        .unwrap()
+        .build();
+    match result {
+        Err(TestBinaryError::CargoFailure(stderr)) => {
+            assert!(stderr.contains(" --target riscv64gc-unknown-linux-gnu"));
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    let result = TestBinary::relative_to_parent("does-build", manifest)
+        .unwrap()
+        .ignore_parent_target()
+        .build();
+    match result {
+        Err(TestBinaryError::CargoFailure(stderr)) => {
+            assert!(!stderr.contains("--target"));
+            assert!(stderr.contains("env: \n"));
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    std::env::remove_var("CARGO_BUILD_TARGET");
+}
+
+// Test that incremental compilation is only set if asked for.
+fn incremental() {
+    let dir = tempfile::tempdir().unwrap();
+    let manifest = Path::new("testbins/does-build/Cargo.toml");
+
+    fake_cargo(
+        dir.path(),
+        "incremental",
+        "echo \"incremental: ${CARGO_INCREMENTAL-unset}\" >&2\nexit 101\n",
+    );
+
+    for (incremental, expected) in [(None, "unset"), (Some(true), "1"), (Some(false), "0")] {
+        let mut binary = TestBinary::relative_to_parent("does-build", manifest).unwrap();
+        if let Some(incremental) = incremental {
+            binary.with_incremental(incremental);
+        }
+        match binary.build() {
+            Err(TestBinaryError::CargoFailure(stderr)) => {
+                assert_eq!(stderr, format!("incremental: {}\n", expected));
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
+
+// Test that the environment variables override what the code asks for.
+fn env_overrides() {
+    use test_binary::{OFFLINE_ENV, PROFILE_ENV, TARGET_ENV};
+
+    let dir = tempfile::tempdir().unwrap();
+    let manifest = Path::new("testbins/does-build/Cargo.toml");
+
+    fake_cargo(dir.path(), "args", "echo \"$@\" >&2\nexit 101\n");
+
+    let args = |binary: &mut TestBinary| match binary.build() {
+        Err(TestBinaryError::CargoFailure(stderr)) => stderr,
+        other => panic!("unexpected result: {:?}", other),
+    };
+
+    std::env::set_var(PROFILE_ENV, "release");
+    std::env::set_var(TARGET_ENV, "riscv64gc-unknown-linux-gnu");
+    std::env::set_var(OFFLINE_ENV, "1");
+    let stderr = args(
+        TestBinary::relative_to_parent("does-build", manifest)
+            .unwrap()
+            .with_profile("dev")
+            .with_target("x86_64-unknown-linux-gnu"),
+    );
+    assert!(stderr.contains(" --profile release --target riscv64gc-unknown-linux-gnu"));
+    assert!(stderr.contains(" --offline"));
+
+    std::env::set_var(PROFILE_ENV, "");
+    std::env::set_var(OFFLINE_ENV, "false");
+    let stderr = args(
+        TestBinary::relative_to_parent("does-build", manifest)
+            .unwrap()
+            .with_profile("dev"),
+    );
+    assert!(stderr.contains(" --profile dev --target riscv64gc-unknown-linux-gnu"));
+    assert!(!stderr.contains("--offline"));
+
+    for key in [PROFILE_ENV, TARGET_ENV, OFFLINE_ENV] {
+        std::env::remove_var(key);
+    }
+}
+
+// Test that building several variants isn't undone by the environment
+// variables.
+fn variant_overrides() {
+    use test_binary::{PROFILE_ENV, TARGET_ENV};
+
+    let dir = tempfile::tempdir().unwrap();
+    let manifest = Path::new("testbins/does-build/Cargo.toml");
+    let log = dir.path().join("args.log");
+
+    fake_cargo(
+        dir.path(),
+        "logged",
+        &format!(
+            "echo \"$@\" >> '{}'\nexec '{}' \"$@\"\n",
+            log.display(),
+            env!("CARGO")
+        ),
+    );
+
+    std::env::set_var(PROFILE_ENV, "release");
+    let (debug, release) = TestBinary::relative_to_parent("does-build", manifest)
+        .unwrap()
+        .build_debug_and_release()
+        .unwrap();
+    assert!(debug.output_dir().ends_with("debug"));
+    assert!(release.output_dir().ends_with("release"));
+    let logged = std::fs::read_to_string(&log).unwrap();
+    assert!(logged.contains(" --profile dev"));
+    assert!(logged.contains(" --profile release"));
+    std::env::remove_var(PROFILE_ENV);
+
+    fake_cargo(dir.path(), "args", "echo \"$@\" >&2\nexit 101\n");
+    std::env::set_var(TARGET_ENV, "riscv64gc-unknown-linux-gnu");
+    let mut binary = TestBinary::relative_to_parent("does-build", manifest).unwrap();
+    match binary.build_universal() {
+        Err(TestBinaryError::CargoFailure(stderr)) => {
+            assert!(stderr.contains(" --target aarch64-apple-darwin"))
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+    let built = binary.build_matrix(["x86_64-unknown-linux-musl"]);
+    match &built["x86_64-unknown-linux-musl"] {
+        Err(TestBinaryError::CargoFailure(stderr)) => {
+            assert!(stderr.contains(" --target x86_64-unknown-linux-musl"))
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+    match binary.build_component() {
+        Err(TestBinaryError::CargoFailure(stderr)) => {
+            assert!(stderr.contains(" --target wasm32-wasip2"))
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+    std::env::set_var(TARGET_ENV, "wasm64-wasip2");
+    match binary.build_component() {
+        Err(TestBinaryError::CargoFailure(stderr)) => {
+            assert!(stderr.contains(" --target wasm64-wasip2"))
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+    std::env::remove_var(TARGET_ENV);
+}
+
+// Test that a binary that depends on the parent is really rebuilt by Cargo
+// when the parent changes, rather than given the build from before.
+fn once_rebuild() {
+    let dir = tempfile::tempdir().unwrap();
+    let parent = dir.path().join("parent");
+    let child = parent.join("testbins").join("child");
+    std::fs::create_dir_all(parent.join("src")).unwrap();
+    std::fs::create_dir_all(child.join("src")).unwrap();
+    std::fs::write(
+        parent.join("Cargo.toml"),
+        "[package]\nname = \"parent\"\nversion = \"0.1.0\"\n\n[workspace]\n",
+    )
+    .unwrap();
+    std::fs::write(parent.join("src").join("lib.rs"), "").unwrap();
+    std::fs::write(
+        child.join("Cargo.toml"),
+        "[package]\nname = \"child\"\nversion = \"0.1.0\"\n\n\
+         [dependencies]\nparent = { path = \"../..\" }\n\n[workspace]\n",
+    )
+    .unwrap();
+    std::fs::write(child.join("src").join("main.rs"), "fn main() {}\n").unwrap();
+
+    let log = dir.path().join("args.log");
+    fake_cargo(
+        dir.path(),
+        "logged",
+        &format!(
+            "echo \"$@\" >> '{}'\nexec '{}' \"$@\"\n",
+            log.display(),
+            env!("CARGO")
+        ),
+    );
+    let builds = || {
+        std::fs::read_to_string(&log)
+            .unwrap()
+            .lines()
+            .filter(|line| line.starts_with("build"))
+            .count()
+    };
+
+    let manifest_dir = std::env::var_os("CARGO_MANIFEST_DIR").unwrap();
+    std::env::set_var("CARGO_MANIFEST_DIR", &parent);
+    static BUILD: OnceBuild = OnceBuild::new();
+    BUILD.path("child", "testbins");
+    BUILD.path("child", "testbins");
+    assert_eq!(builds(), 1);
+
+    std::fs::write(parent.join("src").join("lib.rs"), "pub fn f() {}\n").unwrap();
+    BUILD.path("child", "testbins");
+    assert_eq!(builds(), 2);
+    std::env::set_var("CARGO_MANIFEST_DIR", manifest_dir);
+}
+
+// Test that process-wide defaults are used, unless they're overridden.
+fn build_defaults() {
+    let dir = tempfile::tempdir().unwrap();
+    let manifest = Path::new("testbins/does-build/Cargo.toml");
+
+    fake_cargo(dir.path(), "args", "echo \"$@\" >&2\nexit 101\n");
+
+    let mut defaults = BuildDefaults::new();
+    defaults
+        .profile("release")
+        .target_dir("/tmp/fla")
+        .offline()
+        .jobs(2);
+    defaults.install().unwrap();
+    assert_eq!(BuildDefaults::new().install(), Err(&defaults));
+
+    let args = |binary: &mut TestBinary| match binary.build() {
+        Err(TestBinaryError::CargoFailure(stderr)) => stderr,
+        other => panic!("unexpected result: {:?}", other),
+    };
+
+    let stderr = args(&mut TestBinary::relative_to_parent("does-build", manifest).unwrap());
+    assert!(stderr.contains(" --profile release --target-dir /tmp/fla --offline --jobs 2"));
+
+    let stderr = args(
+        TestBinary::relative_to_parent("does-build", manifest)
+            .unwrap()
+            .with_profile("dev")
+            .with_jobs(1),
+    );
+    assert!(stderr.contains(" --profile dev --target-dir /tmp/fla --offline --jobs 1"));
+}
@@ -0,0 +1,26 @@
+//! This creates a separate test binary so we can set the grace period's
+//! environment variable without affecting other tests.
+
+use std::{
+    env::{remove_var, set_var},
+    time::Duration,
+};
+use test_binary::{grace_period, set_grace_period, Graceful, GRACE_PERIOD_ENV};
+
+// Test that the environment variable overrides the default grace period,
+// unless it's malformed.
+#[test]
+fn test_grace_period_env() {
+    set_grace_period(Duration::from_secs(1));
+    assert_eq!(grace_period(), Duration::from_secs(1));
+
+    set_var(GRACE_PERIOD_ENV, "0.25");
+    assert_eq!(grace_period(), Duration::from_millis(250));
+    assert_eq!(Graceful::default().grace_period, Duration::from_millis(250));
+
+    set_var(GRACE_PERIOD_ENV, "soon");
+    assert_eq!(grace_period(), Duration::from_secs(1));
+
+    remove_var(GRACE_PERIOD_ENV);
+    assert_eq!(grace_period(), Duration::from_secs(1));
+}
@@ -0,0 +1,37 @@
+//! Tests for setting up binaries in a custom test harness, which is behind the
+//! `libtest-mimic` feature. This test has its own `main`.
+
+use libtest_mimic::{Arguments, Trial};
+use test_binary::setup_trials;
+
+fn main() {
+    let args = Arguments::from_args();
+
+    let mut trials = setup_trials(&["actions", "does-build"], "testbins", |binaries| {
+        vec![
+            binaries.test("test_prints", |binaries| {
+                let output = binaries
+                    .artifact("actions")
+                    .runner()
+                    .args(["print", "fla"])
+                    .run()?;
+                assert_eq!(output.stdout(), b"fla\n");
+                Ok(())
+            }),
+            binaries.test("test_path", |binaries| {
+                assert!(binaries.path("does-build").ends_with("does-build"));
+                Ok(())
+            }),
+        ]
+    });
+    trials.push(Trial::test("test_failed_setup", || {
+        let trials = setup_trials(&["doesnt-build"], "testbins", |_| {
+            panic!("tests were created after a failed build")
+        });
+        let names: Vec<_> = trials.iter().map(Trial::name).collect();
+        assert_eq!(names, ["setup::doesnt-build"]);
+        Ok(())
+    }));
+
+    libtest_mimic::run(&args, trials).exit();
+}
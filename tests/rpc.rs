@@ -0,0 +1,53 @@
+//! Tests for driving test binaries over stdio RPC.
+
+use std::{path::PathBuf, time::Duration};
+use test_binary::{Artifact, RunError, TestBinary};
+
+fn rpc_server() -> Artifact {
+    TestBinary::relative_to_parent(
+        "rpc-server",
+        &PathBuf::from_iter(["testbins", "rpc-server", "Cargo.toml"]),
+    )
+    .unwrap()
+    .build_artifact()
+    .unwrap()
+}
+
+// Test calling methods, and getting errors back.
+#[test]
+fn test_rpc_calls() {
+    let server = rpc_server();
+    let mut client = server.runner().rpc().unwrap();
+
+    assert_eq!(client.call::<_, i64>("add", (2, 3)).unwrap(), 5);
+    assert_eq!(client.call::<_, u64>("incr", ()).unwrap(), 1);
+    assert_eq!(client.call::<_, u64>("incr", ()).unwrap(), 2);
+    client.call::<_, ()>("eprint", "fla").unwrap();
+
+    let result = client.call::<_, ()>("fail", "mingo");
+    assert!(
+        matches!(&result, Err(RunError::RpcFailed { method, reason }) if method == "fail" && reason == "mingo"),
+        "{:?}",
+        result
+    );
+    let result = client.call::<_, ()>("pink", ());
+    assert!(matches!(result, Err(RunError::RpcFailed { .. })));
+    let result = client.call::<_, String>("add", (2, 3));
+    assert!(matches!(result, Err(RunError::RpcFailed { .. })));
+
+    let output = client.finish().unwrap();
+    assert!(output.status().success());
+    assert_eq!(output.stderr(), b"fla\n");
+}
+
+// Test that a binary that stops responding fails the call.
+#[test]
+fn test_rpc_no_response() {
+    let artifact = Artifact::from(test_binary::build_test_binary("actions", "testbins").unwrap());
+    let mut client = artifact.runner().args(["sleep", "10000"]).rpc().unwrap();
+    client.timeout(Duration::from_millis(100));
+
+    let result = client.call::<_, i64>("add", (2, 3));
+    assert!(matches!(result, Err(RunError::RpcFailed { .. })));
+    client.kill().unwrap();
+}
@@ -0,0 +1,18 @@
+//! Tests for sharing memory with test binaries, which is behind the
+//! `shared-memory` feature.
+
+use test_binary::{build_test_binary, Artifact, SharedMemory};
+
+// Test that what the binary writes to the region is seen by the test.
+#[test]
+fn test_shared_memory() {
+    let actions = Artifact::from(build_test_binary("actions", "testbins").unwrap());
+    let region = SharedMemory::new(64).unwrap();
+    region.write_at(0, b"ping ping");
+
+    let mut param = region.path().as_os_str().to_owned();
+    param.push("=pong");
+    actions.runner().arg("write").arg(param).run().unwrap();
+
+    assert_eq!(region.read_vec(0, 9), b"pong ping");
+}
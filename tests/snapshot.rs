@@ -0,0 +1,30 @@
+//! Tests for snapshot testing output, which is behind the `insta` feature.
+
+use test_binary::{assert_output_snapshot, build_test_binary, Artifact, Normalizer};
+
+// Test that output which changes from run to run gives the same snapshot.
+#[test]
+fn test_output_snapshot() {
+    let actions = Artifact::from(build_test_binary("actions", "testbins").unwrap());
+    let temp_dir = tempfile::tempdir().unwrap();
+    let temp_file = temp_dir.path().join("out.txt");
+
+    let output = actions
+        .runner()
+        .arg("print")
+        .arg(format!(
+            "\x1b[1mlistening\x1b[0m on 127.0.0.1:{}",
+            std::process::id()
+        ))
+        .arg("eprint")
+        .arg(format!("wrote {}\r", temp_file.display()))
+        .run()
+        .unwrap();
+
+    assert_output_snapshot!(output);
+    assert_output_snapshot!(
+        "output_snapshot_redacted",
+        output,
+        Normalizer::new().replace("listening", "[STATE]")
+    );
+}
@@ -1,7 +1,19 @@
 //! Integration tests for mock binary builds.
 
-use std::path::{Path, PathBuf};
-use test_binary::{build_test_binary, build_test_binary_once, TestBinary, TestBinaryError};
+use indoc::indoc;
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use test_binary::{
+    build_test_binary, build_test_binary_in, build_test_binary_in_dirs, build_test_binary_once,
+    build_test_binary_with, prewarm, resolve_test_binary, run_test_binary, Artifact,
+    ArtifactManifest, BenchOptions, BinDir, BuildProgress, BuildTemplate, ComponentRuntime,
+    DiagnosticLevel, Graceful, Input, Lint, ManifestError, MockScript, Port, Readiness,
+    RetryPolicy, RunError, ShutdownPath, Signal, TestBinary, TestBinaryError, TestBinaryPool,
+    TestBinarySpec, TestHarness, TestResult, WindowsAbi,
+};
 
 // Singleton function for "test_multiple" binary.
 build_test_binary_once!(multiple, "testbins");
@@ -26,11 +38,1280 @@ fn test_release() {
     )
     .unwrap()
     .with_profile("release")
+    .build_artifact()
+    .unwrap();
+
+    assert_path_end(result.path(), "does-build");
+    assert_path_end(result.output_dir(), "release");
+    assert_eq!(result.path().parent(), Some(result.output_dir()));
+}
+
+// Test that builds for different targets are kept apart, and know their
+// target.
+#[test]
+fn test_artifact_target() {
+    let manifest = PathBuf::from_iter(["testbins", "does-build", "Cargo.toml"]);
+    let host = TestBinary::relative_to_parent("does-build", &manifest)
+        .unwrap()
+        .build_artifact()
+        .unwrap();
+    let triple = host.target().expect("no host target").to_owned();
+
+    let cross = TestBinary::relative_to_parent("does-build", &manifest)
+        .unwrap()
+        .with_target(&triple)
+        .build_artifact()
+        .unwrap();
+    assert_eq!(cross.target(), Some(triple.as_str()));
+    assert_ne!(cross.path(), host.path());
+    assert!(cross
+        .path()
+        .components()
+        .any(|c| c.as_os_str() == triple.as_str()));
+}
+
+// Test that choosing a Windows ABI only changes builds for Windows.
+#[test]
+fn test_windows_abi() {
+    let manifest = PathBuf::from_iter(["testbins", "does-build", "Cargo.toml"]);
+    let host = TestBinary::relative_to_parent("does-build", &manifest)
+        .unwrap()
+        .build_artifact()
+        .unwrap();
+    let triple = host.target().expect("no host target");
+    let abi = if triple.ends_with("-windows-gnu") {
+        WindowsAbi::Msvc
+    } else {
+        WindowsAbi::Gnu
+    };
+
+    let result = TestBinary::relative_to_parent("does-build", &manifest)
+        .unwrap()
+        .with_windows_abi(abi)
+        .build_artifact();
+    if !triple.contains("-windows-") {
+        let artifact = result.unwrap();
+        assert_eq!(artifact.target(), Some(triple));
+        assert_eq!(artifact.path(), host.path());
+        return;
+    }
+
+    // The other ABI's target might not be installed, but Cargo should at
+    // least have been asked for it.
+    let expected = format!(
+        "{}-windows-{:?}",
+        triple.rsplit_once("-windows-").unwrap().0,
+        abi
+    )
+    .to_lowercase();
+    match result {
+        Ok(artifact) => {
+            assert_eq!(artifact.target(), Some(expected.as_str()));
+            assert!(artifact
+                .path()
+                .components()
+                .any(|c| c.as_os_str() == expected.as_str()));
+        }
+        Err(TestBinaryError::BuildError(stderr) | TestBinaryError::CargoFailure(stderr)) => {
+            assert!(stderr.contains(&expected), "{}", stderr)
+        }
+        Err(other) => panic!("unexpected error: {}", other),
+    }
+}
+
+// Test combining builds for both macOS architectures. Elsewhere, or if the
+// targets aren't installed, the builds fail.
+#[test]
+fn test_build_universal() {
+    let manifest = PathBuf::from_iter(["testbins", "does-build", "Cargo.toml"]);
+    let result = TestBinary::relative_to_parent("does-build", &manifest)
+        .unwrap()
+        .build_universal();
+
+    match result {
+        Ok(artifact) => {
+            assert_eq!(artifact.target(), Some("universal-apple-darwin"));
+            assert!(artifact
+                .path()
+                .components()
+                .any(|c| c.as_os_str() == "universal-apple-darwin"));
+            let archs = std::process::Command::new("lipo")
+                .arg("-archs")
+                .arg(artifact.path())
+                .output()
+                .unwrap();
+            let archs = String::from_utf8_lossy(&archs.stdout);
+            assert!(
+                archs.contains("arm64") && archs.contains("x86_64"),
+                "{}",
+                archs
+            );
+        }
+        Err(TestBinaryError::BuildError(_) | TestBinaryError::CargoFailure(_)) => {}
+        Err(other) => panic!("unexpected error: {}", other),
+    }
+}
+
+// Test building a WASI component, which can only be run if the target and a
+// runtime are installed.
+#[test]
+fn test_build_component() {
+    let manifest = PathBuf::from_iter(["testbins", "does-build", "Cargo.toml"]);
+    let result = TestBinary::relative_to_parent("does-build", &manifest)
+        .unwrap()
+        .with_target("x86_64-unknown-linux-musl")
+        .build_component();
+
+    match result {
+        Ok(artifact) => {
+            assert_eq!(artifact.target(), Some("wasm32-wasip2"));
+            assert_eq!(artifact.path().extension(), Some("wasm".as_ref()));
+            assert!(artifact.runtime().is_some());
+        }
+        Err(TestBinaryError::BuildError(_) | TestBinaryError::CargoFailure(_)) => {}
+        Err(other) => panic!("unexpected error: {}", other),
+    }
+}
+
+// Test that a component is run with its runtime.
+#[cfg(unix)]
+#[test]
+fn test_component_runtime() {
+    let mut runtime = ComponentRuntime::new("echo");
+    runtime.arg("--fla");
+    let mut artifact = Artifact::new("fla.wasm");
+    artifact.with_runtime(runtime);
+
+    let output = artifact.runner().arg("bar").run().unwrap();
+    assert_eq!(output.stdout(), b"--fla fla.wasm bar\n");
+}
+
+// Test that a binary over its size budget fails, or just warns.
+#[test]
+fn test_size_budget() {
+    let manifest = PathBuf::from_iter(["testbins", "does-build", "Cargo.toml"]);
+    let artifact = TestBinary::relative_to_parent("does-build", &manifest)
+        .unwrap()
+        .with_size_budget(u64::MAX)
+        .build_artifact()
+        .unwrap();
+    let size = artifact.size().unwrap();
+    assert!(size > 0);
+
+    let result = TestBinary::relative_to_parent("does-build", &manifest)
+        .unwrap()
+        .with_size_budget(size - 1)
+        .build_artifact();
+    match result {
+        Err(TestBinaryError::OverSizeBudget {
+            name,
+            size: over,
+            budget,
+        }) => {
+            assert_eq!(name, "does-build");
+            assert_eq!(over, size);
+            assert_eq!(budget, size - 1);
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    let progress = std::cell::RefCell::new(vec![]);
+    TestBinary::relative_to_parent("does-build", &manifest)
+        .unwrap()
+        .with_size_warning(size - 1)
+        .on_progress(|p| progress.borrow_mut().push(p.clone()))
+        .build_artifact()
+        .unwrap();
+    assert_eq!(
+        progress.take(),
+        [BuildProgress::OverSizeBudget {
+            name: "does-build".to_owned(),
+            size,
+            budget: size - 1,
+        }]
+    );
+}
+
+// Test that flags passed to rustc change the binary, and that it's not shared
+// with a build without them.
+#[test]
+fn test_rustc_flags() {
+    let manifest = PathBuf::from_iter(["testbins", "cfg-variants", "Cargo.toml"]);
+    let normal = TestBinary::relative_to_parent("cfg-variants", &manifest)
+        .unwrap()
+        .build_artifact()
+        .unwrap()
+        .runner()
+        .run()
+        .unwrap();
+    assert_eq!(normal.stdout(), b"normal\n");
+
+    let slow = TestBinary::relative_to_parent("cfg-variants", &manifest)
+        .unwrap()
+        .with_rustc_flags(["--cfg", r#"test_mode="slow""#])
+        .build_artifact()
+        .unwrap()
+        .runner()
+        .run()
+        .unwrap();
+    assert_eq!(slow.stdout(), b"slow\n");
+}
+
+// Test that a build isn't shared with an earlier one in the process once a
+// package the binary depends on by path has changed.
+#[test]
+fn test_path_dependency_changes() {
+    let dir = tempfile::tempdir().unwrap();
+    let dependency = dir.path().join("dependency");
+    let binary = dir.path().join("binary");
+    std::fs::create_dir_all(dependency.join("src")).unwrap();
+    std::fs::create_dir_all(binary.join("src")).unwrap();
+    std::fs::write(
+        dependency.join("Cargo.toml"),
+        "[package]\nname = \"dependency\"\nversion = \"0.1.0\"\n",
+    )
+    .unwrap();
+    std::fs::write(
+        binary.join("Cargo.toml"),
+        "[package]\nname = \"binary\"\nversion = \"0.1.0\"\n\n\
+         [dependencies]\ndependency = { path = \"../dependency\" }\n\n\
+         [workspace]\n",
+    )
+    .unwrap();
+    std::fs::write(
+        binary.join("src").join("main.rs"),
+        "fn main() {\n    println!(\"{}\", dependency::GREETING);\n}\n",
+    )
+    .unwrap();
+
+    let manifest = binary.join("Cargo.toml");
+    let run = |greeting: &str| {
+        std::fs::write(
+            dependency.join("src").join("lib.rs"),
+            format!("pub const GREETING: &str = {:?};\n", greeting),
+        )
+        .unwrap();
+        TestBinary::relative_to_parent("binary", &manifest)
+            .unwrap()
+            .build_artifact()
+            .unwrap()
+            .runner()
+            .run()
+            .unwrap()
+    };
+    assert_eq!(run("fla").stdout(), b"fla\n");
+    assert_eq!(run("mingo").stdout(), b"mingo\n");
+}
+
+// Test building variants with different configuration, which are kept apart.
+#[test]
+fn test_cfg() {
+    let manifest = PathBuf::from_iter(["testbins", "cfg-variants", "Cargo.toml"]);
+    let plain = TestBinary::relative_to_parent("cfg-variants", &manifest)
+        .unwrap()
+        .build_artifact()
+        .unwrap();
+    let variant = TestBinary::relative_to_parent("cfg-variants", &manifest)
+        .unwrap()
+        .with_cfg("mock_mode", Some("replay"))
+        .with_cfg("offline", None)
+        .build_artifact()
+        .unwrap();
+
+    let target_dir = plain.output_dir().parent().unwrap();
+    assert_eq!(
+        variant.output_dir(),
+        target_dir
+            .join("variants")
+            .join("mock_mode=replay+offline")
+            .join("debug")
+    );
+    assert_eq!(
+        variant.runner().run().unwrap().stdout(),
+        b"normal\nreplaying\noffline\n"
+    );
+    assert_eq!(plain.runner().run().unwrap().stdout(), b"normal\n");
+}
+
+// Test building with whichever profile this test was built with.
+#[test]
+fn test_profile_matching_parent() {
+    let manifest = PathBuf::from_iter(["testbins", "does-build", "Cargo.toml"]);
+    let artifact = TestBinary::relative_to_parent("does-build", &manifest)
+        .unwrap()
+        .with_profile("release")
+        .with_profile_matching_parent()
+        .build_artifact()
+        .unwrap();
+
+    // This test's executable is in <profile dir>/deps.
+    let exe = std::env::current_exe().unwrap();
+    let profile_dir = exe.parent().unwrap().parent().unwrap().file_name().unwrap();
+    assert_eq!(artifact.output_dir().file_name().unwrap(), profile_dir);
+}
+
+// Test building for several targets, where some fail.
+#[test]
+fn test_build_matrix() {
+    let manifest = PathBuf::from_iter(["testbins", "does-build", "Cargo.toml"]);
+    let host = TestBinary::relative_to_parent("does-build", &manifest)
+        .unwrap()
+        .build_artifact()
+        .unwrap();
+    let host = host.target().expect("no host target");
+
+    let built = TestBinary::relative_to_parent("does-build", &manifest)
+        .unwrap()
+        .build_matrix([host, "fla-unknown-mingo"]);
+    assert_eq!(built.len(), 2);
+    assert_eq!(built[host].as_ref().unwrap().target(), Some(host));
+    assert!(built["fla-unknown-mingo"].is_err());
+}
+
+// Test building both profiles at once.
+#[test]
+fn test_debug_and_release() {
+    let (debug, release) = TestBinary::relative_to_parent(
+        "does-build",
+        &PathBuf::from_iter(["testbins", "does-build", "Cargo.toml"]),
+    )
+    .unwrap()
+    .with_profile("release")
+    .build_debug_and_release()
+    .unwrap();
+
+    assert_path_end(debug.output_dir(), "debug");
+    assert_path_end(release.output_dir(), "release");
+}
+
+// Test building with custom profiles, and with the test profile, which Cargo
+// builds into the same directory as dev.
+#[test]
+fn test_custom_profiles() {
+    let build = |profile: &'static str| {
+        build_test_binary_with("custom-profile", "testbins", |b| b.with_profile(profile)).unwrap()
+    };
+    let printed = |path: &std::ffi::OsString| {
+        let output = Artifact::from(path.clone()).runner().run().unwrap();
+        String::from_utf8(output.stdout().to_vec()).unwrap()
+    };
+
+    let ci = build("ci");
+    assert_path_end(Path::new(&ci).parent().unwrap(), "ci");
+    assert_eq!(printed(&ci), "no debug assertions\n");
+
+    let test = build("test");
+    let dev = build("dev");
+    assert_ne!(test, dev);
+    assert_path_end(Path::new(&test).parent().unwrap(), "profile-test");
+    assert_eq!(printed(&test), "no debug assertions\n");
+    assert_eq!(printed(&dev), "debug assertions\n");
+}
+
+// Test putting the binary in a chosen directory, either by copying it or (with
+// nightly Cargo, if it's installed) by asking Cargo to.
+#[test]
+fn test_artifact_dir() {
+    let dir = tempfile::tempdir().unwrap();
+    let manifest = PathBuf::from_iter(["testbins", "does-build", "Cargo.toml"]);
+    let artifact = TestBinary::relative_to_parent("does-build", &manifest)
+        .unwrap()
+        .with_artifact_dir(dir.path().join("stable"))
+        .build_artifact()
+        .unwrap();
+
+    assert_eq!(
+        artifact.path(),
+        dir.path().join("stable").join("does-build")
+    );
+    assert_path_end(artifact.output_dir(), "debug");
+    assert!(artifact.runner().run().unwrap().status().success());
+
+    let nightly = std::process::Command::new("rustup")
+        .args(["which", "cargo", "--toolchain", "nightly"])
+        .output();
+    let nightly = match nightly {
+        Ok(output) if output.status.success() => String::from_utf8(output.stdout).unwrap(),
+        _ => return,
+    };
+    let artifact = TestBinary::relative_to_parent("does-build", &manifest)
+        .unwrap()
+        .with_cargo(nightly.trim_end())
+        .with_target_dir(dir.path().join("target"))
+        .with_artifact_dir(dir.path().join("nightly"))
+        .build_artifact()
+        .unwrap();
+
+    assert_eq!(
+        artifact.path(),
+        dir.path().join("nightly").join("does-build")
+    );
+    assert!(artifact.runner().run().unwrap().status().success());
+}
+
+// Test recording the packages a binary was built with.
+#[test]
+fn test_capture_dependencies() {
+    let artifact = TestBinary::relative_to_parent(
+        "actions",
+        &PathBuf::from_iter(["testbins", "actions", "Cargo.toml"]),
+    )
+    .unwrap()
+    .capture_dependencies()
+    .build_artifact()
+    .unwrap();
+
+    let dependencies = artifact.dependencies().expect("no dependencies captured");
+    assert!(dependencies.iter().all(|dep| dep.name() != "actions"));
+    if cfg!(unix) {
+        let signal_hook = dependencies
+            .iter()
+            .find(|dep| dep.name() == "signal-hook")
+            .expect("signal-hook missing");
+        assert!(signal_hook.version().starts_with("0.3."));
+        assert!(signal_hook.license().is_some());
+        assert!(signal_hook.source().is_some());
+    } else {
+        assert!(dependencies.is_empty());
+    }
+
+    assert!(actions().dependencies().is_none());
+}
+
+#[test]
+fn test_record_builds() {
+    // A target directory of its own means these really are built, rather
+    // than shared with other tests.
+    let target_dir = tempfile::tempdir().unwrap();
+    let record = target_dir.path().join("builds.jsonl");
+
+    TestBinary::relative_to_parent(
+        "does-build",
+        &PathBuf::from_iter(["testbins", "does-build", "Cargo.toml"]),
+    )
+    .unwrap()
+    .with_target_dir(target_dir.path())
+    .record_builds(&record)
+    .build()
+    .unwrap();
+
+    TestBinary::relative_to_parent(
+        "doesnt-build",
+        &PathBuf::from_iter(["testbins", "doesnt-build", "Cargo.toml"]),
+    )
+    .unwrap()
+    .with_target_dir(target_dir.path())
+    .record_builds(&record)
+    .build()
+    .unwrap_err();
+
+    let records: Vec<serde_json::Value> = std::fs::read_to_string(&record)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(records.len(), 2);
+
+    assert_eq!(records[0]["binary"], "does-build");
+    assert_eq!(records[0]["outcome"], "success");
+    assert_eq!(records[0]["fresh"], false);
+    assert_eq!(records[0]["compiled"][0], "does-build");
+    assert!(records[0]["duration_secs"].as_f64().unwrap() > 0.0);
+    assert!(records[0]["timestamp"].as_f64().unwrap() > 0.0);
+
+    assert_eq!(records[1]["binary"], "doesnt-build");
+    assert_eq!(records[1]["outcome"], "error");
+    assert!(records[1]["error"].as_str().unwrap().contains("error"));
+}
+
+#[test]
+fn test_log_file() {
+    let target_dir = tempfile::tempdir().unwrap();
+    let log = target_dir.path().join("logs").join("cargo.log");
+
+    for name in ["does-build", "doesnt-build"] {
+        let _ = TestBinary::relative_to_parent(
+            name,
+            &PathBuf::from_iter(["testbins", name, "Cargo.toml"]),
+        )
+        .unwrap()
+        .with_target_dir(target_dir.path())
+        .with_log_file(&log)
+        .build();
+    }
+
+    let logged = std::fs::read_to_string(&log).unwrap();
+    let notes: Vec<_> = logged
+        .lines()
+        .filter(|line| line.starts_with("--- test-binary: "))
+        .collect();
+    assert_eq!(notes.len(), 4);
+    assert!(notes[0].contains(r#"building "does-build""#));
+    assert_eq!(
+        notes[1],
+        "--- test-binary: Cargo exited with exit status: 0"
+    );
+    assert!(notes[2].contains(r#"building "doesnt-build""#));
+    assert!(notes[3].contains("Cargo exited with exit status: 101"));
+
+    // Both streams are there in full.
+    assert!(logged.contains(r#"{"reason":"compiler-message""#));
+    assert!(logged.contains(r#"{"reason":"build-finished","success":false}"#));
+    assert!(logged.contains("error: could not compile `doesnt-build`"));
+
+    // This is logged even though an identical build has already been done.
+    TestBinary::relative_to_parent(
+        "does-build",
+        &PathBuf::from_iter(["testbins", "does-build", "Cargo.toml"]),
+    )
+    .unwrap()
+    .with_target_dir(target_dir.path())
+    .with_log_file_per_build(&log)
+    .build()
+    .unwrap();
+    let logged = std::fs::read_to_string(&log).unwrap();
+    assert!(!logged.contains("doesnt-build"));
+    assert!(logged.ends_with("--- test-binary: Cargo exited with exit status: 0\n"));
+}
+
+// Test that an audit of a binary's dependencies can fail the build.
+#[test]
+fn test_audit() {
+    let manifest = PathBuf::from_iter(["testbins", "actions", "Cargo.toml"]);
+    let mut audited = vec![];
+
+    let result = TestBinary::relative_to_parent("actions", &manifest)
+        .unwrap()
+        .with_audit(|input| {
+            audited.push(input.name().to_owned());
+            let lock = std::fs::read_to_string(input.lock_file()).unwrap();
+            if lock.contains("name = \"signal-hook\"") {
+                Err("signal-hook is yanked".to_owned())
+            } else {
+                Ok(())
+            }
+        })
+        .build();
+    match result {
+        Err(TestBinaryError::AuditFailed { name, findings }) => {
+            assert_eq!(name, "actions");
+            assert_eq!(findings, "signal-hook is yanked");
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert_eq!(audited, ["actions"]);
+
+    let result = TestBinary::relative_to_parent("actions", &manifest)
+        .unwrap()
+        .with_audit(|_| Ok(()))
+        .build();
+    assert_path_end(result.unwrap(), "actions");
+}
+
+// Test building several binaries up front.
+#[test]
+fn test_prewarm() {
+    let paths = prewarm(&["does-build", "single-file"], "testbins").unwrap();
+    assert_path_end(paths.get("does-build").unwrap(), "does-build");
+    assert_eq!(
+        paths.get("single-file").unwrap(),
+        Path::new(&build_test_binary("single-file", "testbins").unwrap())
+    );
+
+    assert!(prewarm(&["does-build", "doesnt-build"], "testbins").is_err());
+}
+
+// Test that a frozen build succeeds once the binary is up to date.
+#[test]
+fn test_frozen() {
+    build_test_binary("does-build", "testbins").unwrap();
+
+    let result = TestBinary::relative_to_parent(
+        "does-build",
+        &PathBuf::from_iter(["testbins", "does-build", "Cargo.toml"]),
+    )
+    .unwrap()
+    .frozen()
     .build();
 
     assert_path_end(result.unwrap(), "does-build");
 }
 
+// Test writing a manifest for a batch of binaries.
+#[test]
+fn test_artifact_manifest() {
+    let manifest = ArtifactManifest::build(["does-build", "multiple"], "testbins").unwrap();
+    let entries = manifest.entries();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].name(), "does-build");
+    assert_path_end(entries[0].path(), "does-build");
+    assert_eq!(entries[0].profile(), "dev");
+    assert!(entries[0].hash().starts_with("sha256:"));
+    assert!(!entries[0].target().is_empty());
+
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("manifest.json");
+    manifest.write_json(&file).unwrap();
+    let json = std::fs::read_to_string(file).unwrap();
+    assert!(json.contains(r#""name": "multiple""#));
+}
+
+// Test building a binary from a single source file.
+#[test]
+fn test_single_file() {
+    let path = build_test_binary("single-file", "testbins").unwrap();
+    assert_path_end(&path, "single-file");
+
+    let output = Artifact::from(path).runner().run().unwrap();
+    assert_eq!(output.stdout(), b"single file\n");
+}
+
+// Test building and running a binary in one go.
+#[test]
+fn test_run_test_binary() {
+    for _ in 0..2 {
+        let output = run_test_binary("single-file", "testbins", ["ignored"]).unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"single file\n");
+    }
+
+    let result = run_test_binary("doesnt-build", "testbins", [""; 0]);
+    assert!(matches!(result, Err(TestBinaryError::BuildError(_))));
+}
+
+// Test using `?` in a test that returns a result.
+#[test]
+fn test_result() -> TestResult {
+    let path = build_test_binary("does-build", "testbins")?;
+    let output = Artifact::from(path).runner().run()?;
+    assert!(output.status().success());
+
+    let build = || -> TestResult {
+        build_test_binary("doesnt-build", "testbins")?;
+        Ok(())
+    };
+    let failure = build().unwrap_err();
+    let summary = failure.to_string();
+    assert!(summary.starts_with("build error: "), "{}", summary);
+    assert!(!summary.contains('\n'), "{}", summary);
+    let report = format!("{:#}", failure);
+    assert!(report.lines().count() > 3, "{}", report);
+    assert_eq!(failure.location().file(), file!());
+    Ok(())
+}
+
+// Test building binaries in the background, and on demand.
+#[test]
+fn test_pool() {
+    let pool = TestBinaryPool::new("testbins", 2);
+    pool.request_all(["does-build", "multiple", "doesnt-build"]);
+
+    let artifact = pool.get("multiple").unwrap();
+    assert_path_end(artifact.path(), "multiple");
+    assert_eq!(
+        pool.get("multiple").unwrap().path(),
+        artifact.path(),
+        "should be kept"
+    );
+    assert_path_end(pool.get("does-build").unwrap().path(), "does-build");
+    assert_path_end(pool.get("single-file").unwrap().path(), "single-file");
+
+    for _ in 0..2 {
+        assert!(matches!(
+            pool.get("doesnt-build"),
+            Err(TestBinaryError::BuildError(_))
+        ));
+    }
+}
+
+// Test building binaries whose names don't match their package directory.
+#[test]
+fn test_build_in() {
+    for (bin, expected) in [
+        ("mock-server", &b"mock server\n"[..]),
+        ("mock-client", &b"mock client\n"[..]),
+    ] {
+        let path = build_test_binary_in("testbins", "mock_server", bin).unwrap();
+        assert_path_end(&path, bin);
+
+        let output = Artifact::from(path).runner().run().unwrap();
+        assert_eq!(output.stdout(), expected);
+    }
+
+    let result = build_test_binary_in("testbins", "mock_server", "mock_server");
+    assert!(matches!(result, Err(TestBinaryError::CargoFailure(_))));
+}
+
+// Test looking for binaries in several directories.
+#[test]
+fn test_build_in_dirs() {
+    let (path, directory) =
+        build_test_binary_in_dirs("does-build", ["tests/helpers", "testbins"]).unwrap();
+    assert_path_end(&path, "does-build");
+    assert_eq!(directory, Path::new("testbins"));
+
+    let result = build_test_binary_in_dirs("does-build", ["tests/helpers", "tests"]);
+    assert!(matches!(
+        result,
+        Err(TestBinaryError::ManifestError(
+            ManifestError::PackageNotFound(_)
+        ))
+    ));
+
+    assert_path_end(path_to_warns(), "warns");
+}
+
+build_test_binary_once!(warns, ["tests/helpers", "testbins"]);
+
+// Test picking one of several binaries in a package, or building them all.
+#[test]
+fn test_multiple_bins() {
+    let manifest = PathBuf::from_iter(["testbins", "mock_server", "Cargo.toml"]);
+    let result = TestBinary::relative_to_parent("mock-server", &manifest)
+        .unwrap()
+        .with_bin("mock-client")
+        .build();
+    assert_path_end(result.unwrap(), "mock-client");
+
+    let artifacts = TestBinary::relative_to_parent("mock-server", &manifest)
+        .unwrap()
+        .build_all_bins()
+        .unwrap();
+    assert_eq!(
+        artifacts.keys().collect::<Vec<_>>(),
+        ["mock-client", "mock-server"]
+    );
+    let output = artifacts["mock-client"].runner().run().unwrap();
+    assert_eq!(output.stdout(), b"mock client\n");
+}
+
+// Test finding what a binary's build script produced, and what it said when it
+// failed.
+#[test]
+fn test_build_script() {
+    let manifest = PathBuf::from_iter(["testbins", "build-script", "Cargo.toml"]);
+
+    // The second build is fresh, and should still find the build script output.
+    for _ in 0..2 {
+        let artifact = TestBinary::relative_to_parent("build-script", &manifest)
+            .unwrap()
+            .build_artifact()
+            .unwrap();
+
+        let generated = artifact.out_dir().unwrap().join("generated.txt");
+        assert_eq!(std::fs::read_to_string(generated).unwrap(), "generated\n");
+        assert_eq!(
+            artifact.rustc_env(),
+            [("GREETING".to_owned(), "hello".to_owned())]
+        );
+        assert_eq!(artifact.build_script_warnings(), ["generated a file"]);
+    }
+
+    let result = TestBinary::relative_to_parent("build-script", &manifest)
+        .unwrap()
+        .with_build_script_env("BUILD_SCRIPT_FAIL", "1")
+        .build();
+    match result {
+        Err(TestBinaryError::BuildError(msg)) => {
+            assert!(msg.contains("build script warning: generated a file"));
+            assert!(msg.contains("failed to run custom build command"));
+            assert!(msg.contains("told to fail"));
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    let artifact = TestBinary::relative_to_parent(
+        "does-build",
+        &PathBuf::from_iter(["testbins", "does-build", "Cargo.toml"]),
+    )
+    .unwrap()
+    .build_artifact()
+    .unwrap();
+    assert_eq!(artifact.out_dir(), None);
+    assert!(artifact.rustc_env().is_empty());
+}
+
+// Test passing configuration to a binary's build script.
+#[test]
+fn test_build_script_env() {
+    let manifest = PathBuf::from_iter(["testbins", "build-env", "Cargo.toml"]);
+
+    // Changing the value should rebuild the binary.
+    for fixture in ["fixtures/one.json", "fixtures/two.json"] {
+        let artifact = TestBinary::relative_to_parent("build-env", &manifest)
+            .unwrap()
+            .with_build_script_env("BUILD_ENV_FIXTURE", fixture)
+            .build_artifact()
+            .unwrap();
+
+        let output = artifact.runner().run().unwrap();
+        assert_eq!(output.stdout(), format!("{}\n", fixture).as_bytes());
+    }
+
+    // The same, from a spec. This is here rather than in test_from_spec() so
+    // that the builds don't race.
+    let spec: TestBinarySpec = serde_json::from_str(
+        r#"{
+            "name": "build-env",
+            "manifest": "testbins/build-env/Cargo.toml",
+            "env": { "BUILD_ENV_FIXTURE": "fixtures/spec.json" }
+        }"#,
+    )
+    .unwrap();
+    let output = TestBinary::from_spec(&spec)
+        .unwrap()
+        .build_artifact()
+        .unwrap()
+        .runner()
+        .run()
+        .unwrap();
+    assert_eq!(output.stdout(), b"fixtures/spec.json\n");
+}
+
+// Test giving an artifact default arguments, environment and working directory.
+#[test]
+fn test_run_defaults() {
+    use test_binary::stock;
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut artifact = stock::print_env().unwrap();
+    artifact
+        .with_default_args(["DEFAULT_A"])
+        .with_default_env("DEFAULT_A", "artifact")
+        .with_default_env("DEFAULT_B", "artifact")
+        .with_default_current_dir(dir.path());
+
+    let output = artifact
+        .runner()
+        .arg("DEFAULT_B")
+        .env("DEFAULT_B", "runner")
+        .run()
+        .unwrap();
+    assert_eq!(output.stdout(), b"DEFAULT_A=artifact\nDEFAULT_B=runner\n");
+
+    assert_eq!(artifact.command().get_current_dir(), Some(dir.path()));
+
+    let manifest = PathBuf::from_iter(["testbins", "mock_server", "Cargo.toml"]);
+    let artifact = TestBinary::relative_to_parent("mock-server", &manifest)
+        .unwrap()
+        .with_default_args(["--port", "0"])
+        .with_default_env("MOCK", "1")
+        .build_artifact()
+        .unwrap();
+    let command = artifact.command();
+    assert_eq!(command.get_args().collect::<Vec<_>>(), ["--port", "0"]);
+    assert_eq!(command.get_envs().count(), 1);
+}
+
+// Test building from a deserialized specification.
+#[test]
+fn test_from_spec() {
+    let spec: TestBinarySpec = serde_json::from_str(
+        r#"{
+            "name": "feature-test",
+            "dir": "testbins",
+            "features": ["working"],
+            "default-features": false
+        }"#,
+    )
+    .unwrap();
+    let result = TestBinary::from_spec(&spec).unwrap().build();
+    assert_path_end(result.unwrap(), "feature-test");
+}
+
+// Test building and talking to a scripted mock.
+#[test]
+fn test_mock_script() {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        process::Stdio,
+    };
+
+    let mock = MockScript::from_json(
+        r#"{
+            "steps": [
+                { "send": "ready" },
+                { "expect": "ping" },
+                { "send": "pong" }
+            ],
+            "exit_code": 7
+        }"#,
+    )
+    .unwrap()
+    .build("ping-mock")
+    .unwrap();
+
+    let run = |input: &str| {
+        let mut child = mock
+            .command()
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut line = String::new();
+        stdout.read_line(&mut line).unwrap();
+        assert_eq!(line, "ready\n");
+
+        writeln!(child.stdin.take().unwrap(), "{}", input).unwrap();
+        let mut rest = String::new();
+        std::io::Read::read_to_string(&mut stdout, &mut rest).unwrap();
+        (rest, child.wait().unwrap().code())
+    };
+
+    assert_eq!(run("ping"), ("pong\n".to_owned(), Some(7)));
+    assert_eq!(
+        run("pang"),
+        (String::new(), Some(MockScript::MISMATCH_EXIT_CODE))
+    );
+}
+
+// Test the stock binaries.
+#[test]
+fn test_stock() {
+    use test_binary::stock;
+
+    let output = stock::exit_with_code()
+        .unwrap()
+        .runner()
+        .arg("3")
+        .run()
+        .unwrap();
+    assert_eq!(output.status().code(), Some(3));
+
+    let output = stock::print_env()
+        .unwrap()
+        .runner()
+        .env("STOCK_TEST", "fla")
+        .arg("STOCK_TEST")
+        .arg("STOCK_UNSET")
+        .run()
+        .unwrap();
+    assert_eq!(output.stdout(), b"STOCK_TEST=fla\n");
+
+    let output = stock::sleep_then_exit()
+        .unwrap()
+        .runner()
+        .args(["10", "0"])
+        .run()
+        .unwrap();
+    assert!(output.status().success());
+
+    stock::busy_loop()
+        .unwrap()
+        .runner()
+        .arg("10")
+        .run()
+        .unwrap();
+    stock::echo_stdin().unwrap().runner().run().unwrap();
+
+    let mut guard = stock::ignore_sigterm().unwrap().runner().spawn().unwrap();
+    guard
+        .wait_ready(Readiness::stdout_line("ready"), Duration::from_secs(10))
+        .unwrap();
+    #[cfg(unix)]
+    {
+        let shutdown = guard
+            .shutdown(Graceful {
+                signal: Signal::Term,
+                grace_period: Duration::from_millis(100),
+            })
+            .unwrap();
+        assert_eq!(shutdown.path(), ShutdownPath::Killed);
+    }
+}
+
+// Test resolving binaries from each of the places they can be.
+#[test]
+fn test_resolve() {
+    std::env::set_var("CARGO_BIN_EXE_resolve-env", "/bin/resolve-env");
+    let artifact = resolve_test_binary("resolve-env", "testbins").unwrap();
+    assert_eq!(artifact.path(), Path::new("/bin/resolve-env"));
+
+    let artifact = resolve_test_binary("does-build-new", "testbins").unwrap();
+    assert_path_end(artifact.path(), "does-build-new");
+
+    let artifact = resolve_test_binary("multiple", "testbins").unwrap();
+    assert_path_end(artifact.path(), "multiple");
+
+    let result = resolve_test_binary("resolve-nowhere", "testbins");
+    assert!(matches!(
+        result,
+        Err(TestBinaryError::ManifestError(
+            ManifestError::PackageNotFound(_)
+        ))
+    ));
+}
+
+// Test filtering and capping compiler messages.
+#[test]
+fn test_diagnostics() {
+    let manifest = PathBuf::from_iter(["testbins", "warns", "Cargo.toml"]);
+    let artifact = TestBinary::relative_to_parent("warns", &manifest)
+        .unwrap()
+        .build_artifact()
+        .unwrap();
+    assert!(artifact.diagnostics().contains("unused variable"));
+
+    let artifact = TestBinary::relative_to_parent("warns", &manifest)
+        .unwrap()
+        .with_diagnostic_level(DiagnosticLevel::Errors)
+        .build_artifact()
+        .unwrap();
+    assert_eq!(artifact.diagnostics(), "");
+
+    let manifest = PathBuf::from_iter(["testbins", "doesnt-build", "Cargo.toml"]);
+    let result = TestBinary::relative_to_parent("doesnt-build", &manifest)
+        .unwrap()
+        .with_diagnostic_level(DiagnosticLevel::Errors)
+        .build();
+    match result {
+        Err(TestBinaryError::BuildError(msg)) => {
+            assert!(msg.contains("error"));
+            assert!(!msg.contains("For more information"));
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    let result = TestBinary::relative_to_parent("doesnt-build", &manifest)
+        .unwrap()
+        .with_diagnostic_limit(10)
+        .build();
+    match result {
+        Err(TestBinaryError::BuildError(msg)) => assert!(msg.contains("truncated")),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+// Test that warnings can be made into errors.
+#[test]
+fn test_deny_warnings() {
+    let manifest = PathBuf::from_iter(["testbins", "warns", "Cargo.toml"]);
+    let result = TestBinary::relative_to_parent("warns", &manifest)
+        .unwrap()
+        .deny_warnings()
+        .build();
+    match result {
+        Err(TestBinaryError::BuildError(msg)) => assert!(msg.contains("unused variable")),
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    // Changing the flags makes the binary rebuild, so this uses one that no
+    // other test builds (only lints, which doesn't replace the binary).
+    let manifest = PathBuf::from_iter(["testbins", "lints", "Cargo.toml"]);
+    let result = TestBinary::relative_to_parent("lints", &manifest)
+        .unwrap()
+        .deny_warnings()
+        .build();
+    assert_path_end(result.unwrap(), "lints");
+}
+
+// Test linting binaries with Clippy.
+#[test]
+fn test_clippy() {
+    let manifest = PathBuf::from_iter(["testbins", "lints", "Cargo.toml"]);
+    let lints = TestBinary::relative_to_parent("lints", &manifest)
+        .unwrap()
+        .clippy()
+        .unwrap();
+    assert_eq!(lints.len(), 1, "{:?}", lints);
+    assert_eq!(lints[0].code(), Some("clippy::len_zero"));
+    assert_eq!(lints[0].line(), Some(6));
+    assert!(!lints[0].is_error());
+
+    let manifest = PathBuf::from_iter(["testbins", "doesnt-build", "Cargo.toml"]);
+    let lints = TestBinary::relative_to_parent("doesnt-build", &manifest)
+        .unwrap()
+        .clippy()
+        .unwrap();
+    assert!(lints.iter().any(Lint::is_error));
+}
+
+// Test collecting the report of code that will stop compiling.
+#[test]
+fn test_future_incompat_report() {
+    let target_dir = tempfile::tempdir().unwrap();
+    let manifest = PathBuf::from_iter(["testbins", "future-incompat", "Cargo.toml"]);
+    let build = || {
+        TestBinary::relative_to_parent("future-incompat", &manifest)
+            .unwrap()
+            .with_target_dir(target_dir.path())
+            .with_future_incompat_report()
+            .build_artifact()
+            .unwrap()
+    };
+
+    let report = build().future_incompat_report().unwrap().to_owned();
+    assert!(report.contains("future-incompat v1.0.0"), "{}", report);
+    assert!(report.contains("fn fla<T = u8>() {}"), "{}", report);
+
+    // It's still reported when there's nothing to build.
+    assert_eq!(build().future_incompat_report(), Some(&report[..]));
+
+    let manifest = PathBuf::from_iter(["testbins", "does-build", "Cargo.toml"]);
+    let artifact = TestBinary::relative_to_parent("does-build", &manifest)
+        .unwrap()
+        .with_future_incompat_report()
+        .build_artifact()
+        .unwrap();
+    assert_eq!(artifact.future_incompat_report(), None);
+}
+
+// Test that a binary isn't built for a platform it doesn't support.
+#[test]
+fn test_platforms() {
+    let manifest = PathBuf::from_iter(["testbins", "does-build", "Cargo.toml"]);
+    let (here, elsewhere) = if cfg!(windows) {
+        ("windows", "unix")
+    } else {
+        ("unix", "windows")
+    };
+
+    let result = TestBinary::relative_to_parent("does-build", &manifest)
+        .unwrap()
+        .with_platforms([elsewhere])
+        .build();
+    match result {
+        Err(TestBinaryError::Unsupported { reason }) => {
+            assert!(reason.starts_with(&format!(
+                r#""does-build" only supports {}, not "#,
+                elsewhere
+            )))
+        }
+        other => panic!("expected Unsupported, got {:?}", other),
+    }
+
+    let result = TestBinary::relative_to_parent("does-build", &manifest)
+        .unwrap()
+        .with_platforms([elsewhere, here])
+        .build();
+    assert_path_end(result.unwrap(), "does-build");
+}
+
+// Test bringing a child's locked dependencies into line with ours, where
+// its requirements allow.
+#[test]
+fn test_sync_lockfile() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("src")).unwrap();
+    std::fs::write(dir.path().join("src").join("main.rs"), "fn main() {}\n").unwrap();
+    std::fs::write(
+        dir.path().join("Cargo.toml"),
+        indoc! {r#"
+            [package]
+            name = "sync-lockfile"
+            version = "1.0.0"
+            edition = "2021"
+
+            [workspace]
+
+            [dependencies]
+            itoa = "1"
+            memchr = "~2.7"
+        "#},
+    )
+    .unwrap();
+    let manifest = dir.path().join("Cargo.toml");
+    let cargo = std::env::var_os("CARGO").unwrap();
+    let status = std::process::Command::new(&cargo)
+        .args(["update", "--quiet", "--manifest-path"])
+        .arg(&manifest)
+        .args(["--package", "itoa", "--precise", "1.0.10"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let ours = std::fs::read_to_string("Cargo.lock").unwrap();
+    let locked = |lockfile: &str, name: &str| {
+        let table: toml::Table = lockfile.parse().unwrap();
+        table["package"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|package| package["name"].as_str() == Some(name))
+            .map(|package| package["version"].as_str().unwrap().to_owned())
+    };
+    let our_itoa = locked(&ours, "itoa").unwrap();
+
+    let synced = TestBinary::relative_to_parent("sync-lockfile", &manifest)
+        .unwrap()
+        .sync_lockfile_with_parent()
+        .unwrap();
+    assert_eq!(synced.len(), 1, "{:?}", synced);
+    assert_eq!(synced[0].name(), "itoa");
+    assert_eq!(synced[0].from(), "1.0.10");
+    assert_eq!(synced[0].to(), our_itoa);
+
+    let theirs = std::fs::read_to_string(dir.path().join("Cargo.lock")).unwrap();
+    assert_eq!(locked(&theirs, "itoa"), Some(our_itoa));
+    // Ours is newer than the child allows.
+    assert!(locked(&theirs, "memchr").unwrap().starts_with("2.7."));
+}
+
+// Test finding built binaries by name on PATH.
+#[test]
+fn test_bin_dir() {
+    let mut bins = BinDir::build(["does-build"], "testbins").unwrap();
+    let mock_client = build_test_binary_in("testbins", "mock_server", "mock-client").unwrap();
+    bins.add_as("git-fla", mock_client).unwrap();
+
+    let run = |name: &str| {
+        std::process::Command::new(name)
+            .env("PATH", bins.path_env())
+            .output()
+            .unwrap()
+    };
+    assert!(run("does-build").status.success());
+    assert_eq!(run("git-fla").stdout, b"mock client\n");
+    assert!(bins
+        .path()
+        .join(format!("git-fla{}", std::env::consts::EXE_SUFFIX))
+        .exists());
+}
+
+// Test checking that binaries compile, without building them.
+#[test]
+fn test_check() {
+    let target_dir = tempfile::tempdir().unwrap();
+    let manifest = PathBuf::from_iter(["testbins", "warns", "Cargo.toml"]);
+    let messages = TestBinary::relative_to_parent("warns", &manifest)
+        .unwrap()
+        .with_target_dir(target_dir.path())
+        .check()
+        .unwrap();
+    assert!(!messages.is_empty());
+    assert!(!messages.iter().any(Lint::is_error));
+    assert!(!target_dir.path().join("debug").join("warns").exists());
+
+    let manifest = PathBuf::from_iter(["testbins", "doesnt-build", "Cargo.toml"]);
+    let messages = TestBinary::relative_to_parent("doesnt-build", &manifest)
+        .unwrap()
+        .check()
+        .unwrap();
+    assert!(messages.iter().any(Lint::is_error));
+}
+
+// Test running a binary's own tests.
+#[test]
+fn test_run_child_tests() {
+    let manifest = PathBuf::from_iter(["testbins", "has-tests", "Cargo.toml"]);
+    let tests = TestBinary::relative_to_parent("has-tests", &manifest)
+        .unwrap()
+        .run_child_tests()
+        .unwrap();
+    assert!(tests.success(), "{}", tests.stderr());
+    assert_eq!((tests.passed(), tests.failed()), (2, 0));
+
+    let tests = TestBinary::relative_to_parent("has-tests", &manifest)
+        .unwrap()
+        .with_feature("fail")
+        .run_child_tests()
+        .unwrap();
+    assert!(!tests.success());
+    assert_eq!((tests.passed(), tests.failed()), (1, 1));
+    assert!(tests.stdout().contains("doubles"));
+}
+
 // Test that building a binary that doesn't build produces an error.
 #[test]
 fn test_doesnt_build() {
@@ -90,6 +1371,74 @@ fn test_features() {
     assert_path_end(result.unwrap(), "feature-test");
 }
 
+// Test configuring the builder for a binary in the simple layout.
+#[test]
+fn test_build_with() {
+    let result = build_test_binary_with("feature-test", "testbins", |b| {
+        b.no_default_features().with_feature("working")
+    });
+    assert_path_end(result.unwrap(), "feature-test");
+
+    // Without the feature, it doesn't compile.
+    let result = build_test_binary_with("feature-test", "testbins", |b| b.no_default_features());
+    assert!(matches!(result, Err(TestBinaryError::BuildError(_))));
+}
+
+// Test building several binaries from one template, on different threads.
+#[test]
+fn test_build_template() {
+    let mut template = BuildTemplate::new("testbins");
+    template.configure(|b| b.no_default_features().with_feature("working"));
+
+    let template = &template;
+    std::thread::scope(|scope| {
+        let feature_test = scope.spawn(|| template.build("feature-test"));
+        // Settings a binary doesn't have are an error.
+        let does_build = scope.spawn(|| template.build("does-build"));
+
+        assert_path_end(feature_test.join().unwrap().unwrap(), "feature-test");
+        assert!(matches!(
+            does_build.join().unwrap(),
+            Err(TestBinaryError::CargoFailure(_))
+        ));
+    });
+
+    // A clone of the builder keeps the template's settings, and can change
+    // them without affecting the original.
+    let in_release = |path: &std::ffi::OsStr| Path::new(path).iter().any(|part| part == "release");
+    let mut binary = template.binary("feature-test").unwrap();
+    let mut release = binary.clone();
+    release.with_profile("release");
+    assert!(in_release(&release.build().unwrap()));
+    assert!(!in_release(&binary.build().unwrap()));
+}
+
+// Test building one binary with several sets of features, and that each set
+// gets its own binary.
+#[test]
+fn test_feature_matrix() {
+    let built = TestBinary::relative_to_parent(
+        "capabilities",
+        &PathBuf::from_iter(["testbins", "capabilities", "Cargo.toml"]),
+    )
+    .unwrap()
+    .build_feature_powerset(&["tls", "compression"])
+    .unwrap();
+    assert_eq!(built.len(), 4);
+
+    for (features, artifact) in &built {
+        let output = artifact.runner().run().unwrap();
+        let printed: BTreeSet<String> = String::from_utf8_lossy(output.stdout())
+            .lines()
+            .map(str::to_owned)
+            .collect();
+        assert_eq!(&printed, features);
+    }
+
+    let none = &built[&BTreeSet::new()];
+    assert_path_end(none.path().parent().unwrap(), "none");
+}
+
 #[test]
 fn test_workspace_build() {
     let result = TestBinary::from_workspace("does-build-new")
@@ -98,3 +1447,826 @@ fn test_workspace_build() {
 
     assert_path_end(result.unwrap(), "does-build-new");
 }
+
+// Test that helper packages are found by searching glob patterns.
+#[test]
+fn test_from_globs() {
+    let patterns = ["tests/helpers/**/Cargo.toml", "testbins/*/Cargo.toml"];
+
+    let result = TestBinary::from_globs("mock-client", &patterns)
+        .unwrap()
+        .build();
+    assert_path_end(result.unwrap(), "mock-client");
+
+    let result = TestBinary::from_globs("does-build", &patterns)
+        .unwrap()
+        .build();
+    assert_path_end(result.unwrap(), "does-build");
+
+    let result = TestBinary::from_globs("does-build", &["tests/helpers/**/Cargo.toml"]);
+    assert!(matches!(
+        result,
+        Err(TestBinaryError::ManifestError(
+            ManifestError::PackageNotFound(_)
+        ))
+    ));
+
+    let result = TestBinary::from_globs("does-build", &["testbins/[/Cargo.toml"]);
+    assert!(matches!(
+        result,
+        Err(TestBinaryError::ManifestError(
+            ManifestError::InvalidPattern(..)
+        ))
+    ));
+}
+
+// Test that a crashing binary has its backtrace attached to the run output.
+#[test]
+fn test_crash_backtrace() {
+    let artifact = TestBinary::relative_to_parent(
+        "crashes",
+        &PathBuf::from_iter(["testbins", "crashes", "Cargo.toml"]),
+    )
+    .unwrap()
+    .build_artifact()
+    .unwrap();
+
+    let output = artifact
+        .runner()
+        .arg("panic")
+        .capture_crash_backtrace()
+        .run()
+        .unwrap();
+
+    assert!(!output.status().success());
+    let crash = output.crash().expect("binary did not crash");
+    assert!(crash.backtrace().unwrap().contains("crashes::main"));
+
+    let output = artifact.runner().capture_crash_backtrace().run().unwrap();
+    assert!(output.status().success());
+    assert!(output.crash().is_none());
+}
+
+// Test that a binary's panic is picked out of its stderr, and shown when it
+// fails.
+#[test]
+fn test_capture_panics() {
+    let output = actions()
+        .runner()
+        .args(["print", "started", "fla", "x"])
+        .capture_panics()
+        .run()
+        .unwrap();
+
+    let panic = output.panic().expect("no panic captured");
+    assert_eq!(panic.thread(), "main");
+    assert_eq!(panic.message(), "unknown action: fla");
+    assert!(panic.location().unwrap().ends_with(".rs:33:22"));
+    assert!(panic.backtrace().unwrap().contains("actions::main"));
+
+    let summary = output.failure_summary().unwrap();
+    assert!(summary.contains("--- panic ---\nthread 'main' panicked at "));
+    assert!(summary.contains("unknown action: fla\nstack backtrace:\n"));
+
+    let output = actions().runner().args(["fla", "x"]).run().unwrap();
+    assert!(output.panic().is_none());
+    let output = actions().runner().capture_panics().run().unwrap();
+    assert!(output.panic().is_none());
+    assert!(output.failure_summary().is_none());
+}
+
+// Test that resource usage is reported when sampling is requested.
+#[test]
+fn test_resource_usage() {
+    let artifact = Artifact::from(build_test_binary("does-build", "testbins").unwrap());
+
+    let output = artifact.runner().run().unwrap();
+    assert!(output.resource_usage().is_none());
+
+    let output = artifact.runner().sample_resource_usage().run().unwrap();
+    let usage = output.resource_usage().expect("no resource usage");
+
+    if cfg!(any(unix, windows)) {
+        assert!(usage.cpu_time().is_some());
+        assert!(usage.peak_rss().unwrap() > 0);
+    }
+
+    // The binary's exit status is kept, even though it's reaped along with
+    // its usage, including when it has to be killed.
+    #[cfg(unix)]
+    {
+        let sh = Artifact::from(PathBuf::from("/bin/sh"));
+        let output = sh
+            .runner()
+            .args(["-c", "exit 3"])
+            .sample_resource_usage()
+            .run()
+            .unwrap();
+        assert_eq!(output.status().code(), Some(3));
+
+        let output = sh
+            .runner()
+            .args(["-c", "sleep 5"])
+            .sample_resource_usage()
+            .timeout(Duration::from_millis(100))
+            .run()
+            .unwrap();
+        assert!(output.timed_out());
+        assert!(output.resource_usage().unwrap().cpu_time().is_some());
+    }
+}
+
+// Test timing repeated runs of a binary.
+#[test]
+fn test_bench() {
+    let artifact = Artifact::from(build_test_binary("does-build", "testbins").unwrap());
+
+    let stats = artifact
+        .bench(
+            ["ignored"],
+            BenchOptions {
+                warmups: 1,
+                iterations: 5,
+            },
+        )
+        .unwrap();
+
+    assert_eq!(stats.samples().len(), 5);
+    assert!(stats.min() <= stats.mean());
+    assert!(stats.mean() <= stats.max());
+    assert!(stats.p95() <= stats.max());
+}
+
+// Singleton function for the "actions" binary, which does what its arguments
+// tell it to.
+build_test_binary_once!(actions, "testbins");
+
+fn actions() -> Artifact {
+    Artifact::from(path_to_actions())
+}
+
+// Test that a binary exceeding its timeout is killed.
+#[test]
+fn test_run_timeout() {
+    let output = actions()
+        .runner()
+        .args(["sleep", "10000"])
+        .timeout(Duration::from_millis(100))
+        .run()
+        .unwrap();
+
+    assert!(output.timed_out());
+    assert!(!output.status().success());
+}
+
+// Test that failed runs are retried according to the policy, and that every
+// attempt is kept.
+#[test]
+fn test_run_retry() {
+    let artifact = actions();
+
+    let output = artifact
+        .runner()
+        .args(["print", "hello", "exit", "3"])
+        .retry(RetryPolicy::new(3).on_exit_code(3))
+        .run()
+        .unwrap();
+
+    assert_eq!(output.status().code(), Some(3));
+    assert_eq!(output.previous_attempts().len(), 2);
+    for attempt in output.previous_attempts() {
+        assert_eq!(attempt.stdout(), b"hello\n");
+    }
+
+    let output = artifact
+        .runner()
+        .args(["exit", "3"])
+        .retry(RetryPolicy::new(3).on_exit_code(4).on_timeout())
+        .run()
+        .unwrap();
+
+    assert_eq!(output.status().code(), Some(3));
+    assert!(output.previous_attempts().is_empty());
+}
+
+// Test running several instances at once, each with their own placeholders.
+#[test]
+fn test_run_instances() {
+    let instances = actions()
+        .runner()
+        .args(["print", "{instance} {port}", "print", "{dir}"])
+        .run_instances(3)
+        .unwrap();
+
+    assert!(instances.success());
+    assert_eq!(instances.len(), 3);
+
+    let mut ports = vec![];
+    for (index, output) in instances.outputs().iter().enumerate() {
+        let stdout = String::from_utf8(output.stdout().to_vec()).unwrap();
+        let mut lines = stdout.lines();
+        let (instance, port) = lines.next().unwrap().split_once(' ').unwrap();
+        assert_eq!(instance, index.to_string());
+        ports.push(port.to_owned());
+        assert_eq!(Path::new(lines.next().unwrap()), instances.sandbox(index));
+    }
+
+    ports.sort();
+    ports.dedup();
+    assert_eq!(ports.len(), 3);
+}
+
+// Test starting processes in order with readiness checks, and tearing them down
+// in reverse.
+#[test]
+fn test_harness() {
+    let artifact = actions();
+
+    let mut harness = TestHarness::new();
+    harness
+        .env("GREETING", "shared")
+        .process(
+            "server",
+            artifact.runner().args(["print", "ready", "sleep", "10000"]),
+        )
+        .ready_when(Readiness::stdout_line("ready"))
+        .process("client", artifact.runner().args(["sleep", "10000"]));
+
+    let running = harness.start().unwrap();
+    assert!(running.process("server").is_some());
+    assert!(running.process("client").is_some());
+    assert!(running.process("other").is_none());
+
+    let outputs = running.stop().unwrap();
+    let names: Vec<_> = outputs.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, ["client", "server"]);
+    assert_eq!(outputs[1].1.stdout(), b"ready\n");
+}
+
+// Test that a process which exits before it's ready fails the harness.
+#[test]
+fn test_harness_not_ready() {
+    let artifact = actions();
+
+    let mut harness = TestHarness::new();
+    harness
+        .process("server", artifact.runner().args(["exit", "1"]))
+        .ready_when(Readiness::custom(|_| false));
+
+    assert!(matches!(harness.start(), Err(RunError::NotReady(name)) if name == "server"));
+}
+
+// Test the different readiness probes.
+#[test]
+fn test_wait_ready() {
+    let artifact = actions();
+    let timeout = Duration::from_secs(10);
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let mut guard = artifact.runner().args(["sleep", "10000"]).spawn().unwrap();
+    guard
+        .wait_ready(Readiness::tcp_port(port), timeout)
+        .unwrap();
+    guard
+        .wait_ready(Readiness::file_exists("testbins"), timeout)
+        .unwrap();
+
+    let mut guard = artifact
+        .runner()
+        .args([
+            "print", "one", "sleep", "100", "print", "two", "sleep", "10000",
+        ])
+        .spawn()
+        .unwrap();
+    guard
+        .wait_ready(Readiness::stdout_line("two"), timeout)
+        .unwrap();
+    assert_eq!(guard.stdout_so_far(), b"one\ntwo\n");
+
+    let mut guard = artifact.runner().args(["sleep", "10000"]).spawn().unwrap();
+    let result = guard.wait_ready(Readiness::stdout_line("never"), Duration::from_millis(100));
+    assert!(matches!(result, Err(RunError::NotReady(name)) if name == "actions"));
+}
+
+// Test graceful shutdown, and escalation to killing the binary.
+#[test]
+fn test_shutdown() {
+    let artifact = actions();
+    let graceful = Graceful {
+        signal: Signal::Term,
+        grace_period: Duration::from_secs(5),
+    };
+
+    let guard = artifact.runner().args(["exit", "0"]).spawn().unwrap();
+    std::thread::sleep(Duration::from_millis(200));
+    let shutdown = guard.shutdown(graceful).unwrap();
+    assert_eq!(shutdown.path(), ShutdownPath::AlreadyExited);
+    assert!(shutdown.output().status().success());
+
+    let guard = artifact.runner().args(["sleep", "10000"]).spawn().unwrap();
+    let path = guard.shutdown(graceful).unwrap().path();
+    if cfg!(unix) {
+        assert_eq!(path, ShutdownPath::Graceful);
+    } else {
+        // On Windows, this depends on whether the tests have a console.
+        assert_ne!(path, ShutdownPath::AlreadyExited);
+    }
+}
+
+// Test sending signals, which mean the same thing on every platform.
+#[test]
+fn test_signal() {
+    let mut guard = actions().runner().args(["sleep", "10000"]).spawn().unwrap();
+    guard.signal(Signal::Kill).unwrap();
+    let output = guard.wait().unwrap();
+    assert!(!output.status().success());
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        assert_eq!(output.status().signal(), Some(9));
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        let mut guard = actions().runner().args(["sleep", "10000"]).spawn().unwrap();
+        guard.signal(Signal::Int).unwrap();
+        assert_eq!(guard.wait().unwrap().status().signal(), Some(2));
+    }
+}
+
+// Test that a binary ignoring the signal gets killed after the grace period.
+#[cfg(unix)]
+#[test]
+fn test_shutdown_escalation() {
+    let mut guard = actions()
+        .runner()
+        .args(["ignore", "SIGTERM", "print", "ready", "sleep", "10000"])
+        .spawn()
+        .unwrap();
+    guard
+        .wait_ready(Readiness::stdout_line("ready"), Duration::from_secs(10))
+        .unwrap();
+
+    let shutdown = guard
+        .shutdown(Graceful {
+            signal: Signal::Term,
+            grace_period: Duration::from_millis(100),
+        })
+        .unwrap();
+    assert_eq!(shutdown.path(), ShutdownPath::Killed);
+}
+
+// Test that dropping a guard gives the binary its grace period before killing
+// it, and that harnesses stop their processes gracefully.
+#[cfg(unix)]
+#[test]
+fn test_grace_period() {
+    use std::os::unix::process::ExitStatusExt;
+
+    let artifact = actions();
+    let grace_period = Duration::from_millis(300);
+    let mut guard = artifact
+        .runner()
+        .args(["ignore", "SIGTERM", "print", "ready", "sleep", "10000"])
+        .grace_period(grace_period)
+        .spawn()
+        .unwrap();
+    assert_eq!(guard.grace_period(), grace_period);
+    guard
+        .wait_ready(Readiness::stdout_line("ready"), Duration::from_secs(10))
+        .unwrap();
+    let start = Instant::now();
+    drop(guard);
+    let elapsed = start.elapsed();
+    assert!(elapsed >= grace_period, "{:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(5), "{:?}", elapsed);
+
+    let mut harness = TestHarness::new();
+    harness
+        .process("server", artifact.runner().args(["sleep", "10000"]))
+        .process(
+            "client",
+            artifact
+                .runner()
+                .args(["sleep", "10000"])
+                .grace_period(Duration::ZERO),
+        );
+    let outputs = harness.start().unwrap().stop().unwrap();
+    assert_eq!(outputs[0].1.status().signal(), Some(9));
+    assert_eq!(outputs[1].1.status().signal(), Some(15));
+}
+
+// Test that killing a binary also kills the processes it started.
+#[cfg(unix)]
+#[test]
+fn test_kill_process_group() {
+    let mut guard = actions()
+        .runner()
+        .args(["spawn", "10000", "sleep", "10000"])
+        .spawn()
+        .unwrap();
+    guard
+        .wait_ready(
+            Readiness::custom(|guard| guard.stdout_so_far().ends_with(b"\n")),
+            Duration::from_secs(10),
+        )
+        .unwrap();
+    let stdout = String::from_utf8(guard.stdout_so_far()).unwrap();
+    let grandchild = stdout.trim().to_owned();
+
+    // The grandchild shares the binary's stdout, so this would wait for it if
+    // it were left running.
+    let started = Instant::now();
+    guard.kill().unwrap();
+    assert!(started.elapsed() < Duration::from_secs(5));
+
+    // It might linger briefly as a zombie, until it's reaped.
+    let running = || {
+        let ps = std::process::Command::new("ps")
+            .args(["-o", "stat=", "-p", &grandchild])
+            .output()
+            .unwrap();
+        let stat = String::from_utf8_lossy(&ps.stdout);
+        !stat.trim().is_empty() && !stat.trim().starts_with('Z')
+    };
+    assert!(!running());
+}
+
+#[test]
+fn test_log_on_failure() {
+    let artifact = actions();
+    let log_dir = std::env::temp_dir().join("test-binary-logs");
+
+    let output = artifact
+        .runner()
+        .args(["print", "out", "eprint", "err", "exit", "1"])
+        .echo_output()
+        .keep_log_on_failure(&log_dir)
+        .run()
+        .unwrap();
+
+    let log_file = output.log_file().expect("failed run has no log file");
+    assert_eq!(
+        std::fs::read_to_string(log_file).unwrap().lines().count(),
+        2
+    );
+    std::fs::remove_file(log_file).unwrap();
+
+    let output = artifact
+        .runner()
+        .args(["print", "out"])
+        .keep_log_on_failure(&log_dir)
+        .run()
+        .unwrap();
+    assert!(output.log_file().is_none());
+    assert_eq!(output.stdout(), b"out\n");
+}
+
+// Test an interactive session, and that a failed expectation has the
+// transcript.
+#[test]
+fn test_session() {
+    let echo = test_binary::stock::echo_stdin().unwrap();
+    let mut session = echo.runner().interact().unwrap();
+
+    session.send_line("fla mingo").unwrap();
+    assert_eq!(
+        session.expect("mingo", Duration::from_secs(10)).unwrap(),
+        "fla "
+    );
+    session.send_line("pink").unwrap();
+    session.expect("pink\n", Duration::from_secs(10)).unwrap();
+
+    match session.expect("mingo", Duration::from_millis(100)) {
+        Err(RunError::ExpectFailed {
+            pattern,
+            transcript,
+        }) => {
+            assert_eq!(pattern, "mingo");
+            assert_eq!(
+                transcript,
+                "[sent] \"fla mingo\\n\"\nfla mingo\n[sent] \"pink\\n\"\npink\n"
+            );
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+
+    let output = session.wait().unwrap();
+    assert!(output.status().success());
+    assert_eq!(output.stdout(), b"fla mingo\npink\n");
+}
+
+// Test an interactive session over a pseudoterminal, which echoes input.
+#[cfg(unix)]
+#[test]
+fn test_session_pty() {
+    let echo = test_binary::stock::echo_stdin().unwrap();
+    let mut session = echo.runner().interact_pty().unwrap();
+
+    session.send_line("fla").unwrap();
+    session.expect("fla\r\n", Duration::from_secs(10)).unwrap();
+    session.expect("fla\r\n", Duration::from_secs(10)).unwrap();
+    assert!(session.transcript().ends_with("fla\r\nfla\r\n"));
+
+    session.kill().unwrap();
+}
+
+// Test feeding a binary's stdin from bytes, files and paced writes.
+#[test]
+fn test_stdin() {
+    let echo = test_binary::stock::echo_stdin().unwrap();
+
+    let output = echo.runner().stdin(&Input::bytes("fla")).run().unwrap();
+    assert_eq!(output.stdout(), b"fla");
+
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("input");
+    std::fs::write(&file, "mingo\n").unwrap();
+    let start = Instant::now();
+    let output = echo
+        .runner()
+        .stdin(
+            Input::file(&file)
+                .wait(Duration::from_millis(200))
+                .write_line("pink"),
+        )
+        .run()
+        .unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(200));
+    assert_eq!(output.stdout(), b"mingo\npink\n");
+
+    let result = echo
+        .runner()
+        .stdin(&Input::file(dir.path().join("missing")))
+        .run();
+    assert!(matches!(result, Err(RunError::SpawnError(_))));
+
+    // Input the binary doesn't read is discarded.
+    let output = actions()
+        .runner()
+        .args(["exit", "0"])
+        .stdin(Input::bytes(vec![0; 1 << 20]).wait(Duration::from_millis(100)))
+        .run()
+        .unwrap();
+    assert!(output.status().success());
+}
+
+// Test that output past the threshold is spilled to a file.
+#[test]
+fn test_spill_output() {
+    use std::io::Read;
+
+    let text = "fla".repeat(10_000);
+    let output = actions()
+        .runner()
+        .args(["print", &text, "eprint", "mingo"])
+        .spill_output(1000)
+        .run()
+        .unwrap();
+
+    assert_eq!(output.stdout(), &text.as_bytes()[..1000]);
+    let path = output.stdout_path().unwrap().to_owned();
+    assert_eq!(
+        std::fs::read(&path).unwrap(),
+        format!("{}\n", text).as_bytes()
+    );
+    let mut stdout = String::new();
+    output
+        .stdout_reader()
+        .unwrap()
+        .read_to_string(&mut stdout)
+        .unwrap();
+    assert_eq!(stdout, format!("{}\n", text));
+
+    assert_eq!(output.stderr(), b"mingo\n");
+    assert!(output.stderr_path().is_none());
+    let mut stderr = vec![];
+    output
+        .stderr_reader()
+        .unwrap()
+        .read_to_end(&mut stderr)
+        .unwrap();
+    assert_eq!(stderr, b"mingo\n");
+
+    let clone = output.clone();
+    drop(output);
+    assert!(path.exists());
+    drop(clone);
+    assert!(!path.exists());
+}
+
+// Test choosing which Cargo to run.
+#[test]
+fn test_with_cargo() {
+    let manifest = PathBuf::from_iter(["testbins", "does-build", "Cargo.toml"]);
+
+    let result = TestBinary::relative_to_parent("does-build", &manifest)
+        .unwrap()
+        .with_cargo(std::env::var_os("CARGO").unwrap())
+        .build();
+    assert_path_end(result.unwrap(), "does-build");
+
+    let result = TestBinary::relative_to_parent("does-build", &manifest)
+        .unwrap()
+        .with_cargo("no-such-cargo")
+        .build();
+    assert!(matches!(result, Err(TestBinaryError::CargoRunError(_))));
+}
+
+// Test that a toolchain file in the binary's package is respected.
+#[test]
+fn test_toolchain_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let package = dir.path().join("does-build");
+    std::fs::create_dir_all(package.join("src")).unwrap();
+    for file in ["Cargo.toml", "src/main.rs"] {
+        std::fs::copy(
+            Path::new("testbins/does-build").join(file),
+            package.join(file),
+        )
+        .unwrap();
+    }
+    let manifest = package.join("Cargo.toml");
+    let toolchain_file = package.join("rust-toolchain.toml");
+
+    std::fs::write(
+        &toolchain_file,
+        "[toolchain]\nchannel = \"test-binary-no-such-toolchain\"\n",
+    )
+    .unwrap();
+    let result = TestBinary::relative_to_parent("does-build", &manifest)
+        .unwrap()
+        .build();
+    match result {
+        Err(TestBinaryError::ToolchainUnavailable {
+            toolchain, file, ..
+        }) => {
+            assert_eq!(toolchain, "test-binary-no-such-toolchain");
+            assert_eq!(file, toolchain_file);
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    // Choosing a Cargo explicitly overrides the toolchain file.
+    TestBinary::relative_to_parent("does-build", &manifest)
+        .unwrap()
+        .with_cargo(std::env::var_os("CARGO").unwrap())
+        .build()
+        .unwrap();
+
+    let stable_installed = std::process::Command::new("rustup")
+        .args(["which", "cargo", "--toolchain", "stable"])
+        .output()
+        .is_ok_and(|output| output.status.success());
+    if stable_installed {
+        std::fs::write(&toolchain_file, "[toolchain]\nchannel = \"stable\"\n").unwrap();
+        TestBinary::relative_to_parent("does-build", &manifest)
+            .unwrap()
+            .build()
+            .unwrap();
+    }
+}
+
+// Test that a reserved port is passed to the binary and free for it to use.
+#[test]
+fn test_port() {
+    let port = Port::tcp().unwrap();
+    let output = actions()
+        .runner()
+        .arg("print")
+        .port_arg(&port)
+        .run()
+        .unwrap();
+    assert_eq!(output.stdout(), format!("{}\n", port.number()).as_bytes());
+    std::net::TcpListener::bind(port.addr()).unwrap();
+}
+
+// Test that how a binary was started is recorded, and shown when it fails.
+#[test]
+fn test_launch_context() {
+    let artifact = actions();
+    let dir = tempfile::tempdir().unwrap();
+    let output = artifact
+        .runner()
+        .args(["eprint", "oops", "exit", "3"])
+        .env("LAUNCH_TEST", "fla")
+        .current_dir(dir.path())
+        .run()
+        .unwrap();
+
+    let launch = output.launch();
+    assert_eq!(launch.program(), artifact.path());
+    assert_eq!(launch.args(), ["eprint", "oops", "exit", "3"]);
+    assert!(launch
+        .envs()
+        .iter()
+        .any(|(key, value)| key == "LAUNCH_TEST" && value.as_deref() == Some("fla".as_ref())));
+    assert_eq!(launch.current_dir(), dir.path());
+    assert!(launch.started_at().elapsed().unwrap() < Duration::from_secs(60));
+
+    let panic = std::panic::catch_unwind(|| {
+        output.assert_success();
+    })
+    .unwrap_err();
+    let message = panic.downcast_ref::<String>().unwrap();
+    assert!(message.contains("LAUNCH_TEST=\"fla\""), "{}", message);
+    assert!(message.contains("oops"), "{}", message);
+}
+
+// Test running a binary as another user, which needs us to be root.
+#[cfg(unix)]
+#[test]
+fn test_run_as() {
+    use std::os::unix::fs::PermissionsExt;
+
+    const NOBODY: u32 = 65534;
+
+    // Somewhere nobody can get to the binary, unlike the target directory.
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::set_permissions(dir.path(), std::fs::Permissions::from_mode(0o755)).unwrap();
+    let path = dir.path().join("actions");
+    std::fs::copy(actions().path(), &path).unwrap();
+    let artifact = Artifact::from(path);
+
+    let file = dir.path().join("root-only");
+    std::fs::write(&file, "fla").unwrap();
+    std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o644)).unwrap();
+    let mut param = file.into_os_string();
+    param.push("=pink");
+
+    let result = artifact
+        .runner()
+        .run_as(NOBODY, NOBODY)
+        .arg("write")
+        .arg(&param)
+        .run();
+
+    if is_root() {
+        assert!(!result.unwrap().status().success());
+        assert!(artifact
+            .runner()
+            .run_as(0, 0)
+            .arg("write")
+            .arg(&param)
+            .run()
+            .unwrap()
+            .status()
+            .success());
+    } else {
+        assert!(matches!(
+            result,
+            Err(RunError::CannotSwitchUser {
+                uid: NOBODY,
+                gid: NOBODY
+            })
+        ));
+    }
+}
+
+// Test building and running at a lower priority, and on some CPUs only.
+#[cfg(unix)]
+#[test]
+fn test_scheduling() {
+    let manifest = PathBuf::from_iter(["testbins", "does-build", "Cargo.toml"]);
+    let mut binary = TestBinary::relative_to_parent("does-build", &manifest).unwrap();
+    binary.with_niceness(5);
+    #[cfg(target_os = "linux")]
+    binary.with_cpus([0]);
+    let output = binary.build_artifact().unwrap().runner().run().unwrap();
+    assert!(output.status().success());
+
+    let sh = Artifact::from(PathBuf::from("/bin/sh"));
+    let niceness = |output: test_binary::RunOutput| -> i32 {
+        String::from_utf8_lossy(output.stdout())
+            .trim()
+            .parse()
+            .unwrap()
+    };
+    let ours = niceness(sh.runner().arg("-c").arg("nice").run().unwrap());
+    let lowered = sh.runner().arg("-c").arg("nice").niceness(5).run().unwrap();
+    assert_eq!(niceness(lowered), (ours + 5).min(19));
+
+    #[cfg(target_os = "linux")]
+    {
+        let output = sh
+            .runner()
+            .arg("-c")
+            .arg("grep Cpus_allowed_list /proc/self/status")
+            .cpus([0])
+            .run()
+            .unwrap();
+        assert_eq!(output.stdout(), b"Cpus_allowed_list:\t0\n");
+    }
+}
+
+/// Whether the tests are running as root.
+#[cfg(unix)]
+fn is_root() -> bool {
+    std::process::Command::new("id")
+        .arg("-u")
+        .output()
+        .map(|output| output.stdout == b"0\n")
+        .unwrap_or(false)
+}
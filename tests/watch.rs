@@ -0,0 +1,44 @@
+//! Tests for rebuilding on changes, which is behind the `watch` feature.
+
+use std::{ops::ControlFlow, path::Path};
+use test_binary::TestBinary;
+
+fn write_package(dir: &Path, message: &str) {
+    std::fs::create_dir_all(dir.join("src")).unwrap();
+    std::fs::write(
+        dir.join("Cargo.toml"),
+        "[package]\nname = \"watched\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[workspace]\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("src").join("main.rs"),
+        format!("fn main() {{ println!(\"{}\"); }}\n", message),
+    )
+    .unwrap();
+}
+
+// Test that changing the source causes a rebuild.
+#[test]
+fn test_watch() {
+    let dir = tempfile::tempdir().unwrap();
+    write_package(dir.path(), "fla");
+    let manifest = dir.path().join("Cargo.toml");
+
+    let mut printed = vec![];
+    TestBinary::relative_to_parent("watched", &manifest)
+        .unwrap()
+        .watch(|result| {
+            let output = result.unwrap().runner().run().unwrap();
+            printed.push(String::from_utf8(output.stdout().to_vec()).unwrap());
+
+            if printed.len() == 1 {
+                write_package(dir.path(), "mingo");
+                ControlFlow::Continue(())
+            } else {
+                ControlFlow::Break(())
+            }
+        })
+        .unwrap();
+
+    assert_eq!(printed, ["fla\n", "mingo\n"]);
+}